@@ -47,12 +47,16 @@ pub fn localhost_client() -> Client {
 }
 
 pub fn localhost_client_with_device_id(device_id: &str) -> Client {
+  localhost_client_with_config(device_id, ClientConfiguration::default())
+}
+
+pub fn localhost_client_with_config(device_id: &str, config: ClientConfiguration) -> Client {
   Client::new(
     &LOCALHOST_URL,
     &LOCALHOST_WS,
     &LOCALHOST_GOTRUE,
     device_id,
-    ClientConfiguration::default(),
+    config,
     "test",
   )
 }
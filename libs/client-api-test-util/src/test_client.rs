@@ -14,9 +14,9 @@ use collab_entity::CollabType;
 use collab_folder::Folder;
 use database_entity::dto::{
   AFAccessLevel, AFRole, AFSnapshotMeta, AFSnapshotMetas, AFUserWorkspaceInfo, AFWorkspace,
-  AFWorkspaceMember, BatchQueryCollabResult, CollabParams, CreateCollabParams,
-  InsertCollabMemberParams, QueryCollab, QueryCollabParams, QuerySnapshotParams, SnapshotData,
-  UpdateCollabMemberParams,
+  AFWorkspaceMember, BatchQueryCollabResult, CollabMemberIdentify, CollabParams,
+  CreateCollabParams, InsertCollabMemberParams, QueryCollab, QueryCollabParams,
+  QuerySnapshotParams, SnapshotData, UpdateCollabMemberParams,
 };
 use mime::Mime;
 use serde_json::Value;
@@ -241,6 +241,24 @@ impl TestClient {
       .unwrap();
   }
 
+  pub async fn remove_client_as_collab_member(
+    &self,
+    workspace_id: &str,
+    object_id: &str,
+    other_client: &TestClient,
+  ) {
+    let uid = other_client.uid().await;
+    self
+      .api_client
+      .remove_collab_member(CollabMemberIdentify {
+        uid,
+        workspace_id: workspace_id.to_string(),
+        object_id: object_id.to_string(),
+      })
+      .await
+      .unwrap();
+  }
+
   pub async fn wait_object_sync_complete(&self, object_id: &str) {
     self
       .wait_object_sync_complete_with_secs(object_id, 20)
@@ -266,7 +284,7 @@ impl TestClient {
 
   #[allow(dead_code)]
   pub async fn get_blob_metadata(&self, workspace_id: &str, file_id: &str) -> BlobMetadata {
-    let url = self.api_client.get_blob_url(workspace_id, file_id);
+    let url = self.api_client.get_blob_metadata_url(workspace_id, file_id);
     self.api_client.get_blob_metadata(&url).await.unwrap()
   }
 
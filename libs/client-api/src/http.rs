@@ -9,11 +9,17 @@ use std::io::Read;
 use app_error::AppError;
 use bytes::Bytes;
 use database_entity::dto::{
-  AFCollabMember, AFCollabMembers, AFSnapshotMeta, AFSnapshotMetas, AFUserProfile,
-  AFUserWorkspaceInfo, AFWorkspace, AFWorkspaceMember, AFWorkspaces, BatchQueryCollabParams,
-  BatchQueryCollabResult, CollabMemberIdentify, CreateCollabParams, DeleteCollabParams,
-  InsertCollabMemberParams, QueryCollab, QueryCollabMembers, QueryCollabParams,
-  QuerySnapshotParams, SnapshotData, UpdateCollabMemberParams,
+  AFCollabJson, AFCollabMember, AFCollabMembers, AFCollabSearchResult, AFCollabSearchResults,
+  AFDatabaseImport, AFDocumentDiff, AFDocumentImport, AFSnapshotMeta, AFSnapshotMetas,
+  AFSnapshotRestore, AFSnapshotRestores, AFSnapshotRetentionPolicies, AFSnapshotRetentionPolicy,
+  AFUserProfile, AFUserWorkspaceInfo, AFWorkspace, AFWorkspaceExport, AFWorkspaceImport,
+  AFWorkspaceMember, AFWorkspaces, BatchQueryCollabParams, BatchQueryCollabResult,
+  CollabMemberIdentify, CreateCollabParams, DeleteCollabParams,
+  DeleteSnapshotRetentionPolicyParams, DocumentExportFormat, ExportDatabaseParams,
+  ExportDocumentParams, ImportCsvParams, ImportMarkdownParams, InsertCollabMemberParams,
+  PinSnapshotParams, QueryCollab, QueryCollabJsonParams, QueryCollabMembers, QueryCollabParams,
+  QuerySnapshotDiffParams, QuerySnapshotParams, RestoreSnapshotParams, SearchCollabParams,
+  SnapshotData, UpdateCollabMemberParams,
 };
 use futures_util::StreamExt;
 use gotrue::grant::Grant;
@@ -33,8 +39,11 @@ use reqwest::RequestBuilder;
 use shared_entity::dto::auth_dto::SignInTokenResponse;
 use shared_entity::dto::auth_dto::UpdateUserParams;
 use shared_entity::dto::workspace_dto::{
-  BlobMetadata, CreateWorkspaceMembers, RepeatedBlobMetaData, WorkspaceMemberChangeset,
-  WorkspaceMembers, WorkspaceSpaceUsage,
+  BlobGcParams, BlobGcReport, BlobMetadata, BlobPublicLink, BlobUpload, BlobUploadPart,
+  CompletePresignedPutParams, CreateBlobPublicLinkParams, CreateBlobUploadParams,
+  CreatePresignedPutParams, CreateWorkspaceMembers, PresignedPutBlob, RepeatedBlobMetaData,
+  RepeatedBlobUploadPart, RepeatedWorkspacePlan, UpdateWorkspaceQuotaParams,
+  WorkspaceMemberChangeset, WorkspaceMembers, WorkspacePlan, WorkspaceQuota, WorkspaceSpaceUsage,
 };
 use shared_entity::response::{AppResponse, AppResponseError};
 use std::sync::atomic::AtomicBool;
@@ -42,6 +51,7 @@ use std::sync::Arc;
 use std::time::Duration;
 use tracing::{error, event, info, instrument, trace, warn};
 use url::Url;
+use uuid::Uuid;
 
 use gotrue_entity::dto::SignUpResponse::{Authenticated, NotAuthenticated};
 use gotrue_entity::dto::{GotrueTokenResponse, UpdateGotrueUserParams, User};
@@ -59,6 +69,9 @@ pub struct ClientConfiguration {
   /// A larger buffer size means more data is compressed in a single operation, which can lead to better compression ratios
   /// since Brotli has more data to analyze for patterns and repetitions.
   pub(crate) compression_buffer_size: usize,
+  /// Upload the blobs to the bucket directly with presigned urls when the server supports it. It
+  /// costs an extra request per upload, so it's disabled by default.
+  pub(crate) presigned_blob_upload: bool,
 }
 
 impl ClientConfiguration {
@@ -76,6 +89,11 @@ impl ClientConfiguration {
     };
     self
   }
+
+  pub fn with_presigned_blob_upload(mut self, presigned_blob_upload: bool) -> Self {
+    self.presigned_blob_upload = presigned_blob_upload;
+    self
+  }
 }
 
 impl Default for ClientConfiguration {
@@ -83,6 +101,7 @@ impl Default for ClientConfiguration {
     Self {
       compression_quality: 8,
       compression_buffer_size: 10240,
+      presigned_blob_upload: false,
     }
   }
 }
@@ -540,6 +559,101 @@ impl Client {
       .into_data()
   }
 
+  /// Start exporting the workspace as an archive. Poll the returned job with
+  /// [Client::get_workspace_export] until it's completed, then download the archive with
+  /// [Client::download_workspace_export].
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn export_workspace(
+    &self,
+    workspace_id: &str,
+  ) -> Result<AFWorkspaceExport, AppResponseError> {
+    let url = format!("{}/api/workspace/{}/export", self.base_url, workspace_id);
+    let resp = self
+      .http_client_with_auth(Method::POST, &url)
+      .await?
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<AFWorkspaceExport>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn get_workspace_export(
+    &self,
+    workspace_id: &str,
+    job_id: &Uuid,
+  ) -> Result<AFWorkspaceExport, AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/export/{}",
+      self.base_url, workspace_id, job_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::GET, &url)
+      .await?
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<AFWorkspaceExport>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  /// Download the archive of a completed export job.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn download_workspace_export(
+    &self,
+    workspace_id: &str,
+    job_id: &Uuid,
+  ) -> Result<Vec<u8>, AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/export/{}/archive",
+      self.base_url, workspace_id, job_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::GET, &url)
+      .await?
+      .send()
+      .await?;
+    log_request_id(&resp);
+
+    // The errors are returned as json
+    let is_archive = resp
+      .headers()
+      .get(header::CONTENT_TYPE)
+      .map(|value| value == "application/octet-stream")
+      .unwrap_or(false);
+    if !is_archive {
+      AppResponse::<()>::from_response(resp).await?.into_error()?;
+      return Err(AppResponseError::from(AppError::Unhandled(
+        "the response is not a workspace archive".to_string(),
+      )));
+    }
+    let archive = resp.bytes().await?;
+    Ok(archive.to_vec())
+  }
+
+  /// Recreate the workspace of the archive as a new workspace owned by the user.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn import_workspace(
+    &self,
+    archive: Vec<u8>,
+  ) -> Result<AFWorkspaceImport, AppResponseError> {
+    let url = format!("{}/api/workspace/import", self.base_url);
+    let resp = self
+      .http_client_with_auth(Method::POST, &url)
+      .await?
+      .header(header::CONTENT_TYPE, "application/octet-stream")
+      .body(archive)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<AFWorkspaceImport>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
   #[instrument(level = "debug", skip_all, err)]
   pub async fn get_workspaces(&self) -> Result<AFWorkspaces, AppResponseError> {
     let url = format!("{}/api/workspace", self.base_url);
@@ -776,174 +890,243 @@ impl Client {
       .into_data()
   }
 
-  pub async fn get_snapshot(
+  /// Replace the content of the collab with the content of one of its snapshots. The connected
+  /// clients receive the restored content as a regular update.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn restore_snapshot(
     &self,
     workspace_id: &str,
     object_id: &str,
-    params: QuerySnapshotParams,
-  ) -> Result<SnapshotData, AppResponseError> {
+    params: RestoreSnapshotParams,
+  ) -> Result<AFSnapshotRestore, AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/{}/snapshot",
-      self.base_url, workspace_id, object_id,
+      "{}/api/workspace/{}/{}/snapshot/restore",
+      self.base_url, workspace_id, object_id
     );
     let resp = self
-      .http_client_with_auth(Method::GET, &url)
+      .http_client_with_auth(Method::POST, &url)
       .await?
       .json(&params)
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<SnapshotData>::from_response(resp)
+    AppResponse::<AFSnapshotRestore>::from_response(resp)
       .await?
       .into_data()
   }
 
-  pub async fn create_snapshot(
+  /// Return the snapshots that were restored into the collab, most recent first.
+  pub async fn get_snapshot_restores(
     &self,
     workspace_id: &str,
     object_id: &str,
-    collab_type: CollabType,
-  ) -> Result<AFSnapshotMeta, AppResponseError> {
+  ) -> Result<AFSnapshotRestores, AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/{}/snapshot",
-      self.base_url, workspace_id, object_id,
+      "{}/api/workspace/{}/{}/snapshot/restore",
+      self.base_url, workspace_id, object_id
     );
     let resp = self
-      .http_client_with_auth(Method::POST, &url)
+      .http_client_with_auth(Method::GET, &url)
       .await?
-      .json(&collab_type)
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<AFSnapshotMeta>::from_response(resp)
+    AppResponse::<AFSnapshotRestores>::from_response(resp)
       .await?
       .into_data()
   }
 
+  /// Return the blocks of the document that changed between the `from` snapshot and the `to`
+  /// snapshot, or the current state of the document if `to_snapshot_id` is None.
   #[instrument(level = "debug", skip_all, err)]
-  pub async fn update_collab(&self, params: CreateCollabParams) -> Result<(), AppResponseError> {
+  pub async fn get_snapshot_diff(
+    &self,
+    workspace_id: &str,
+    object_id: &str,
+    from_snapshot_id: i64,
+    to_snapshot_id: Option<i64>,
+  ) -> Result<AFDocumentDiff, AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/collab/{}",
-      self.base_url, &params.workspace_id, &params.object_id
+      "{}/api/workspace/{}/{}/snapshot/diff",
+      self.base_url, workspace_id, object_id
     );
     let resp = self
-      .http_client_with_auth(Method::PUT, &url)
+      .http_client_with_auth(Method::GET, &url)
       .await?
-      .json(&params)
+      .query(&QuerySnapshotDiffParams {
+        from_snapshot_id,
+        to_snapshot_id,
+      })
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<()>::from_response(resp).await?.into_error()
+    AppResponse::<AFDocumentDiff>::from_response(resp)
+      .await?
+      .into_data()
   }
 
-  #[instrument(level = "debug", skip_all)]
-  pub async fn get_collab(
+  /// Render the document as Markdown or HTML.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn export_document(
     &self,
-    params: QueryCollabParams,
-  ) -> Result<EncodedCollab, AppResponseError> {
+    workspace_id: &str,
+    object_id: &str,
+    format: DocumentExportFormat,
+  ) -> Result<String, AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/collab/{}",
-      self.base_url, &params.workspace_id, &params.object_id
+      "{}/api/workspace/{}/collab/{}/export",
+      self.base_url, workspace_id, object_id
     );
     let resp = self
       .http_client_with_auth(Method::GET, &url)
       .await?
-      .json(&params)
+      .query(&ExportDocumentParams { format })
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<EncodedCollab>::from_response(resp)
-      .await?
-      .into_data()
+
+    // The errors are returned as json
+    let is_text = resp
+      .headers()
+      .get(header::CONTENT_TYPE)
+      .and_then(|value| value.to_str().ok())
+      .map(|value| value.starts_with("text/"))
+      .unwrap_or(false);
+    if !is_text {
+      AppResponse::<()>::from_response(resp).await?.into_error()?;
+      return Err(AppResponseError::from(AppError::Unhandled(
+        "the response is not an exported document".to_string(),
+      )));
+    }
+    let content = resp.text().await?;
+    Ok(content)
   }
 
+  /// Create a document from the markdown and add its view to the folder of the workspace.
   #[instrument(level = "debug", skip_all, err)]
-  pub async fn batch_get_collab(
+  pub async fn import_markdown(
     &self,
     workspace_id: &str,
-    params: Vec<QueryCollab>,
-  ) -> Result<BatchQueryCollabResult, AppResponseError> {
+    params: ImportMarkdownParams,
+  ) -> Result<AFDocumentImport, AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/collab_list",
+      "{}/api/workspace/{}/import/markdown",
       self.base_url, workspace_id
     );
-    let params = BatchQueryCollabParams(params);
     let resp = self
-      .http_client_with_auth(Method::GET, &url)
+      .http_client_with_auth(Method::POST, &url)
       .await?
       .json(&params)
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<BatchQueryCollabResult>::from_response(resp)
+    AppResponse::<AFDocumentImport>::from_response(resp)
       .await?
       .into_data()
   }
 
+  /// Return the json of the collab, or of its snapshot if `snapshot_id` is given.
   #[instrument(level = "debug", skip_all, err)]
-  pub async fn delete_collab(&self, params: DeleteCollabParams) -> Result<(), AppResponseError> {
+  pub async fn get_collab_json(
+    &self,
+    workspace_id: &str,
+    object_id: &str,
+    collab_type: CollabType,
+    snapshot_id: Option<i64>,
+  ) -> Result<AFCollabJson, AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/collab/{}",
-      self.base_url, &params.workspace_id, &params.object_id
+      "{}/api/workspace/{}/collab/{}/json",
+      self.base_url, workspace_id, object_id
     );
     let resp = self
-      .http_client_with_auth(Method::DELETE, &url)
+      .http_client_with_auth(Method::GET, &url)
       .await?
-      .json(&params)
+      .query(&QueryCollabJsonParams {
+        collab_type,
+        snapshot_id,
+      })
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<()>::from_response(resp).await?.into_error()
+    AppResponse::<AFCollabJson>::from_response(resp)
+      .await?
+      .into_data()
   }
 
+  /// Export a view of the database as csv, or the inline view of the database if `view_id` is
+  /// None.
   #[instrument(level = "debug", skip_all, err)]
-  pub async fn add_collab_member(
+  pub async fn export_database_csv(
     &self,
-    params: InsertCollabMemberParams,
-  ) -> Result<(), AppResponseError> {
+    workspace_id: &str,
+    object_id: &str,
+    view_id: Option<String>,
+  ) -> Result<String, AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/collab/{}/member",
-      self.base_url, params.workspace_id, &params.object_id
+      "{}/api/workspace/{}/collab/{}/csv",
+      self.base_url, workspace_id, object_id
     );
     let resp = self
-      .http_client_with_auth(Method::POST, &url)
+      .http_client_with_auth(Method::GET, &url)
       .await?
-      .json(&params)
+      .query(&ExportDatabaseParams { view_id })
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<()>::from_response(resp).await?.into_error()
+
+    // The errors are returned as json
+    let is_csv = resp
+      .headers()
+      .get(header::CONTENT_TYPE)
+      .and_then(|value| value.to_str().ok())
+      .map(|value| value.starts_with("text/csv"))
+      .unwrap_or(false);
+    if !is_csv {
+      AppResponse::<()>::from_response(resp).await?.into_error()?;
+      return Err(AppResponseError::from(AppError::Unhandled(
+        "the response is not a csv file".to_string(),
+      )));
+    }
+    let csv = resp.text().await?;
+    Ok(csv)
   }
 
+  /// Create a database from the csv. The first line of the csv holds the names of the fields.
   #[instrument(level = "debug", skip_all, err)]
-  pub async fn get_collab_member(
+  pub async fn import_csv(
     &self,
-    params: CollabMemberIdentify,
-  ) -> Result<AFCollabMember, AppResponseError> {
+    workspace_id: &str,
+    params: ImportCsvParams,
+  ) -> Result<AFDatabaseImport, AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/collab/{}/member",
-      self.base_url, params.workspace_id, &params.object_id
+      "{}/api/workspace/{}/import/csv",
+      self.base_url, workspace_id
     );
     let resp = self
-      .http_client_with_auth(Method::GET, &url)
+      .http_client_with_auth(Method::POST, &url)
       .await?
       .json(&params)
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<AFCollabMember>::from_response(resp)
+    AppResponse::<AFDatabaseImport>::from_response(resp)
       .await?
       .into_data()
   }
 
+  /// Pin or unpin a snapshot of the collab. Pinned snapshots are never removed by the retention
+  /// policy of the workspace.
   #[instrument(level = "debug", skip_all, err)]
-  pub async fn update_collab_member(
+  pub async fn pin_snapshot(
     &self,
-    params: UpdateCollabMemberParams,
-  ) -> Result<(), AppResponseError> {
+    workspace_id: &str,
+    object_id: &str,
+    snapshot_id: i64,
+    params: PinSnapshotParams,
+  ) -> Result<AFSnapshotMeta, AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/collab/{}/member",
-      self.base_url, params.workspace_id, &params.object_id
+      "{}/api/workspace/{}/{}/snapshot/{}/pin",
+      self.base_url, workspace_id, object_id, snapshot_id
     );
     let resp = self
       .http_client_with_auth(Method::PUT, &url)
@@ -952,113 +1135,539 @@ impl Client {
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<()>::from_response(resp).await?.into_error()
+    AppResponse::<AFSnapshotMeta>::from_response(resp)
+      .await?
+      .into_data()
   }
 
-  #[instrument(level = "debug", skip_all, err)]
-  pub async fn remove_collab_member(
+  pub async fn get_snapshot_retention_policies(
     &self,
-    params: CollabMemberIdentify,
-  ) -> Result<(), AppResponseError> {
+    workspace_id: &str,
+  ) -> Result<AFSnapshotRetentionPolicies, AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/collab/{}/member",
-      self.base_url, params.workspace_id, &params.object_id
+      "{}/api/workspace/{}/snapshot/retention",
+      self.base_url, workspace_id
     );
     let resp = self
-      .http_client_with_auth(Method::DELETE, &url)
+      .http_client_with_auth(Method::GET, &url)
       .await?
-      .json(&params)
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<()>::from_response(resp).await?.into_error()
+    AppResponse::<AFSnapshotRetentionPolicies>::from_response(resp)
+      .await?
+      .into_data()
   }
 
+  /// Set the retention policy of the snapshots of the workspace, or of the collabs of the given
+  /// type in the workspace.
   #[instrument(level = "debug", skip_all, err)]
-  pub async fn get_collab_members(
+  pub async fn set_snapshot_retention_policy(
     &self,
-    params: QueryCollabMembers,
-  ) -> Result<AFCollabMembers, AppResponseError> {
+    workspace_id: &str,
+    params: AFSnapshotRetentionPolicy,
+  ) -> Result<(), AppResponseError> {
     let url = format!(
-      "{}/api/workspace/{}/collab/{}/member/list",
-      self.base_url, params.workspace_id, &params.object_id
+      "{}/api/workspace/{}/snapshot/retention",
+      self.base_url, workspace_id
     );
     let resp = self
-      .http_client_with_auth(Method::GET, &url)
+      .http_client_with_auth(Method::PUT, &url)
       .await?
       .json(&params)
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<AFCollabMembers>::from_response(resp)
-      .await?
-      .into_data()
-  }
-
-  pub async fn ws_url(&self, device_id: &str) -> Result<String, AppResponseError> {
-    self
-      .refresh_if_expired(chrono::Local::now().timestamp())
-      .await?;
-
-    let access_token = self.access_token()?;
-    Ok(format!("{}/{}/{}", self.ws_addr, access_token, device_id))
-  }
-
-  pub fn get_blob_url(&self, workspace_id: &str, file_id: &str) -> String {
-    format!(
-      "{}/api/file_storage/{}/blob/{}",
-      self.base_url, workspace_id, file_id
-    )
+    AppResponse::<()>::from_response(resp).await?.into_error()?;
+    Ok(())
   }
 
-  pub async fn put_blob<T: Into<Bytes>>(
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn delete_snapshot_retention_policy(
     &self,
-    url: &str,
-    data: T,
-    mime: &Mime,
+    workspace_id: &str,
+    collab_type: Option<CollabType>,
   ) -> Result<(), AppResponseError> {
-    let data = data.into();
+    let url = format!(
+      "{}/api/workspace/{}/snapshot/retention",
+      self.base_url, workspace_id
+    );
     let resp = self
-      .http_client_with_auth(Method::PUT, url)
+      .http_client_with_auth(Method::DELETE, &url)
       .await?
-      .header(header::CONTENT_TYPE, mime.to_string())
-      .body(data)
+      .json(&DeleteSnapshotRetentionPolicyParams { collab_type })
       .send()
       .await?;
     log_request_id(&resp);
-    if resp.status() == StatusCode::PAYLOAD_TOO_LARGE {
-      return Err(AppResponseError::from(AppError::PayloadTooLarge(
-        StatusCode::PAYLOAD_TOO_LARGE.to_string(),
-      )));
-    }
-    AppResponse::<()>::from_response(resp).await?.into_error()
+    AppResponse::<()>::from_response(resp).await?.into_error()?;
+    Ok(())
   }
 
-  /// Only expose this method for testing
-  #[cfg(debug_assertions)]
-  pub async fn put_blob_with_content_length<T: Into<Bytes>>(
+  /// Search the documents and database rows of the workspace that the user can read.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn search_collab(
     &self,
-    url: &str,
-    data: T,
-    mime: &Mime,
-    content_length: usize,
-  ) -> Result<crate::entity::AFBlobRecord, AppResponseError> {
+    workspace_id: &str,
+    query: &str,
+    limit: Option<u32>,
+  ) -> Result<Vec<AFCollabSearchResult>, AppResponseError> {
+    let url = format!("{}/api/workspace/{}/search", self.base_url, workspace_id);
     let resp = self
-      .http_client_with_auth(Method::PUT, url)
+      .http_client_with_auth(Method::GET, &url)
       .await?
-      .header(header::CONTENT_TYPE, mime.to_string())
-      .header(header::CONTENT_LENGTH, content_length)
-      .body(data.into())
+      .query(&SearchCollabParams {
+        query: query.to_string(),
+        limit,
+      })
       .send()
       .await?;
     log_request_id(&resp);
-    AppResponse::<crate::entity::AFBlobRecord>::from_response(resp)
+    AppResponse::<AFCollabSearchResults>::from_response(resp)
       .await?
       .into_data()
+      .map(|results| results.0)
   }
 
-  /// Get the file with the given url. The url should be in the format of
+  pub async fn get_snapshot(
+    &self,
+    workspace_id: &str,
+    object_id: &str,
+    params: QuerySnapshotParams,
+  ) -> Result<SnapshotData, AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/{}/snapshot",
+      self.base_url, workspace_id, object_id,
+    );
+    let resp = self
+      .http_client_with_auth(Method::GET, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<SnapshotData>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  pub async fn create_snapshot(
+    &self,
+    workspace_id: &str,
+    object_id: &str,
+    collab_type: CollabType,
+  ) -> Result<AFSnapshotMeta, AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/{}/snapshot",
+      self.base_url, workspace_id, object_id,
+    );
+    let resp = self
+      .http_client_with_auth(Method::POST, &url)
+      .await?
+      .json(&collab_type)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<AFSnapshotMeta>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn update_collab(&self, params: CreateCollabParams) -> Result<(), AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/collab/{}",
+      self.base_url, &params.workspace_id, &params.object_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::PUT, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<()>::from_response(resp).await?.into_error()
+  }
+
+  #[instrument(level = "debug", skip_all)]
+  pub async fn get_collab(
+    &self,
+    params: QueryCollabParams,
+  ) -> Result<EncodedCollab, AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/collab/{}",
+      self.base_url, &params.workspace_id, &params.object_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::GET, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<EncodedCollab>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn batch_get_collab(
+    &self,
+    workspace_id: &str,
+    params: Vec<QueryCollab>,
+  ) -> Result<BatchQueryCollabResult, AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/collab_list",
+      self.base_url, workspace_id
+    );
+    let params = BatchQueryCollabParams(params);
+    let resp = self
+      .http_client_with_auth(Method::GET, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<BatchQueryCollabResult>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn delete_collab(&self, params: DeleteCollabParams) -> Result<(), AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/collab/{}",
+      self.base_url, &params.workspace_id, &params.object_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::DELETE, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<()>::from_response(resp).await?.into_error()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn add_collab_member(
+    &self,
+    params: InsertCollabMemberParams,
+  ) -> Result<(), AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/collab/{}/member",
+      self.base_url, params.workspace_id, &params.object_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::POST, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<()>::from_response(resp).await?.into_error()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn get_collab_member(
+    &self,
+    params: CollabMemberIdentify,
+  ) -> Result<AFCollabMember, AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/collab/{}/member",
+      self.base_url, params.workspace_id, &params.object_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::GET, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<AFCollabMember>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn update_collab_member(
+    &self,
+    params: UpdateCollabMemberParams,
+  ) -> Result<(), AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/collab/{}/member",
+      self.base_url, params.workspace_id, &params.object_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::PUT, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<()>::from_response(resp).await?.into_error()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn remove_collab_member(
+    &self,
+    params: CollabMemberIdentify,
+  ) -> Result<(), AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/collab/{}/member",
+      self.base_url, params.workspace_id, &params.object_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::DELETE, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<()>::from_response(resp).await?.into_error()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn get_collab_members(
+    &self,
+    params: QueryCollabMembers,
+  ) -> Result<AFCollabMembers, AppResponseError> {
+    let url = format!(
+      "{}/api/workspace/{}/collab/{}/member/list",
+      self.base_url, params.workspace_id, &params.object_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::GET, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<AFCollabMembers>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  pub async fn ws_url(&self, device_id: &str) -> Result<String, AppResponseError> {
+    self
+      .refresh_if_expired(chrono::Local::now().timestamp())
+      .await?;
+
+    let access_token = self.access_token()?;
+    Ok(format!("{}/{}/{}", self.ws_addr, access_token, device_id))
+  }
+
+  pub fn get_blob_url(&self, workspace_id: &str, file_id: &str) -> String {
+    format!(
+      "{}/api/file_storage/{}/blob/{}",
+      self.base_url, workspace_id, file_id
+    )
+  }
+
+  /// The url of a downscaled variant of an image blob. The variant is one of `small`, `medium`
+  /// or `large`.
+  pub fn get_blob_variant_url(&self, workspace_id: &str, file_id: &str, variant: &str) -> String {
+    format!(
+      "{}?variant={}",
+      self.get_blob_url(workspace_id, file_id),
+      variant
+    )
+  }
+
+  pub fn get_blob_metadata_url(&self, workspace_id: &str, file_id: &str) -> String {
+    format!(
+      "{}/api/file_storage/{}/metadata/{}",
+      self.base_url, workspace_id, file_id
+    )
+  }
+
+  /// The url of a blob owned by the collab object. Unlike [Self::get_blob_url], the blob can be
+  /// read by the members of the collab object who are not members of the workspace.
+  pub fn get_collab_blob_url(&self, object_id: &str, file_id: &str) -> String {
+    format!(
+      "{}/api/file_storage/collab/{}/blob/{}",
+      self.base_url, object_id, file_id
+    )
+  }
+
+  /// Create a link that allows anyone to read the blob without being authenticated. The link
+  /// never expires if `expires_in_secs` is None.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn create_blob_public_link(
+    &self,
+    workspace_id: &str,
+    file_id: &str,
+    expires_in_secs: Option<u64>,
+  ) -> Result<String, AppResponseError> {
+    let url = format!("{}/public_link", self.get_blob_url(workspace_id, file_id));
+    let resp = self
+      .http_client_with_auth(Method::POST, &url)
+      .await?
+      .json(&CreateBlobPublicLinkParams { expires_in_secs })
+      .send()
+      .await?;
+    log_request_id(&resp);
+    let link = AppResponse::<BlobPublicLink>::from_response(resp)
+      .await?
+      .into_data()?;
+    Ok(format!("{}{}", self.base_url, link.path))
+  }
+
+  /// Upload the file to the given url. If [ClientConfiguration::with_presigned_blob_upload] is
+  /// enabled and the server supports presigned urls, the content is uploaded to the bucket directly
+  /// instead of through the server.
+  pub async fn put_blob<T: Into<Bytes>>(
+    &self,
+    url: &str,
+    data: T,
+    mime: &Mime,
+  ) -> Result<(), AppResponseError> {
+    self.put_blob_with_owner(url, data, mime, None).await
+  }
+
+  /// Upload the file to the given url as a blob owned by the collab object, so that it can be
+  /// read from [Self::get_collab_blob_url].
+  pub async fn put_collab_blob<T: Into<Bytes>>(
+    &self,
+    url: &str,
+    object_id: &str,
+    data: T,
+    mime: &Mime,
+  ) -> Result<(), AppResponseError> {
+    self
+      .put_blob_with_owner(url, data, mime, Some(object_id))
+      .await
+  }
+
+  async fn put_blob_with_owner<T: Into<Bytes>>(
+    &self,
+    url: &str,
+    data: T,
+    mime: &Mime,
+    object_id: Option<&str>,
+  ) -> Result<(), AppResponseError> {
+    let data = data.into();
+    let presigned_put = if self.config.presigned_blob_upload {
+      self.create_presigned_put(url, data.len()).await?
+    } else {
+      PresignedPutBlob::Disabled
+    };
+    match presigned_put {
+      PresignedPutBlob::Disabled => {},
+      PresignedPutBlob::Completed => return Ok(()),
+      PresignedPutBlob::Upload {
+        upload_id,
+        url: presigned_url,
+      } => {
+        let resp = self
+          .cloud_client
+          .put(&presigned_url)
+          .header(header::CONTENT_TYPE, mime.to_string())
+          .body(data)
+          .send()
+          .await?;
+        let status = resp.status();
+        if !status.is_success() {
+          return Err(AppResponseError::from(AppError::S3ResponseError(format!(
+            "status code: {}, message: {}",
+            status,
+            resp.text().await?
+          ))));
+        }
+        let params = CompletePresignedPutParams {
+          upload_id,
+          file_type: mime.to_string(),
+          object_id: object_id.map(|object_id| object_id.to_string()),
+        };
+        return self.complete_presigned_put(url, &params).await;
+      },
+    }
+
+    let mut request = self
+      .http_client_with_auth(Method::PUT, url)
+      .await?
+      .header(header::CONTENT_TYPE, mime.to_string());
+    if let Some(object_id) = object_id {
+      request = request.query(&[("object_id", object_id)]);
+    }
+    let resp = request.body(data).send().await?;
+    log_request_id(&resp);
+    if resp.status() == StatusCode::PAYLOAD_TOO_LARGE {
+      return Err(AppResponseError::from(AppError::PayloadTooLarge(
+        StatusCode::PAYLOAD_TOO_LARGE.to_string(),
+      )));
+    }
+    AppResponse::<()>::from_response(resp).await?.into_error()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  async fn create_presigned_put(
+    &self,
+    url: &str,
+    file_size: usize,
+  ) -> Result<PresignedPutBlob, AppResponseError> {
+    let url = format!("{}/presigned", url);
+    let resp = self
+      .http_client_with_auth(Method::POST, &url)
+      .await?
+      .json(&CreatePresignedPutParams {
+        file_size: file_size as u64,
+      })
+      .send()
+      .await?;
+    log_request_id(&resp);
+    // The servers that don't have the presigned endpoint only accept the uploads through them
+    if resp.status() == StatusCode::NOT_FOUND {
+      return Ok(PresignedPutBlob::Disabled);
+    }
+    AppResponse::<PresignedPutBlob>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  async fn complete_presigned_put(
+    &self,
+    url: &str,
+    params: &CompletePresignedPutParams,
+  ) -> Result<(), AppResponseError> {
+    let url = format!("{}/presigned/complete", url);
+    let resp = self
+      .http_client_with_auth(Method::POST, &url)
+      .await?
+      .json(params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<()>::from_response(resp).await?.into_error()
+  }
+
+  /// Only expose this method for testing
+  #[cfg(debug_assertions)]
+  pub async fn put_blob_with_content_length<T: Into<Bytes>>(
+    &self,
+    url: &str,
+    data: T,
+    mime: &Mime,
+    content_length: usize,
+  ) -> Result<crate::entity::AFBlobRecord, AppResponseError> {
+    let resp = self
+      .http_client_with_auth(Method::PUT, url)
+      .await?
+      .header(header::CONTENT_TYPE, mime.to_string())
+      .header(header::CONTENT_LENGTH, content_length)
+      .body(data.into())
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<crate::entity::AFBlobRecord>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  /// Get the file with the given url. The url should be in the format of
   /// `https://appflowy.io/api/file_storage/<workspace_id>/<file_id>`.
+  /// If the server redirects to a presigned url of the bucket, the redirect is followed and the
+  /// authorization header is not sent to the bucket.
   pub async fn get_blob(&self, url: &str) -> Result<(Mime, Vec<u8>), AppResponseError> {
     let resp = self
       .http_client_with_auth(Method::GET, url)
@@ -1146,6 +1755,115 @@ impl Client {
       .into_data()
   }
 
+  /// Return the quota of the workspace. Only the server admin can access it.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn get_workspace_quota(
+    &self,
+    workspace_id: &str,
+  ) -> Result<WorkspaceQuota, AppResponseError> {
+    let url = format!(
+      "{}/api/admin/workspace/{}/quota",
+      self.base_url, workspace_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::GET, &url)
+      .await?
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<WorkspaceQuota>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  /// Update the quota of the workspace. Only the server admin can update it.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn update_workspace_quota(
+    &self,
+    workspace_id: &str,
+    params: &UpdateWorkspaceQuotaParams,
+  ) -> Result<WorkspaceQuota, AppResponseError> {
+    let url = format!(
+      "{}/api/admin/workspace/{}/quota",
+      self.base_url, workspace_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::PUT, &url)
+      .await?
+      .json(params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<WorkspaceQuota>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  /// Remove the overridden limits of the workspace, so it uses the limits of its plan.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn reset_workspace_quota(
+    &self,
+    workspace_id: &str,
+  ) -> Result<WorkspaceQuota, AppResponseError> {
+    let url = format!(
+      "{}/api/admin/workspace/{}/quota",
+      self.base_url, workspace_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::DELETE, &url)
+      .await?
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<WorkspaceQuota>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn get_workspace_plans(&self) -> Result<RepeatedWorkspacePlan, AppResponseError> {
+    let url = format!("{}/api/admin/plan", self.base_url);
+    let resp = self
+      .http_client_with_auth(Method::GET, &url)
+      .await?
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<RepeatedWorkspacePlan>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  /// Create or update the plan with the given workspace type.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn update_workspace_plan(&self, plan: &WorkspacePlan) -> Result<(), AppResponseError> {
+    let url = format!("{}/api/admin/plan/{}", self.base_url, plan.workspace_type);
+    let resp = self
+      .http_client_with_auth(Method::PUT, &url)
+      .await?
+      .json(plan)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<()>::from_response(resp).await?.into_error()
+  }
+
+  /// Run the blob garbage collector. Only the server admin can run it.
+  #[instrument(level = "debug", skip_all, err)]
+  pub async fn run_blob_gc(&self, params: &BlobGcParams) -> Result<BlobGcReport, AppResponseError> {
+    let url = format!("{}/api/admin/blob/gc", self.base_url);
+    let resp = self
+      .http_client_with_auth(Method::POST, &url)
+      .await?
+      .json(params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<BlobGcReport>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
   pub async fn get_workspace_all_blob_metadata(
     &self,
     workspace_id: &str,
@@ -1162,6 +1880,118 @@ impl Client {
       .into_data()
   }
 
+  /// Create a multipart upload for the file. If the file already has a pending upload, the
+  /// pending upload is returned and can be resumed with [Client::get_upload_parts].
+  pub async fn create_upload(
+    &self,
+    workspace_id: &str,
+    file_id: &str,
+    mime: &Mime,
+  ) -> Result<BlobUpload, AppResponseError> {
+    let url = format!("{}/api/file_storage/{}/upload", self.base_url, workspace_id);
+    let params = CreateBlobUploadParams {
+      file_id: file_id.to_string(),
+      file_type: mime.to_string(),
+    };
+    let resp = self
+      .http_client_with_auth(Method::POST, &url)
+      .await?
+      .json(&params)
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<BlobUpload>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  /// Upload a part of the multipart upload. The part number starts from 1. Every part except
+  /// the last one must be at least 5 MiB.
+  pub async fn upload_part<T: Into<Bytes>>(
+    &self,
+    workspace_id: &str,
+    upload_id: &str,
+    part_number: i32,
+    data: T,
+  ) -> Result<BlobUploadPart, AppResponseError> {
+    let url = format!(
+      "{}/api/file_storage/{}/upload/{}/part/{}",
+      self.base_url, workspace_id, upload_id, part_number
+    );
+    let resp = self
+      .http_client_with_auth(Method::PUT, &url)
+      .await?
+      .body(data.into())
+      .send()
+      .await?;
+    log_request_id(&resp);
+    if resp.status() == StatusCode::PAYLOAD_TOO_LARGE {
+      return Err(AppResponseError::from(AppError::PayloadTooLarge(
+        StatusCode::PAYLOAD_TOO_LARGE.to_string(),
+      )));
+    }
+    AppResponse::<BlobUploadPart>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  /// Return the parts that have been uploaded, ordered by the part number.
+  pub async fn get_upload_parts(
+    &self,
+    workspace_id: &str,
+    upload_id: &str,
+  ) -> Result<RepeatedBlobUploadPart, AppResponseError> {
+    let url = format!(
+      "{}/api/file_storage/{}/upload/{}",
+      self.base_url, workspace_id, upload_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::GET, &url)
+      .await?
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<RepeatedBlobUploadPart>::from_response(resp)
+      .await?
+      .into_data()
+  }
+
+  pub async fn complete_upload(
+    &self,
+    workspace_id: &str,
+    upload_id: &str,
+  ) -> Result<(), AppResponseError> {
+    let url = format!(
+      "{}/api/file_storage/{}/upload/{}/complete",
+      self.base_url, workspace_id, upload_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::POST, &url)
+      .await?
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<()>::from_response(resp).await?.into_error()
+  }
+
+  pub async fn abort_upload(
+    &self,
+    workspace_id: &str,
+    upload_id: &str,
+  ) -> Result<(), AppResponseError> {
+    let url = format!(
+      "{}/api/file_storage/{}/upload/{}",
+      self.base_url, workspace_id, upload_id
+    );
+    let resp = self
+      .http_client_with_auth(Method::DELETE, &url)
+      .await?
+      .send()
+      .await?;
+    log_request_id(&resp);
+    AppResponse::<()>::from_response(resp).await?.into_error()
+  }
+
   // Refresh token if given timestamp is close to the token expiration time
   pub async fn refresh_if_expired(&self, ts: i64) -> Result<(), AppResponseError> {
     let expires_at = self.token_expires_at()?;
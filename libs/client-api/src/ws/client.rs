@@ -62,6 +62,7 @@ pub struct WSClient {
   sender: Sender<Message>,
   http_sender: Arc<dyn WSClientHttpSender>,
   user_channel: Arc<Sender<UserMessage>>,
+  access_revoked_channel: Arc<Sender<Vec<String>>>,
   collab_channels: Arc<RwLock<ChannelByObjectId>>,
   ping: Arc<Mutex<Option<ServerFixIntervalPing>>>,
   stop_tx: Mutex<Option<oneshot::Sender<()>>>,
@@ -80,6 +81,7 @@ impl WSClient {
     let ping = Arc::new(Mutex::new(None));
     let http_sender = Arc::new(http_sender);
     let (user_channel, _) = channel(1);
+    let (access_revoked_channel, _) = channel(1);
     let rate_limiter = gen_rate_limiter(10);
     WSClient {
       addr: Arc::new(parking_lot::Mutex::new(None)),
@@ -88,6 +90,7 @@ impl WSClient {
       sender,
       http_sender,
       user_channel: Arc::new(user_channel),
+      access_revoked_channel: Arc::new(access_revoked_channel),
       collab_channels,
       ping,
       stop_tx: Mutex::new(None),
@@ -155,7 +158,9 @@ impl WSClient {
     *self.ping.lock().await = Some(ping);
 
     let user_message_tx = self.user_channel.as_ref().clone();
+    let access_revoked_tx = self.access_revoked_channel.as_ref().clone();
     let rate_limiter = self.rate_limiter.clone();
+    let weak_state_notify = Arc::downgrade(&self.state_notify);
     // Receive messages from the websocket, and send them to the channels.
     platform_spawn(async move {
       while let Some(Ok(ws_msg)) = stream.next().await {
@@ -198,6 +203,10 @@ impl WSClient {
                     SystemMessage::KickOff => {
                       //
                     },
+                    SystemMessage::AccessRevoked(object_ids) => {
+                      info!("access revoked to collabs: {:?}", object_ids);
+                      let _ = access_revoked_tx.send(object_ids);
+                    },
                   },
                 }
               },
@@ -215,6 +224,13 @@ impl WSClient {
           },
           Message::Close(close) => {
             info!("websocket close: {:?}", close);
+            // The server is shutting down, so mark the connection as closed for the caller to
+            // connect again, and be served by another server.
+            if matches!(&close, Some(frame) if frame.code == CloseCode::Restart) {
+              if let Some(state_notify) = weak_state_notify.upgrade() {
+                state_notify.lock().set_state(ConnectState::Closed);
+              }
+            }
           },
           Message::Pong(_) => {
             if let Err(err) = pong_tx.send(()).await {
@@ -292,6 +308,12 @@ impl WSClient {
     self.user_channel.subscribe()
   }
 
+  /// Receives the ids of the collabs the user can't access anymore. The server stopped sending
+  /// their changes.
+  pub fn subscribe_access_revoked(&self) -> Receiver<Vec<String>> {
+    self.access_revoked_channel.subscribe()
+  }
+
   pub fn subscribe_connect_state(&self) -> WSConnectStateReceiver {
     self.state_notify.lock().subscribe()
   }
@@ -228,6 +228,11 @@ pub struct AFSnapshotMeta {
   pub snapshot_id: i64,
   pub object_id: String,
   pub created_at: DateTime<Utc>,
+  /// Pinned snapshots are never removed by the retention policy
+  #[serde(default)]
+  pub pinned: bool,
+  #[serde(default)]
+  pub name: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -238,6 +243,171 @@ pub struct QueryObjectSnapshotParams {
   pub object_id: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreSnapshotParams {
+  pub snapshot_id: i64,
+  pub collab_type: CollabType,
+}
+
+/// A snapshot that was restored into a collab.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFSnapshotRestore {
+  pub restore_id: i64,
+  pub object_id: String,
+  pub snapshot_id: i64,
+  /// The uid of the user who restored the snapshot
+  pub restored_by: i64,
+  pub restored_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFSnapshotRestores(pub Vec<AFSnapshotRestore>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuerySnapshotDiffParams {
+  pub from_snapshot_id: i64,
+  /// Compare with the current state of the collab if it's None
+  #[serde(default)]
+  pub to_snapshot_id: Option<i64>,
+}
+
+/// The blocks of a document that changed between two versions of the document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFDocumentDiff {
+  pub from_snapshot_id: i64,
+  pub to_snapshot_id: Option<i64>,
+  /// The added and changed blocks in the order of the new version, followed by the removed blocks
+  /// in the order of the old version.
+  pub blocks: Vec<AFBlockDiff>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockChangeType {
+  Added,
+  Removed,
+  Changed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AFBlockDiff {
+  pub block_id: String,
+  pub block_type: String,
+  pub change_type: BlockChangeType,
+  /// The delta that turns the text of the old block into the text of the new block. Empty if the
+  /// text didn't change.
+  pub text_delta: Vec<TextDeltaOp>,
+  /// The data of the block other than its text, e.g. the level of a heading, changed
+  pub data_changed: bool,
+  /// The block was moved to another parent
+  pub moved: bool,
+}
+
+/// An operation of a text delta. The lengths are counted in UTF-16 code units, like the offsets of
+/// the texts of the collabs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TextDeltaOp {
+  Retain { retain: usize },
+  Insert { insert: String },
+  Delete { delete: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentExportFormat {
+  Markdown,
+  Html,
+}
+
+impl DocumentExportFormat {
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      DocumentExportFormat::Markdown => "text/markdown; charset=utf-8",
+      DocumentExportFormat::Html => "text/html; charset=utf-8",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportDocumentParams {
+  pub format: DocumentExportFormat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinSnapshotParams {
+  pub pinned: bool,
+  /// Name of the snapshot, only kept while the snapshot is pinned
+  #[serde(default)]
+  pub name: Option<String>,
+}
+
+/// How long the snapshots of a collab are kept. The most recent snapshots are always kept, then
+/// the most recent snapshot of each hour, day and week is kept for the given number of hours, days
+/// and weeks. The other snapshots are removed, unless they are pinned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotRetentionPolicy {
+  pub keep_latest: u32,
+  pub hourly_for_hours: u32,
+  pub daily_for_days: u32,
+  /// None keeps one snapshot per week forever
+  pub weekly_for_weeks: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFSnapshotRetentionPolicy {
+  /// The policy applies to the collabs of this type, or to all the collabs of the workspace if
+  /// it's None. A policy of a collab type takes precedence over the policy of the workspace.
+  pub collab_type: Option<CollabType>,
+  #[serde(flatten)]
+  pub policy: SnapshotRetentionPolicy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFSnapshotRetentionPolicies(pub Vec<AFSnapshotRetentionPolicy>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteSnapshotRetentionPolicyParams {
+  pub collab_type: Option<CollabType>,
+}
+
+/// Full-text search over the documents and database rows of a workspace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchCollabParams {
+  /// Supports the web search syntax, e.g. `"exact phrase" -excluded or other`
+  pub query: String,
+  /// Maximum number of results. Defaults to 20
+  pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFCollabSearchResult {
+  pub object_id: String,
+  pub collab_type: CollabType,
+  /// Fragment of the content around the matching words
+  pub snippet: String,
+  pub rank: f32,
+  pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryCollabJsonParams {
+  pub collab_type: CollabType,
+  /// Render the snapshot instead of the current state of the collab
+  pub snapshot_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFCollabJson {
+  pub object_id: String,
+  pub collab_type: CollabType,
+  pub snapshot_id: Option<i64>,
+  pub content: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFCollabSearchResults(pub Vec<AFCollabSearchResult>);
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AFBlobRecord {
   pub file_id: String,
@@ -464,4 +634,107 @@ pub struct AFWorkspaceMember {
   pub avatar_url: Option<String>,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceExportStatus {
+  Running,
+  Completed,
+  Failed,
+}
+
+impl From<i16> for WorkspaceExportStatus {
+  fn from(value: i16) -> Self {
+    match value {
+      0 => WorkspaceExportStatus::Running,
+      1 => WorkspaceExportStatus::Completed,
+      _ => WorkspaceExportStatus::Failed,
+    }
+  }
+}
+
+impl From<WorkspaceExportStatus> for i16 {
+  fn from(status: WorkspaceExportStatus) -> Self {
+    match status {
+      WorkspaceExportStatus::Running => 0,
+      WorkspaceExportStatus::Completed => 1,
+      WorkspaceExportStatus::Failed => 2,
+    }
+  }
+}
+
+/// A job that exports a workspace as an archive. The archive can be downloaded once the job is
+/// completed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFWorkspaceExport {
+  pub job_id: Uuid,
+  pub workspace_id: Uuid,
+  pub status: WorkspaceExportStatus,
+  /// Size of the archive in bytes
+  pub file_size: Option<i64>,
+  pub error: Option<String>,
+  pub created_at: DateTime<Utc>,
+  pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// The result of importing a workspace archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFWorkspaceImport {
+  pub workspace: AFWorkspace,
+  pub collab_count: usize,
+  pub blob_count: usize,
+  /// The emails of the members of the exported workspace that have no account on this server
+  pub skipped_members: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportMarkdownParams {
+  /// Name of the view of the document
+  pub name: String,
+  pub markdown: String,
+  /// The view is added to the first level views of the workspace if None
+  #[serde(default)]
+  pub parent_view_id: Option<String>,
+  /// The base64 encoded content of the local images of the markdown, keyed by the path of the
+  /// image in the markdown
+  #[serde(default)]
+  pub images: HashMap<String, String>,
+}
+
+/// The result of importing a markdown file as a document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFDocumentImport {
+  /// The id of the view, which is also the object id of the document
+  pub view_id: String,
+  /// The number of local images that were uploaded as blobs
+  pub image_count: usize,
+  /// The local images of the markdown whose content was not provided
+  pub missing_images: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportDatabaseParams {
+  /// The view whose fields and rows are exported, the inline view of the database if None
+  #[serde(default)]
+  pub view_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportCsvParams {
+  /// Name of the view of the database
+  pub name: String,
+  /// The first line of the csv holds the names of the fields
+  pub csv: String,
+}
+
+/// The result of importing a csv file as a database.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AFDatabaseImport {
+  /// The object id of the database collab
+  pub database_id: String,
+  pub view_id: String,
+  pub field_count: usize,
+  /// The number of database row collabs
+  pub row_count: usize,
+}
+
 // pub type AFBlobMetadata = AFBlobMetadataRow;
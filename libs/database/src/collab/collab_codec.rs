@@ -0,0 +1,294 @@
+use anyhow::anyhow;
+use app_error::AppError;
+use brotli::{CompressorReader, Decompressor};
+use sqlx::PgPool;
+use std::io::Read;
+use std::ops::DerefMut;
+use std::str::FromStr;
+
+const ZSTD_LEVEL: i32 = 3;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// The format of the `blob` column of the `af_collab` and `af_collab_snapshot` tables, stored in
+/// their `codec` column. The value of the variants must not be changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollabCodec {
+  /// The encoded collab as is
+  Raw = 0,
+  Zstd = 1,
+  Brotli = 2,
+}
+
+impl CollabCodec {
+  pub fn value(&self) -> i32 {
+    *self as i32
+  }
+
+  pub fn from_value(value: i32) -> Result<Self, AppError> {
+    match value {
+      0 => Ok(Self::Raw),
+      1 => Ok(Self::Zstd),
+      2 => Ok(Self::Brotli),
+      other => Err(AppError::Internal(anyhow!(
+        "unknown collab codec: {}",
+        other
+      ))),
+    }
+  }
+
+  pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, AppError> {
+    match self {
+      CollabCodec::Raw => Ok(data.to_vec()),
+      CollabCodec::Zstd => zstd::stream::encode_all(data, ZSTD_LEVEL).map_err(AppError::from),
+      CollabCodec::Brotli => {
+        let mut compressor =
+          CompressorReader::new(data, BROTLI_BUFFER_SIZE, BROTLI_QUALITY, BROTLI_WINDOW_SIZE);
+        let mut compressed = Vec::new();
+        compressor.read_to_end(&mut compressed)?;
+        Ok(compressed)
+      },
+    }
+  }
+
+  pub fn decompress(&self, data: Vec<u8>) -> Result<Vec<u8>, AppError> {
+    match self {
+      CollabCodec::Raw => Ok(data),
+      CollabCodec::Zstd => zstd::stream::decode_all(data.as_slice()).map_err(AppError::from),
+      CollabCodec::Brotli => {
+        let mut decompressor = Decompressor::new(data.as_slice(), BROTLI_BUFFER_SIZE);
+        let mut decompressed = Vec::new();
+        decompressor.read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+      },
+    }
+  }
+}
+
+impl FromStr for CollabCodec {
+  type Err = AppError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "none" | "raw" => Ok(Self::Raw),
+      "zstd" => Ok(Self::Zstd),
+      "brotli" => Ok(Self::Brotli),
+      other => Err(AppError::InvalidRequest(format!(
+        "{} is not a supported collab compression. Use either `none`, `zstd` or `brotli`.",
+        other
+      ))),
+    }
+  }
+}
+
+/// Decompress the `blob` column of a row according to its `codec` column.
+pub fn decompress_collab_blob(codec: i32, blob: Vec<u8>) -> Result<Vec<u8>, AppError> {
+  CollabCodec::from_value(codec)?.decompress(blob)
+}
+
+/// An encoded collab compressed with a [CollabCodec], ready to be stored.
+pub struct CompressedCollab {
+  pub codec: CollabCodec,
+  pub blob: Vec<u8>,
+  /// Length of the encoded collab before compression
+  pub len: usize,
+}
+
+impl CompressedCollab {
+  /// Compress the encoded collab on a blocking thread.
+  pub async fn compress(codec: CollabCodec, encoded_collab_v1: Vec<u8>) -> Result<Self, AppError> {
+    tokio::task::spawn_blocking(move || {
+      let blob = codec.compress(&encoded_collab_v1)?;
+      Ok(Self {
+        codec,
+        blob,
+        len: encoded_collab_v1.len(),
+      })
+    })
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?
+  }
+}
+
+#[derive(Debug)]
+struct CollabCodecRow {
+  oid: String,
+  partition_key: i32,
+  blob: Vec<u8>,
+  codec: i32,
+}
+
+#[derive(Debug)]
+struct SnapshotCodecRow {
+  sid: i64,
+  blob: Vec<u8>,
+  codec: i32,
+}
+
+/// Rewrite the next batch of collab rows, ordered by object id and starting after `after_oid`,
+/// whose blob isn't compressed with `codec`.
+///
+/// The batch is chosen without locking, which sets the cursor, and its rows are then locked while
+/// they are rewritten. A row that is locked by a concurrent write is waited for rather than
+/// skipped, so every row is visited once the cursor moves past it. The codec is checked again
+/// after the lock, as the concurrent write may have already used `codec`.
+///
+/// Return the number of rewritten rows and the object id to continue from, or None once all the
+/// rows have been visited.
+pub async fn recompress_collab_batch(
+  pg_pool: &PgPool,
+  codec: CollabCodec,
+  after_oid: &str,
+  limit: i64,
+) -> Result<(usize, Option<String>), AppError> {
+  let oids: Vec<String> = sqlx::query_scalar!(
+    r#"
+      SELECT oid
+      FROM af_collab
+      WHERE oid > $1 AND codec <> $2
+      ORDER BY oid
+      LIMIT $3
+    "#,
+    after_oid,
+    codec.value(),
+    limit,
+  )
+  .fetch_all(pg_pool)
+  .await?;
+  let next_oid = if (oids.len() as i64) < limit {
+    None
+  } else {
+    oids.last().cloned()
+  };
+  if oids.is_empty() {
+    return Ok((0, next_oid));
+  }
+
+  let mut transaction = pg_pool.begin().await?;
+  let rows = sqlx::query_as!(
+    CollabCodecRow,
+    r#"
+      SELECT oid, partition_key, blob, codec
+      FROM af_collab
+      WHERE oid = ANY($1) AND codec <> $2
+      FOR UPDATE
+    "#,
+    &oids,
+    codec.value(),
+  )
+  .fetch_all(transaction.deref_mut())
+  .await?;
+
+  let count = rows.len();
+  for row in rows {
+    let blob = recompress(codec, row.codec, row.blob).await?;
+    sqlx::query!(
+      "UPDATE af_collab SET blob = $3, codec = $4 WHERE oid = $1 AND partition_key = $2",
+      row.oid,
+      row.partition_key,
+      blob,
+      codec.value(),
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+  }
+  transaction.commit().await?;
+  Ok((count, next_oid))
+}
+
+/// Same as [recompress_collab_batch] for the snapshots, ordered by snapshot id.
+pub async fn recompress_snapshot_batch(
+  pg_pool: &PgPool,
+  codec: CollabCodec,
+  after_sid: i64,
+  limit: i64,
+) -> Result<(usize, Option<i64>), AppError> {
+  let sids: Vec<i64> = sqlx::query_scalar!(
+    r#"
+      SELECT sid
+      FROM af_collab_snapshot
+      WHERE sid > $1 AND codec <> $2
+      ORDER BY sid
+      LIMIT $3
+    "#,
+    after_sid,
+    codec.value(),
+    limit,
+  )
+  .fetch_all(pg_pool)
+  .await?;
+  let next_sid = if (sids.len() as i64) < limit {
+    None
+  } else {
+    sids.last().copied()
+  };
+  if sids.is_empty() {
+    return Ok((0, next_sid));
+  }
+
+  let mut transaction = pg_pool.begin().await?;
+  let rows = sqlx::query_as!(
+    SnapshotCodecRow,
+    r#"
+      SELECT sid, blob, codec
+      FROM af_collab_snapshot
+      WHERE sid = ANY($1) AND codec <> $2
+      FOR UPDATE
+    "#,
+    &sids,
+    codec.value(),
+  )
+  .fetch_all(transaction.deref_mut())
+  .await?;
+
+  let count = rows.len();
+  for row in rows {
+    let blob = recompress(codec, row.codec, row.blob).await?;
+    sqlx::query!(
+      "UPDATE af_collab_snapshot SET blob = $2, codec = $3 WHERE sid = $1",
+      row.sid,
+      blob,
+      codec.value(),
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+  }
+  transaction.commit().await?;
+  Ok((count, next_sid))
+}
+
+async fn recompress(codec: CollabCodec, from: i32, blob: Vec<u8>) -> Result<Vec<u8>, AppError> {
+  tokio::task::spawn_blocking(move || {
+    let data = decompress_collab_blob(from, blob)?;
+    codec.compress(&data)
+  })
+  .await
+  .map_err(|err| AppError::Internal(err.into()))?
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn compress_and_decompress() {
+    let data = "hello world ".repeat(100).into_bytes();
+    for codec in [CollabCodec::Raw, CollabCodec::Zstd, CollabCodec::Brotli] {
+      let compressed = codec.compress(&data).unwrap();
+      if codec != CollabCodec::Raw {
+        assert!(compressed.len() < data.len());
+      }
+      let decompressed = decompress_collab_blob(codec.value(), compressed).unwrap();
+      assert_eq!(decompressed, data);
+    }
+  }
+
+  #[test]
+  fn parse_codec() {
+    assert_eq!("ZSTD".parse::<CollabCodec>().unwrap(), CollabCodec::Zstd);
+    assert_eq!("none".parse::<CollabCodec>().unwrap(), CollabCodec::Raw);
+    assert!("gzip".parse::<CollabCodec>().is_err());
+    assert!(CollabCodec::from_value(3).is_err());
+  }
+}
@@ -1,13 +1,15 @@
 use anyhow::{anyhow, Context};
 use collab_entity::CollabType;
 use database_entity::dto::{
-  AFAccessLevel, AFCollabMember, AFPermission, AFSnapshotMeta, AFSnapshotMetas, CollabParams,
-  QueryCollab, QueryCollabResult, RawData,
+  AFAccessLevel, AFCollabMember, AFPermission, AFSnapshotMeta, AFSnapshotMetas, AFSnapshotRestore,
+  AFSnapshotRestores, CollabParams, QueryCollab, QueryCollabResult, RawData,
 };
 
-use crate::collab::SNAPSHOT_PER_HOUR;
+use crate::collab::{
+  collab_type_from_partition_key, decompress_collab_blob, CompressedCollab, SNAPSHOT_PER_HOUR,
+};
 use crate::pg_row::AFCollabMemerAccessLevelRow;
-use crate::pg_row::AFSnapshotRow;
+use crate::pg_row::{AFSnapshotMetaRow, AFSnapshotRestoreRow, AFSnapshotRow};
 use app_error::AppError;
 use chrono::{Duration, Utc};
 use futures_util::stream::BoxStream;
@@ -42,6 +44,7 @@ pub async fn collab_exists(pg_pool: &PgPool, oid: &str) -> Result<bool, sqlx::Er
 /// * `tx` - A mutable reference to a PostgreSQL transaction.
 /// * `params` - Parameters required for the insertion or update operation, encapsulated in
 /// the `InsertCollabParams` struct.
+/// * `compressed` - The `encoded_collab_v1` of the params compressed with the configured codec.
 ///
 /// # Returns
 ///
@@ -63,6 +66,7 @@ pub async fn insert_into_af_collab(
   uid: &i64,
   workspace_id: &str,
   params: &CollabParams,
+  compressed: &CompressedCollab,
 ) -> Result<(), AppError> {
   let encrypt = 0;
   let partition_key = params.collab_type.value();
@@ -89,13 +93,15 @@ pub async fn insert_into_af_collab(
       if existing_workspace_id == workspace_id {
         sqlx::query!(
           "UPDATE af_collab \
-        SET blob = $2, len = $3, partition_key = $4, encrypt = $5, owner_uid = $6 WHERE oid = $1",
+        SET blob = $2, len = $3, partition_key = $4, encrypt = $5, owner_uid = $6, codec = $7 \
+        WHERE oid = $1",
           params.object_id,
-          params.encoded_collab_v1,
-          params.encoded_collab_v1.len() as i32,
+          compressed.blob,
+          compressed.len as i32,
           partition_key,
           encrypt,
           uid,
+          compressed.codec.value(),
         )
         .execute(tx.deref_mut())
         .await
@@ -148,15 +154,16 @@ pub async fn insert_into_af_collab(
       ))?;
 
       sqlx::query!(
-        "INSERT INTO af_collab (oid, blob, len, partition_key, encrypt, owner_uid, workspace_id)\
-          VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        "INSERT INTO af_collab (oid, blob, len, partition_key, encrypt, owner_uid, workspace_id, codec)\
+          VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
         params.object_id,
-        params.encoded_collab_v1,
-        params.encoded_collab_v1.len() as i32,
+        compressed.blob,
+        compressed.len as i32,
         partition_key,
         encrypt,
         uid,
         workspace_id,
+        compressed.codec.value(),
       )
       .execute(tx.deref_mut())
       .await
@@ -178,6 +185,7 @@ pub async fn insert_into_af_collab(
   Ok(())
 }
 
+/// Return the encoded collab, decompressed if it was stored compressed.
 #[inline]
 pub async fn select_blob_from_af_collab<'a, E>(
   conn: E,
@@ -188,9 +196,9 @@ where
   E: Executor<'a, Database = Postgres>,
 {
   let partition_key = collab_type.value();
-  sqlx::query_scalar!(
+  let row = sqlx::query!(
     r#"
-        SELECT blob
+        SELECT blob, codec
         FROM af_collab
         WHERE oid = $1 AND partition_key = $2 AND deleted_at IS NULL;
         "#,
@@ -198,7 +206,9 @@ where
     partition_key,
   )
   .fetch_one(conn)
-  .await
+  .await?;
+  decompress_collab_blob(row.codec, row.blob)
+    .map_err(|err| sqlx::Error::Decode(err.to_string().into()))
 }
 
 #[inline]
@@ -220,7 +230,7 @@ pub async fn batch_select_collab_blob(
     let par_results: Result<Vec<QueryCollabData>, sqlx::Error> = sqlx::query_as!(
       QueryCollabData,
       r#"
-       SELECT oid, blob
+       SELECT oid, blob, codec
        FROM af_collab
        WHERE oid = ANY($1) AND partition_key = $2 AND deleted_at IS NULL;
     "#,
@@ -235,12 +245,13 @@ pub async fn batch_select_collab_blob(
         object_ids.retain(|oid| !par_results.iter().any(|par_result| par_result.oid == *oid));
 
         results.extend(par_results.into_iter().map(|par_result| {
-          (
-            par_result.oid,
-            QueryCollabResult::Success {
-              encode_collab_v1: par_result.blob,
+          let result = match decompress_collab_blob(par_result.codec, par_result.blob) {
+            Ok(encode_collab_v1) => QueryCollabResult::Success { encode_collab_v1 },
+            Err(err) => QueryCollabResult::Failed {
+              error: err.to_string(),
             },
-          )
+          };
+          (par_result.oid, result)
         }));
 
         results.extend(object_ids.into_iter().map(|oid| {
@@ -259,10 +270,11 @@ pub async fn batch_select_collab_blob(
   results
 }
 
-#[derive(Debug, sqlx::FromRow)]
+#[derive(Debug)]
 struct QueryCollabData {
   oid: String,
   blob: RawData,
+  codec: i32,
 }
 
 #[inline]
@@ -284,21 +296,22 @@ pub async fn delete_collab(pg_pool: &PgPool, object_id: &str) -> Result<(), sqlx
 pub async fn create_snapshot(
   pg_pool: &PgPool,
   object_id: &str,
-  encoded_collab_v1: &[u8],
+  compressed: &CompressedCollab,
   workspace_id: &Uuid,
 ) -> Result<(), sqlx::Error> {
   let encrypt = 0;
 
   sqlx::query!(
     r#"
-        INSERT INTO af_collab_snapshot (oid, blob, len, encrypt, workspace_id)
-        VALUES ($1, $2, $3, $4, $5)
+        INSERT INTO af_collab_snapshot (oid, blob, len, encrypt, workspace_id, codec)
+        VALUES ($1, $2, $3, $4, $5, $6)
         "#,
     object_id,
-    encoded_collab_v1,
-    encoded_collab_v1.len() as i32,
+    compressed.blob,
+    compressed.len as i32,
     encrypt,
     workspace_id,
+    compressed.codec.value(),
   )
   .execute(pg_pool)
   .await?;
@@ -327,54 +340,36 @@ pub async fn should_create_snapshot<'a, E: Executor<'a, Database = Postgres>>(
   Ok(latest_snapshot_time.map(|t| t < hours).unwrap_or(true))
 }
 
-/// Creates a new snapshot in the `af_collab_snapshot` table and maintains the total number of snapshots
-/// within a specified limit for a given object ID (`oid`).
-///
-/// This asynchronous function inserts a new snapshot into the database and ensures that the total number
-/// of snapshots stored for the specified `oid` does not exceed the provided `snapshot_limit`. If the limit
-/// is exceeded, the oldest snapshots are deleted to maintain the limit.
-///
-pub(crate) async fn create_snapshot_and_maintain_limit<'a>(
-  mut transaction: Transaction<'a, Postgres>,
+/// Inserts a new snapshot into the `af_collab_snapshot` table. The snapshots that are not kept by
+/// the retention policy of the collab are removed afterwards by [prune_collab_snapshots].
+pub(crate) async fn insert_snapshot<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
   oid: &str,
-  encoded_collab_v1: &[u8],
+  compressed: &CompressedCollab,
   workspace_id: &Uuid,
-  snapshot_limit: i64,
 ) -> Result<AFSnapshotMeta, AppError> {
-  let snapshot_meta = sqlx::query_as!(
-    AFSnapshotMeta,
+  let row = sqlx::query!(
     r#"
-      INSERT INTO af_collab_snapshot (oid, blob, len, encrypt, workspace_id) 
-      VALUES ($1, $2, $3, $4, $5)
-      RETURNING sid AS snapshot_id, oid AS object_id, created_at
+      INSERT INTO af_collab_snapshot (oid, blob, len, encrypt, workspace_id, codec)
+      VALUES ($1, $2, $3, $4, $5, $6)
+      RETURNING sid, oid, created_at
     "#,
     oid,
-    encoded_collab_v1,
-    encoded_collab_v1.len() as i64,
+    compressed.blob,
+    compressed.len as i32,
     0,
     workspace_id,
+    compressed.codec.value(),
   )
-  .fetch_one(transaction.deref_mut())
+  .fetch_one(executor)
   .await?;
-
-  // When a new snapshot is created that surpasses the preset limit, older snapshots will be deleted to maintain the limit
-  sqlx::query(
-    r#"
-       DELETE FROM af_collab_snapshot 
-       WHERE oid = $1 AND sid NOT IN ( SELECT sid FROM af_collab_snapshot WHERE oid = $1 ORDER BY created_at DESC LIMIT $2)
-      "#,
-    )
-    .bind(oid)
-    .bind(snapshot_limit)
-    .execute(transaction.deref_mut())
-    .await?;
-
-  transaction
-    .commit()
-    .await
-    .context("fail to commit the transaction to insert collab snapshot")?;
-
-  Ok(snapshot_meta)
+  Ok(AFSnapshotMeta {
+    snapshot_id: row.sid,
+    object_id: row.oid,
+    created_at: row.created_at,
+    pinned: false,
+    name: None,
+  })
 }
 
 #[inline]
@@ -385,7 +380,8 @@ pub async fn select_snapshot(
   let row = sqlx::query_as!(
     AFSnapshotRow,
     r#"
-      SELECT * FROM af_collab_snapshot
+      SELECT sid, oid, blob, len, encrypt, deleted_at, created_at, workspace_id, codec
+      FROM af_collab_snapshot
       WHERE sid = $1 AND deleted_at IS NULL;
     "#,
     snapshot_id,
@@ -399,19 +395,90 @@ pub async fn get_all_collab_snapshot_meta(
   pg_pool: &PgPool,
   object_id: &str,
 ) -> Result<AFSnapshotMetas, Error> {
-  let snapshots: Vec<AFSnapshotMeta> = sqlx::query_as!(
-    AFSnapshotMeta,
+  let rows = sqlx::query_as::<_, AFSnapshotMetaRow>(
     r#"
-    SELECT sid as "snapshot_id", oid as "object_id", created_at
+    SELECT sid, oid, created_at, pinned, name
     FROM af_collab_snapshot 
     WHERE oid = $1 AND deleted_at IS NULL
     ORDER BY created_at DESC;
     "#,
-    object_id
   )
+  .bind(object_id)
   .fetch_all(pg_pool)
   .await?;
-  Ok(AFSnapshotMetas(snapshots))
+  Ok(AFSnapshotMetas(
+    rows.into_iter().map(AFSnapshotMeta::from).collect(),
+  ))
+}
+
+/// Pin or unpin the snapshot of the collab. The name of the snapshot is cleared when it's unpinned.
+/// Return None if the collab has no such snapshot.
+pub async fn update_snapshot_pin<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  object_id: &str,
+  snapshot_id: i64,
+  pinned: bool,
+  name: Option<&str>,
+) -> Result<Option<AFSnapshotMeta>, Error> {
+  let row = sqlx::query_as::<_, AFSnapshotMetaRow>(
+    r#"
+      UPDATE af_collab_snapshot
+      SET pinned = $3, name = CASE WHEN $3 THEN $4 ELSE NULL END
+      WHERE sid = $1 AND oid = $2 AND deleted_at IS NULL
+      RETURNING sid, oid, created_at, pinned, name
+    "#,
+  )
+  .bind(snapshot_id)
+  .bind(object_id)
+  .bind(pinned)
+  .bind(name)
+  .fetch_optional(executor)
+  .await?;
+  Ok(row.map(AFSnapshotMeta::from))
+}
+
+pub async fn insert_snapshot_restore<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  object_id: &str,
+  workspace_id: &Uuid,
+  snapshot_id: i64,
+  restored_by: i64,
+) -> Result<AFSnapshotRestore, Error> {
+  let row = sqlx::query_as::<_, AFSnapshotRestoreRow>(
+    r#"
+      INSERT INTO af_collab_snapshot_restore (oid, workspace_id, snapshot_id, restored_by)
+      VALUES ($1, $2, $3, $4)
+      RETURNING restore_id, oid, snapshot_id, restored_by, restored_at
+    "#,
+  )
+  .bind(object_id)
+  .bind(workspace_id)
+  .bind(snapshot_id)
+  .bind(restored_by)
+  .fetch_one(executor)
+  .await?;
+  Ok(row.into())
+}
+
+/// Return the snapshots restored into the collab, most recent first.
+pub async fn select_snapshot_restores<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  object_id: &str,
+) -> Result<AFSnapshotRestores, Error> {
+  let rows = sqlx::query_as::<_, AFSnapshotRestoreRow>(
+    r#"
+      SELECT restore_id, oid, snapshot_id, restored_by, restored_at
+      FROM af_collab_snapshot_restore
+      WHERE oid = $1
+      ORDER BY restored_at DESC
+    "#,
+  )
+  .bind(object_id)
+  .fetch_all(executor)
+  .await?;
+  Ok(AFSnapshotRestores(
+    rows.into_iter().map(AFSnapshotRestore::from).collect(),
+  ))
 }
 
 #[inline]
@@ -555,6 +622,47 @@ fn collab_member_try_from_row(row: PgRow) -> Result<AFCollabMember, sqlx::Error>
   })
 }
 
+/// Return the workspace of the collab object, or None if the collab object doesn't exist.
+#[inline]
+pub async fn select_workspace_id_of_collab<'a, E: Executor<'a, Database = Postgres>>(
+  oid: &str,
+  executor: E,
+) -> Result<Option<Uuid>, AppError> {
+  let workspace_id =
+    sqlx::query_scalar::<_, Uuid>("SELECT workspace_id FROM af_collab WHERE oid = $1")
+      .bind(oid)
+      .fetch_optional(executor)
+      .await?;
+  Ok(workspace_id)
+}
+
+/// Return the object id and the type of all the collabs of the workspace.
+pub async fn select_workspace_collabs(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+) -> Result<Vec<(String, CollabType)>, AppError> {
+  let rows = sqlx::query_as::<_, (String, i32)>(
+    r#"
+      SELECT oid, partition_key
+      FROM af_collab
+      WHERE workspace_id = $1 AND deleted_at IS NULL
+      ORDER BY oid
+    "#,
+  )
+  .bind(workspace_id)
+  .fetch_all(pg_pool)
+  .await?;
+  Ok(
+    rows
+      .into_iter()
+      .filter_map(|(oid, partition_key)| {
+        let collab_type = collab_type_from_partition_key(partition_key)?;
+        Some((oid, collab_type))
+      })
+      .collect(),
+  )
+}
+
 #[inline]
 pub async fn is_collab_member_exists<'a, E: Executor<'a, Database = Postgres>>(
   uid: i64,
@@ -573,6 +681,27 @@ pub async fn is_collab_member_exists<'a, E: Executor<'a, Database = Postgres>>(
   transform_record_not_found_error(result)
 }
 
+/// Return the collab objects of the workspace that the user is a member of.
+#[inline]
+pub async fn select_member_collab_ids_of_workspace<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  uid: i64,
+  workspace_id: &Uuid,
+) -> Result<Vec<String>, sqlx::Error> {
+  sqlx::query_scalar!(
+    r#"
+      SELECT af_collab_member.oid
+      FROM af_collab_member
+      JOIN af_collab ON af_collab.oid = af_collab_member.oid
+      WHERE af_collab_member.uid = $1 AND af_collab.workspace_id = $2
+    "#,
+    uid,
+    workspace_id,
+  )
+  .fetch_all(executor)
+  .await
+}
+
 #[inline]
 fn transform_record_not_found_error(
   result: Result<Option<bool>, sqlx::Error>,
@@ -0,0 +1,112 @@
+use crate::pg_row::AFCollabSearchRow;
+use app_error::AppError;
+use sqlx::{Executor, Postgres};
+use uuid::Uuid;
+
+/// Postgres limits the size of a tsvector to 1MB, so the content is truncated to stay below it.
+const MAX_SEARCH_CONTENT_LEN: usize = 512 * 1024;
+
+/// Insert or replace the plain text of the collab object used by the full-text search.
+#[inline]
+pub async fn upsert_collab_search_content<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  workspace_id: &Uuid,
+  oid: &str,
+  partition_key: i32,
+  content: &str,
+) -> Result<(), AppError> {
+  let content = truncate_content(content, MAX_SEARCH_CONTENT_LEN);
+  sqlx::query(
+    r#"
+      INSERT INTO af_collab_search (workspace_id, oid, partition_key, content)
+      VALUES ($1, $2, $3, $4)
+      ON CONFLICT (workspace_id, oid) DO UPDATE
+      SET partition_key = EXCLUDED.partition_key,
+          content = EXCLUDED.content,
+          updated_at = CURRENT_TIMESTAMP
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(oid)
+  .bind(partition_key)
+  .bind(content)
+  .execute(executor)
+  .await?;
+  Ok(())
+}
+
+#[inline]
+pub async fn delete_collab_search_content<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  oid: &str,
+) -> Result<(), AppError> {
+  sqlx::query("DELETE FROM af_collab_search WHERE oid = $1")
+    .bind(oid)
+    .execute(executor)
+    .await?;
+  Ok(())
+}
+
+/// Search the collab objects of the workspace whose content matches the query. The query uses the
+/// web search syntax, e.g. `"exact phrase" -excluded or other`. Only the collab objects that the
+/// user has at least `min_access_level` on, either as a member of the collab object or through the
+/// role in the workspace, are returned, ordered by relevance.
+pub async fn search_collab<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  workspace_id: &Uuid,
+  uid: i64,
+  query: &str,
+  min_access_level: i32,
+  limit: i64,
+) -> Result<Vec<AFCollabSearchRow>, AppError> {
+  let rows = sqlx::query_as::<_, AFCollabSearchRow>(
+    r#"
+      SELECT s.oid,
+             s.partition_key,
+             ts_headline('simple', s.content, q, 'MaxFragments=1, MinWords=5, MaxWords=20') AS snippet,
+             ts_rank(s.tsv, q) AS rank,
+             s.updated_at
+      FROM af_collab_search s
+      JOIN af_collab c ON c.oid = s.oid AND c.deleted_at IS NULL,
+      websearch_to_tsquery('simple', $3) q
+      WHERE s.workspace_id = $1
+        AND s.tsv @@ q
+        AND (
+          EXISTS (
+            SELECT 1
+            FROM af_workspace_member wm
+            JOIN af_role_permissions rp ON rp.role_id = wm.role_id
+            JOIN af_permissions p ON p.id = rp.permission_id
+            WHERE wm.workspace_id = s.workspace_id AND wm.uid = $2 AND p.access_level >= $4
+          )
+          OR EXISTS (
+            SELECT 1
+            FROM af_collab_member m
+            JOIN af_permissions p ON p.id = m.permission_id
+            WHERE m.oid = s.oid AND m.uid = $2 AND p.access_level >= $4
+          )
+        )
+      ORDER BY rank DESC, s.updated_at DESC
+      LIMIT $5
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(uid)
+  .bind(query)
+  .bind(min_access_level)
+  .bind(limit)
+  .fetch_all(executor)
+  .await?;
+  Ok(rows)
+}
+
+fn truncate_content(content: &str, max_len: usize) -> &str {
+  if content.len() <= max_len {
+    return content;
+  }
+  let mut end = max_len;
+  while !content.is_char_boundary(end) {
+    end -= 1;
+  }
+  &content[..end]
+}
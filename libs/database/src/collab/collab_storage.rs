@@ -1,9 +1,15 @@
-use crate::collab::{collab_db_ops, is_collab_exists};
+use crate::collab::{
+  batch_select_collab_updates, collab_db_ops, compact_collab_updates, decompress_collab_blob,
+  delete_collab_search_content, delete_collab_updates, insert_collab_updates, is_collab_exists,
+  merge_collab_updates, prune_collab_snapshots, select_collab_updates, CollabCodec,
+  CompactedCollab, CompressedCollab,
+};
 use anyhow::anyhow;
 use app_error::AppError;
 use async_trait::async_trait;
 use collab::core::collab::MutexCollab;
 use collab::core::collab_plugin::EncodedCollab;
+use collab_entity::CollabType;
 use database_entity::dto::{
   AFAccessLevel, AFRole, AFSnapshotMeta, AFSnapshotMetas, CollabParams, CreateCollabParams,
   InsertSnapshotParams, QueryCollab, QueryCollabParams, QueryCollabResult, SnapshotData,
@@ -18,6 +24,7 @@ use tokio::time::sleep;
 use tracing::{debug, event, warn, Level};
 use validator::Validate;
 
+/// Number of snapshots kept by the default retention policy, see [default_snapshot_retention_policy].
 pub const COLLAB_SNAPSHOT_LIMIT: i64 = 15;
 pub const SNAPSHOT_PER_HOUR: i64 = 6;
 pub type DatabaseResult<T, E = AppError> = core::result::Result<T, E>;
@@ -105,6 +112,24 @@ pub trait CollabStorage: Send + Sync + 'static {
     queries: Vec<QueryCollab>,
   ) -> HashMap<String, QueryCollabResult>;
 
+  /// Appends the updates applied to a collab by the realtime server to the update log of the
+  /// collab. The access level is not checked, the realtime server has already checked it before
+  /// applying the updates.
+  async fn append_collab_updates(
+    &self,
+    workspace_id: &str,
+    object_id: &str,
+    collab_type: &CollabType,
+    updates: Vec<Vec<u8>>,
+  ) -> DatabaseResult<()>;
+
+  /// Merges the update log of a collab into the stored collab and clears the log.
+  async fn compact_collab_updates(
+    &self,
+    object_id: &str,
+    collab_type: &CollabType,
+  ) -> DatabaseResult<()>;
+
   /// Deletes a collaboration from the storage.
   ///
   /// # Arguments
@@ -178,6 +203,30 @@ where
     self.as_ref().batch_get_collab(uid, queries).await
   }
 
+  async fn append_collab_updates(
+    &self,
+    workspace_id: &str,
+    object_id: &str,
+    collab_type: &CollabType,
+    updates: Vec<Vec<u8>>,
+  ) -> DatabaseResult<()> {
+    self
+      .as_ref()
+      .append_collab_updates(workspace_id, object_id, collab_type, updates)
+      .await
+  }
+
+  async fn compact_collab_updates(
+    &self,
+    object_id: &str,
+    collab_type: &CollabType,
+  ) -> DatabaseResult<()> {
+    self
+      .as_ref()
+      .compact_collab_updates(object_id, collab_type)
+      .await
+  }
+
   async fn delete_collab(&self, uid: &i64, object_id: &str) -> DatabaseResult<()> {
     self.as_ref().delete_collab(uid, object_id).await
   }
@@ -202,12 +251,65 @@ where
 #[derive(Debug, Clone)]
 pub struct WriteConfig {
   pub flush_per_update: u32,
+  /// When the collabs that are being edited are written to the storage
+  pub flush_policy: FlushPolicyConfig,
 }
 
 impl Default for WriteConfig {
   fn default() -> Self {
     Self {
       flush_per_update: 100,
+      flush_policy: FlushPolicyConfig::default(),
+    }
+  }
+}
+
+/// Decides when the update log of a collab that is being edited is merged into the stored collab.
+/// The log is merged once the collab received `max_edit_count` updates since it was last written,
+/// or `max_interval_secs` after it was last written if it received any update since.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollabFlushPolicy {
+  pub max_edit_count: u32,
+  pub max_interval_secs: i64,
+}
+
+/// The [CollabFlushPolicy] of each kind of collab.
+#[derive(Debug, Clone)]
+pub struct FlushPolicyConfig {
+  pub document: CollabFlushPolicy,
+  /// Used by the databases and their rows
+  pub database: CollabFlushPolicy,
+  /// Used by the folders, the workspace databases and the user awareness
+  pub workspace: CollabFlushPolicy,
+}
+
+impl FlushPolicyConfig {
+  pub fn policy(&self, collab_type: &CollabType) -> CollabFlushPolicy {
+    match collab_type {
+      CollabType::Document => self.document,
+      CollabType::Database | CollabType::DatabaseRow => self.database,
+      CollabType::WorkspaceDatabase | CollabType::Folder | CollabType::UserAwareness => {
+        self.workspace
+      },
+    }
+  }
+}
+
+impl Default for FlushPolicyConfig {
+  fn default() -> Self {
+    Self {
+      document: CollabFlushPolicy {
+        max_edit_count: 50,
+        max_interval_secs: 60,
+      },
+      database: CollabFlushPolicy {
+        max_edit_count: 100,
+        max_interval_secs: 120,
+      },
+      workspace: CollabFlushPolicy {
+        max_edit_count: 100,
+        max_interval_secs: 300,
+      },
     }
   }
 }
@@ -216,12 +318,17 @@ impl Default for WriteConfig {
 pub struct CollabStoragePgImpl {
   pub pg_pool: PgPool,
   config: WriteConfig,
+  /// The codec used to compress the collabs and snapshots that are written
+  codec: CollabCodec,
 }
 
 impl CollabStoragePgImpl {
-  pub fn new(pg_pool: PgPool) -> Self {
-    let config = WriteConfig::default();
-    Self { pg_pool, config }
+  pub fn new(pg_pool: PgPool, codec: CollabCodec, config: WriteConfig) -> Self {
+    Self {
+      pg_pool,
+      config,
+      codec,
+    }
   }
   pub fn config(&self) -> &WriteConfig {
     &self.config
@@ -245,7 +352,10 @@ impl CollabStoragePgImpl {
     params: CollabParams,
     transaction: &mut Transaction<'_, sqlx::Postgres>,
   ) -> DatabaseResult<()> {
-    collab_db_ops::insert_into_af_collab(transaction, uid, workspace_id, &params).await?;
+    let compressed =
+      CompressedCollab::compress(self.codec, params.encoded_collab_v1.clone()).await?;
+    collab_db_ops::insert_into_af_collab(transaction, uid, workspace_id, &params, &compressed)
+      .await?;
     Ok(())
   }
 
@@ -264,21 +374,25 @@ impl CollabStoragePgImpl {
     let mut attempts = 0;
 
     loop {
-      let result = collab_db_ops::select_blob_from_af_collab(
-        &self.pg_pool,
-        &params.collab_type,
-        &params.object_id,
-      )
+      // The update log is read before the collab. An update that is compacted in between is
+      // already part of the collab, and applying it twice is a no-op.
+      let result = async {
+        let updates =
+          select_collab_updates(&self.pg_pool, &params.object_id, &params.collab_type).await?;
+        let data = collab_db_ops::select_blob_from_af_collab(
+          &self.pg_pool,
+          &params.collab_type,
+          &params.object_id,
+        )
+        .await?;
+        Ok::<_, sqlx::Error>((data, updates))
+      }
       .await;
 
       match result {
-        Ok(data) => {
-          return tokio::task::spawn_blocking(move || {
-            EncodedCollab::decode_from_bytes(&data).map_err(|err| {
-              AppError::Internal(anyhow!("fail to decode data to EncodedCollab: {:?}", err))
-            })
-          })
-          .await?;
+        Ok((data, updates)) => {
+          return tokio::task::spawn_blocking(move || merge_collab_updates(&data, &updates))
+            .await?;
         },
         Err(e) => {
           // Handle non-retryable errors immediately
@@ -305,11 +419,84 @@ impl CollabStoragePgImpl {
     _uid: &i64,
     queries: Vec<QueryCollab>,
   ) -> HashMap<String, QueryCollabResult> {
-    collab_db_ops::batch_select_collab_blob(&self.pg_pool, queries).await
+    // Same as get_collab_encoded, the update logs are read before the collabs
+    let collabs = queries
+      .iter()
+      .map(|query| (query.object_id.clone(), query.collab_type.clone()))
+      .collect::<Vec<_>>();
+    let updates_by_oid = match batch_select_collab_updates(&self.pg_pool, &collabs).await {
+      Ok(updates_by_oid) => updates_by_oid,
+      Err(err) => {
+        let error = format!("fail to get the updates of collab: {}", err);
+        return failed_results(collabs, &error);
+      },
+    };
+
+    let results = collab_db_ops::batch_select_collab_blob(&self.pg_pool, queries).await;
+    if updates_by_oid.is_empty() {
+      return results;
+    }
+    let merged = tokio::task::spawn_blocking(move || {
+      results
+        .into_iter()
+        .map(|(oid, result)| match (result, updates_by_oid.get(&oid)) {
+          (QueryCollabResult::Success { encode_collab_v1 }, Some(updates)) => {
+            let result = merge_collab_updates(&encode_collab_v1, updates).and_then(|collab| {
+              collab
+                .encode_to_bytes()
+                .map_err(|err| AppError::Internal(anyhow!("fail to encode collab: {:?}", err)))
+            });
+            let result = match result {
+              Ok(encode_collab_v1) => QueryCollabResult::Success { encode_collab_v1 },
+              Err(err) => QueryCollabResult::Failed {
+                error: err.to_string(),
+              },
+            };
+            (oid, result)
+          },
+          (result, _) => (oid, result),
+        })
+        .collect::<HashMap<_, _>>()
+    })
+    .await;
+    merged.unwrap_or_else(|err| {
+      let error = format!("fail to merge the updates of collab: {}", err);
+      failed_results(collabs, &error)
+    })
+  }
+
+  pub async fn append_collab_updates(
+    &self,
+    workspace_id: &str,
+    object_id: &str,
+    collab_type: &CollabType,
+    updates: Vec<Vec<u8>>,
+  ) -> DatabaseResult<()> {
+    let workspace_id = Uuid::parse_str(workspace_id)?;
+    insert_collab_updates(
+      &self.pg_pool,
+      &workspace_id,
+      object_id,
+      collab_type,
+      &updates,
+    )
+    .await?;
+    Ok(())
+  }
+
+  pub async fn compact_collab_updates_with_transaction(
+    &self,
+    object_id: &str,
+    collab_type: &CollabType,
+    transaction: &mut Transaction<'_, sqlx::Postgres>,
+  ) -> DatabaseResult<Option<CompactedCollab>> {
+    compact_collab_updates(transaction, self.codec, object_id, collab_type).await
   }
 
   pub async fn delete_collab(&self, _uid: &i64, object_id: &str) -> DatabaseResult<()> {
     collab_db_ops::delete_collab(&self.pg_pool, object_id).await?;
+    delete_collab_search_content(&self.pg_pool, object_id).await?;
+    delete_collab_updates(&self.pg_pool, object_id).await?;
     Ok(())
   }
 
@@ -331,23 +518,27 @@ impl CollabStoragePgImpl {
     params.validate()?;
 
     debug!("create snapshot for object:{}", params.object_id);
-    match self.pg_pool.try_begin().await {
-      Ok(Some(transaction)) => {
-        let meta = collab_db_ops::create_snapshot_and_maintain_limit(
-          transaction,
-          &params.object_id,
-          &params.encoded_collab_v1,
-          &params.workspace_id.parse::<Uuid>()?,
-          COLLAB_SNAPSHOT_LIMIT,
-        )
-        .await?;
-
-        Ok(meta)
-      },
-      _ => Err(AppError::Internal(anyhow!(
-        "fail to acquire transaction to create snapshot",
-      ))),
-    }
+    let compressed = CompressedCollab::compress(self.codec, params.encoded_collab_v1).await?;
+    let meta = collab_db_ops::insert_snapshot(
+      &self.pg_pool,
+      &params.object_id,
+      &compressed,
+      &params.workspace_id.parse::<Uuid>()?,
+    )
+    .await?;
+
+    // The old snapshots are pruned in the background, so creating a snapshot doesn't wait for it
+    let pg_pool = self.pg_pool.clone();
+    let object_id = params.object_id;
+    tokio::spawn(async move {
+      if let Err(err) = prune_collab_snapshots(&pg_pool, &object_id).await {
+        warn!(
+          "fail to prune the snapshots of collab:{}: {}",
+          object_id, err
+        );
+      }
+    });
+    Ok(meta)
   }
 
   pub async fn get_collab_snapshot(&self, snapshot_id: &i64) -> DatabaseResult<SnapshotData> {
@@ -356,11 +547,18 @@ impl CollabStoragePgImpl {
         "Can't find the snapshot with id:{}",
         snapshot_id
       ))),
-      Some(row) => Ok(SnapshotData {
-        object_id: row.oid,
-        encoded_collab_v1: row.blob,
-        workspace_id: row.workspace_id.to_string(),
-      }),
+      Some(row) => {
+        let (codec, blob) = (row.codec, row.blob);
+        let encoded_collab_v1 =
+          tokio::task::spawn_blocking(move || decompress_collab_blob(codec, blob))
+            .await
+            .map_err(|err| AppError::Internal(err.into()))??;
+        Ok(SnapshotData {
+          object_id: row.oid,
+          encoded_collab_v1,
+          workspace_id: row.workspace_id.to_string(),
+        })
+      },
     }
   }
 
@@ -369,3 +567,16 @@ impl CollabStoragePgImpl {
     Ok(metas)
   }
 }
+
+fn failed_results(
+  collabs: Vec<(String, CollabType)>,
+  error: &str,
+) -> HashMap<String, QueryCollabResult> {
+  collabs
+    .into_iter()
+    .map(|(oid, _)| {
+      let error = error.to_string();
+      (oid, QueryCollabResult::Failed { error })
+    })
+    .collect()
+}
@@ -0,0 +1,373 @@
+use crate::collab::{decompress_collab_blob, CollabCodec, CompressedCollab};
+use anyhow::anyhow;
+use app_error::AppError;
+use chrono::{DateTime, Utc};
+use collab::core::collab::TransactionMutExt;
+use collab::core::collab_plugin::EncodedCollab;
+use collab::core::transaction::DocTransactionExtension;
+use collab_entity::CollabType;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::ops::DerefMut;
+use uuid::Uuid;
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, Transact, Update};
+
+/// Append the updates to the update log of the collab. The updates are merged into the collab
+/// when it's read, until they are compacted into the collab by [compact_collab_updates].
+pub async fn insert_collab_updates(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+  object_id: &str,
+  collab_type: &CollabType,
+  updates: &[Vec<u8>],
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+      INSERT INTO af_collab_update (oid, partition_key, workspace_id, blob)
+      SELECT $1, $2, $3, UNNEST($4::bytea[])
+    "#,
+    object_id,
+    collab_type.value(),
+    workspace_id,
+    updates,
+  )
+  .execute(pg_pool)
+  .await?;
+  Ok(())
+}
+
+/// Return true if the update log of the collab holds updates that were not compacted yet.
+pub async fn has_collab_updates<'a, E>(
+  executor: E,
+  object_id: &str,
+  collab_type: &CollabType,
+) -> Result<bool, sqlx::Error>
+where
+  E: Executor<'a, Database = Postgres>,
+{
+  sqlx::query_scalar!(
+    r#"
+      SELECT EXISTS (
+        SELECT 1 FROM af_collab_update WHERE oid = $1 AND partition_key = $2
+      ) AS "exists!"
+    "#,
+    object_id,
+    collab_type.value(),
+  )
+  .fetch_one(executor)
+  .await
+}
+
+/// Return the updates of the collab that were not compacted yet, in the order they were written.
+pub async fn select_collab_updates<'a, E>(
+  executor: E,
+  object_id: &str,
+  collab_type: &CollabType,
+) -> Result<Vec<Vec<u8>>, sqlx::Error>
+where
+  E: Executor<'a, Database = Postgres>,
+{
+  sqlx::query_scalar!(
+    r#"
+      SELECT blob
+      FROM af_collab_update
+      WHERE oid = $1 AND partition_key = $2
+      ORDER BY seq
+    "#,
+    object_id,
+    collab_type.value(),
+  )
+  .fetch_all(executor)
+  .await
+}
+
+/// Same as [select_collab_updates] for several collabs at once, grouped by object id.
+pub async fn batch_select_collab_updates(
+  pg_pool: &PgPool,
+  collabs: &[(String, CollabType)],
+) -> Result<HashMap<String, Vec<Vec<u8>>>, sqlx::Error> {
+  let (object_ids, partition_keys): (Vec<_>, Vec<_>) = collabs
+    .iter()
+    .map(|(object_id, collab_type)| (object_id.clone(), collab_type.value()))
+    .unzip();
+  let rows = sqlx::query!(
+    r#"
+      SELECT oid, blob
+      FROM af_collab_update
+      WHERE (oid, partition_key) IN (SELECT * FROM UNNEST($1::text[], $2::int[]))
+      ORDER BY seq
+    "#,
+    &object_ids,
+    &partition_keys,
+  )
+  .fetch_all(pg_pool)
+  .await?;
+
+  let mut updates_by_oid: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+  for row in rows {
+    updates_by_oid.entry(row.oid).or_default().push(row.blob);
+  }
+  Ok(updates_by_oid)
+}
+
+pub async fn delete_collab_updates<'a, E>(executor: E, object_id: &str) -> Result<(), sqlx::Error>
+where
+  E: Executor<'a, Database = Postgres>,
+{
+  sqlx::query!(r#"DELETE FROM af_collab_update WHERE oid = $1"#, object_id)
+    .execute(executor)
+    .await?;
+  Ok(())
+}
+
+/// Position of [select_collabs_to_compact] in the collabs to compact, which are ordered by their
+/// oldest update.
+#[derive(Debug, Clone)]
+pub struct CompactionCursor {
+  pub first_update_at: DateTime<Utc>,
+  pub object_id: String,
+}
+
+/// Return the next collabs, after `cursor`, whose update log holds at least `min_updates` updates,
+/// or an update that was written before `written_before`. The collabs are ordered by their oldest
+/// update, so the collabs that can't be compacted don't keep the others from being selected.
+///
+/// Return the cursor to continue from, or None once all the collabs have been visited.
+pub async fn select_collabs_to_compact(
+  pg_pool: &PgPool,
+  min_updates: i64,
+  written_before: DateTime<Utc>,
+  cursor: Option<&CompactionCursor>,
+  limit: i64,
+) -> Result<(Vec<(String, CollabType)>, Option<CompactionCursor>), sqlx::Error> {
+  let rows = sqlx::query!(
+    r#"
+      SELECT oid, partition_key, MIN(created_at) AS "first_update_at!"
+      FROM af_collab_update
+      GROUP BY oid, partition_key
+      HAVING (COUNT(*) >= $1 OR MIN(created_at) < $2)
+        AND ($3::timestamptz IS NULL OR (MIN(created_at), oid) > ($3, $4::text))
+      ORDER BY MIN(created_at), oid
+      LIMIT $5
+    "#,
+    min_updates,
+    written_before,
+    cursor.map(|cursor| cursor.first_update_at),
+    cursor.map(|cursor| cursor.object_id.as_str()),
+    limit,
+  )
+  .fetch_all(pg_pool)
+  .await?;
+
+  let next_cursor = if (rows.len() as i64) < limit {
+    None
+  } else {
+    rows.last().map(|row| CompactionCursor {
+      first_update_at: row.first_update_at,
+      object_id: row.oid.clone(),
+    })
+  };
+  let collabs = rows
+    .into_iter()
+    .filter_map(|row| {
+      collab_type_from_partition_key(row.partition_key).map(|collab_type| (row.oid, collab_type))
+    })
+    .collect();
+  Ok((collabs, next_cursor))
+}
+
+pub(crate) fn collab_type_from_partition_key(partition_key: i32) -> Option<CollabType> {
+  [
+    CollabType::Document,
+    CollabType::Database,
+    CollabType::WorkspaceDatabase,
+    CollabType::Folder,
+    CollabType::DatabaseRow,
+    CollabType::UserAwareness,
+  ]
+  .into_iter()
+  .find(|collab_type| collab_type.value() == partition_key)
+}
+
+/// The collab after its updates were compacted into it.
+pub struct CompactedCollab {
+  pub workspace_id: Uuid,
+  pub encoded_collab_v1: Vec<u8>,
+}
+
+/// Merge the update log of the collab into its row in `af_collab`, compressed with `codec`, and
+/// remove the merged updates from the log.
+///
+/// The row of the collab is locked until the transaction is committed, so concurrent compactions
+/// and writes of the same collab are serialized. Return None if there was nothing to compact.
+///
+/// The updates of a collab that was deleted, which the realtime server may still append while the
+/// collab is open, are removed, as they will never be compacted. So are the updates of a collab
+/// that was never inserted, once they are older than a day.
+pub async fn compact_collab_updates(
+  transaction: &mut Transaction<'_, Postgres>,
+  codec: CollabCodec,
+  object_id: &str,
+  collab_type: &CollabType,
+) -> Result<Option<CompactedCollab>, AppError> {
+  let partition_key = collab_type.value();
+  let row = sqlx::query!(
+    r#"
+      SELECT workspace_id, blob, codec
+      FROM af_collab
+      WHERE oid = $1 AND partition_key = $2 AND deleted_at IS NULL
+      FOR UPDATE
+    "#,
+    object_id,
+    partition_key,
+  )
+  .fetch_optional(transaction.deref_mut())
+  .await?;
+  let (workspace_id, blob, blob_codec) = match row {
+    Some(row) => (row.workspace_id, row.blob, row.codec),
+    None => {
+      // Keep the recent updates of a collab that has no row yet, as it may still be inserted
+      sqlx::query!(
+        r#"
+          DELETE FROM af_collab_update
+          WHERE oid = $1 AND partition_key = $2
+            AND (
+              EXISTS (SELECT 1 FROM af_collab WHERE oid = $1 AND partition_key = $2)
+              OR created_at < NOW() - INTERVAL '1 day'
+            )
+        "#,
+        object_id,
+        partition_key,
+      )
+      .execute(transaction.deref_mut())
+      .await?;
+      return Ok(None);
+    },
+  };
+
+  let updates = sqlx::query!(
+    r#"
+      SELECT seq, blob
+      FROM af_collab_update
+      WHERE oid = $1 AND partition_key = $2
+      ORDER BY seq
+    "#,
+    object_id,
+    partition_key,
+  )
+  .fetch_all(transaction.deref_mut())
+  .await?;
+  let last_seq = match updates.last() {
+    Some(update) => update.seq,
+    None => return Ok(None),
+  };
+
+  // Merging and compressing the collab is CPU bound
+  let (encoded_collab_v1, compressed) = tokio::task::spawn_blocking(move || {
+    let encoded_collab_v1 = decompress_collab_blob(blob_codec, blob)?;
+    let updates = updates
+      .into_iter()
+      .map(|update| update.blob)
+      .collect::<Vec<_>>();
+    let encoded_collab_v1 = merge_collab_updates(&encoded_collab_v1, &updates)?
+      .encode_to_bytes()
+      .map_err(|err| AppError::Internal(anyhow!("fail to encode collab: {:?}", err)))?;
+    let compressed = CompressedCollab {
+      codec,
+      blob: codec.compress(&encoded_collab_v1)?,
+      len: encoded_collab_v1.len(),
+    };
+    Ok::<_, AppError>((encoded_collab_v1, compressed))
+  })
+  .await
+  .map_err(|err| AppError::Internal(err.into()))??;
+
+  sqlx::query!(
+    r#"UPDATE af_collab SET blob = $3, len = $4, codec = $5 WHERE oid = $1 AND partition_key = $2"#,
+    object_id,
+    partition_key,
+    &compressed.blob,
+    compressed.len as i32,
+    compressed.codec.value(),
+  )
+  .execute(transaction.deref_mut())
+  .await?;
+  sqlx::query!(
+    r#"DELETE FROM af_collab_update WHERE oid = $1 AND partition_key = $2 AND seq <= $3"#,
+    object_id,
+    partition_key,
+    last_seq,
+  )
+  .execute(transaction.deref_mut())
+  .await?;
+
+  Ok(Some(CompactedCollab {
+    workspace_id,
+    encoded_collab_v1,
+  }))
+}
+
+/// Apply the updates on top of the encoded collab. Applying an update that is already part of the
+/// collab is a no-op, so the updates may overlap with the encoded collab.
+pub fn merge_collab_updates(
+  encoded_collab_v1: &[u8],
+  updates: &[Vec<u8>],
+) -> Result<EncodedCollab, AppError> {
+  let encoded_collab = EncodedCollab::decode_from_bytes(encoded_collab_v1).map_err(|err| {
+    AppError::Internal(anyhow!("fail to decode data to EncodedCollab: {:?}", err))
+  })?;
+  if updates.is_empty() {
+    return Ok(encoded_collab);
+  }
+
+  let doc = Doc::new();
+  {
+    let mut txn = doc.transact_mut();
+    let doc_state = std::iter::once(encoded_collab.doc_state.as_ref());
+    for update in doc_state.chain(updates.iter().map(|update| update.as_slice())) {
+      let update = Update::decode_v1(update)
+        .map_err(|err| AppError::Internal(anyhow!("fail to decode collab update: {:?}", err)))?;
+      txn
+        .try_apply_update(update)
+        .map_err(|err| AppError::Internal(anyhow!("fail to apply collab update: {:?}", err)))?;
+    }
+  }
+  Ok(doc.get_encoded_collab_v1())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yrs::{GetString, ReadTxn, Text};
+
+  #[test]
+  fn merge_updates_into_encoded_collab() {
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    text.insert(&mut doc.transact_mut(), 0, "hello");
+    let encoded_collab_v1 = doc.get_encoded_collab_v1().encode_to_bytes().unwrap();
+
+    let mut updates = vec![];
+    for chunk in [" world", "!"] {
+      let state_vector = doc.transact().state_vector();
+      let len = text.len(&doc.transact());
+      text.insert(&mut doc.transact_mut(), len, chunk);
+      updates.push(doc.transact().encode_state_as_update_v1(&state_vector));
+    }
+    // The first update is applied twice, which must not duplicate its content
+    updates.push(updates[0].clone());
+
+    let merged = merge_collab_updates(&encoded_collab_v1, &updates).unwrap();
+    let merged_doc = Doc::new();
+    merged_doc
+      .transact_mut()
+      .try_apply_update(Update::decode_v1(&merged.doc_state).unwrap())
+      .unwrap();
+    let merged_text = merged_doc.get_or_insert_text("text");
+    assert_eq!(
+      merged_text.get_string(&merged_doc.transact()),
+      "hello world!"
+    );
+  }
+}
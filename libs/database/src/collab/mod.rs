@@ -1,6 +1,14 @@
+mod collab_codec;
 mod collab_db_ops;
+mod collab_search;
 mod collab_storage;
+mod collab_update;
+mod snapshot_retention;
 // mod recent;
 
+pub use collab_codec::*;
 pub use collab_db_ops::*;
+pub use collab_search::*;
 pub use collab_storage::*;
+pub use collab_update::*;
+pub use snapshot_retention::*;
@@ -0,0 +1,318 @@
+use crate::collab::{collab_type_from_partition_key, COLLAB_SNAPSHOT_LIMIT};
+use app_error::AppError;
+use chrono::{DateTime, Duration, Utc};
+use collab_entity::CollabType;
+use database_entity::dto::{AFSnapshotRetentionPolicy, SnapshotRetentionPolicy};
+use sqlx::{Executor, PgPool, Postgres};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// The policy of the collabs whose workspace has no retention policy: only the most recent
+/// snapshots are kept.
+pub fn default_snapshot_retention_policy() -> SnapshotRetentionPolicy {
+  SnapshotRetentionPolicy {
+    keep_latest: COLLAB_SNAPSHOT_LIMIT as u32,
+    hourly_for_hours: 0,
+    daily_for_days: 0,
+    weekly_for_weeks: Some(0),
+  }
+}
+
+type RetentionPolicyRow = (Option<i32>, i32, i32, i32, Option<i32>);
+
+fn policy_from_row(row: RetentionPolicyRow) -> SnapshotRetentionPolicy {
+  let (_, keep_latest, hourly_for_hours, daily_for_days, weekly_for_weeks) = row;
+  SnapshotRetentionPolicy {
+    keep_latest: keep_latest as u32,
+    hourly_for_hours: hourly_for_hours as u32,
+    daily_for_days: daily_for_days as u32,
+    weekly_for_weeks: weekly_for_weeks.map(|weeks| weeks as u32),
+  }
+}
+
+pub async fn select_snapshot_retention_policies(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+) -> Result<Vec<AFSnapshotRetentionPolicy>, sqlx::Error> {
+  let rows = sqlx::query_as::<_, RetentionPolicyRow>(
+    r#"
+      SELECT partition_key, keep_latest, hourly_for_hours, daily_for_days, weekly_for_weeks
+      FROM af_snapshot_retention_policy
+      WHERE workspace_id = $1
+      ORDER BY partition_key NULLS FIRST
+    "#,
+  )
+  .bind(workspace_id)
+  .fetch_all(pg_pool)
+  .await?;
+  Ok(
+    rows
+      .into_iter()
+      .filter_map(|row| {
+        let collab_type = match row.0 {
+          Some(partition_key) => Some(collab_type_from_partition_key(partition_key)?),
+          None => None,
+        };
+        Some(AFSnapshotRetentionPolicy {
+          collab_type,
+          policy: policy_from_row(row),
+        })
+      })
+      .collect(),
+  )
+}
+
+/// Return the retention policy of the collabs of the given type in the workspace. The policy of the
+/// collab type takes precedence over the policy of the workspace.
+pub async fn select_snapshot_retention_policy<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  workspace_id: &Uuid,
+  partition_key: Option<i32>,
+) -> Result<Option<SnapshotRetentionPolicy>, sqlx::Error> {
+  let row = sqlx::query_as::<_, RetentionPolicyRow>(
+    r#"
+      SELECT partition_key, keep_latest, hourly_for_hours, daily_for_days, weekly_for_weeks
+      FROM af_snapshot_retention_policy
+      WHERE workspace_id = $1 AND (partition_key IS NULL OR partition_key = $2)
+      ORDER BY partition_key NULLS LAST
+      LIMIT 1
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(partition_key)
+  .fetch_optional(executor)
+  .await?;
+  Ok(row.map(policy_from_row))
+}
+
+pub async fn upsert_snapshot_retention_policy(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+  collab_type: Option<&CollabType>,
+  policy: &SnapshotRetentionPolicy,
+) -> Result<(), sqlx::Error> {
+  sqlx::query(
+    r#"
+      INSERT INTO af_snapshot_retention_policy
+        (workspace_id, partition_key, keep_latest, hourly_for_hours, daily_for_days, weekly_for_weeks)
+      VALUES ($1, $2, $3, $4, $5, $6)
+      ON CONFLICT (workspace_id, (COALESCE(partition_key, -1))) DO UPDATE SET
+        keep_latest = EXCLUDED.keep_latest,
+        hourly_for_hours = EXCLUDED.hourly_for_hours,
+        daily_for_days = EXCLUDED.daily_for_days,
+        weekly_for_weeks = EXCLUDED.weekly_for_weeks,
+        updated_at = CURRENT_TIMESTAMP
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(collab_type.map(|collab_type| collab_type.value()))
+  .bind(policy.keep_latest as i32)
+  .bind(policy.hourly_for_hours as i32)
+  .bind(policy.daily_for_days as i32)
+  .bind(policy.weekly_for_weeks.map(|weeks| weeks as i32))
+  .execute(pg_pool)
+  .await?;
+  Ok(())
+}
+
+pub async fn delete_snapshot_retention_policy(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+  collab_type: Option<&CollabType>,
+) -> Result<(), sqlx::Error> {
+  sqlx::query(
+    r#"
+      DELETE FROM af_snapshot_retention_policy
+      WHERE workspace_id = $1 AND partition_key IS NOT DISTINCT FROM $2
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(collab_type.map(|collab_type| collab_type.value()))
+  .execute(pg_pool)
+  .await?;
+  Ok(())
+}
+
+/// Return the collabs that have more than one unpinned snapshot, ordered by object id and starting
+/// after `after_object_id`, so all the collabs can be visited in batches.
+pub async fn select_collabs_with_snapshots(
+  pg_pool: &PgPool,
+  after_object_id: &str,
+  limit: i64,
+) -> Result<Vec<String>, sqlx::Error> {
+  sqlx::query_scalar::<_, String>(
+    r#"
+      SELECT oid
+      FROM af_collab_snapshot
+      WHERE oid > $1 AND pinned = FALSE AND deleted_at IS NULL
+      GROUP BY oid
+      HAVING COUNT(*) > 1
+      ORDER BY oid
+      LIMIT $2
+    "#,
+  )
+  .bind(after_object_id)
+  .bind(limit)
+  .fetch_all(pg_pool)
+  .await
+}
+
+/// Remove the snapshots of the collab that are not kept by its retention policy, and return the
+/// number of removed snapshots. Pinned snapshots are never removed.
+pub async fn prune_collab_snapshots(pg_pool: &PgPool, object_id: &str) -> Result<u64, AppError> {
+  let snapshots = sqlx::query_as::<_, (i64, Uuid, DateTime<Utc>)>(
+    r#"
+      SELECT sid, workspace_id, created_at
+      FROM af_collab_snapshot
+      WHERE oid = $1 AND pinned = FALSE AND deleted_at IS NULL
+    "#,
+  )
+  .bind(object_id)
+  .fetch_all(pg_pool)
+  .await?;
+  let workspace_id = match snapshots.first() {
+    Some((_, workspace_id, _)) => *workspace_id,
+    None => return Ok(0),
+  };
+
+  let partition_key = sqlx::query_scalar::<_, i32>(
+    "SELECT partition_key FROM af_collab WHERE oid = $1 AND deleted_at IS NULL LIMIT 1",
+  )
+  .bind(object_id)
+  .fetch_optional(pg_pool)
+  .await?;
+  let policy = select_snapshot_retention_policy(pg_pool, &workspace_id, partition_key)
+    .await?
+    .unwrap_or_else(default_snapshot_retention_policy);
+
+  let snapshots = snapshots
+    .into_iter()
+    .map(|(snapshot_id, _, created_at)| (snapshot_id, created_at))
+    .collect::<Vec<_>>();
+  let snapshot_ids = snapshots_to_prune(&policy, Utc::now(), snapshots);
+  if snapshot_ids.is_empty() {
+    return Ok(0);
+  }
+
+  // A snapshot that was pinned in the meantime is kept
+  let result = sqlx::query("DELETE FROM af_collab_snapshot WHERE sid = ANY($1) AND pinned = FALSE")
+    .bind(&snapshot_ids)
+    .execute(pg_pool)
+    .await?;
+  Ok(result.rows_affected())
+}
+
+/// Return the ids of the snapshots that are not kept by the policy at `now`.
+pub fn snapshots_to_prune(
+  policy: &SnapshotRetentionPolicy,
+  now: DateTime<Utc>,
+  mut snapshots: Vec<(i64, DateTime<Utc>)>,
+) -> Vec<i64> {
+  // Most recent first, so the first snapshot of a period is the one that is kept
+  snapshots.sort_by(|a, b| b.1.cmp(&a.1));
+
+  let mut kept = snapshots
+    .iter()
+    .take(policy.keep_latest as usize)
+    .map(|(snapshot_id, _)| *snapshot_id)
+    .collect::<HashSet<_>>();
+  let periods = [
+    (Duration::hours(1), Some(policy.hourly_for_hours)),
+    (Duration::days(1), Some(policy.daily_for_days)),
+    (Duration::weeks(1), policy.weekly_for_weeks),
+  ];
+  for (period, count) in periods {
+    keep_one_per_period(&snapshots, now, period, count, &mut kept);
+  }
+
+  snapshots
+    .into_iter()
+    .filter(|(snapshot_id, _)| !kept.contains(snapshot_id))
+    .map(|(snapshot_id, _)| snapshot_id)
+    .collect()
+}
+
+/// Keep the most recent snapshot of each period within the last `count` periods, or of every
+/// period if `count` is None. The periods are aligned on the unix epoch.
+fn keep_one_per_period(
+  snapshots: &[(i64, DateTime<Utc>)],
+  now: DateTime<Utc>,
+  period: Duration,
+  count: Option<u32>,
+  kept: &mut HashSet<i64>,
+) {
+  if count == Some(0) {
+    return;
+  }
+
+  let mut last_period = None;
+  for (snapshot_id, created_at) in snapshots {
+    if let Some(count) = count {
+      if now - *created_at >= period * count as i32 {
+        break;
+      }
+    }
+    let current_period = created_at.timestamp().div_euclid(period.num_seconds());
+    if last_period != Some(current_period) {
+      kept.insert(*snapshot_id);
+      last_period = Some(current_period);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  fn policy(
+    keep_latest: u32,
+    hourly_for_hours: u32,
+    daily_for_days: u32,
+    weekly_for_weeks: Option<u32>,
+  ) -> SnapshotRetentionPolicy {
+    SnapshotRetentionPolicy {
+      keep_latest,
+      hourly_for_hours,
+      daily_for_days,
+      weekly_for_weeks,
+    }
+  }
+
+  #[test]
+  fn keep_latest_snapshots() {
+    let now = Utc.with_ymd_and_hms(2024, 3, 8, 12, 0, 0).unwrap();
+    let snapshots = (0..5)
+      .map(|i| (i, now - Duration::minutes(i)))
+      .collect::<Vec<_>>();
+    let mut pruned = snapshots_to_prune(&policy(2, 0, 0, Some(0)), now, snapshots);
+    pruned.sort();
+    assert_eq!(pruned, vec![2, 3, 4]);
+  }
+
+  #[test]
+  fn keep_one_snapshot_per_period() {
+    let now = Utc.with_ymd_and_hms(2024, 3, 8, 12, 30, 0).unwrap();
+    let snapshots = vec![
+      // Two snapshots in the current hour, only the most recent one is kept
+      (1, now - Duration::minutes(5)),
+      (2, now - Duration::minutes(20)),
+      // The previous hour
+      (3, now - Duration::minutes(45)),
+      // Older than the hourly window, but the most recent of the previous day
+      (4, now - Duration::hours(20)),
+      (5, now - Duration::hours(22)),
+      // Older than the daily window, kept by the weekly rule that has no limit
+      (6, now - Duration::days(40)),
+      (7, now - Duration::days(40) - Duration::hours(1)),
+    ];
+    let mut pruned = snapshots_to_prune(&policy(0, 2, 2, None), now, snapshots.clone());
+    pruned.sort();
+    assert_eq!(pruned, vec![2, 5, 7]);
+
+    // Without the weekly rule, the old snapshots are removed
+    let mut pruned = snapshots_to_prune(&policy(0, 2, 2, Some(0)), now, snapshots);
+    pruned.sort();
+    assert_eq!(pruned, vec![2, 5, 6, 7]);
+  }
+}
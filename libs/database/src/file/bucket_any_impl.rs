@@ -0,0 +1,222 @@
+use crate::file::bucket_fs_impl::{BucketClientFsImpl, FsResponseData};
+use crate::file::bucket_s3_impl::{BucketClientS3Impl, S3ResponseData};
+use crate::file::{BucketClient, BucketStorage, CompletedPart, ObjectMeta, ResponseBlob};
+use app_error::AppError;
+use async_trait::async_trait;
+
+/// The bucket storage used by the server. The concrete backend is chosen at startup from the
+/// configuration.
+pub type AnyBucketStorage = BucketStorage<AnyBucketClient>;
+
+pub enum AnyBucketClient {
+  S3(BucketClientS3Impl),
+  Fs(BucketClientFsImpl),
+}
+
+impl From<BucketClientS3Impl> for AnyBucketClient {
+  fn from(client: BucketClientS3Impl) -> Self {
+    Self::S3(client)
+  }
+}
+
+impl From<BucketClientFsImpl> for AnyBucketClient {
+  fn from(client: BucketClientFsImpl) -> Self {
+    Self::Fs(client)
+  }
+}
+
+#[async_trait]
+impl BucketClient for AnyBucketClient {
+  type ResponseData = AnyResponseData;
+
+  async fn pub_blob<P>(&self, id: P, content: &[u8]) -> Result<(), AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.pub_blob(id, content).await,
+      AnyBucketClient::Fs(client) => client.pub_blob(id, content).await,
+    }
+  }
+
+  async fn delete_blob<P>(&self, id: P) -> Result<Self::ResponseData, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.delete_blob(id).await.map(AnyResponseData::S3),
+      AnyBucketClient::Fs(client) => client.delete_blob(id).await.map(AnyResponseData::Fs),
+    }
+  }
+
+  async fn get_blob<P>(&self, id: P) -> Result<Self::ResponseData, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.get_blob(id).await.map(AnyResponseData::S3),
+      AnyBucketClient::Fs(client) => client.get_blob(id).await.map(AnyResponseData::Fs),
+    }
+  }
+
+  async fn get_blob_range<P>(
+    &self,
+    id: P,
+    start: u64,
+    end: u64,
+  ) -> Result<Self::ResponseData, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client
+        .get_blob_range(id, start, end)
+        .await
+        .map(AnyResponseData::S3),
+      AnyBucketClient::Fs(client) => client
+        .get_blob_range(id, start, end)
+        .await
+        .map(AnyResponseData::Fs),
+    }
+  }
+
+  async fn create_upload<P>(&self, id: P, content_type: &str) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.create_upload(id, content_type).await,
+      AnyBucketClient::Fs(client) => client.create_upload(id, content_type).await,
+    }
+  }
+
+  async fn upload_part<P>(
+    &self,
+    id: P,
+    upload_id: &str,
+    part_number: i32,
+    content: Vec<u8>,
+    content_type: &str,
+  ) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => {
+        client
+          .upload_part(id, upload_id, part_number, content, content_type)
+          .await
+      },
+      AnyBucketClient::Fs(client) => {
+        client
+          .upload_part(id, upload_id, part_number, content, content_type)
+          .await
+      },
+    }
+  }
+
+  async fn complete_upload<P>(
+    &self,
+    id: P,
+    upload_id: &str,
+    parts: Vec<CompletedPart>,
+  ) -> Result<(), AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.complete_upload(id, upload_id, parts).await,
+      AnyBucketClient::Fs(client) => client.complete_upload(id, upload_id, parts).await,
+    }
+  }
+
+  async fn abort_upload<P>(&self, id: P, upload_id: &str) -> Result<(), AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.abort_upload(id, upload_id).await,
+      AnyBucketClient::Fs(client) => client.abort_upload(id, upload_id).await,
+    }
+  }
+
+  async fn list_prefixes<P>(&self, prefix: P) -> Result<Vec<String>, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.list_prefixes(prefix).await,
+      AnyBucketClient::Fs(client) => client.list_prefixes(prefix).await,
+    }
+  }
+
+  async fn list_blobs<P>(&self, prefix: P) -> Result<Vec<ObjectMeta>, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.list_blobs(prefix).await,
+      AnyBucketClient::Fs(client) => client.list_blobs(prefix).await,
+    }
+  }
+
+  async fn move_blob<F, T>(&self, from: F, to: T) -> Result<(), AppError>
+  where
+    F: AsRef<str> + Send,
+    T: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.move_blob(from, to).await,
+      AnyBucketClient::Fs(client) => client.move_blob(from, to).await,
+    }
+  }
+
+  async fn head_blob<P>(&self, id: P) -> Result<ObjectMeta, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.head_blob(id).await,
+      AnyBucketClient::Fs(client) => client.head_blob(id).await,
+    }
+  }
+
+  async fn presign_get<P>(
+    &self,
+    id: P,
+    expires_secs: u32,
+    content_type: &str,
+  ) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.presign_get(id, expires_secs, content_type).await,
+      AnyBucketClient::Fs(client) => client.presign_get(id, expires_secs, content_type).await,
+    }
+  }
+
+  async fn presign_put<P>(&self, id: P, expires_secs: u32) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match self {
+      AnyBucketClient::S3(client) => client.presign_put(id, expires_secs).await,
+      AnyBucketClient::Fs(client) => client.presign_put(id, expires_secs).await,
+    }
+  }
+}
+
+pub enum AnyResponseData {
+  S3(S3ResponseData),
+  Fs(FsResponseData),
+}
+
+impl ResponseBlob for AnyResponseData {
+  fn to_blob(self) -> Vec<u8> {
+    match self {
+      AnyResponseData::S3(data) => data.to_blob(),
+      AnyResponseData::Fs(data) => data.to_blob(),
+    }
+  }
+}
@@ -0,0 +1,477 @@
+use crate::file::utils::content_hash;
+use crate::file::{BucketClient, BucketStorage, CompletedPart, ObjectMeta, ResponseBlob};
+use app_error::AppError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::trace;
+
+/// Directory under the root that holds the parts of the pending multipart uploads.
+const UPLOAD_DIR: &str = ".uploads";
+
+pub type FsBucketStorage = BucketStorage<BucketClientFsImpl>;
+
+impl FsBucketStorage {
+  pub fn from_root_dir<P: Into<PathBuf>>(root: P, pg_pool: sqlx::PgPool) -> Self {
+    Self::new(BucketClientFsImpl::new(root), pg_pool)
+  }
+}
+
+/// Stores blobs as plain files under `root`. An object key such as `{workspace_id}/{file_id}`
+/// maps to the file `{root}/{workspace_id}/{file_id}`.
+pub struct BucketClientFsImpl {
+  root: PathBuf,
+}
+
+impl BucketClientFsImpl {
+  pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+    Self { root: root.into() }
+  }
+
+  /// Resolve the object key to a path under the root directory. Keys that try to escape the
+  /// root directory, e.g. `../file` or `/etc/passwd`, are rejected.
+  fn object_path(&self, id: &str) -> Result<PathBuf, AppError> {
+    let key = Path::new(id);
+    let is_valid = key.components().count() > 0
+      && key
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !is_valid {
+      return Err(AppError::InvalidRequest(format!(
+        "invalid object key: {}",
+        id
+      )));
+    }
+    Ok(self.root.join(key))
+  }
+
+  fn upload_dir(&self, upload_id: &str) -> Result<PathBuf, AppError> {
+    self.object_path(&format!("{}/{}", UPLOAD_DIR, upload_id))
+  }
+
+  /// Resolve the key prefix to a directory under the root. Only prefixes that end at a `/`
+  /// boundary are supported. The empty prefix is the root directory itself.
+  fn prefix_dir(&self, prefix: &str) -> Result<PathBuf, AppError> {
+    let prefix = prefix.trim_end_matches('/');
+    if prefix.is_empty() {
+      Ok(self.root.clone())
+    } else {
+      self.object_path(prefix)
+    }
+  }
+
+  /// The inverse of [Self::object_path].
+  fn object_key(&self, path: &Path) -> String {
+    path
+      .strip_prefix(&self.root)
+      .unwrap_or(path)
+      .components()
+      .map(|component| component.as_os_str().to_string_lossy())
+      .collect::<Vec<_>>()
+      .join("/")
+  }
+
+  /// The parts of the pending uploads are not objects, so they are excluded from the listing.
+  fn is_upload_dir(&self, path: &Path) -> bool {
+    path == self.root.join(UPLOAD_DIR)
+  }
+}
+
+/// Write to a temporary file first and then rename it, so readers never observe a partially
+/// written file.
+async fn write_file_atomically(path: &Path, content: &[u8]) -> Result<(), AppError> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).await?;
+  }
+  let tmp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+  fs::write(&tmp_path, content).await?;
+  if let Err(err) = fs::rename(&tmp_path, path).await {
+    let _ = fs::remove_file(&tmp_path).await;
+    return Err(err.into());
+  }
+  Ok(())
+}
+
+#[async_trait]
+impl BucketClient for BucketClientFsImpl {
+  type ResponseData = FsResponseData;
+
+  async fn pub_blob<P>(&self, id: P, content: &[u8]) -> Result<(), AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let path = self.object_path(id.as_ref())?;
+    write_file_atomically(&path, content).await?;
+    trace!("write blob to {:?}", path);
+    Ok(())
+  }
+
+  async fn delete_blob<P>(&self, id: P) -> Result<Self::ResponseData, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let path = self.object_path(id.as_ref())?;
+    match fs::remove_file(&path).await {
+      Ok(_) => Ok(FsResponseData(vec![])),
+      // Same as S3, deleting a non-existent object is not an error.
+      Err(err) if err.kind() == ErrorKind::NotFound => Ok(FsResponseData(vec![])),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  async fn get_blob<P>(&self, id: P) -> Result<Self::ResponseData, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let path = self.object_path(id.as_ref())?;
+    match fs::read(&path).await {
+      Ok(data) => Ok(FsResponseData(data)),
+      Err(err) if err.kind() == ErrorKind::NotFound => {
+        Err(AppError::RecordNotFound(id.as_ref().to_string()))
+      },
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  async fn get_blob_range<P>(
+    &self,
+    id: P,
+    start: u64,
+    end: u64,
+  ) -> Result<Self::ResponseData, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let path = self.object_path(id.as_ref())?;
+    let mut file = match fs::File::open(&path).await {
+      Ok(file) => file,
+      Err(err) if err.kind() == ErrorKind::NotFound => {
+        return Err(AppError::RecordNotFound(id.as_ref().to_string()))
+      },
+      Err(err) => return Err(err.into()),
+    };
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut data = vec![];
+    file.take(end - start + 1).read_to_end(&mut data).await?;
+    Ok(FsResponseData(data))
+  }
+
+  async fn create_upload<P>(&self, _id: P, _content_type: &str) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let upload_id = uuid::Uuid::new_v4().to_string();
+    fs::create_dir_all(self.upload_dir(&upload_id)?).await?;
+    Ok(upload_id)
+  }
+
+  async fn upload_part<P>(
+    &self,
+    _id: P,
+    upload_id: &str,
+    part_number: i32,
+    content: Vec<u8>,
+    _content_type: &str,
+  ) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let upload_dir = self.upload_dir(upload_id)?;
+    if !fs::try_exists(&upload_dir).await? {
+      return Err(AppError::RecordNotFound(format!(
+        "upload:{} not found",
+        upload_id
+      )));
+    }
+    let e_tag = content_hash(&content);
+    write_file_atomically(&upload_dir.join(part_number.to_string()), &content).await?;
+    Ok(e_tag)
+  }
+
+  async fn complete_upload<P>(
+    &self,
+    id: P,
+    upload_id: &str,
+    parts: Vec<CompletedPart>,
+  ) -> Result<(), AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let upload_dir = self.upload_dir(upload_id)?;
+    let path = self.object_path(id.as_ref())?;
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).await?;
+    }
+
+    let tmp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    let result: Result<(), AppError> = async {
+      let mut file = fs::File::create(&tmp_path).await?;
+      for part in parts {
+        let content = fs::read(upload_dir.join(part.part_number.to_string())).await?;
+        if content_hash(&content) != part.e_tag {
+          return Err(AppError::InvalidRequest(format!(
+            "part:{} of upload:{} doesn't match its e_tag",
+            part.part_number, upload_id
+          )));
+        }
+        file.write_all(&content).await?;
+      }
+      file.flush().await?;
+      fs::rename(&tmp_path, &path).await?;
+      Ok(())
+    }
+    .await;
+
+    if result.is_err() {
+      let _ = fs::remove_file(&tmp_path).await;
+      return result;
+    }
+    let _ = fs::remove_dir_all(&upload_dir).await;
+    Ok(())
+  }
+
+  async fn abort_upload<P>(&self, _id: P, upload_id: &str) -> Result<(), AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    match fs::remove_dir_all(self.upload_dir(upload_id)?).await {
+      Ok(_) => Ok(()),
+      Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  async fn list_prefixes<P>(&self, prefix: P) -> Result<Vec<String>, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let mut entries = match fs::read_dir(self.prefix_dir(prefix.as_ref())?).await {
+      Ok(entries) => entries,
+      Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+      Err(err) => return Err(err.into()),
+    };
+    let mut prefixes = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+      let path = entry.path();
+      if entry.file_type().await?.is_dir() && !self.is_upload_dir(&path) {
+        prefixes.push(self.object_key(&path));
+      }
+    }
+    Ok(prefixes)
+  }
+
+  async fn list_blobs<P>(&self, prefix: P) -> Result<Vec<ObjectMeta>, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let mut objects = vec![];
+    let mut dirs = vec![self.prefix_dir(prefix.as_ref())?];
+    while let Some(dir) = dirs.pop() {
+      let mut entries = match fs::read_dir(&dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => continue,
+        Err(err) => return Err(err.into()),
+      };
+      while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let metadata = entry.metadata().await?;
+        if metadata.is_dir() {
+          if !self.is_upload_dir(&path) {
+            dirs.push(path);
+          }
+          continue;
+        }
+        objects.push(ObjectMeta {
+          key: self.object_key(&path),
+          size: metadata.len(),
+          last_modified: metadata
+            .modified()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now()),
+        });
+      }
+    }
+    Ok(objects)
+  }
+
+  async fn move_blob<F, T>(&self, from: F, to: T) -> Result<(), AppError>
+  where
+    F: AsRef<str> + Send,
+    T: AsRef<str> + Send,
+  {
+    let from_path = self.object_path(from.as_ref())?;
+    let to_path = self.object_path(to.as_ref())?;
+    if let Some(parent) = to_path.parent() {
+      fs::create_dir_all(parent).await?;
+    }
+    match fs::rename(&from_path, &to_path).await {
+      Ok(_) => Ok(()),
+      Err(err) if err.kind() == ErrorKind::NotFound => {
+        Err(AppError::RecordNotFound(from.as_ref().to_string()))
+      },
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  async fn head_blob<P>(&self, id: P) -> Result<ObjectMeta, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let path = self.object_path(id.as_ref())?;
+    match fs::metadata(&path).await {
+      Ok(metadata) if metadata.is_file() => Ok(ObjectMeta {
+        key: id.as_ref().to_string(),
+        size: metadata.len(),
+        last_modified: metadata
+          .modified()
+          .map(DateTime::<Utc>::from)
+          .unwrap_or_else(|_| Utc::now()),
+      }),
+      Ok(_) => Err(AppError::RecordNotFound(id.as_ref().to_string())),
+      Err(err) if err.kind() == ErrorKind::NotFound => {
+        Err(AppError::RecordNotFound(id.as_ref().to_string()))
+      },
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  async fn presign_get<P>(
+    &self,
+    _id: P,
+    _expires_secs: u32,
+    _content_type: &str,
+  ) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    Err(AppError::Internal(anyhow::anyhow!(
+      "presigned url is not supported by the file system storage"
+    )))
+  }
+
+  async fn presign_put<P>(&self, _id: P, _expires_secs: u32) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    Err(AppError::Internal(anyhow::anyhow!(
+      "presigned url is not supported by the file system storage"
+    )))
+  }
+}
+
+pub struct FsResponseData(Vec<u8>);
+impl ResponseBlob for FsResponseData {
+  fn to_blob(self) -> Vec<u8> {
+    self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn test_client() -> BucketClientFsImpl {
+    let root = std::env::temp_dir().join(format!("af_blob_{}", uuid::Uuid::new_v4()));
+    BucketClientFsImpl::new(root)
+  }
+
+  #[tokio::test]
+  async fn put_get_and_delete_blob() {
+    let client = test_client();
+    let key = format!("{}/{}", uuid::Uuid::new_v4(), "file_1");
+    client.pub_blob(&key, b"hello world").await.unwrap();
+    let blob = client.get_blob(&key).await.unwrap().to_blob();
+    assert_eq!(blob, b"hello world");
+    let blob = client.get_blob_range(&key, 6, 10).await.unwrap().to_blob();
+    assert_eq!(blob, b"world");
+
+    client.delete_blob(&key).await.unwrap();
+    let err = client.get_blob(&key).await.err().unwrap();
+    assert!(err.is_record_not_found());
+
+    // delete again should be ok
+    client.delete_blob(&key).await.unwrap();
+    let _ = std::fs::remove_dir_all(&client.root);
+  }
+
+  #[tokio::test]
+  async fn multipart_upload() {
+    let client = test_client();
+    let key = format!("{}/{}", uuid::Uuid::new_v4(), "file_1");
+    let upload_id = client.create_upload(&key, "text/plain").await.unwrap();
+    let mut parts = vec![];
+    for (part_number, content) in [(1, "hello "), (2, "world")] {
+      let e_tag = client
+        .upload_part(
+          &key,
+          &upload_id,
+          part_number,
+          content.as_bytes().to_vec(),
+          "text/plain",
+        )
+        .await
+        .unwrap();
+      parts.push(CompletedPart { part_number, e_tag });
+    }
+    client
+      .complete_upload(&key, &upload_id, parts)
+      .await
+      .unwrap();
+
+    let blob = client.get_blob(&key).await.unwrap().to_blob();
+    assert_eq!(blob, b"hello world");
+    assert!(!client.upload_dir(&upload_id).unwrap().exists());
+    let _ = std::fs::remove_dir_all(&client.root);
+  }
+
+  #[tokio::test]
+  async fn list_prefixes_and_blobs() {
+    let client = test_client();
+    let workspace_id = uuid::Uuid::new_v4().to_string();
+    client
+      .pub_blob(format!("{}/file_1", workspace_id), b"hello")
+      .await
+      .unwrap();
+    client
+      .pub_blob(format!("{}/dir/file_2", workspace_id), b"world!")
+      .await
+      .unwrap();
+    client.create_upload("upload", "text/plain").await.unwrap();
+
+    let prefixes = client.list_prefixes("").await.unwrap();
+    assert_eq!(prefixes, vec![workspace_id.clone()]);
+
+    let mut objects = client
+      .list_blobs(format!("{}/", workspace_id))
+      .await
+      .unwrap();
+    objects.sort_by(|a, b| a.key.cmp(&b.key));
+    let keys = objects
+      .into_iter()
+      .map(|object| (object.key, object.size))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      keys,
+      vec![
+        (format!("{}/dir/file_2", workspace_id), 6),
+        (format!("{}/file_1", workspace_id), 5),
+      ]
+    );
+
+    // the parts of the pending uploads are not objects
+    assert_eq!(client.list_blobs("").await.unwrap().len(), 2);
+    assert!(client.list_blobs("not_exist/").await.unwrap().is_empty());
+    let _ = std::fs::remove_dir_all(&client.root);
+  }
+
+  #[tokio::test]
+  async fn reject_key_outside_root() {
+    let client = test_client();
+    assert!(client.pub_blob("../escape", b"data").await.is_err());
+    assert!(client.get_blob("/etc/passwd").await.is_err());
+    assert!(client.get_blob("").await.is_err());
+  }
+}
@@ -1,6 +1,8 @@
-use crate::file::{BucketClient, BucketStorage, ResponseBlob};
+use crate::file::{BucketClient, BucketStorage, CompletedPart, ObjectMeta, ResponseBlob};
 use app_error::AppError;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
 
 pub type S3BucketStorage = BucketStorage<BucketClientS3Impl>;
 
@@ -12,6 +14,12 @@ impl S3BucketStorage {
 
 pub struct BucketClientS3Impl(s3::Bucket);
 
+impl BucketClientS3Impl {
+  pub fn new(bucket: s3::Bucket) -> Self {
+    Self(bucket)
+  }
+}
+
 #[async_trait]
 impl BucketClient for BucketClientS3Impl {
   type ResponseData = S3ResponseData;
@@ -41,6 +49,181 @@ impl BucketClient for BucketClientS3Impl {
     let response = self.0.get_object(id).await?;
     Ok(S3ResponseData(response))
   }
+
+  async fn get_blob_range<P>(
+    &self,
+    id: P,
+    start: u64,
+    end: u64,
+  ) -> Result<Self::ResponseData, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let response = self.0.get_object_range(id, start, Some(end)).await?;
+    check_s3_response_data(&response)?;
+    Ok(S3ResponseData(response))
+  }
+
+  async fn create_upload<P>(&self, id: P, content_type: &str) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let response = self
+      .0
+      .initiate_multipart_upload(id.as_ref(), content_type)
+      .await?;
+    Ok(response.upload_id)
+  }
+
+  async fn upload_part<P>(
+    &self,
+    id: P,
+    upload_id: &str,
+    part_number: i32,
+    content: Vec<u8>,
+    content_type: &str,
+  ) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let part = self
+      .0
+      .put_multipart_chunk(
+        content,
+        id.as_ref(),
+        part_number as u32,
+        upload_id,
+        content_type,
+      )
+      .await?;
+    Ok(part.etag)
+  }
+
+  async fn complete_upload<P>(
+    &self,
+    id: P,
+    upload_id: &str,
+    parts: Vec<CompletedPart>,
+  ) -> Result<(), AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let parts = parts
+      .into_iter()
+      .map(|part| s3::serde_types::Part {
+        part_number: part.part_number as u32,
+        etag: part.e_tag,
+      })
+      .collect();
+    let response = self
+      .0
+      .complete_multipart_upload(id.as_ref(), upload_id, parts)
+      .await?;
+    check_s3_response_data(&response)?;
+    Ok(())
+  }
+
+  async fn abort_upload<P>(&self, id: P, upload_id: &str) -> Result<(), AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    self.0.abort_upload(id.as_ref(), upload_id).await?;
+    Ok(())
+  }
+
+  async fn list_prefixes<P>(&self, prefix: P) -> Result<Vec<String>, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let results = self
+      .0
+      .list(prefix.as_ref().to_string(), Some("/".to_string()))
+      .await?;
+    let prefixes = results
+      .into_iter()
+      .flat_map(|result| result.common_prefixes.unwrap_or_default())
+      .map(|common_prefix| common_prefix.prefix.trim_end_matches('/').to_string())
+      .collect();
+    Ok(prefixes)
+  }
+
+  async fn list_blobs<P>(&self, prefix: P) -> Result<Vec<ObjectMeta>, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let results = self.0.list(prefix.as_ref().to_string(), None).await?;
+    let objects = results
+      .into_iter()
+      .flat_map(|result| result.contents)
+      .map(|object| ObjectMeta {
+        // Treat an unparsable timestamp as just modified, so the object is never considered stale.
+        last_modified: DateTime::parse_from_rfc3339(&object.last_modified)
+          .map(|time| time.with_timezone(&Utc))
+          .unwrap_or_else(|_| Utc::now()),
+        key: object.key,
+        size: object.size,
+      })
+      .collect();
+    Ok(objects)
+  }
+
+  async fn move_blob<F, T>(&self, from: F, to: T) -> Result<(), AppError>
+  where
+    F: AsRef<str> + Send,
+    T: AsRef<str> + Send,
+  {
+    // S3 doesn't support renaming, so the object is copied and the original is deleted
+    let code = self.0.copy_object_internal(from.as_ref(), to).await?;
+    check_s3_status_code(code)?;
+    let response = self.0.delete_object(from).await?;
+    check_s3_response_data(&response)?;
+    Ok(())
+  }
+
+  async fn head_blob<P>(&self, id: P) -> Result<ObjectMeta, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let (head, code) = self.0.head_object(id.as_ref()).await?;
+    if code == 404 {
+      return Err(AppError::RecordNotFound(id.as_ref().to_string()));
+    }
+    check_s3_status_code(code)?;
+    Ok(ObjectMeta {
+      key: id.as_ref().to_string(),
+      size: head.content_length.unwrap_or(0) as u64,
+      last_modified: head
+        .last_modified
+        .and_then(|time| DateTime::parse_from_rfc2822(&time).ok())
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now),
+    })
+  }
+
+  async fn presign_get<P>(
+    &self,
+    id: P,
+    expires_secs: u32,
+    content_type: &str,
+  ) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let queries = HashMap::from([(
+      "response-content-type".to_string(),
+      content_type.to_string(),
+    )]);
+    let url = self.0.presign_get(id, expires_secs, Some(queries))?;
+    Ok(url)
+  }
+
+  async fn presign_put<P>(&self, id: P, expires_secs: u32) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send,
+  {
+    let url = self.0.presign_put(id, expires_secs, None)?;
+    Ok(url)
+  }
 }
 
 pub struct S3ResponseData(s3::request::ResponseData);
@@ -1,17 +1,36 @@
-use crate::pg_row::AFBlobMetadataRow;
+use crate::file::image_variant::{
+  generate_image_variants, image_variant_content_type, original_object_key,
+  should_generate_image_variants, ImageVariant,
+};
+use crate::file::utils::content_hash;
+use crate::pg_row::{AFBlobMetadataRow, AFBlobUploadPartRow, AFBlobUploadRow};
 use crate::resource_usage::{
-  delete_blob_metadata, get_blob_metadata, get_workspace_usage_size, insert_blob_metadata,
-  is_blob_metadata_exists,
+  delete_blob_metadata, delete_blob_upload, get_all_workspace_object_keys, get_blob_metadata,
+  get_blob_object_key, get_blob_upload, get_blob_upload_by_file_id, get_blob_upload_parts,
+  get_workspace_pending_upload_size, get_workspace_quota, get_workspace_usage_size,
+  insert_blob_metadata, insert_blob_upload, insert_or_ref_blob_object, is_blob_metadata_exists,
+  is_blob_object_exists, is_object_key_referenced, lock_workspace_storage, release_blob_object,
+  update_blob_dimensions, upsert_blob_upload_part,
 };
 use app_error::AppError;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use sqlx::PgPool;
+use std::ops::DerefMut;
 use tracing::{event, instrument, warn};
 use uuid::Uuid;
 
-/// Maximum size of a blob in bytes.
-pub const MAX_BLOB_SIZE: usize = 6 * 1024 * 1024;
-pub const MAX_USAGE: u64 = 10 * 1024 * 1024 * 1024;
+/// Maximum size of a single part of a multipart upload in bytes.
+pub const MAX_UPLOAD_PART_SIZE: usize = 32 * 1024 * 1024;
+/// Minimum size of each part of a multipart upload except the last one. This is required by S3.
+pub const MIN_UPLOAD_PART_SIZE: usize = 5 * 1024 * 1024;
+/// Maximum number of parts of a multipart upload. This is the limit of S3.
+pub const MAX_UPLOAD_PART_NUMBER: i32 = 10000;
+/// Prefix of the orphan objects that are quarantined instead of deleted.
+pub const QUARANTINE_PREFIX: &str = ".orphans";
+/// Prefix of the workspace export archives, which are kept out of the objects of the workspace.
+pub const EXPORT_PREFIX: &str = ".exports";
+const EXPORT_CONTENT_TYPE: &str = "application/octet-stream";
 
 pub trait ResponseBlob {
   fn to_blob(self) -> Vec<u8>;
@@ -32,6 +51,124 @@ pub trait BucketClient {
   async fn get_blob<P>(&self, id: P) -> Result<Self::ResponseData, AppError>
   where
     P: AsRef<str> + Send;
+
+  /// Return the bytes of the object from `start` to `end`, both inclusive.
+  async fn get_blob_range<P>(
+    &self,
+    id: P,
+    start: u64,
+    end: u64,
+  ) -> Result<Self::ResponseData, AppError>
+  where
+    P: AsRef<str> + Send;
+
+  /// Start a multipart upload for the object and return the upload id.
+  async fn create_upload<P>(&self, id: P, content_type: &str) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send;
+
+  /// Upload a part of the multipart upload and return the e_tag of the part.
+  async fn upload_part<P>(
+    &self,
+    id: P,
+    upload_id: &str,
+    part_number: i32,
+    content: Vec<u8>,
+    content_type: &str,
+  ) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send;
+
+  /// Assemble the uploaded parts into the object. The parts must be ordered by the part number.
+  async fn complete_upload<P>(
+    &self,
+    id: P,
+    upload_id: &str,
+    parts: Vec<CompletedPart>,
+  ) -> Result<(), AppError>
+  where
+    P: AsRef<str> + Send;
+
+  async fn abort_upload<P>(&self, id: P, upload_id: &str) -> Result<(), AppError>
+  where
+    P: AsRef<str> + Send;
+
+  /// Return the prefixes directly under `prefix`, without the trailing `/`. For example, the
+  /// workspace ids are returned for the empty prefix.
+  async fn list_prefixes<P>(&self, prefix: P) -> Result<Vec<String>, AppError>
+  where
+    P: AsRef<str> + Send;
+
+  /// Return all the objects whose key starts with `prefix`.
+  async fn list_blobs<P>(&self, prefix: P) -> Result<Vec<ObjectMeta>, AppError>
+  where
+    P: AsRef<str> + Send;
+
+  /// Move the object to another key. The content is copied inside the bucket, without being
+  /// downloaded.
+  async fn move_blob<F, T>(&self, from: F, to: T) -> Result<(), AppError>
+  where
+    F: AsRef<str> + Send,
+    T: AsRef<str> + Send;
+
+  /// Return the metadata of the object, or [AppError::RecordNotFound] if it doesn't exist.
+  async fn head_blob<P>(&self, id: P) -> Result<ObjectMeta, AppError>
+  where
+    P: AsRef<str> + Send;
+
+  /// Return a url that allows downloading the object without credentials until it expires. The
+  /// object is served with the given content type.
+  async fn presign_get<P>(
+    &self,
+    id: P,
+    expires_secs: u32,
+    content_type: &str,
+  ) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send;
+
+  /// Return a url that allows uploading the object without credentials until it expires.
+  async fn presign_put<P>(&self, id: P, expires_secs: u32) -> Result<String, AppError>
+  where
+    P: AsRef<str> + Send;
+}
+
+pub struct CompletedPart {
+  pub part_number: i32,
+  pub e_tag: String,
+}
+
+/// The result of scanning the objects of a workspace for orphans.
+#[derive(Debug)]
+pub struct OrphanScan {
+  pub scanned: usize,
+  pub orphans: Vec<ObjectMeta>,
+}
+
+/// A url to upload a blob directly to the bucket.
+#[derive(Debug)]
+pub struct PresignedPut {
+  pub upload_id: String,
+  pub url: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectMeta {
+  pub key: String,
+  pub size: u64,
+  pub last_modified: DateTime<Utc>,
+}
+
+/// The key of the object that holds the content with the given hash.
+#[inline]
+fn content_object_key(workspace_id: &Uuid, content_hash: &str) -> String {
+  format!("{}/sha256-{}", workspace_id, content_hash)
+}
+
+/// The key of the object that holds the archive of the workspace export job.
+#[inline]
+fn export_object_key(workspace_id: &Uuid, job_id: &Uuid) -> String {
+  format!("{}/{}/{}", EXPORT_PREFIX, workspace_id, job_id)
 }
 
 pub struct BucketStorage<C> {
@@ -47,6 +184,8 @@ where
     Self { client, pg_pool }
   }
 
+  /// Store the blob. If `object_id` is given, the blob is owned by the collab object, so the
+  /// members of the collab object can read it.
   #[instrument(skip_all, err)]
   #[inline]
   pub async fn put_blob(
@@ -55,6 +194,7 @@ where
     file_id: String,
     file_data: Vec<u8>,
     file_type: String,
+    object_id: Option<String>,
   ) -> Result<(), AppError> {
     if is_blob_metadata_exists(&self.pg_pool, &workspace_id, &file_id).await? {
       warn!(
@@ -64,46 +204,200 @@ where
       return Ok(());
     }
 
-    // query the storage space of the workspace
-    let obj_key = format!("{}/{}", workspace_id, file_id);
-    let usage = get_workspace_usage_size(&self.pg_pool, &workspace_id).await?;
-    event!(
-      tracing::Level::TRACE,
-      "workspace consumed space: {}, new file:{} with size: {}",
-      obj_key,
-      file_id,
-      file_data.len(),
-    );
-    if usage > MAX_USAGE {
-      return Err(AppError::StorageSpaceNotEnough);
+    let quota = get_workspace_quota(&self.pg_pool, &workspace_id).await?;
+    if file_data.len() as i64 > quota.blob_size_limit {
+      return Err(AppError::PayloadTooLarge(format!(
+        "The uploading file is too large, the limit is {} bytes",
+        quota.blob_size_limit
+      )));
     }
 
-    let obj_key = format!("{}/{}", workspace_id, file_id);
+    // Blobs with the same content share one object, so only new content consumes storage space
+    let content_hash = content_hash(&file_data);
+    if !is_blob_object_exists(&self.pg_pool, &workspace_id, &content_hash).await? {
+      let usage = get_workspace_usage_size(&self.pg_pool, &workspace_id).await?;
+      event!(
+        tracing::Level::TRACE,
+        "workspace consumed space: {}, new file:{} with size: {}",
+        usage,
+        file_id,
+        file_data.len(),
+      );
+      if usage + file_data.len() as u64 > quota.storage_limit as u64 {
+        return Err(AppError::StorageSpaceNotEnough);
+      }
+    }
 
+    let obj_key = content_object_key(&workspace_id, &content_hash);
     let mut tx = self.pg_pool.begin().await?;
-    insert_blob_metadata(
+    lock_workspace_storage(&mut tx, &workspace_id).await?;
+    let ref_count = insert_or_ref_blob_object(
+      &mut tx,
+      &workspace_id,
+      &content_hash,
+      &obj_key,
+      file_data.len(),
+    )
+    .await?;
+    // Checked again with the new object, as other blobs may have been stored since the check above
+    if ref_count == 1
+      && get_workspace_usage_size(tx.deref_mut(), &workspace_id).await? > quota.storage_limit as u64
+    {
+      return Err(AppError::StorageSpaceNotEnough);
+    }
+    let replaced_obj_key = insert_blob_metadata(
       &mut tx,
       &file_id,
       &workspace_id,
       &file_type,
       file_data.len(),
+      &content_hash,
+      object_id.as_deref(),
     )
     .await?;
 
-    self.client.pub_blob(obj_key, &file_data).await?;
+    if ref_count == 1 {
+      self.client.pub_blob(&obj_key, &file_data).await?;
+    } else {
+      event!(
+        tracing::Level::TRACE,
+        "reuse existing object for file:{}, reference count: {}",
+        file_id,
+        ref_count
+      );
+    }
     tx.commit().await?;
+    if let Some(obj_key) = replaced_obj_key {
+      self.delete_unreferenced_object(&obj_key).await;
+    }
+
+    if ref_count == 1 && should_generate_image_variants(&file_type, file_data.len()) {
+      self
+        .put_image_variants(
+          &workspace_id,
+          &content_hash,
+          &obj_key,
+          &file_type,
+          file_data,
+        )
+        .await;
+    }
     Ok(())
   }
 
+  /// Generate the downscaled variants of the image and record its dimensions. Failures are only
+  /// logged, because a file that can't be decoded as an image is still a valid blob.
+  async fn put_image_variants(
+    &self,
+    workspace_id: &Uuid,
+    content_hash: &str,
+    obj_key: &str,
+    file_type: &str,
+    content: Vec<u8>,
+  ) {
+    if let Err(err) = self
+      .try_put_image_variants(workspace_id, content_hash, obj_key, file_type, content)
+      .await
+    {
+      warn!(
+        "failed to generate image variants of object:{}, error: {}",
+        obj_key, err
+      );
+    }
+  }
+
+  async fn try_put_image_variants(
+    &self,
+    workspace_id: &Uuid,
+    content_hash: &str,
+    obj_key: &str,
+    file_type: &str,
+    content: Vec<u8>,
+  ) -> Result<(), AppError> {
+    let file_type = file_type.to_string();
+    let image =
+      tokio::task::spawn_blocking(move || generate_image_variants(&content, &file_type)).await??;
+    for (variant, data) in image.variants {
+      self
+        .client
+        .pub_blob(variant.object_key(obj_key), &data)
+        .await?;
+    }
+    update_blob_dimensions(
+      &self.pg_pool,
+      workspace_id,
+      content_hash,
+      image.width,
+      image.height,
+    )
+    .await
+  }
+
+  /// Same as [Self::put_image_variants], but the content of the image is read from the bucket.
+  async fn put_image_variants_of_object(
+    &self,
+    workspace_id: &Uuid,
+    content_hash: &str,
+    obj_key: &str,
+    file_type: &str,
+    file_size: usize,
+  ) {
+    if !should_generate_image_variants(file_type, file_size) {
+      return;
+    }
+    match self.client.get_blob(obj_key).await {
+      Ok(content) => {
+        self
+          .put_image_variants(
+            workspace_id,
+            content_hash,
+            obj_key,
+            file_type,
+            content.to_blob(),
+          )
+          .await
+      },
+      Err(err) => warn!(
+        "failed to read object:{} to generate image variants, error: {}",
+        obj_key, err
+      ),
+    }
+  }
+
+  /// Delete the blob. The underlying object is only removed from the bucket when no other blob
+  /// references it, once the metadata is deleted.
   pub async fn delete_blob(&self, workspace_id: &Uuid, file_id: &str) -> Result<(), AppError> {
-    let obj_key = format!("{}/{}", workspace_id, file_id);
     let mut tx = self.pg_pool.begin().await?;
-    delete_blob_metadata(&mut tx, workspace_id, file_id).await?;
-    self.client.delete_blob(obj_key).await?;
+    let metadata = delete_blob_metadata(&mut tx, workspace_id, file_id).await?;
+    let released_obj_key =
+      release_blob_object(&mut tx, workspace_id, &metadata.content_hash).await?;
     tx.commit().await?;
+    if let Some(obj_key) = released_obj_key {
+      self.delete_unreferenced_object(&obj_key).await;
+    }
     Ok(())
   }
 
+  /// Delete the object, and the variants of the image, that is no longer referenced by any blob,
+  /// because its blobs were deleted or replaced by another content. Failures are only logged, the
+  /// garbage collector removes the leftover objects.
+  async fn delete_unreferenced_object(&self, obj_key: &str) {
+    for variant in ImageVariant::ALL {
+      if let Err(err) = self.client.delete_blob(variant.object_key(obj_key)).await {
+        warn!(
+          "failed to delete variant of unreferenced object:{}, error: {}",
+          obj_key, err
+        );
+      }
+    }
+    if let Err(err) = self.client.delete_blob(obj_key).await {
+      warn!(
+        "failed to delete unreferenced object:{}, error: {}",
+        obj_key, err
+      );
+    }
+  }
+
   pub async fn get_blob_metadata(
     &self,
     workspace_id: &Uuid,
@@ -114,8 +408,548 @@ where
   }
 
   pub async fn get_blob(&self, workspace_id: &Uuid, file_id: &str) -> Result<Vec<u8>, AppError> {
-    let obj_key = format!("{}/{}", workspace_id, file_id);
+    let obj_key = get_blob_object_key(&self.pg_pool, workspace_id, file_id).await?;
     let blob = self.client.get_blob(obj_key).await?.to_blob();
     Ok(blob)
   }
+
+  /// Return the bytes of the blob from `start` to `end`, both inclusive.
+  pub async fn get_blob_range(
+    &self,
+    workspace_id: &Uuid,
+    file_id: &str,
+    start: u64,
+    end: u64,
+  ) -> Result<Vec<u8>, AppError> {
+    let obj_key = get_blob_object_key(&self.pg_pool, workspace_id, file_id).await?;
+    let blob = self
+      .client
+      .get_blob_range(obj_key, start, end)
+      .await?
+      .to_blob();
+    Ok(blob)
+  }
+
+  /// Start uploading the archive of the workspace export job, which is uploaded in parts while
+  /// it's written. Return the upload id.
+  pub async fn create_workspace_export_upload(
+    &self,
+    workspace_id: &Uuid,
+    job_id: &Uuid,
+  ) -> Result<String, AppError> {
+    let obj_key = export_object_key(workspace_id, job_id);
+    self
+      .client
+      .create_upload(obj_key, EXPORT_CONTENT_TYPE)
+      .await
+  }
+
+  /// Upload a part of the archive. Every part but the last one must be at least
+  /// [MIN_UPLOAD_PART_SIZE] bytes.
+  pub async fn upload_workspace_export_part(
+    &self,
+    workspace_id: &Uuid,
+    job_id: &Uuid,
+    upload_id: &str,
+    part_number: i32,
+    content: Vec<u8>,
+  ) -> Result<CompletedPart, AppError> {
+    let obj_key = export_object_key(workspace_id, job_id);
+    let e_tag = self
+      .client
+      .upload_part(
+        obj_key,
+        upload_id,
+        part_number,
+        content,
+        EXPORT_CONTENT_TYPE,
+      )
+      .await?;
+    Ok(CompletedPart { part_number, e_tag })
+  }
+
+  pub async fn complete_workspace_export_upload(
+    &self,
+    workspace_id: &Uuid,
+    job_id: &Uuid,
+    upload_id: &str,
+    parts: Vec<CompletedPart>,
+  ) -> Result<(), AppError> {
+    let obj_key = export_object_key(workspace_id, job_id);
+    self.client.complete_upload(obj_key, upload_id, parts).await
+  }
+
+  pub async fn abort_workspace_export_upload(
+    &self,
+    workspace_id: &Uuid,
+    job_id: &Uuid,
+    upload_id: &str,
+  ) -> Result<(), AppError> {
+    let obj_key = export_object_key(workspace_id, job_id);
+    self.client.abort_upload(obj_key, upload_id).await
+  }
+
+  /// Return the bytes of the archive from `start` to `end`, both inclusive.
+  pub async fn get_workspace_export_range(
+    &self,
+    workspace_id: &Uuid,
+    job_id: &Uuid,
+    start: u64,
+    end: u64,
+  ) -> Result<Vec<u8>, AppError> {
+    let obj_key = export_object_key(workspace_id, job_id);
+    let archive = self
+      .client
+      .get_blob_range(obj_key, start, end)
+      .await?
+      .to_blob();
+    Ok(archive)
+  }
+
+  pub async fn delete_workspace_export(
+    &self,
+    workspace_id: &Uuid,
+    job_id: &Uuid,
+  ) -> Result<(), AppError> {
+    let obj_key = export_object_key(workspace_id, job_id);
+    self.client.delete_blob(obj_key).await?;
+    Ok(())
+  }
+
+  /// Return a url to download the blob, or the given variant of the image, directly from the
+  /// bucket. The url expires after `expires_secs`.
+  pub async fn get_blob_presigned_url(
+    &self,
+    workspace_id: &Uuid,
+    metadata: &AFBlobMetadataRow,
+    variant: Option<ImageVariant>,
+    expires_secs: u32,
+  ) -> Result<String, AppError> {
+    let obj_key = get_blob_object_key(&self.pg_pool, workspace_id, &metadata.file_id).await?;
+    match variant {
+      None => {
+        self
+          .client
+          .presign_get(obj_key, expires_secs, &metadata.file_type)
+          .await
+      },
+      Some(variant) => {
+        let content_type = image_variant_content_type(&metadata.file_type);
+        self
+          .client
+          .presign_get(variant.object_key(&obj_key), expires_secs, content_type)
+          .await
+      },
+    }
+  }
+
+  /// Return the content of the given variant of the image.
+  pub async fn get_blob_variant(
+    &self,
+    workspace_id: &Uuid,
+    file_id: &str,
+    variant: ImageVariant,
+  ) -> Result<Vec<u8>, AppError> {
+    let obj_key = get_blob_object_key(&self.pg_pool, workspace_id, file_id).await?;
+    let blob = self
+      .client
+      .get_blob(variant.object_key(&obj_key))
+      .await?
+      .to_blob();
+    Ok(blob)
+  }
+
+  /// Return a url that allows the client to upload the file directly to the bucket. The upload
+  /// must be finished by calling [Self::complete_presigned_put] with the returned upload id.
+  /// Return None if the file already exists.
+  #[instrument(skip_all, err)]
+  pub async fn create_presigned_put(
+    &self,
+    workspace_id: &Uuid,
+    file_id: &str,
+    file_size: u64,
+    expires_secs: u32,
+  ) -> Result<Option<PresignedPut>, AppError> {
+    if is_blob_metadata_exists(&self.pg_pool, workspace_id, file_id).await? {
+      warn!(
+        "file already exists, workspace_id: {}, file_id: {}",
+        workspace_id, file_id
+      );
+      return Ok(None);
+    }
+
+    let quota = get_workspace_quota(&self.pg_pool, workspace_id).await?;
+    if file_size > quota.blob_size_limit as u64 {
+      return Err(AppError::PayloadTooLarge(format!(
+        "The uploading file is too large, the limit is {} bytes",
+        quota.blob_size_limit
+      )));
+    }
+    let usage = get_workspace_usage_size(&self.pg_pool, workspace_id).await?
+      + get_workspace_pending_upload_size(&self.pg_pool, workspace_id).await?;
+    if usage + file_size > quota.storage_limit as u64 {
+      return Err(AppError::StorageSpaceNotEnough);
+    }
+
+    // Same as multipart uploads, the object is stored under a unique key because the content hash
+    // can't be trusted before the upload is completed.
+    let upload_id = Uuid::new_v4().to_string();
+    let obj_key = format!("{}/{}", workspace_id, upload_id);
+    let url = self.client.presign_put(&obj_key, expires_secs).await?;
+    Ok(Some(PresignedPut { upload_id, url }))
+  }
+
+  /// Register the object uploaded with a presigned url as the blob of the file. The size of the
+  /// blob is taken from the bucket. The content never passes through the server, so its hash is
+  /// unknown and the object is not deduplicated with the other blobs.
+  #[instrument(skip_all, err)]
+  pub async fn complete_presigned_put(
+    &self,
+    workspace_id: &Uuid,
+    file_id: &str,
+    file_type: &str,
+    upload_id: &str,
+    object_id: Option<&str>,
+  ) -> Result<(), AppError> {
+    let upload_id = Uuid::parse_str(upload_id)
+      .map_err(|_| AppError::InvalidRequest(format!("invalid upload id: {}", upload_id)))?;
+    if is_blob_metadata_exists(&self.pg_pool, workspace_id, file_id).await? {
+      return Err(AppError::RecordAlreadyExists(format!(
+        "file already exists, workspace_id: {}, file_id: {}",
+        workspace_id, file_id
+      )));
+    }
+
+    let obj_key = format!("{}/{}", workspace_id, upload_id);
+    if is_object_key_referenced(&self.pg_pool, workspace_id, &obj_key).await? {
+      return Err(AppError::RecordAlreadyExists(format!(
+        "upload:{} has already been completed",
+        upload_id
+      )));
+    }
+    let object = match self.client.head_blob(&obj_key).await {
+      Ok(object) => object,
+      Err(err) if err.is_record_not_found() => {
+        return Err(AppError::InvalidRequest(format!(
+          "upload:{} doesn't have any content",
+          upload_id
+        )));
+      },
+      Err(err) => return Err(err),
+    };
+
+    // The presigned url doesn't limit the size of the uploaded content
+    let quota = get_workspace_quota(&self.pg_pool, workspace_id).await?;
+    if object.size > quota.blob_size_limit as u64 {
+      self.client.delete_blob(&obj_key).await?;
+      return Err(AppError::PayloadTooLarge(format!(
+        "The uploaded file is too large, the limit is {} bytes",
+        quota.blob_size_limit
+      )));
+    }
+
+    // The hash identifies the uploaded object instead of its content, so no other blob shares it
+    let content_hash = format!("presigned-{}", upload_id);
+    let file_size = object.size as usize;
+    let mut tx = self.pg_pool.begin().await?;
+    // The declared size was checked when the url was created, but it was not reserved and the
+    // uploaded content may be larger, so the limit is checked again with the uploaded object
+    lock_workspace_storage(&mut tx, workspace_id).await?;
+    let usage = get_workspace_usage_size(tx.deref_mut(), workspace_id).await?
+      + get_workspace_pending_upload_size(tx.deref_mut(), workspace_id).await?;
+    if usage + object.size > quota.storage_limit as u64 {
+      drop(tx);
+      self.client.delete_blob(&obj_key).await?;
+      return Err(AppError::StorageSpaceNotEnough);
+    }
+    insert_or_ref_blob_object(&mut tx, workspace_id, &content_hash, &obj_key, file_size).await?;
+    let replaced_obj_key = insert_blob_metadata(
+      &mut tx,
+      file_id,
+      workspace_id,
+      file_type,
+      file_size,
+      &content_hash,
+      object_id,
+    )
+    .await?;
+    tx.commit().await?;
+    if let Some(obj_key) = replaced_obj_key {
+      self.delete_unreferenced_object(&obj_key).await;
+    }
+
+    self
+      .put_image_variants_of_object(workspace_id, &content_hash, &obj_key, file_type, file_size)
+      .await;
+    Ok(())
+  }
+
+  /// Create a multipart upload for the file. If there is already a pending upload for the file,
+  /// the existing upload is returned so that the client can resume it.
+  #[instrument(skip_all, err)]
+  pub async fn create_upload(
+    &self,
+    workspace_id: Uuid,
+    file_id: String,
+    file_type: String,
+  ) -> Result<AFBlobUploadRow, AppError> {
+    if is_blob_metadata_exists(&self.pg_pool, &workspace_id, &file_id).await? {
+      return Err(AppError::RecordAlreadyExists(format!(
+        "file already exists, workspace_id: {}, file_id: {}",
+        workspace_id, file_id
+      )));
+    }
+
+    if let Some(upload) = get_blob_upload_by_file_id(&self.pg_pool, &workspace_id, &file_id).await?
+    {
+      return Ok(upload);
+    }
+
+    let quota = get_workspace_quota(&self.pg_pool, &workspace_id).await?;
+    let usage = get_workspace_usage_size(&self.pg_pool, &workspace_id).await?;
+    if usage >= quota.storage_limit as u64 {
+      return Err(AppError::StorageSpaceNotEnough);
+    }
+
+    // The assembled object is stored under a unique key, because its content hash is unknown
+    // until the upload is completed.
+    let obj_key = format!("{}/{}", workspace_id, Uuid::new_v4());
+    let upload_id = self.client.create_upload(&obj_key, &file_type).await?;
+    insert_blob_upload(
+      &self.pg_pool,
+      &upload_id,
+      &workspace_id,
+      &file_id,
+      &file_type,
+      &obj_key,
+    )
+    .await?;
+    get_blob_upload(&self.pg_pool, &workspace_id, &upload_id).await
+  }
+
+  #[instrument(skip_all, err)]
+  pub async fn upload_part(
+    &self,
+    workspace_id: &Uuid,
+    upload_id: &str,
+    part_number: i32,
+    content: Vec<u8>,
+  ) -> Result<AFBlobUploadPartRow, AppError> {
+    if !(1..=MAX_UPLOAD_PART_NUMBER).contains(&part_number) {
+      return Err(AppError::InvalidRequest(format!(
+        "part number must be between 1 and {}",
+        MAX_UPLOAD_PART_NUMBER
+      )));
+    }
+
+    let upload = get_blob_upload(&self.pg_pool, workspace_id, upload_id).await?;
+    let quota = get_workspace_quota(&self.pg_pool, workspace_id).await?;
+    // A part that is uploaded again replaces the previous one, so its size is not counted twice
+    let replaced_part_size = get_blob_upload_parts(&self.pg_pool, upload_id)
+      .await?
+      .iter()
+      .find(|part| part.part_number == part_number)
+      .map(|part| part.part_size as u64)
+      .unwrap_or(0);
+    let usage = (get_workspace_usage_size(&self.pg_pool, workspace_id).await?
+      + get_workspace_pending_upload_size(&self.pg_pool, workspace_id).await?)
+      .saturating_sub(replaced_part_size);
+    event!(
+      tracing::Level::TRACE,
+      "workspace consumed space: {}, upload:{} part:{} with size: {}",
+      usage,
+      upload_id,
+      part_number,
+      content.len(),
+    );
+    if usage + content.len() as u64 > quota.storage_limit as u64 {
+      return Err(AppError::StorageSpaceNotEnough);
+    }
+
+    let part_size = content.len();
+    let e_tag = self
+      .client
+      .upload_part(
+        &upload.object_key,
+        upload_id,
+        part_number,
+        content,
+        &upload.file_type,
+      )
+      .await?;
+    upsert_blob_upload_part(&self.pg_pool, upload_id, part_number, &e_tag, part_size).await
+  }
+
+  pub async fn get_upload_parts(
+    &self,
+    workspace_id: &Uuid,
+    upload_id: &str,
+  ) -> Result<Vec<AFBlobUploadPartRow>, AppError> {
+    let upload = get_blob_upload(&self.pg_pool, workspace_id, upload_id).await?;
+    get_blob_upload_parts(&self.pg_pool, &upload.upload_id).await
+  }
+
+  /// Complete the multipart upload. After completion, the file is accessible like any other blob.
+  #[instrument(skip_all, err)]
+  pub async fn complete_upload(
+    &self,
+    workspace_id: &Uuid,
+    upload_id: &str,
+  ) -> Result<(), AppError> {
+    let upload = get_blob_upload(&self.pg_pool, workspace_id, upload_id).await?;
+    let parts = get_blob_upload_parts(&self.pg_pool, upload_id).await?;
+    if parts.is_empty() {
+      return Err(AppError::InvalidRequest(format!(
+        "upload:{} doesn't have any part",
+        upload_id
+      )));
+    }
+    // The parts are ordered by part number, so the last one is the only part that can be smaller
+    if let Some(part) = parts[..parts.len() - 1]
+      .iter()
+      .find(|part| (part.part_size as usize) < MIN_UPLOAD_PART_SIZE)
+    {
+      return Err(AppError::InvalidRequest(format!(
+        "part:{} of upload:{} is smaller than {} bytes, only the last part can be smaller",
+        part.part_number, upload_id, MIN_UPLOAD_PART_SIZE
+      )));
+    }
+
+    let file_size = parts.iter().map(|part| part.part_size as usize).sum();
+    // The content of the parts is not available here, so the hash is derived from the e_tags
+    // of the parts, which is similar to the e_tag S3 generates for multipart uploads.
+    let e_tags = parts
+      .iter()
+      .map(|part| part.e_tag.as_str())
+      .collect::<String>();
+    let content_hash = format!("{}-{}", content_hash(e_tags.as_bytes()), parts.len());
+    let parts = parts
+      .into_iter()
+      .map(|part| CompletedPart {
+        part_number: part.part_number,
+        e_tag: part.e_tag,
+      })
+      .collect::<Vec<_>>();
+
+    let mut tx = self.pg_pool.begin().await?;
+    let ref_count = insert_or_ref_blob_object(
+      &mut tx,
+      workspace_id,
+      &content_hash,
+      &upload.object_key,
+      file_size,
+    )
+    .await?;
+    let replaced_obj_key = insert_blob_metadata(
+      &mut tx,
+      &upload.file_id,
+      workspace_id,
+      &upload.file_type,
+      file_size,
+      &content_hash,
+      None,
+    )
+    .await?;
+    delete_blob_upload(&mut tx, upload_id).await?;
+
+    self
+      .client
+      .complete_upload(&upload.object_key, upload_id, parts)
+      .await?;
+    tx.commit().await?;
+    if let Some(obj_key) = replaced_obj_key {
+      self.delete_unreferenced_object(&obj_key).await;
+    }
+
+    // An object with the same content already exists, so the assembled object is not needed.
+    if ref_count > 1 {
+      if let Err(err) = self.client.delete_blob(&upload.object_key).await {
+        warn!(
+          "failed to delete duplicate object:{}, error: {}",
+          upload.object_key, err
+        );
+      }
+    } else {
+      self
+        .put_image_variants_of_object(
+          workspace_id,
+          &content_hash,
+          &upload.object_key,
+          &upload.file_type,
+          file_size,
+        )
+        .await;
+    }
+    Ok(())
+  }
+
+  #[instrument(skip_all, err)]
+  pub async fn abort_upload(&self, workspace_id: &Uuid, upload_id: &str) -> Result<(), AppError> {
+    let upload = get_blob_upload(&self.pg_pool, workspace_id, upload_id).await?;
+    let mut tx = self.pg_pool.begin().await?;
+    delete_blob_upload(&mut tx, upload_id).await?;
+    self
+      .client
+      .abort_upload(&upload.object_key, upload_id)
+      .await?;
+    tx.commit().await?;
+    Ok(())
+  }
+
+  /// Return the workspaces that have objects in the bucket, including the deleted ones.
+  pub async fn list_bucket_workspaces(&self) -> Result<Vec<Uuid>, AppError> {
+    let prefixes = self.client.list_prefixes("").await?;
+    Ok(
+      prefixes
+        .iter()
+        .filter_map(|prefix| Uuid::parse_str(prefix).ok())
+        .collect(),
+    )
+  }
+
+  /// Return the objects of the workspace that are not referenced by any blob or pending upload.
+  /// The variants of an image are referenced if the image is. Objects modified after
+  /// `modified_before` are skipped, because their metadata might not be committed yet. All the
+  /// objects of a deleted workspace are orphans.
+  #[instrument(skip(self), err)]
+  pub async fn find_orphan_objects(
+    &self,
+    workspace_id: &Uuid,
+    modified_before: DateTime<Utc>,
+  ) -> Result<OrphanScan, AppError> {
+    let objects = self.client.list_blobs(format!("{}/", workspace_id)).await?;
+    let referenced_keys = get_all_workspace_object_keys(&self.pg_pool, workspace_id).await?;
+    let scanned = objects.len();
+    let orphans = objects
+      .into_iter()
+      .filter(|object| {
+        object.last_modified < modified_before
+          && !referenced_keys.contains(original_object_key(&object.key))
+      })
+      .collect();
+    Ok(OrphanScan { scanned, orphans })
+  }
+
+  /// Remove the orphan object from the bucket. If `quarantine` is true, the object is moved under
+  /// [QUARANTINE_PREFIX] instead, so it can be restored manually. Return false if the object has
+  /// been referenced again since it was found.
+  #[instrument(skip(self), err)]
+  pub async fn remove_orphan_object(
+    &self,
+    workspace_id: &Uuid,
+    object: &ObjectMeta,
+    quarantine: bool,
+  ) -> Result<bool, AppError> {
+    let original_key = original_object_key(&object.key);
+    if is_object_key_referenced(&self.pg_pool, workspace_id, original_key).await? {
+      return Ok(false);
+    }
+    if quarantine {
+      let quarantine_key = format!("{}/{}", QUARANTINE_PREFIX, object.key);
+      self.client.move_blob(&object.key, quarantine_key).await?;
+    } else {
+      self.client.delete_blob(&object.key).await?;
+    }
+    Ok(true)
+  }
 }
@@ -0,0 +1,205 @@
+use app_error::AppError;
+use image::{DynamicImage, ImageOutputFormat};
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// Images larger than this are stored as is, without generating any variant.
+pub const MAX_IMAGE_VARIANT_SOURCE_SIZE: usize = 32 * 1024 * 1024;
+/// Images with more pixels than this are not decoded, to avoid exhausting the memory of the server
+/// with a small but highly compressed image.
+const MAX_IMAGE_PIXELS: u64 = 64 * 1024 * 1024;
+/// Quality of the JPEG encoded variants, from 1 to 100.
+const JPEG_QUALITY: u8 = 80;
+
+/// A downscaled version of an image blob. The variants are stored as separate objects next to the
+/// object of the original image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageVariant {
+  Small,
+  Medium,
+  Large,
+}
+
+impl ImageVariant {
+  pub const ALL: [ImageVariant; 3] = [Self::Small, Self::Medium, Self::Large];
+
+  /// The maximum width and height of the variant in pixels.
+  pub fn max_size(&self) -> u32 {
+    match self {
+      ImageVariant::Small => 128,
+      ImageVariant::Medium => 512,
+      ImageVariant::Large => 1024,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ImageVariant::Small => "small",
+      ImageVariant::Medium => "medium",
+      ImageVariant::Large => "large",
+    }
+  }
+
+  /// Return true if the variant is generated for an image of the given dimensions. Images that
+  /// already fit in the variant are served as is.
+  pub fn is_generated_for(&self, width: u32, height: u32) -> bool {
+    width.max(height) > self.max_size()
+  }
+
+  /// The key of the object that holds the variant of the image stored under `object_key`.
+  pub fn object_key(&self, object_key: &str) -> String {
+    format!("{}@{}", object_key, self.as_str())
+  }
+}
+
+impl FromStr for ImageVariant {
+  type Err = AppError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "small" => Ok(Self::Small),
+      "medium" => Ok(Self::Medium),
+      "large" => Ok(Self::Large),
+      other => Err(AppError::InvalidRequest(format!(
+        "{} is not a supported image variant. Use one of small, medium or large",
+        other
+      ))),
+    }
+  }
+}
+
+/// Return the key of the original image if `key` is the key of an image variant, otherwise return
+/// `key` itself.
+pub fn original_object_key(key: &str) -> &str {
+  match key.rsplit_once('@') {
+    Some((original, variant)) if ImageVariant::from_str(variant).is_ok() => original,
+    _ => key,
+  }
+}
+
+fn mime_essence(file_type: &str) -> String {
+  file_type
+    .split(';')
+    .next()
+    .unwrap_or_default()
+    .trim()
+    .to_lowercase()
+}
+
+/// Return true if the variants should be generated for a blob of the given type and size.
+pub fn should_generate_image_variants(file_type: &str, file_size: usize) -> bool {
+  let is_supported = matches!(
+    mime_essence(file_type).as_str(),
+    "image/png" | "image/jpeg" | "image/gif" | "image/webp" | "image/bmp"
+  );
+  is_supported && file_size <= MAX_IMAGE_VARIANT_SOURCE_SIZE
+}
+
+/// The content type of the variants of an image. JPEG images keep their format and all the other
+/// formats are converted to PNG, which preserves the transparency.
+pub fn image_variant_content_type(file_type: &str) -> &'static str {
+  if mime_essence(file_type) == "image/jpeg" {
+    "image/jpeg"
+  } else {
+    "image/png"
+  }
+}
+
+pub struct ImageVariants {
+  pub width: u32,
+  pub height: u32,
+  pub variants: Vec<(ImageVariant, Vec<u8>)>,
+}
+
+/// Decode the image and encode the variants that are smaller than it. This is CPU bound, so it
+/// should be called from a blocking thread.
+pub fn generate_image_variants(content: &[u8], file_type: &str) -> Result<ImageVariants, AppError> {
+  let (width, height) = image::io::Reader::new(Cursor::new(content))
+    .with_guessed_format()?
+    .into_dimensions()
+    .map_err(|err| AppError::InvalidRequest(format!("invalid image: {}", err)))?;
+  if width as u64 * height as u64 > MAX_IMAGE_PIXELS {
+    return Err(AppError::InvalidRequest(format!(
+      "image of {}x{} pixels is too large to process",
+      width, height
+    )));
+  }
+
+  let image = image::load_from_memory(content)
+    .map_err(|err| AppError::InvalidRequest(format!("invalid image: {}", err)))?;
+  let is_jpeg = image_variant_content_type(file_type) == "image/jpeg";
+  let mut variants = vec![];
+  for variant in ImageVariant::ALL {
+    if !variant.is_generated_for(width, height) {
+      continue;
+    }
+    let thumbnail = image.thumbnail(variant.max_size(), variant.max_size());
+    let mut data = vec![];
+    let result = if is_jpeg {
+      DynamicImage::ImageRgb8(thumbnail.to_rgb8())
+        .write_to(&mut data, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+    } else {
+      thumbnail.write_to(&mut data, ImageOutputFormat::Png)
+    };
+    result.map_err(|err| AppError::Internal(err.into()))?;
+    variants.push((variant, data));
+  }
+
+  Ok(ImageVariants {
+    width,
+    height,
+    variants,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use image::{ImageBuffer, Rgba};
+
+  fn png_image(width: u32, height: u32) -> Vec<u8> {
+    let image = ImageBuffer::from_pixel(width, height, Rgba([255u8, 0, 0, 128]));
+    let mut data = vec![];
+    DynamicImage::ImageRgba8(image)
+      .write_to(&mut data, ImageOutputFormat::Png)
+      .unwrap();
+    data
+  }
+
+  #[test]
+  fn generate_variants_smaller_than_image() {
+    let content = png_image(800, 400);
+    let image = generate_image_variants(&content, "image/png").unwrap();
+    assert_eq!((image.width, image.height), (800, 400));
+
+    let variants = image
+      .variants
+      .iter()
+      .map(|(variant, data)| {
+        let thumbnail = image::load_from_memory(data).unwrap();
+        (*variant, thumbnail.width(), thumbnail.height())
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      variants,
+      vec![
+        (ImageVariant::Small, 128, 64),
+        (ImageVariant::Medium, 512, 256)
+      ]
+    );
+  }
+
+  #[test]
+  fn reject_invalid_image() {
+    let result = generate_image_variants(b"not an image", "image/png");
+    assert!(matches!(result, Err(AppError::InvalidRequest(_))));
+  }
+
+  #[test]
+  fn variant_object_key() {
+    let key = ImageVariant::Medium.object_key("workspace/sha256-abc");
+    assert_eq!(key, "workspace/sha256-abc@medium");
+    assert_eq!(original_object_key(&key), "workspace/sha256-abc");
+    assert_eq!(original_object_key("workspace/a@b"), "workspace/a@b");
+  }
+}
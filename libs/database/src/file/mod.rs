@@ -1,5 +1,9 @@
+pub mod bucket_any_impl;
+pub mod bucket_fs_impl;
 pub mod bucket_s3_impl;
 mod file_storage;
+mod image_variant;
 mod utils;
 
 pub use file_storage::*;
+pub use image_variant::*;
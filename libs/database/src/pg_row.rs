@@ -1,7 +1,10 @@
 use anyhow::anyhow;
 use app_error::AppError;
 use chrono::{DateTime, Utc};
-use database_entity::dto::{AFAccessLevel, AFRole, AFUserProfile, AFWorkspace};
+use database_entity::dto::{
+  AFAccessLevel, AFRole, AFSnapshotMeta, AFSnapshotRestore, AFUserProfile, AFWorkspace,
+  AFWorkspaceExport,
+};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
@@ -137,6 +140,12 @@ pub struct AFBlobMetadataRow {
   pub file_type: String,
   pub file_size: i64,
   pub modified_at: DateTime<Utc>,
+  pub content_hash: String,
+  /// Width of the image in pixels. Only set for images whose variants have been generated.
+  pub width: Option<i32>,
+  pub height: Option<i32>,
+  /// The collab object that owns the blob, if any.
+  pub object_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -162,4 +171,123 @@ pub struct AFSnapshotRow {
   pub deleted_at: Option<DateTime<Utc>>,
   pub created_at: DateTime<Utc>,
   pub workspace_id: Uuid,
+  /// See [crate::collab::CollabCodec]
+  pub codec: i32,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct AFBlobUploadRow {
+  pub upload_id: String,
+  pub workspace_id: Uuid,
+  pub file_id: String,
+  pub file_type: String,
+  pub created_at: DateTime<Utc>,
+  pub object_key: String,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct AFBlobUploadPartRow {
+  pub upload_id: String,
+  pub part_number: i32,
+  pub e_tag: String,
+  pub part_size: i64,
+  pub modified_at: DateTime<Utc>,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct AFWorkspacePlanRow {
+  pub workspace_type: i32,
+  pub name: String,
+  pub storage_limit: i64,
+  pub blob_size_limit: i64,
+  pub collab_size_limit: i64,
+}
+
+/// The effective quota of a workspace, i.e. the quota of its plan merged with the overrides of
+/// the workspace.
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct AFWorkspaceQuotaRow {
+  pub workspace_type: i32,
+  pub storage_limit: i64,
+  pub blob_size_limit: i64,
+  pub collab_size_limit: i64,
+}
+
+/// A collab object that matches the full-text search query
+#[derive(Debug, Clone, FromRow)]
+pub struct AFCollabSearchRow {
+  pub oid: String,
+  pub partition_key: i32,
+  /// Fragment of the content with the matching words highlighted
+  pub snippet: String,
+  pub rank: f32,
+  pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct AFSnapshotMetaRow {
+  pub sid: i64,
+  pub oid: String,
+  pub created_at: DateTime<Utc>,
+  pub pinned: bool,
+  pub name: Option<String>,
+}
+
+impl From<AFSnapshotMetaRow> for AFSnapshotMeta {
+  fn from(row: AFSnapshotMetaRow) -> Self {
+    Self {
+      snapshot_id: row.sid,
+      object_id: row.oid,
+      created_at: row.created_at,
+      pinned: row.pinned,
+      name: row.name,
+    }
+  }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct AFSnapshotRestoreRow {
+  pub restore_id: i64,
+  pub oid: String,
+  pub snapshot_id: i64,
+  pub restored_by: i64,
+  pub restored_at: DateTime<Utc>,
+}
+
+impl From<AFSnapshotRestoreRow> for AFSnapshotRestore {
+  fn from(row: AFSnapshotRestoreRow) -> Self {
+    Self {
+      restore_id: row.restore_id,
+      object_id: row.oid,
+      snapshot_id: row.snapshot_id,
+      restored_by: row.restored_by,
+      restored_at: row.restored_at,
+    }
+  }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct AFWorkspaceExportRow {
+  pub job_id: Uuid,
+  pub workspace_id: Uuid,
+  pub created_by: i64,
+  pub status: i16,
+  pub file_size: Option<i64>,
+  pub error: Option<String>,
+  pub created_at: DateTime<Utc>,
+  pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl From<AFWorkspaceExportRow> for AFWorkspaceExport {
+  fn from(row: AFWorkspaceExportRow) -> Self {
+    Self {
+      job_id: row.job_id,
+      workspace_id: row.workspace_id,
+      status: row.status.into(),
+      file_size: row.file_size,
+      error: row.error,
+      created_at: row.created_at,
+      completed_at: row.completed_at,
+    }
+  }
 }
@@ -1,10 +1,13 @@
+use std::collections::HashSet;
 use std::ops::DerefMut;
 
-use crate::pg_row::AFBlobMetadataRow;
+use crate::pg_row::{
+  AFBlobMetadataRow, AFBlobUploadPartRow, AFBlobUploadRow, AFWorkspacePlanRow, AFWorkspaceQuotaRow,
+};
 use app_error::AppError;
 use rust_decimal::prelude::ToPrimitive;
 use sqlx::types::Decimal;
-use sqlx::{PgPool, Transaction};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
 use tracing::instrument;
 use uuid::Uuid;
 
@@ -32,6 +35,9 @@ pub async fn is_blob_metadata_exists(
   Ok(exists.0)
 }
 
+/// Insert the metadata of the blob, or replace the metadata of the blob with the same id. If the
+/// replaced content is no longer referenced, its object key is returned so that the object can be
+/// removed from the bucket.
 #[instrument(level = "trace", skip_all, err)]
 pub async fn insert_blob_metadata(
   tx: &mut Transaction<'_, sqlx::Postgres>,
@@ -39,50 +45,236 @@ pub async fn insert_blob_metadata(
   workspace_id: &Uuid,
   file_type: &str,
   file_size: usize,
-) -> Result<(), AppError> {
+  content_hash: &str,
+  object_id: Option<&str>,
+) -> Result<Option<String>, AppError> {
+  // The dimensions are copied from the blobs with the same content, whose image variants are
+  // shared with this blob.
   let res = sqlx::query!(
     r#"
+        WITH image AS (
+            SELECT width, height FROM af_blob_metadata
+            WHERE workspace_id = $1 AND content_hash = $5 AND width IS NOT NULL
+            LIMIT 1
+        )
         INSERT INTO af_blob_metadata
-        (workspace_id, file_id, file_type, file_size)
-        VALUES ($1, $2, $3, $4)
-        ON CONFLICT (workspace_id, file_id) DO UPDATE SET
+        (workspace_id, file_id, file_type, file_size, content_hash, width, height, object_id)
+        VALUES ($1, $2, $3, $4, $5, (SELECT width FROM image), (SELECT height FROM image), $6)
+        ON CONFLICT (workspace_id, file_id) DO NOTHING
+        "#,
+    workspace_id,
+    file_id,
+    file_type,
+    file_size as i64,
+    content_hash,
+    object_id,
+  )
+  .execute(tx.deref_mut())
+  .await?;
+  if res.rows_affected() == 1 {
+    return Ok(None);
+  }
+
+  // The blob already exists. Its row is locked until the end of the transaction, so that the
+  // reference to the replaced content is released exactly once.
+  let replaced_hash = sqlx::query_scalar!(
+    r#"
+        SELECT content_hash FROM af_blob_metadata
+        WHERE workspace_id = $1 AND file_id = $2
+        FOR UPDATE
+        "#,
+    workspace_id,
+    file_id,
+  )
+  .fetch_one(tx.deref_mut())
+  .await?;
+  sqlx::query!(
+    r#"
+        WITH image AS (
+            SELECT width, height FROM af_blob_metadata
+            WHERE workspace_id = $1 AND content_hash = $5 AND width IS NOT NULL
+            LIMIT 1
+        )
+        UPDATE af_blob_metadata SET
             file_type = $3,
-            file_size = $4
+            file_size = $4,
+            content_hash = $5,
+            width = (SELECT width FROM image),
+            height = (SELECT height FROM image),
+            object_id = $6
+        WHERE workspace_id = $1 AND file_id = $2
         "#,
     workspace_id,
     file_id,
     file_type,
     file_size as i64,
+    content_hash,
+    object_id,
   )
   .execute(tx.deref_mut())
   .await?;
-  let n = res.rows_affected();
-  assert_eq!(n, 1);
-  Ok(())
+
+  if replaced_hash == content_hash {
+    return Ok(None);
+  }
+  release_blob_object(tx, workspace_id, &replaced_hash).await
 }
 
+/// Delete the blob metadata and return the deleted metadata.
 #[instrument(level = "trace", skip_all, err)]
 #[inline]
 pub async fn delete_blob_metadata(
   tx: &mut Transaction<'_, sqlx::Postgres>,
   workspace_id: &Uuid,
   file_id: &str,
-) -> Result<(), AppError> {
-  let result = sqlx::query!(
+) -> Result<AFBlobMetadataRow, AppError> {
+  let metadata = sqlx::query_as!(
+    AFBlobMetadataRow,
     r#"
         DELETE FROM af_blob_metadata
         WHERE workspace_id = $1 AND file_id = $2
+        RETURNING *
         "#,
     workspace_id,
     file_id,
   )
-  .execute(tx.deref_mut())
+  .fetch_one(tx.deref_mut())
+  .await?;
+  Ok(metadata)
+}
+
+/// Record the dimensions of the image for all the blobs with the given content.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn update_blob_dimensions(
+  pool: &PgPool,
+  workspace_id: &Uuid,
+  content_hash: &str,
+  width: u32,
+  height: u32,
+) -> Result<(), AppError> {
+  sqlx::query(
+    r#"
+        UPDATE af_blob_metadata SET width = $3, height = $4
+        WHERE workspace_id = $1 AND content_hash = $2
+        "#,
+  )
+  .bind(workspace_id)
+  .bind(content_hash)
+  .bind(width as i32)
+  .bind(height as i32)
+  .execute(pool)
   .await?;
-  let n = result.rows_affected();
-  assert_eq!(n, 1);
   Ok(())
 }
 
+#[instrument(level = "trace", skip_all, err)]
+#[inline]
+pub async fn is_blob_object_exists(
+  pool: &PgPool,
+  workspace_id: &Uuid,
+  content_hash: &str,
+) -> Result<bool, AppError> {
+  let exists: (bool,) = sqlx::query_as(
+    r#"
+     SELECT EXISTS (
+         SELECT 1
+         FROM af_blob_object
+         WHERE workspace_id = $1 AND content_hash = $2
+     );
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(content_hash)
+  .fetch_one(pool)
+  .await?;
+  Ok(exists.0)
+}
+
+/// Add a reference to the blob object with the given content hash. The object is inserted if
+/// it doesn't exist. Return the reference count after the insertion, so 1 means the object is new
+/// and its content must be uploaded.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn insert_or_ref_blob_object(
+  tx: &mut Transaction<'_, sqlx::Postgres>,
+  workspace_id: &Uuid,
+  content_hash: &str,
+  object_key: &str,
+  file_size: usize,
+) -> Result<i32, AppError> {
+  let (ref_count,): (i32,) = sqlx::query_as(
+    r#"
+      INSERT INTO af_blob_object (workspace_id, content_hash, object_key, file_size, ref_count)
+      VALUES ($1, $2, $3, $4, 1)
+      ON CONFLICT (workspace_id, content_hash) DO UPDATE SET
+          ref_count = af_blob_object.ref_count + 1
+      RETURNING ref_count
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(content_hash)
+  .bind(object_key)
+  .bind(file_size as i64)
+  .fetch_one(tx.deref_mut())
+  .await?;
+  Ok(ref_count)
+}
+
+/// Remove a reference to the blob object. Return the object key if it was the last reference,
+/// in which case the object should be removed from the bucket.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn release_blob_object(
+  tx: &mut Transaction<'_, sqlx::Postgres>,
+  workspace_id: &Uuid,
+  content_hash: &str,
+) -> Result<Option<String>, AppError> {
+  let (ref_count, object_key): (i32, String) = sqlx::query_as(
+    r#"
+      UPDATE af_blob_object SET ref_count = ref_count - 1
+      WHERE workspace_id = $1 AND content_hash = $2
+      RETURNING ref_count, object_key
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(content_hash)
+  .fetch_one(tx.deref_mut())
+  .await?;
+
+  if ref_count > 0 {
+    return Ok(None);
+  }
+
+  sqlx::query(r#"DELETE FROM af_blob_object WHERE workspace_id = $1 AND content_hash = $2"#)
+    .bind(workspace_id)
+    .bind(content_hash)
+    .execute(tx.deref_mut())
+    .await?;
+  Ok(Some(object_key))
+}
+
+/// Return the key of the object that holds the content of the blob.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn get_blob_object_key(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+  file_id: &str,
+) -> Result<String, AppError> {
+  let (object_key,): (String,) = sqlx::query_as(
+    r#"
+      SELECT object.object_key
+      FROM af_blob_metadata metadata
+      JOIN af_blob_object object
+        ON object.workspace_id = metadata.workspace_id
+        AND object.content_hash = metadata.content_hash
+      WHERE metadata.workspace_id = $1 AND metadata.file_id = $2
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(file_id)
+  .fetch_one(pg_pool)
+  .await?;
+  Ok(object_key)
+}
+
 #[instrument(level = "trace", skip_all, err)]
 pub async fn get_blob_metadata(
   pg_pool: &PgPool,
@@ -145,17 +337,355 @@ pub async fn get_all_workspace_blob_ids(
   Ok(file_ids)
 }
 
-/// Return the total size of a workspace in bytes
+/// Return the keys of the objects that are referenced by the blobs or the pending uploads of the
+/// workspace.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn get_all_workspace_object_keys(
+  pool: &PgPool,
+  workspace_id: &Uuid,
+) -> Result<HashSet<String>, AppError> {
+  let keys: Vec<(String,)> = sqlx::query_as(
+    r#"
+      SELECT object_key FROM af_blob_object WHERE workspace_id = $1
+      UNION
+      SELECT object_key FROM af_blob_upload WHERE workspace_id = $1
+    "#,
+  )
+  .bind(workspace_id)
+  .fetch_all(pool)
+  .await?;
+  Ok(keys.into_iter().map(|(key,)| key).collect())
+}
+
+#[instrument(level = "trace", skip_all, err)]
+pub async fn is_object_key_referenced(
+  pool: &PgPool,
+  workspace_id: &Uuid,
+  object_key: &str,
+) -> Result<bool, AppError> {
+  let exists: (bool,) = sqlx::query_as(
+    r#"
+     SELECT EXISTS (
+         SELECT 1 FROM af_blob_object WHERE workspace_id = $1 AND object_key = $2
+         UNION ALL
+         SELECT 1 FROM af_blob_upload WHERE workspace_id = $1 AND object_key = $2
+     );
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(object_key)
+  .fetch_one(pool)
+  .await?;
+  Ok(exists.0)
+}
+
+/// Return the total size of a workspace in bytes. Blobs sharing the same content are only
+/// counted once.
 #[instrument(level = "trace", skip_all, err)]
 #[inline]
-pub async fn get_workspace_usage_size(pool: &PgPool, workspace_id: &Uuid) -> Result<u64, AppError> {
+pub async fn get_workspace_usage_size<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  workspace_id: &Uuid,
+) -> Result<u64, AppError> {
   let row: (Option<Decimal>,) =
-    sqlx::query_as(r#"SELECT SUM(file_size) FROM af_blob_metadata WHERE workspace_id = $1;"#)
+    sqlx::query_as(r#"SELECT SUM(file_size) FROM af_blob_object WHERE workspace_id = $1;"#)
       .bind(workspace_id)
-      .fetch_one(pool)
+      .fetch_one(executor)
       .await?;
   match row.0 {
     Some(decimal) => Ok(decimal.to_u64().unwrap_or(0)),
     None => Ok(0),
   }
 }
+
+#[instrument(level = "trace", skip_all, err)]
+pub async fn insert_blob_upload(
+  pg_pool: &PgPool,
+  upload_id: &str,
+  workspace_id: &Uuid,
+  file_id: &str,
+  file_type: &str,
+  object_key: &str,
+) -> Result<(), AppError> {
+  sqlx::query(
+    r#"
+      INSERT INTO af_blob_upload (upload_id, workspace_id, file_id, file_type, object_key)
+      VALUES ($1, $2, $3, $4, $5)
+    "#,
+  )
+  .bind(upload_id)
+  .bind(workspace_id)
+  .bind(file_id)
+  .bind(file_type)
+  .bind(object_key)
+  .execute(pg_pool)
+  .await?;
+  Ok(())
+}
+
+/// Return the pending upload of the given file, if any.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn get_blob_upload_by_file_id(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+  file_id: &str,
+) -> Result<Option<AFBlobUploadRow>, AppError> {
+  let row = sqlx::query_as::<_, AFBlobUploadRow>(
+    r#"
+      SELECT * FROM af_blob_upload
+      WHERE workspace_id = $1 AND file_id = $2
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(file_id)
+  .fetch_optional(pg_pool)
+  .await?;
+  Ok(row)
+}
+
+#[instrument(level = "trace", skip_all, err)]
+pub async fn get_blob_upload(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+  upload_id: &str,
+) -> Result<AFBlobUploadRow, AppError> {
+  let row = sqlx::query_as::<_, AFBlobUploadRow>(
+    r#"
+      SELECT * FROM af_blob_upload
+      WHERE workspace_id = $1 AND upload_id = $2
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(upload_id)
+  .fetch_one(pg_pool)
+  .await?;
+  Ok(row)
+}
+
+#[instrument(level = "trace", skip_all, err)]
+#[inline]
+pub async fn delete_blob_upload(
+  tx: &mut Transaction<'_, sqlx::Postgres>,
+  upload_id: &str,
+) -> Result<(), AppError> {
+  sqlx::query(r#"DELETE FROM af_blob_upload WHERE upload_id = $1"#)
+    .bind(upload_id)
+    .execute(tx.deref_mut())
+    .await?;
+  Ok(())
+}
+
+/// Insert the part of the upload. Uploading the same part number again replaces the previous one.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn upsert_blob_upload_part(
+  pg_pool: &PgPool,
+  upload_id: &str,
+  part_number: i32,
+  e_tag: &str,
+  part_size: usize,
+) -> Result<AFBlobUploadPartRow, AppError> {
+  let part = sqlx::query_as::<_, AFBlobUploadPartRow>(
+    r#"
+      INSERT INTO af_blob_upload_part (upload_id, part_number, e_tag, part_size)
+      VALUES ($1, $2, $3, $4)
+      ON CONFLICT (upload_id, part_number) DO UPDATE SET
+          e_tag = $3,
+          part_size = $4,
+          modified_at = CURRENT_TIMESTAMP
+      RETURNING *
+    "#,
+  )
+  .bind(upload_id)
+  .bind(part_number)
+  .bind(e_tag)
+  .bind(part_size as i64)
+  .fetch_one(pg_pool)
+  .await?;
+  Ok(part)
+}
+
+/// Return the uploaded parts of the upload, ordered by the part number.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn get_blob_upload_parts(
+  pg_pool: &PgPool,
+  upload_id: &str,
+) -> Result<Vec<AFBlobUploadPartRow>, AppError> {
+  let parts = sqlx::query_as::<_, AFBlobUploadPartRow>(
+    r#"
+      SELECT * FROM af_blob_upload_part
+      WHERE upload_id = $1
+      ORDER BY part_number
+    "#,
+  )
+  .bind(upload_id)
+  .fetch_all(pg_pool)
+  .await?;
+  Ok(parts)
+}
+
+/// Return the total size of the parts that have been uploaded but not yet completed in a workspace.
+#[instrument(level = "trace", skip_all, err)]
+#[inline]
+pub async fn get_workspace_pending_upload_size<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  workspace_id: &Uuid,
+) -> Result<u64, AppError> {
+  let row: (Option<Decimal>,) = sqlx::query_as(
+    r#"
+      SELECT SUM(part.part_size)
+      FROM af_blob_upload_part part
+      JOIN af_blob_upload upload ON upload.upload_id = part.upload_id
+      WHERE upload.workspace_id = $1
+    "#,
+  )
+  .bind(workspace_id)
+  .fetch_one(executor)
+  .await?;
+  match row.0 {
+    Some(decimal) => Ok(decimal.to_u64().unwrap_or(0)),
+    None => Ok(0),
+  }
+}
+
+/// Lock the storage of the workspace until the end of the transaction, so the uploads that are
+/// completed at the same time are checked against the storage limit one after the other.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn lock_workspace_storage(
+  tx: &mut Transaction<'_, sqlx::Postgres>,
+  workspace_id: &Uuid,
+) -> Result<(), AppError> {
+  sqlx::query(r#"SELECT pg_advisory_xact_lock(hashtextextended($1::text, 0))"#)
+    .bind(workspace_id)
+    .execute(tx.deref_mut())
+    .await?;
+  Ok(())
+}
+
+/// Return the quota of the workspace. The limits that are not overridden by the workspace fall back
+/// to the plan of the workspace.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn get_workspace_quota<'a, E: Executor<'a, Database = Postgres>>(
+  executor: E,
+  workspace_id: &Uuid,
+) -> Result<AFWorkspaceQuotaRow, AppError> {
+  let quota = sqlx::query_as::<_, AFWorkspaceQuotaRow>(
+    r#"
+      SELECT
+          workspace.workspace_type,
+          COALESCE(quota.storage_limit, plan.storage_limit) AS storage_limit,
+          COALESCE(quota.blob_size_limit, plan.blob_size_limit) AS blob_size_limit,
+          COALESCE(quota.collab_size_limit, plan.collab_size_limit) AS collab_size_limit
+      FROM af_workspace workspace
+      JOIN af_workspace_plan plan ON plan.workspace_type = workspace.workspace_type
+      LEFT JOIN af_workspace_quota quota ON quota.workspace_id = workspace.workspace_id
+      WHERE workspace.workspace_id = $1
+    "#,
+  )
+  .bind(workspace_id)
+  .fetch_one(executor)
+  .await?;
+  Ok(quota)
+}
+
+/// Override the quota of the workspace. The limits that are `None` are left unchanged.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn upsert_workspace_quota(
+  tx: &mut Transaction<'_, sqlx::Postgres>,
+  workspace_id: &Uuid,
+  storage_limit: Option<i64>,
+  blob_size_limit: Option<i64>,
+  collab_size_limit: Option<i64>,
+) -> Result<(), AppError> {
+  sqlx::query(
+    r#"
+      INSERT INTO af_workspace_quota
+      (workspace_id, storage_limit, blob_size_limit, collab_size_limit)
+      VALUES ($1, $2, $3, $4)
+      ON CONFLICT (workspace_id) DO UPDATE SET
+          storage_limit = COALESCE($2, af_workspace_quota.storage_limit),
+          blob_size_limit = COALESCE($3, af_workspace_quota.blob_size_limit),
+          collab_size_limit = COALESCE($4, af_workspace_quota.collab_size_limit),
+          updated_at = CURRENT_TIMESTAMP
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(storage_limit)
+  .bind(blob_size_limit)
+  .bind(collab_size_limit)
+  .execute(tx.deref_mut())
+  .await?;
+  Ok(())
+}
+
+/// Remove the quota overrides of the workspace, so the workspace uses the quota of its plan.
+#[instrument(level = "trace", skip_all, err)]
+pub async fn delete_workspace_quota(pool: &PgPool, workspace_id: &Uuid) -> Result<(), AppError> {
+  sqlx::query(r#"DELETE FROM af_workspace_quota WHERE workspace_id = $1"#)
+    .bind(workspace_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+#[instrument(level = "trace", skip_all, err)]
+pub async fn update_workspace_type(
+  tx: &mut Transaction<'_, sqlx::Postgres>,
+  workspace_id: &Uuid,
+  workspace_type: i32,
+) -> Result<(), AppError> {
+  let res = sqlx::query(
+    r#"
+      UPDATE af_workspace SET workspace_type = $2
+      WHERE workspace_id = $1
+        AND EXISTS (SELECT 1 FROM af_workspace_plan WHERE workspace_type = $2)
+    "#,
+  )
+  .bind(workspace_id)
+  .bind(workspace_type)
+  .execute(tx.deref_mut())
+  .await?;
+  if res.rows_affected() == 0 {
+    return Err(AppError::RecordNotFound(format!(
+      "workspace:{} or plan:{} not found",
+      workspace_id, workspace_type
+    )));
+  }
+  Ok(())
+}
+
+#[instrument(level = "trace", skip_all, err)]
+pub async fn select_workspace_plans(pool: &PgPool) -> Result<Vec<AFWorkspacePlanRow>, AppError> {
+  let plans = sqlx::query_as::<_, AFWorkspacePlanRow>(
+    r#"SELECT * FROM af_workspace_plan ORDER BY workspace_type"#,
+  )
+  .fetch_all(pool)
+  .await?;
+  Ok(plans)
+}
+
+#[instrument(level = "trace", skip_all, err)]
+pub async fn upsert_workspace_plan(
+  pool: &PgPool,
+  plan: &AFWorkspacePlanRow,
+) -> Result<(), AppError> {
+  sqlx::query(
+    r#"
+      INSERT INTO af_workspace_plan
+      (workspace_type, name, storage_limit, blob_size_limit, collab_size_limit)
+      VALUES ($1, $2, $3, $4, $5)
+      ON CONFLICT (workspace_type) DO UPDATE SET
+          name = $2,
+          storage_limit = $3,
+          blob_size_limit = $4,
+          collab_size_limit = $5
+    "#,
+  )
+  .bind(plan.workspace_type)
+  .bind(&plan.name)
+  .bind(plan.storage_limit)
+  .bind(plan.blob_size_limit)
+  .bind(plan.collab_size_limit)
+  .execute(pool)
+  .await?;
+  Ok(())
+}
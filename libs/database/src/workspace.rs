@@ -1,4 +1,5 @@
-use database_entity::dto::AFRole;
+use chrono::{DateTime, Utc};
+use database_entity::dto::{AFRole, WorkspaceExportStatus};
 use futures_util::stream::BoxStream;
 use sqlx::{
   types::{uuid, Uuid},
@@ -8,7 +9,9 @@ use std::ops::DerefMut;
 use tracing::{event, instrument};
 
 use crate::pg_row::AFWorkspaceMemberPermRow;
-use crate::pg_row::{AFPermissionRow, AFUserProfileRow, AFWorkspaceMemberRow, AFWorkspaceRow};
+use crate::pg_row::{
+  AFPermissionRow, AFUserProfileRow, AFWorkspaceExportRow, AFWorkspaceMemberRow, AFWorkspaceRow,
+};
 use crate::user::select_uid_from_email;
 use app_error::AppError;
 
@@ -465,3 +468,115 @@ pub async fn select_permission_from_role_id(
   .await?;
   Ok(permission)
 }
+
+pub async fn insert_workspace_export(
+  pg_pool: &PgPool,
+  job_id: &Uuid,
+  workspace_id: &Uuid,
+  uid: i64,
+) -> Result<AFWorkspaceExportRow, AppError> {
+  let row = sqlx::query_as::<_, AFWorkspaceExportRow>(
+    r#"
+      INSERT INTO af_workspace_export (job_id, workspace_id, created_by, status)
+      VALUES ($1, $2, $3, $4)
+      RETURNING *
+    "#,
+  )
+  .bind(job_id)
+  .bind(workspace_id)
+  .bind(uid)
+  .bind(i16::from(WorkspaceExportStatus::Running))
+  .fetch_one(pg_pool)
+  .await?;
+  Ok(row)
+}
+
+/// Mark the export job as completed or failed.
+pub async fn update_workspace_export_result(
+  pg_pool: &PgPool,
+  job_id: &Uuid,
+  result: Result<i64, &str>,
+) -> Result<(), AppError> {
+  let (status, file_size, error) = match result {
+    Ok(file_size) => (WorkspaceExportStatus::Completed, Some(file_size), None),
+    Err(error) => (WorkspaceExportStatus::Failed, None, Some(error)),
+  };
+  sqlx::query(
+    r#"
+      UPDATE af_workspace_export
+      SET status = $2, file_size = $3, error = $4, completed_at = CURRENT_TIMESTAMP
+      WHERE job_id = $1 AND status = $5
+    "#,
+  )
+  .bind(job_id)
+  .bind(i16::from(status))
+  .bind(file_size)
+  .bind(error)
+  .bind(i16::from(WorkspaceExportStatus::Running))
+  .execute(pg_pool)
+  .await?;
+  Ok(())
+}
+
+/// Mark the export jobs that are still running and were started before `started_before` as
+/// failed. Return the number of failed jobs.
+pub async fn fail_interrupted_workspace_exports(
+  pg_pool: &PgPool,
+  started_before: DateTime<Utc>,
+  error: &str,
+) -> Result<u64, AppError> {
+  let result = sqlx::query(
+    r#"
+      UPDATE af_workspace_export
+      SET status = $3, error = $4, completed_at = CURRENT_TIMESTAMP
+      WHERE status = $2 AND created_at < $1
+    "#,
+  )
+  .bind(started_before)
+  .bind(i16::from(WorkspaceExportStatus::Running))
+  .bind(i16::from(WorkspaceExportStatus::Failed))
+  .bind(error)
+  .execute(pg_pool)
+  .await?;
+  Ok(result.rows_affected())
+}
+
+/// Delete the export jobs that are not running and were started before `started_before`. Return
+/// the workspace id and the job id of the deleted jobs, whose archives are to be removed.
+pub async fn delete_expired_workspace_exports(
+  pg_pool: &PgPool,
+  started_before: DateTime<Utc>,
+) -> Result<Vec<(Uuid, Uuid)>, AppError> {
+  let jobs = sqlx::query_as::<_, (Uuid, Uuid)>(
+    r#"
+      DELETE FROM af_workspace_export
+      WHERE status <> $2 AND created_at < $1
+      RETURNING workspace_id, job_id
+    "#,
+  )
+  .bind(started_before)
+  .bind(i16::from(WorkspaceExportStatus::Running))
+  .fetch_all(pg_pool)
+  .await?;
+  Ok(jobs)
+}
+
+pub async fn select_workspace_export(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+  job_id: &Uuid,
+) -> Result<AFWorkspaceExportRow, AppError> {
+  sqlx::query_as::<_, AFWorkspaceExportRow>(
+    "SELECT * FROM af_workspace_export WHERE job_id = $1 AND workspace_id = $2",
+  )
+  .bind(job_id)
+  .bind(workspace_id)
+  .fetch_optional(pg_pool)
+  .await?
+  .ok_or_else(|| {
+    AppError::RecordNotFound(format!(
+      "Can't find the export job:{} of workspace:{}",
+      job_id, workspace_id
+    ))
+  })
+}
@@ -116,4 +116,6 @@ impl From<RealtimeMessage> for Message {
 pub enum SystemMessage {
   RateLimit(u32),
   KickOff,
+  /// The user can't access the collabs anymore, so the server stopped sending their changes.
+  AccessRevoked(Vec<String>),
 }
@@ -1,5 +1,7 @@
 use crate::collaborate::{CollabAccessControl, CollabServer};
-use crate::entities::{ClientMessage, Connect, Disconnect, RealtimeMessage, RealtimeUser};
+use crate::entities::{
+  ClientMessage, Connect, Disconnect, RealtimeMessage, RealtimeUser, RestartSession,
+};
 use crate::error::RealtimeError;
 use actix::{
   fut, Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, ContextFutureSpawner, Handler,
@@ -18,8 +20,6 @@ use tokio::time::sleep;
 use database::pg_row::AFUserNotification;
 use realtime_entity::user::{AFUserChange, UserMessage};
 use tracing::{debug, error, trace, warn};
-const MAX_MESSAGES_PER_INTERVAL: usize = 10;
-const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct ClientSession<
   U: Unpin + RealtimeUser,
@@ -33,8 +33,6 @@ pub struct ClientSession<
   heartbeat_interval: Duration,
   client_timeout: Duration,
   user_change_recv: Option<tokio::sync::mpsc::Receiver<AFUserNotification>>,
-  message_count: usize,
-  interval_start: Instant,
 }
 
 impl<U, S, AC> ClientSession<U, S, AC>
@@ -58,8 +56,6 @@ where
       client_timeout,
       user_change_recv: Some(user_change_recv),
       session_id: uuid::Uuid::new_v4().to_string(),
-      message_count: 0,
-      interval_start: Instant::now(),
     }
   }
 
@@ -174,6 +170,7 @@ where
       .server
       .send(Connect {
         socket: ctx.address().recipient(),
+        restart: ctx.address().recipient(),
         user: self.user.clone(),
         session_id: self.session_id.clone(),
       })
@@ -227,6 +224,23 @@ where
   }
 }
 
+impl<U, S, AC> Handler<RestartSession> for ClientSession<U, S, AC>
+where
+  U: Unpin + RealtimeUser,
+  S: Unpin + CollabStorage,
+  AC: CollabAccessControl + Unpin,
+{
+  type Result = ();
+
+  fn handle(&mut self, _msg: RestartSession, ctx: &mut Self::Context) {
+    ctx.close(Some(ws::CloseReason {
+      code: ws::CloseCode::Restart,
+      description: Some("server is shutting down".to_string()),
+    }));
+    ctx.stop();
+  }
+}
+
 /// Handle the messages sent from the client
 impl<U, S, AC> StreamHandler<Result<ws::Message, ws::ProtocolError>> for ClientSession<U, S, AC>
 where
@@ -235,18 +249,8 @@ where
   AC: CollabAccessControl + Unpin,
 {
   fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-    let now = Instant::now();
-    if now.duration_since(self.interval_start) > RATE_LIMIT_INTERVAL {
-      self.message_count = 0;
-      self.interval_start = now;
-    }
-
-    self.message_count += 1;
-    if self.message_count > MAX_MESSAGES_PER_INTERVAL {
-      // TODO(nathan): inform the client to slow down
-      return;
-    }
-
+    // The collab messages are rate limited by the [CollabServer], which tells the client to slow
+    // down.
     let msg = match msg {
       Err(err) => {
         error!("Websocket stream error: {}", err);
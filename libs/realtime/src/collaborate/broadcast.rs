@@ -335,7 +335,7 @@ fn gen_update_message(update: &[u8]) -> Vec<u8> {
 }
 
 #[inline]
-fn gen_awareness_update_message(
+pub(crate) fn gen_awareness_update_message(
   awareness: &Awareness,
   event: &awareness::Event,
 ) -> Result<AwarenessUpdate, RealtimeError> {
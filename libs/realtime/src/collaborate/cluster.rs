@@ -0,0 +1,223 @@
+use crate::collaborate::broadcast::gen_awareness_update_message;
+use crate::error::RealtimeError;
+use anyhow::anyhow;
+use app_error::AppError;
+use async_trait::async_trait;
+use collab::core::awareness;
+use collab::core::awareness::AwarenessUpdate;
+use collab::core::collab::{MutexCollab, TransactionMutExt};
+use collab::core::origin::CollabOrigin;
+use collab::core::transaction::TransactionRetry;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
+use tracing::{error, trace};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{ReadTxn, StateVector, Transact, Update, UpdateSubscription};
+
+/// A message exchanged between the nodes that hold a group of the same collab.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterMessage {
+  /// The node that published the message
+  pub node_id: String,
+  pub object_id: String,
+  pub payload: ClusterPayload,
+}
+
+impl ClusterMessage {
+  pub fn encode(&self) -> Result<Vec<u8>, RealtimeError> {
+    Ok(bincode::serialize(self)?)
+  }
+
+  pub fn decode(data: &[u8]) -> Result<Self, RealtimeError> {
+    Ok(bincode::deserialize(data)?)
+  }
+}
+
+/// The messages the other nodes publish for a collab, received until the node unsubscribes from
+/// the collab.
+pub struct ClusterSubscription {
+  pub messages: mpsc::Receiver<ClusterMessage>,
+  /// Notified when some messages of the collab were lost, because the receiver was full or the
+  /// node was disconnected from the cluster. The collab must then be synced again.
+  pub missed: Arc<Notify>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ClusterPayload {
+  /// An update of the collab, encoded with the v1 encoding
+  Update(Vec<u8>),
+  /// An update of the awareness of the clients connected to the node
+  Awareness(Vec<u8>),
+  /// The node opened the collab and asks the other nodes for the updates that are missing from
+  /// the state vector
+  SyncRequest(Vec<u8>),
+  /// The owner of the collab closed its group, so one of the other nodes can own the collab
+  Released,
+}
+
+/// Connects the [CollabServer](crate::collaborate::CollabServer) of this node with the servers of
+/// the other nodes.
+///
+/// Each node holds its own group of a collab that is edited by its clients, and the groups of the
+/// same collab are kept in sync by publishing their changes to each other. Only the node that
+/// owns the collab writes it to the storage, the groups of the other nodes are replicas.
+#[async_trait]
+pub trait CollabCluster: Send + Sync + 'static {
+  fn node_id(&self) -> &str;
+
+  /// Acquire the ownership of the collab if it has no owner, or renew it if this node is the
+  /// owner. Returns true if this node owns the collab. The ownership expires if it's not renewed,
+  /// so the collabs of a node that stopped are taken over by the other nodes.
+  async fn claim_ownership(&self, object_id: &str) -> Result<bool, AppError>;
+
+  /// Give up the ownership of the collab if this node owns it.
+  async fn release_ownership(&self, object_id: &str) -> Result<(), AppError>;
+
+  /// Send the message to the other nodes that subscribed to the collab.
+  async fn publish(&self, message: ClusterMessage) -> Result<(), AppError>;
+
+  /// Receive the messages the other nodes publish for the collab. A node only subscribes to the
+  /// collabs of its groups, so it doesn't receive the changes of the other collabs.
+  async fn subscribe(&self, object_id: &str) -> Result<ClusterSubscription, AppError>;
+
+  /// Stop receiving the messages of the collab.
+  async fn unsubscribe(&self, object_id: &str) -> Result<(), AppError>;
+}
+
+/// Publishes the changes of the collab of a group to the other nodes, and applies the changes
+/// they publish.
+pub struct ClusterSync {
+  /// Set while the changes of the other nodes are applied, so they are not published back.
+  applying_remote: Arc<AtomicBool>,
+  payload_sender: mpsc::UnboundedSender<ClusterPayload>,
+  /// The changes are published until the subscriptions are dropped with the group
+  #[allow(dead_code)]
+  subscriptions: Mutex<(UpdateSubscription, awareness::UpdateSubscription)>,
+}
+
+impl ClusterSync {
+  pub fn new(
+    object_id: &str,
+    collab: &MutexCollab,
+    cluster: Arc<dyn CollabCluster>,
+  ) -> Result<Self, RealtimeError> {
+    let (payload_sender, mut payload_receiver) = mpsc::unbounded_channel::<ClusterPayload>();
+    let cloned_object_id = object_id.to_string();
+    tokio::spawn(async move {
+      while let Some(payload) = payload_receiver.recv().await {
+        let message = ClusterMessage {
+          node_id: cluster.node_id().to_string(),
+          object_id: cloned_object_id.clone(),
+          payload,
+        };
+        if let Err(err) = cluster.publish(message).await {
+          error!(
+            "fail to publish the change of collab:{}: {}",
+            cloned_object_id, err
+          );
+        }
+      }
+    });
+
+    let applying_remote = Arc::new(AtomicBool::new(false));
+    let mut collab = collab.lock();
+    let cloned_applying_remote = applying_remote.clone();
+    let sender = payload_sender.clone();
+    let doc_subscription = collab
+      .get_mut_awareness()
+      .doc_mut()
+      .observe_update_v1(move |_, event| {
+        if !cloned_applying_remote.load(Ordering::SeqCst) {
+          let _ = sender.send(ClusterPayload::Update(event.update.clone()));
+        }
+      })
+      .map_err(|err| RealtimeError::Internal(anyhow!("fail to observe the collab: {}", err)))?;
+
+    let cloned_applying_remote = applying_remote.clone();
+    let sender = payload_sender.clone();
+    let awareness_subscription = collab
+      .get_mut_awareness()
+      .on_update(move |awareness, event| {
+        if cloned_applying_remote.load(Ordering::SeqCst) {
+          return;
+        }
+        if let Ok(update) = gen_awareness_update_message(awareness, event) {
+          let _ = sender.send(ClusterPayload::Awareness(update.encode_v1()));
+        }
+      });
+
+    Ok(Self {
+      applying_remote,
+      payload_sender,
+      subscriptions: Mutex::new((doc_subscription, awareness_subscription)),
+    })
+  }
+
+  /// Ask the other nodes for the updates that are missing from the collab.
+  pub fn request_sync(&self, collab: &MutexCollab) -> Result<(), RealtimeError> {
+    let state_vector = {
+      let mut collab = collab.lock();
+      let txn = collab
+        .get_mut_awareness()
+        .doc()
+        .try_transact()
+        .map_err(|err| RealtimeError::Internal(anyhow!("fail to read the collab: {}", err)))?;
+      txn.state_vector().encode_v1()
+    };
+    self.send(ClusterPayload::SyncRequest(state_vector));
+    Ok(())
+  }
+
+  /// Apply the change published by another node. The changes are then broadcast to the clients
+  /// of this node by the group.
+  pub fn apply(&self, collab: &MutexCollab, payload: ClusterPayload) -> Result<(), RealtimeError> {
+    let mut collab = collab.lock();
+    match payload {
+      ClusterPayload::Update(update) => {
+        let update = Update::decode_v1(&update)?;
+        self.applying_remote.store(true, Ordering::SeqCst);
+        let result = (|| {
+          let mut retry_txn = TransactionRetry::new(collab.get_mut_awareness().doc());
+          let mut txn = retry_txn
+            .try_get_write_txn_with(CollabOrigin::Server)
+            .map_err(|err| anyhow!("fail to acquire transaction: {}", err))?;
+          txn
+            .try_apply_update(update)
+            .map_err(|err| anyhow!("fail to apply update: {}", err))
+        })();
+        self.applying_remote.store(false, Ordering::SeqCst);
+        result?;
+      },
+      ClusterPayload::Awareness(update) => {
+        let update = AwarenessUpdate::decode_v1(&update)?;
+        self.applying_remote.store(true, Ordering::SeqCst);
+        let result = collab.get_mut_awareness().apply_update(update);
+        self.applying_remote.store(false, Ordering::SeqCst);
+        result?;
+      },
+      ClusterPayload::SyncRequest(state_vector) => {
+        let state_vector = StateVector::decode_v1(&state_vector)?;
+        let update = collab
+          .get_mut_awareness()
+          .doc()
+          .try_transact()
+          .map_err(|err| RealtimeError::Internal(anyhow!("fail to read the collab: {}", err)))?
+          .encode_state_as_update_v1(&state_vector);
+        trace!("reply the sync request with update len:{}", update.len());
+        self.send(ClusterPayload::Update(update));
+      },
+      ClusterPayload::Released => {},
+    }
+    Ok(())
+  }
+
+  pub fn send(&self, payload: ClusterPayload) {
+    if self.payload_sender.send(payload).is_err() {
+      error!("the publisher of the collab changes is closed");
+    }
+  }
+}
@@ -1,27 +1,52 @@
-use crate::collaborate::{CollabAccessControl, CollabBroadcast, CollabStoragePlugin, Subscription};
+use crate::collaborate::{
+  ClusterMessage, ClusterPayload, ClusterSubscription, ClusterSync, CollabAccessControl,
+  CollabBroadcast, CollabCluster, CollabEditState, CollabStoragePlugin, Subscription,
+};
 use crate::entities::RealtimeUser;
 use anyhow::Error;
 use collab::core::collab::MutexCollab;
 use collab::core::origin::CollabOrigin;
 use collab::preclude::Collab;
 use collab_entity::CollabType;
-use database::collab::CollabStorage;
+use database::collab::{CollabFlushPolicy, CollabStorage};
 use std::collections::HashMap;
 
 use collab::core::collab_plugin::EncodedCollab;
 use futures_util::{SinkExt, StreamExt};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 use tokio::sync::{Mutex, RwLock};
 use tokio::task::spawn_blocking;
-use tokio::time::Instant;
+use tokio::time::{interval, sleep_until, Instant};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
 use realtime_entity::collab_msg::CollabMessage;
-use tracing::{debug, error, event, instrument, trace, warn};
+use tracing::{debug, error, event, info, instrument, trace, warn};
+
+/// How often the node renews the ownership of the collabs of its groups. It must be shorter than
+/// the expiration of the ownership.
+const OWNERSHIP_RENEW_INTERVAL: Duration = Duration::from_secs(10);
+/// The delay before a group that missed some messages of the cluster asks for them again. It's
+/// doubled, up to [MAX_RESYNC_DELAY], while the group keeps missing messages.
+const MIN_RESYNC_DELAY: Duration = Duration::from_millis(500);
+const MAX_RESYNC_DELAY: Duration = Duration::from_secs(30);
+
+type GroupByObjectId<U> = Arc<RwLock<HashMap<String, Arc<CollabGroup<U>>>>>;
 
 pub struct CollabGroupCache<S, U, AC> {
-  group_by_object_id: Arc<RwLock<HashMap<String, Arc<CollabGroup<U>>>>>,
+  group_by_object_id: GroupByObjectId<U>,
   storage: Arc<S>,
   access_control: Arc<AC>,
+  /// Keeps the groups in sync with the groups of the other nodes. None if the server runs on a
+  /// single node, which then owns all the collabs.
+  cluster: Option<Arc<dyn CollabCluster>>,
+  /// The tasks spawned by the plugins of the groups to write the collabs to the storage
+  flush_tasks: TaskTracker,
+  /// Cancelled once all the groups are closed on shutdown, so the plugins stop waiting for
+  /// updates to append to the update logs
+  shutdown: CancellationToken,
 }
 
 impl<S, U, AC> CollabGroupCache<S, U, AC>
@@ -30,30 +55,73 @@ where
   U: RealtimeUser,
   AC: CollabAccessControl,
 {
-  pub fn new(storage: Arc<S>, access_control: Arc<AC>) -> Self {
+  pub fn new(
+    storage: Arc<S>,
+    access_control: Arc<AC>,
+    cluster: Option<Arc<dyn CollabCluster>>,
+  ) -> Self {
+    let group_by_object_id: GroupByObjectId<U> = Arc::new(RwLock::new(HashMap::new()));
+    if let Some(cluster) = &cluster {
+      tokio::spawn(renew_ownership(
+        Arc::downgrade(&group_by_object_id),
+        cluster.clone(),
+      ));
+    }
     Self {
-      group_by_object_id: Arc::new(RwLock::new(HashMap::new())),
+      group_by_object_id,
       storage,
       access_control,
+      cluster,
+      flush_tasks: TaskTracker::new(),
+      shutdown: CancellationToken::new(),
     }
   }
 
   /// Performs a periodic check to remove groups based on the following conditions:
   /// 1. Groups without any subscribers.
   /// 2. Groups that have been inactive for a specified period of time.
+  ///
+  /// The update logs of the other groups are merged into their collabs according to the
+  /// [CollabFlushPolicy] of their type. The collabs are not written as a whole, their updates are
+  /// already in the update logs.
   pub async fn tick(&self) {
     let mut inactive_group_ids = vec![];
-    if let Ok(groups) = self.group_by_object_id.try_read() {
+    let mut flush_groups = vec![];
+    {
+      let groups = self.group_by_object_id.read().await;
+      let flush_policy = &self.storage.config().flush_policy;
       for (object_id, group) in groups.iter() {
-        if group.is_inactive().await {
+        if inactive_group_ids.len() <= 5 && group.is_inactive().await {
           inactive_group_ids.push(object_id.clone());
-          if inactive_group_ids.len() > 5 {
-            break;
-          }
+        } else if group.should_flush(flush_policy.policy(&group.collab_type)) {
+          flush_groups.push((object_id.clone(), group.clone()));
         }
       }
     }
 
+    for (object_id, group) in flush_groups {
+      let edit_count = match group.edit_state.begin_policy_flush() {
+        Some(edit_count) => edit_count,
+        None => continue,
+      };
+      trace!("flush policy reached, compact collab:{}", object_id);
+      let storage = self.storage.clone();
+      let collab_type = group.collab_type.clone();
+      let edit_state = group.edit_state.clone();
+      self.flush_tasks.spawn(async move {
+        match storage
+          .compact_collab_updates(&object_id, &collab_type)
+          .await
+        {
+          Ok(_) => edit_state.end_policy_flush(Some(edit_count)),
+          Err(err) => {
+            error!("fail to compact updates of collab:{}: {:?}", object_id, err);
+            edit_state.end_policy_flush(None);
+          },
+        }
+      });
+    }
+
     if !inactive_group_ids.is_empty() {
       for object_id in inactive_group_ids {
         self.remove_group(&object_id).await;
@@ -83,6 +151,18 @@ where
     Ok(())
   }
 
+  /// Returns the object ids of the groups whose collab belongs to the workspace.
+  pub async fn object_ids_of_workspace(&self, workspace_id: &str) -> Vec<String> {
+    self
+      .group_by_object_id
+      .read()
+      .await
+      .iter()
+      .filter(|(_, group)| group.workspace_id == workspace_id)
+      .map(|(object_id, _)| object_id.clone())
+      .collect()
+  }
+
   pub async fn contains_group(&self, object_id: &str) -> Result<bool, Error> {
     let group_by_object_id = self.group_by_object_id.try_read()?;
     Ok(group_by_object_id.get(object_id).is_some())
@@ -109,19 +189,69 @@ where
     let group = group_by_object_id.remove(object_id);
     drop(group_by_object_id);
 
-    if let Some(group) = group {
-      group.flush_collab().await;
-      // As we've already removed the group, we directly operate on the removed group's subscribers.
-      if let Ok(mut subscribers) = group.subscribers.try_write() {
-        for (_, subscriber) in subscribers.iter_mut() {
-          subscriber.stop().await;
-        }
+    match group {
+      Some(group) => self.close_group(object_id, group).await,
+      None => {
+        // Log error if the group doesn't exist
+        error!("Group for object_id:{} not found", object_id);
+        self.storage.remove_collab_cache(object_id).await;
+      },
+    }
+  }
+
+  /// Close all the groups and wait until their collabs and their updates are written to the
+  /// storage, for at most `timeout`. No group can be created once the groups are closed.
+  ///
+  /// Returns false if the groups were not all written within the timeout.
+  #[instrument(skip(self))]
+  pub async fn close_all_groups(&self, timeout: Duration) -> bool {
+    self.flush_tasks.close();
+    let drain = async {
+      let groups = self
+        .group_by_object_id
+        .write()
+        .await
+        .drain()
+        .collect::<Vec<_>>();
+      info!("closing {} groups", groups.len());
+      for (object_id, group) in groups {
+        self.close_group(&object_id, group).await;
       }
-    } else {
-      // Log error if the group doesn't exist
-      error!("Group for object_id:{} not found", object_id);
+      self.shutdown.cancel();
+      self.flush_tasks.wait().await;
+    };
+    match tokio::time::timeout(timeout, drain).await {
+      Ok(_) => {
+        info!("all groups are flushed");
+        true
+      },
+      Err(_) => {
+        error!(
+          "{} writes of the groups are still pending after {:?}, stop waiting",
+          self.flush_tasks.len(),
+          timeout
+        );
+        false
+      },
     }
+  }
 
+  async fn close_group(&self, object_id: &str, group: Arc<CollabGroup<U>>) {
+    group.flush_collab().await;
+    if let Some(cluster) = &self.cluster {
+      if let Err(err) = cluster.unsubscribe(object_id).await {
+        error!("fail to unsubscribe collab:{}: {}", object_id, err);
+      }
+      if group.is_authoritative() {
+        release_ownership(cluster.as_ref(), object_id).await;
+      }
+    }
+    // As we've already removed the group, we directly operate on the removed group's subscribers.
+    if let Ok(mut subscribers) = group.subscribers.try_write() {
+      for (_, subscriber) in subscribers.iter_mut() {
+        subscriber.stop().await;
+      }
+    }
     self.storage.remove_collab_cache(object_id).await;
   }
 
@@ -138,6 +268,10 @@ where
           warn!("Group for object_id:{} already exists", object_id);
           return;
         }
+        if self.flush_tasks.is_closed() {
+          warn!("The groups are closed, skip creating group:{}", object_id);
+          return;
+        }
 
         let group = self
           .init_group(uid, workspace_id, object_id, collab_type)
@@ -162,29 +296,85 @@ where
     let broadcast = CollabBroadcast::new(object_id, collab.clone(), 10);
     let collab = Arc::new(collab.clone());
 
-    // The lifecycle of the collab is managed by the group.
-    let group = Arc::new(CollabGroup::new(
-      collab_type.clone(),
-      collab.clone(),
-      broadcast,
-    ));
+    // Only the owner of the collab writes it to the storage. If the ownership can't be claimed,
+    // the group is a replica until the ownership is renewed, as another node may own the collab.
+    let authoritative = match &self.cluster {
+      None => true,
+      Some(cluster) => cluster
+        .claim_ownership(object_id)
+        .await
+        .unwrap_or_else(|err| {
+          error!(
+            "fail to claim the ownership of collab:{}: {}",
+            object_id, err
+          );
+          false
+        }),
+    };
+    let authoritative = Arc::new(AtomicBool::new(authoritative));
+    let edit_state = Arc::new(CollabEditState::new());
     let plugin = CollabStoragePlugin::new(
       uid,
       workspace_id,
-      collab_type,
+      object_id,
+      collab_type.clone(),
       self.storage.clone(),
-      Arc::downgrade(&group),
       self.access_control.clone(),
+      authoritative.clone(),
+      self.flush_tasks.clone(),
+      self.shutdown.clone(),
+      edit_state.clone(),
     );
     collab.lock().add_plugin(Box::new(plugin));
     event!(tracing::Level::TRACE, "Init group collab:{}", object_id);
     collab.lock_arc().initialize().await;
 
+    // The changes are published to the other nodes once the collab is loaded. The group
+    // subscribes to the collab before asking for the missing updates, so it receives the replies.
+    let mut cluster_subscription = None;
+    let mut cluster_sync = None;
+    if let Some(cluster) = &self.cluster {
+      let result = match cluster.subscribe(object_id).await {
+        Ok(subscription) => ClusterSync::new(object_id, &collab, cluster.clone())
+          .and_then(|sync| sync.request_sync(&collab).map(|_| (subscription, sync))),
+        Err(err) => Err(crate::error::RealtimeError::Internal(err.into())),
+      };
+      match result {
+        Ok((subscription, sync)) => {
+          cluster_subscription = Some(subscription);
+          cluster_sync = Some(sync);
+        },
+        Err(err) => error!(
+          "fail to sync collab:{} with the cluster: {}",
+          object_id, err
+        ),
+      }
+    }
+
+    // The lifecycle of the collab is managed by the group.
+    let group = Arc::new(CollabGroup::new(
+      workspace_id,
+      collab_type,
+      collab.clone(),
+      broadcast,
+      authoritative,
+      cluster_sync,
+      edit_state,
+    ));
+
     self
       .storage
       .cache_collab(object_id, Arc::downgrade(&collab))
       .await;
     group.observe_collab().await;
+    if let (Some(cluster), Some(subscription)) = (&self.cluster, cluster_subscription) {
+      tokio::spawn(receive_cluster_messages(
+        Arc::downgrade(&group),
+        cluster.clone(),
+        object_id.to_string(),
+        subscription,
+      ));
+    }
     group
   }
 
@@ -194,10 +384,119 @@ where
   }
 }
 
+/// Apply the changes the other nodes publish for the collab of the group, until the group is
+/// closed. The messages the group missed are asked again to the other nodes with a sync request,
+/// which is delayed longer while the group keeps missing messages, so a lagging node doesn't
+/// flood the cluster with requests.
+async fn receive_cluster_messages<U>(
+  group: Weak<CollabGroup<U>>,
+  cluster: Arc<dyn CollabCluster>,
+  object_id: String,
+  mut subscription: ClusterSubscription,
+) where
+  U: RealtimeUser,
+{
+  let mut resync_at: Option<Instant> = None;
+  let mut resync_delay = MIN_RESYNC_DELAY;
+  let mut last_resync_at: Option<Instant> = None;
+  loop {
+    tokio::select! {
+      message = subscription.messages.recv() => {
+        let (message, group) = match (message, group.upgrade()) {
+          (Some(message), Some(group)) => (message, group),
+          _ => break,
+        };
+        match message.payload {
+          ClusterPayload::Released => {
+            group.claim_ownership(cluster.as_ref(), &object_id).await;
+          },
+          payload => {
+            let result = spawn_blocking(move || group.apply_cluster_payload(payload)).await;
+            match result {
+              Ok(Ok(_)) => {},
+              Ok(Err(err)) => error!("fail to apply the change of collab:{}: {}", object_id, err),
+              Err(err) => error!("fail to apply the change of collab:{}: {}", object_id, err),
+            }
+          },
+        }
+      },
+      _ = subscription.missed.notified(), if resync_at.is_none() => {
+        // The delay is reset once the group stopped missing messages for a while
+        if last_resync_at.map_or(true, |at| at.elapsed() > MAX_RESYNC_DELAY) {
+          resync_delay = MIN_RESYNC_DELAY;
+        }
+        warn!(
+          "collab:{} missed messages of the cluster, resync in {:?}",
+          object_id, resync_delay
+        );
+        resync_at = Some(Instant::now() + resync_delay);
+      },
+      _ = sleep_until(resync_at.unwrap_or_else(Instant::now)), if resync_at.is_some() => {
+        let group = match group.upgrade() {
+          Some(group) => group,
+          None => break,
+        };
+        group.request_sync();
+        resync_at = None;
+        last_resync_at = Some(Instant::now());
+        resync_delay = (resync_delay * 2).min(MAX_RESYNC_DELAY);
+      },
+    }
+  }
+}
+
+/// Renew the ownership of the collabs of the groups, or take it over if their owner stopped.
+async fn renew_ownership<U>(
+  group_by_object_id: Weak<RwLock<HashMap<String, Arc<CollabGroup<U>>>>>,
+  cluster: Arc<dyn CollabCluster>,
+) where
+  U: RealtimeUser,
+{
+  let mut interval = interval(OWNERSHIP_RENEW_INTERVAL);
+  loop {
+    interval.tick().await;
+    let groups = match group_by_object_id.upgrade() {
+      Some(group_by_object_id) => group_by_object_id
+        .read()
+        .await
+        .iter()
+        .map(|(object_id, group)| (object_id.clone(), group.clone()))
+        .collect::<Vec<_>>(),
+      None => break,
+    };
+    for (object_id, group) in groups {
+      group.claim_ownership(cluster.as_ref(), &object_id).await;
+    }
+  }
+}
+
+/// Give up the ownership of the collab and tell the other nodes, so one of them can own it.
+async fn release_ownership(cluster: &dyn CollabCluster, object_id: &str) {
+  if let Err(err) = cluster.release_ownership(object_id).await {
+    error!(
+      "fail to release the ownership of collab:{}: {}",
+      object_id, err
+    );
+    return;
+  }
+  let message = ClusterMessage {
+    node_id: cluster.node_id().to_string(),
+    object_id: object_id.to_string(),
+    payload: ClusterPayload::Released,
+  };
+  if let Err(err) = cluster.publish(message).await {
+    error!(
+      "fail to publish the release of collab:{}: {}",
+      object_id, err
+    );
+  }
+}
+
 /// A group used to manage a single [Collab] object
 pub struct CollabGroup<U> {
   pub collab: Arc<MutexCollab>,
-  #[allow(dead_code)]
+  /// The workspace the collab belongs to
+  workspace_id: String,
   collab_type: CollabType,
 
   /// A broadcast used to propagate updates produced by yrs [yrs::Doc] and [Awareness]
@@ -209,6 +508,16 @@ pub struct CollabGroup<U> {
   pub subscribers: RwLock<HashMap<U, Subscription>>,
 
   pub modified_at: Arc<Mutex<Instant>>,
+
+  /// Whether this node owns the collab and writes it to the storage. Always true if the server
+  /// runs on a single node.
+  authoritative: Arc<AtomicBool>,
+
+  /// Keeps the collab in sync with the groups of the same collab on the other nodes
+  cluster_sync: Option<ClusterSync>,
+
+  /// The edits of the collab since it was last flushed
+  edit_state: Arc<CollabEditState>,
 }
 
 impl<U> CollabGroup<U>
@@ -216,17 +525,82 @@ where
   U: RealtimeUser,
 {
   pub fn new(
+    workspace_id: &str,
     collab_type: CollabType,
     collab: Arc<MutexCollab>,
     broadcast: CollabBroadcast,
+    authoritative: Arc<AtomicBool>,
+    cluster_sync: Option<ClusterSync>,
+    edit_state: Arc<CollabEditState>,
   ) -> Self {
     let modified_at = Arc::new(Mutex::new(Instant::now()));
     Self {
+      workspace_id: workspace_id.to_string(),
       collab_type,
       collab,
       broadcast,
       subscribers: Default::default(),
       modified_at,
+      authoritative,
+      cluster_sync,
+      edit_state,
+    }
+  }
+
+  pub fn is_authoritative(&self) -> bool {
+    self.authoritative.load(Ordering::SeqCst)
+  }
+
+  /// Whether the update log of the collab should be merged into the collab. Only the owner of the
+  /// collab writes it.
+  fn should_flush(&self, policy: CollabFlushPolicy) -> bool {
+    self.is_authoritative()
+      && self
+        .edit_state
+        .should_flush(policy.max_edit_count, policy.max_interval_secs)
+  }
+
+  /// Acquire or renew the ownership of the collab. The node that takes the ownership over writes
+  /// the collab right away, as its replica holds the changes that were not written by the
+  /// previous owner.
+  ///
+  /// The node stops writing the collab if the ownership can't be renewed, as it may expire and
+  /// be taken by another node meanwhile. It's claimed again on the next renewal.
+  async fn claim_ownership(&self, cluster: &dyn CollabCluster, object_id: &str) {
+    let owned = cluster
+      .claim_ownership(object_id)
+      .await
+      .unwrap_or_else(|err| {
+        error!(
+          "fail to claim the ownership of collab:{}: {}",
+          object_id, err
+        );
+        false
+      });
+    let was_owned = self.authoritative.swap(owned, Ordering::SeqCst);
+    if owned && !was_owned {
+      info!("take over the ownership of collab:{}", object_id);
+      self.flush_collab().await;
+    } else if !owned && was_owned {
+      warn!("lose the ownership of collab:{}", object_id);
+    }
+  }
+
+  fn apply_cluster_payload(
+    &self,
+    payload: ClusterPayload,
+  ) -> Result<(), crate::error::RealtimeError> {
+    match &self.cluster_sync {
+      Some(cluster_sync) => cluster_sync.apply(&self.collab, payload),
+      None => Ok(()),
+    }
+  }
+
+  fn request_sync(&self) {
+    if let Some(cluster_sync) = &self.cluster_sync {
+      if let Err(err) = cluster_sync.request_sync(&self.collab) {
+        error!("fail to request the sync of the collab: {}", err);
+      }
     }
   }
 
@@ -308,3 +682,310 @@ where
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use app_error::AppError;
+  use async_trait::async_trait;
+  use database::collab::{DatabaseResult, FlushPolicyConfig, WriteConfig};
+  use database_entity::dto::{
+    AFAccessLevel, AFSnapshotMeta, AFSnapshotMetas, CollabParams, CreateCollabParams,
+    InsertSnapshotParams, QueryCollab, QueryCollabParams, QueryCollabResult, SnapshotData,
+  };
+  use reqwest::Method;
+  use std::fmt::{Display, Formatter};
+  use std::sync::atomic::AtomicU32;
+  use std::sync::Weak;
+
+  /// Keeps the collabs and their update logs in memory. The writes never complete once stalled.
+  #[derive(Default)]
+  struct MemoryCollabStorage {
+    config: WriteConfig,
+    collabs: parking_lot::Mutex<HashMap<String, Vec<u8>>>,
+    updates: parking_lot::Mutex<HashMap<String, Vec<Vec<u8>>>>,
+    upsert_count: AtomicU32,
+    stalled: AtomicBool,
+  }
+
+  impl MemoryCollabStorage {
+    async fn wait_if_stalled(&self) {
+      if self.stalled.load(Ordering::SeqCst) {
+        std::future::pending::<()>().await;
+      }
+    }
+  }
+
+  #[async_trait]
+  impl CollabStorage for MemoryCollabStorage {
+    fn config(&self) -> &WriteConfig {
+      &self.config
+    }
+
+    fn mem_usage(&self) -> usize {
+      0
+    }
+
+    async fn cache_collab(&self, _object_id: &str, _collab: Weak<MutexCollab>) {}
+
+    async fn remove_collab_cache(&self, _object_id: &str) {}
+
+    async fn upsert_collab(&self, _uid: &i64, params: CreateCollabParams) -> DatabaseResult<()> {
+      self.wait_if_stalled().await;
+      self.upsert_count.fetch_add(1, Ordering::SeqCst);
+      self
+        .collabs
+        .lock()
+        .insert(params.object_id, params.encoded_collab_v1);
+      Ok(())
+    }
+
+    async fn upsert_collab_with_transaction(
+      &self,
+      _workspace_id: &str,
+      _uid: &i64,
+      params: CollabParams,
+      _transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> DatabaseResult<()> {
+      self.wait_if_stalled().await;
+      self.upsert_count.fetch_add(1, Ordering::SeqCst);
+      self
+        .collabs
+        .lock()
+        .insert(params.object_id, params.encoded_collab_v1);
+      Ok(())
+    }
+
+    async fn get_collab_encoded(
+      &self,
+      _uid: &i64,
+      params: QueryCollabParams,
+    ) -> DatabaseResult<EncodedCollab> {
+      let object_id = params.inner.object_id;
+      match self.collabs.lock().get(&object_id) {
+        Some(encoded_collab_v1) => EncodedCollab::decode_from_bytes(encoded_collab_v1)
+          .map_err(|err| AppError::Internal(err.into())),
+        None => Err(AppError::RecordNotFound(object_id)),
+      }
+    }
+
+    async fn batch_get_collab(
+      &self,
+      _uid: &i64,
+      _queries: Vec<QueryCollab>,
+    ) -> HashMap<String, QueryCollabResult> {
+      HashMap::new()
+    }
+
+    async fn append_collab_updates(
+      &self,
+      _workspace_id: &str,
+      object_id: &str,
+      _collab_type: &CollabType,
+      updates: Vec<Vec<u8>>,
+    ) -> DatabaseResult<()> {
+      self.wait_if_stalled().await;
+      self
+        .updates
+        .lock()
+        .entry(object_id.to_string())
+        .or_default()
+        .extend(updates);
+      Ok(())
+    }
+
+    async fn compact_collab_updates(
+      &self,
+      object_id: &str,
+      _collab_type: &CollabType,
+    ) -> DatabaseResult<()> {
+      self.wait_if_stalled().await;
+      self.updates.lock().remove(object_id);
+      Ok(())
+    }
+
+    async fn delete_collab(&self, _uid: &i64, object_id: &str) -> DatabaseResult<()> {
+      self.collabs.lock().remove(object_id);
+      Ok(())
+    }
+
+    async fn should_create_snapshot(&self, _oid: &str) -> bool {
+      false
+    }
+
+    async fn create_snapshot(
+      &self,
+      params: InsertSnapshotParams,
+    ) -> DatabaseResult<AFSnapshotMeta> {
+      Err(AppError::Unhandled(format!(
+        "snapshot of {} is not supported by the memory storage",
+        params.object_id
+      )))
+    }
+
+    async fn get_collab_snapshot(&self, snapshot_id: &i64) -> DatabaseResult<SnapshotData> {
+      Err(AppError::RecordNotFound(snapshot_id.to_string()))
+    }
+
+    async fn get_collab_snapshot_list(&self, _oid: &str) -> DatabaseResult<AFSnapshotMetas> {
+      Ok(AFSnapshotMetas(vec![]))
+    }
+  }
+
+  struct FullAccessControl;
+
+  #[async_trait]
+  impl CollabAccessControl for FullAccessControl {
+    async fn get_collab_access_level(
+      &self,
+      _uid: &i64,
+      _oid: &str,
+    ) -> Result<AFAccessLevel, AppError> {
+      Ok(AFAccessLevel::FullAccess)
+    }
+
+    async fn insert_collab_access_level(
+      &self,
+      _uid: &i64,
+      _oid: &str,
+      _level: AFAccessLevel,
+    ) -> Result<(), AppError> {
+      Ok(())
+    }
+
+    async fn can_access_http_method(
+      &self,
+      _uid: &i64,
+      _oid: &str,
+      _method: &Method,
+    ) -> Result<bool, AppError> {
+      Ok(true)
+    }
+
+    async fn can_send_collab_update(&self, _uid: &i64, _oid: &str) -> Result<bool, AppError> {
+      Ok(true)
+    }
+
+    async fn can_receive_collab_update(&self, _uid: &i64, _oid: &str) -> Result<bool, AppError> {
+      Ok(true)
+    }
+  }
+
+  #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+  struct TestUser {
+    uid: i64,
+    device_id: String,
+  }
+
+  impl Display for TestUser {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+      write!(f, "{}:{}", self.uid, self.device_id)
+    }
+  }
+
+  impl RealtimeUser for TestUser {
+    fn uid(&self) -> i64 {
+      self.uid
+    }
+
+    fn device_id(&self) -> &str {
+      &self.device_id
+    }
+  }
+
+  type TestGroupCache = CollabGroupCache<MemoryCollabStorage, TestUser, FullAccessControl>;
+
+  async fn edited_group(groups: &TestGroupCache, object_id: &str) {
+    groups
+      .create_group_if_need(1, "workspace", object_id, CollabType::Document)
+      .await;
+    let group = groups.get_group(object_id).await.unwrap();
+    group.get_mut_collab(|collab| collab.insert("title", "hello".to_string()));
+  }
+
+  async fn wait_until(condition: impl Fn() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+      while !condition() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+      }
+    })
+    .await
+    .unwrap();
+  }
+
+  #[actix_rt::test]
+  async fn close_all_groups_writes_collabs_and_updates_test() {
+    let storage = Arc::new(MemoryCollabStorage::default());
+    let groups = TestGroupCache::new(storage.clone(), Arc::new(FullAccessControl), None);
+    let object_id = uuid::Uuid::new_v4().to_string();
+    edited_group(&groups, &object_id).await;
+
+    assert!(groups.close_all_groups(Duration::from_secs(5)).await);
+    assert!(!storage.updates.lock()[&object_id].is_empty());
+    let encoded_collab =
+      EncodedCollab::decode_from_bytes(&storage.collabs.lock()[&object_id]).unwrap();
+    let collab = Collab::new_with_doc_state(
+      CollabOrigin::Server,
+      &object_id,
+      encoded_collab.doc_state.to_vec(),
+      vec![],
+    )
+    .unwrap();
+    assert_eq!(collab.to_json_value()["title"], "hello");
+
+    // No group is created once the groups are closed
+    groups
+      .create_group_if_need(1, "workspace", &object_id, CollabType::Document)
+      .await;
+    assert!(groups.get_group(&object_id).await.is_none());
+  }
+
+  #[actix_rt::test]
+  async fn close_all_groups_stops_waiting_after_timeout_test() {
+    let storage = Arc::new(MemoryCollabStorage::default());
+    let groups = TestGroupCache::new(storage.clone(), Arc::new(FullAccessControl), None);
+    let object_id = uuid::Uuid::new_v4().to_string();
+    edited_group(&groups, &object_id).await;
+
+    storage.stalled.store(true, Ordering::SeqCst);
+    let started_at = Instant::now();
+    assert!(!groups.close_all_groups(Duration::from_millis(200)).await);
+    assert!(started_at.elapsed() < Duration::from_secs(5));
+  }
+
+  #[actix_rt::test]
+  async fn flush_policy_compacts_updates_test() {
+    let mut flush_policy = FlushPolicyConfig::default();
+    flush_policy.document.max_edit_count = 1;
+    let storage = Arc::new(MemoryCollabStorage {
+      config: WriteConfig {
+        flush_per_update: 100,
+        flush_policy,
+      },
+      ..Default::default()
+    });
+    let groups = TestGroupCache::new(storage.clone(), Arc::new(FullAccessControl), None);
+    let object_id = uuid::Uuid::new_v4().to_string();
+    edited_group(&groups, &object_id).await;
+    wait_until(|| storage.updates.lock().contains_key(&object_id)).await;
+    let upsert_count = storage.upsert_count.load(Ordering::SeqCst);
+
+    // The update log is merged into the collab, which is not written as a whole
+    groups.tick().await;
+    wait_until(|| !storage.updates.lock().contains_key(&object_id)).await;
+    assert_eq!(storage.upsert_count.load(Ordering::SeqCst), upsert_count);
+
+    // Nothing is compacted until the collab is edited again
+    let group = groups.get_group(&object_id).await.unwrap();
+    // The policy starts over once the compaction returns, which is after the log was removed
+    wait_until(|| match group.edit_state.begin_policy_flush() {
+      Some(_) => {
+        group.edit_state.end_policy_flush(None);
+        true
+      },
+      None => false,
+    })
+    .await;
+    assert!(!group.should_flush(storage.config().flush_policy.document));
+  }
+}
@@ -1,12 +1,30 @@
+use crate::collaborate::{RateLimitConfig, RateLimitScope};
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
 use prometheus_client::metrics::gauge::Gauge;
 use prometheus_client::registry::Registry;
 use tracing::trace;
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct RateLimitLabel {
+  pub scope: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct RateLimitedLabel {
+  pub scope: String,
+  /// `delayed` or `dropped`
+  pub action: String,
+}
+
 #[derive(Clone)]
 pub struct RealtimeMetrics {
   connected_users: Gauge,
   mem_cache_usage: Gauge,
   opening_collab_count: Gauge,
+  rate_limit: Family<RateLimitLabel, Gauge>,
+  rate_limited_messages: Family<RateLimitedLabel, Counter>,
 }
 
 impl RealtimeMetrics {
@@ -15,6 +33,8 @@ impl RealtimeMetrics {
       connected_users: Gauge::default(),
       mem_cache_usage: Gauge::default(),
       opening_collab_count: Gauge::default(),
+      rate_limit: Family::default(),
+      rate_limited_messages: Family::default(),
     }
   }
 
@@ -36,6 +56,16 @@ impl RealtimeMetrics {
       "number of opening collabs",
       metrics.opening_collab_count.clone(),
     );
+    realtime_registry.register(
+      "rate_limit",
+      "number of collab messages allowed per second",
+      metrics.rate_limit.clone(),
+    );
+    realtime_registry.register(
+      "rate_limited_messages",
+      "number of collab messages delayed or dropped by the rate limits",
+      metrics.rate_limited_messages.clone(),
+    );
 
     metrics
   }
@@ -55,4 +85,35 @@ impl RealtimeMetrics {
     trace!("[metrics]: opening_collab_count: {}", count);
     self.opening_collab_count.set(count as i64);
   }
+
+  pub fn record_rate_limit(&self, config: &RateLimitConfig) {
+    for (scope, limit) in [
+      (RateLimitScope::User, config.user_per_sec),
+      (RateLimitScope::Device, config.device_per_sec),
+      (RateLimitScope::Object, config.object_per_sec),
+    ] {
+      self
+        .rate_limit
+        .get_or_create(&RateLimitLabel {
+          scope: scope.as_str().to_string(),
+        })
+        .set(limit as i64);
+    }
+  }
+
+  pub fn record_rate_limited_message(&self, scope: RateLimitScope, dropped: bool) {
+    let action = if dropped { "dropped" } else { "delayed" };
+    trace!(
+      "[metrics]: rate limited message: {} {}",
+      scope.as_str(),
+      action
+    );
+    self
+      .rate_limited_messages
+      .get_or_create(&RateLimitedLabel {
+        scope: scope.as_str().to_string(),
+        action: action.to_string(),
+      })
+      .inc();
+  }
 }
@@ -1,14 +1,18 @@
 mod broadcast;
+mod cluster;
 mod group;
 mod metrics;
 mod permission;
 mod plugin;
+mod rate_limit;
 mod retry;
 mod server;
 mod sync_protocol;
 
 pub use broadcast::*;
+pub use cluster::*;
 pub use metrics::*;
 pub use permission::*;
 pub use plugin::*;
+pub use rate_limit::*;
 pub use server::*;
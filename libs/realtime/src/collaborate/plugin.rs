@@ -1,5 +1,3 @@
-use crate::collaborate::group::CollabGroup;
-use crate::entities::RealtimeUser;
 use crate::error::RealtimeError;
 use app_error::AppError;
 use async_trait::async_trait;
@@ -20,53 +18,82 @@ use database_entity::dto::{
 use md5::Digest;
 use parking_lot::Mutex;
 use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, Ordering};
-use std::sync::{Arc, Weak};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
 use tracing::{debug, error, event, info, instrument, trace};
 
 use yrs::updates::decoder::Decode;
 use yrs::{Transact, Update};
 
-pub struct CollabStoragePlugin<S, U, AC> {
+/// Maximum number of updates that are written to the update log at once.
+const MAX_UPDATE_BATCH_SIZE: usize = 100;
+
+pub struct CollabStoragePlugin<S, AC> {
   uid: i64,
   workspace_id: String,
   storage: Arc<S>,
   edit_state: Arc<CollabEditState>,
-  group: Weak<CollabGroup<U>>,
   collab_type: CollabType,
   access_control: Arc<AC>,
   latest_collab_md5: Mutex<Option<Digest>>,
+  /// Sends the updates received by the collab to the task that appends them to the update log.
+  update_sender: mpsc::UnboundedSender<Vec<u8>>,
+  /// Whether this node owns the collab. The collab is only written by its owner, the other nodes
+  /// hold replicas that are kept in sync with the owner.
+  authoritative: Arc<AtomicBool>,
+  /// Tracks the tasks that write the collab to the storage, so they can be awaited on shutdown.
+  flush_tasks: TaskTracker,
 }
 
-impl<S, U, AC> CollabStoragePlugin<S, U, AC>
+impl<S, AC> CollabStoragePlugin<S, AC>
 where
   S: CollabStorage,
-  U: RealtimeUser,
   AC: CollabAccessControl,
 {
   pub fn new(
     uid: i64,
     workspace_id: &str,
+    object_id: &str,
     collab_type: CollabType,
     storage: S,
-    group: Weak<CollabGroup<U>>,
     access_control: Arc<AC>,
+    authoritative: Arc<AtomicBool>,
+    flush_tasks: TaskTracker,
+    shutdown: CancellationToken,
+    edit_state: Arc<CollabEditState>,
   ) -> Self {
     let storage = Arc::new(storage);
     let workspace_id = workspace_id.to_string();
-    let edit_state = Arc::new(CollabEditState::new());
+    let (update_sender, update_receiver) = mpsc::unbounded_channel();
+    flush_tasks.spawn(append_collab_updates(
+      storage.clone(),
+      workspace_id.clone(),
+      object_id.to_string(),
+      collab_type.clone(),
+      update_receiver,
+      shutdown,
+    ));
     Self {
       uid,
       workspace_id,
       storage,
       edit_state,
-      group,
       collab_type,
       access_control,
       latest_collab_md5: Default::default(),
+      update_sender,
+      authoritative,
+      flush_tasks,
     }
   }
 
+  fn is_authoritative(&self) -> bool {
+    self.authoritative.load(Ordering::SeqCst)
+  }
+
   #[instrument(level = "info", skip(self,doc), err, fields(object_id = %object_id))]
   async fn insert_new_collab(&self, doc: &Doc, object_id: &str) -> Result<(), AppError> {
     match doc.get_encoded_collab_v1().encode_to_bytes() {
@@ -101,6 +128,49 @@ where
   }
 }
 
+/// Append the updates received by the plugin to the update log of the collab, in the order they
+/// were received. The updates that are received while a batch is written are written together in
+/// the next batch. The task ends when the plugin is dropped, or once the received updates are
+/// written when the server shuts down.
+///
+/// An update that fails to be written is not retried: the whole collab is still written when the
+/// group of the collab is closed.
+async fn append_collab_updates<S>(
+  storage: Arc<S>,
+  workspace_id: String,
+  object_id: String,
+  collab_type: CollabType,
+  mut update_receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+  shutdown: CancellationToken,
+) where
+  S: CollabStorage,
+{
+  loop {
+    let update = tokio::select! {
+      biased;
+      update = update_receiver.recv() => match update {
+        Some(update) => update,
+        None => break,
+      },
+      _ = shutdown.cancelled() => break,
+    };
+    let mut updates = vec![update];
+    while updates.len() < MAX_UPDATE_BATCH_SIZE {
+      match update_receiver.try_recv() {
+        Ok(update) => updates.push(update),
+        Err(_) => break,
+      }
+    }
+
+    if let Err(err) = storage
+      .append_collab_updates(&workspace_id, &object_id, &collab_type, updates)
+      .await
+    {
+      error!("fail to append updates of collab:{}: {:?}", object_id, err);
+    }
+  }
+}
+
 async fn init_collab(
   oid: &str,
   encoded_collab: &EncodedCollab,
@@ -129,10 +199,9 @@ async fn init_collab(
 }
 
 #[async_trait]
-impl<S, U, AC> CollabPlugin for CollabStoragePlugin<S, U, AC>
+impl<S, AC> CollabPlugin for CollabStoragePlugin<S, AC>
 where
   S: CollabStorage,
-  U: RealtimeUser,
   AC: CollabAccessControl,
 {
   async fn init(&self, object_id: &str, _origin: &CollabOrigin, doc: &Doc) {
@@ -143,7 +212,7 @@ where
           // Attempt to create a snapshot for the collaboration object. When creating this snapshot, it is
           // assumed that the 'encoded_collab_v1' is already in a valid format. Therefore, there is no need
           // to verify the outcome of the 'encode_to_bytes' operation.
-          if self.storage.should_create_snapshot(object_id).await {
+          if self.is_authoritative() && self.storage.should_create_snapshot(object_id).await {
             let cloned_workspace_id = self.workspace_id.clone();
             let cloned_object_id = object_id.to_string();
             let storage = self.storage.clone();
@@ -192,7 +261,7 @@ where
         },
       },
       Err(err) => match &err {
-        AppError::RecordNotFound(_) => {
+        AppError::RecordNotFound(_) if self.is_authoritative() => {
           // When attempting to retrieve collaboration data from the disk and a 'Record Not Found' error is returned,
           // this indicates that the collaboration is new. Therefore, the current collaboration data should be saved to disk.
           event!(
@@ -204,6 +273,8 @@ where
             error!("Insert collab {:?}", err);
           }
         },
+        // The new collab is inserted by the node that owns it
+        AppError::RecordNotFound(_) => trace!("wait for the owner to create collab:{}", object_id),
         _ => error!("{}", err),
       },
     }
@@ -212,27 +283,43 @@ where
     self.edit_state.set_did_load()
   }
 
-  fn receive_update(&self, _object_id: &str, _txn: &TransactionMut, _update: &[u8]) {
+  fn receive_update(&self, object_id: &str, _txn: &TransactionMut, update: &[u8]) {
     let count = self.edit_state.increment_edit_count();
-    if !self.edit_state.did_load() {
+    if !self.edit_state.did_load() || !self.is_authoritative() {
       return;
     }
 
-    if count >= self.storage.config().flush_per_update {
-      self.edit_state.flush_edit();
-      trace!("number of updates reach flush_per_update, start flushing");
-      match self.group.upgrade() {
-        None => error!("Group is dropped, skip flush collab"),
-        Some(group) => {
-          tokio::spawn(async move {
-            group.flush_collab().await;
-          });
-        },
-      }
+    if self.update_sender.send(update.to_vec()).is_err() {
+      error!("fail to append update of collab:{}", object_id);
+    }
+
+    // Instead of writing the whole collab, the updates are appended to the update log and merged
+    // into the collab from time to time.
+    if count.saturating_sub(self.edit_state.compact_edit_count())
+      >= self.storage.config().flush_per_update
+    {
+      self.edit_state.compact_edit();
+      trace!("number of updates reach flush_per_update, start compacting");
+      let storage = self.storage.clone();
+      let object_id = object_id.to_string();
+      let collab_type = self.collab_type.clone();
+      self.flush_tasks.spawn(async move {
+        if let Err(err) = storage
+          .compact_collab_updates(&object_id, &collab_type)
+          .await
+        {
+          error!("fail to compact updates of collab:{}: {:?}", object_id, err);
+        }
+      });
     }
   }
 
   fn flush(&self, object_id: &str, doc: &Doc) {
+    if !self.is_authoritative() {
+      return;
+    }
+    self.edit_state.flush_edit();
+
     let encoded_collab_v1 = match doc.get_encoded_collab_v1().encode_to_bytes() {
       Ok(data) => data,
       Err(err) => {
@@ -242,7 +329,7 @@ where
     };
 
     // compare the current encoded collab md5 with the latest one, if they are the same, skip the flush.
-    // TODO(nathan): compress the encoded collab to reduce disk usage.
+    // The storage compresses the encoded collab before writing it to the disk.
     let digest = md5::compute(&encoded_collab_v1);
     if self.latest_collab_md5.lock().as_ref() == Some(&digest) {
       debug!(
@@ -264,7 +351,7 @@ where
 
     let storage = self.storage.clone();
     let uid = self.uid;
-    tokio::spawn(async move {
+    self.flush_tasks.spawn(async move {
       info!("[realtime] flush collab: {}", params.object_id);
       match storage.upsert_collab(&uid, params).await {
         Ok(_) => {},
@@ -284,19 +371,34 @@ where
   EncodedCollab::decode_from_bytes(&snapshot_data.encoded_collab_v1).ok()
 }
 
-struct CollabEditState {
+/// Counts the edits of a collab since it was last written to the storage. Shared by the plugin and
+/// the group of the collab, which decides when to flush the collab.
+pub struct CollabEditState {
   edit_count: AtomicU32,
+  /// The edit count when the plugin last compacted the updates after `flush_per_update` edits
+  compact_edit_count: AtomicU32,
+  /// The edit count and the time when the collab was last flushed, by its [CollabFlushPolicy] or
+  /// when its group is closed
+  ///
+  /// [CollabFlushPolicy]: database::collab::CollabFlushPolicy
   flush_edit_count: AtomicU32,
   flush_interval: AtomicI64,
+  /// Set while the update log is compacted by the [CollabFlushPolicy], so it's compacted once at a
+  /// time
+  ///
+  /// [CollabFlushPolicy]: database::collab::CollabFlushPolicy
+  policy_flushing: AtomicBool,
   did_load_collab: AtomicBool,
 }
 
 impl CollabEditState {
-  fn new() -> Self {
+  pub(crate) fn new() -> Self {
     Self {
       edit_count: AtomicU32::new(0),
+      compact_edit_count: Default::default(),
       flush_edit_count: Default::default(),
       flush_interval: AtomicI64::new(chrono::Utc::now().timestamp()),
+      policy_flushing: AtomicBool::new(false),
       did_load_collab: AtomicBool::new(false),
     }
   }
@@ -313,44 +415,143 @@ impl CollabEditState {
     self.edit_count.fetch_add(1, Ordering::SeqCst)
   }
 
-  fn flush_edit(&self) {
+  fn compact_edit_count(&self) -> u32 {
+    self.compact_edit_count.load(Ordering::SeqCst)
+  }
+
+  fn compact_edit(&self) {
     self
-      .flush_edit_count
+      .compact_edit_count
       .store(self.edit_count.load(Ordering::SeqCst), Ordering::SeqCst);
+  }
+
+  fn flush_edit(&self) {
+    self.flush_edit_at(self.edit_count.load(Ordering::SeqCst));
+  }
+
+  fn flush_edit_at(&self, edit_count: u32) {
+    self.flush_edit_count.store(edit_count, Ordering::SeqCst);
     self
       .flush_interval
       .store(chrono::Utc::now().timestamp(), Ordering::SeqCst);
   }
 
+  /// Start a flush by the policy and return the edit count it covers, or None if one is already
+  /// running.
+  pub(crate) fn begin_policy_flush(&self) -> Option<u32> {
+    self
+      .policy_flushing
+      .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+      .ok()?;
+    Some(self.edit_count.load(Ordering::SeqCst))
+  }
+
+  /// End the flush started by [Self::begin_policy_flush]. The policy only starts over from the
+  /// flushed edit count if the flush succeeded, otherwise the flush is retried on the next check.
+  pub(crate) fn end_policy_flush(&self, flushed_edit_count: Option<u32>) {
+    if let Some(edit_count) = flushed_edit_count {
+      self.flush_edit_at(edit_count);
+    }
+    self.policy_flushing.store(false, Ordering::SeqCst);
+  }
+
   /// Determines whether a flush operation should be performed based on edit count and time interval.
   ///
-  /// This function checks two conditions to decide if flushing is necessary:
-  /// 1. Time-based: Compares the current time with the last flush time. A flush is needed if the time
-  /// elapsed since the last flush is greater than or equal to `max_interval`.
+  /// Nothing is flushed if there is no edit since the last flush. Otherwise, a flush is needed if
+  /// either of the following conditions is met:
+  /// 1. Time-based: the time elapsed since the last flush is greater than or equal to
+  /// `max_interval`.
   ///
-  /// 2. Edit count-based: Compares the current edit count with the last flush edit count. A flush is
-  /// required if the number of new edits since the last flush is greater than or equal to `max_edit_count`.
+  /// 2. Edit count-based: the number of new edits since the last flush is greater than or equal to
+  /// `max_edit_count`.
   ///
   /// # Arguments
   /// * `max_edit_count` - The maximum number of edits allowed before a flush is triggered.
   /// * `max_interval` - The maximum time interval (in seconds) allowed before a flush is triggered.
-  #[allow(dead_code)]
-  fn should_flush(&self, max_edit_count: u32, max_interval: i64) -> bool {
-    // compare current time with last flush time
-    let current = chrono::Utc::now().timestamp();
-    let prev = self.flush_interval.load(Ordering::SeqCst);
+  pub(crate) fn should_flush(&self, max_edit_count: u32, max_interval: i64) -> bool {
+    if self.policy_flushing.load(Ordering::SeqCst) {
+      return false;
+    }
     let current_edit_count = self.edit_count.load(Ordering::SeqCst);
     let prev_flush_edit_count = self.flush_edit_count.load(Ordering::SeqCst);
+    if current_edit_count <= prev_flush_edit_count {
+      return false;
+    }
 
-    if current > prev && current_edit_count != prev_flush_edit_count {
-      return current - prev >= max_interval;
+    // compare current time with last flush time
+    let current = chrono::Utc::now().timestamp();
+    let prev = self.flush_interval.load(Ordering::SeqCst);
+    if current - prev >= max_interval {
+      return true;
     }
 
     // compare current edit count with last flush edit count
-    if current_edit_count > prev_flush_edit_count {
-      return current_edit_count - prev_flush_edit_count >= max_edit_count;
-    }
+    current_edit_count - prev_flush_edit_count >= max_edit_count
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn should_not_flush_without_edit_test() {
+    let state = CollabEditState::new();
+    state
+      .flush_interval
+      .store(chrono::Utc::now().timestamp() - 120, Ordering::SeqCst);
+    assert!(!state.should_flush(1, 60));
+  }
+
+  #[test]
+  fn should_flush_after_max_edit_count_test() {
+    let state = CollabEditState::new();
+    state.increment_edit_count();
+    assert!(!state.should_flush(2, 60));
+    state.increment_edit_count();
+    assert!(state.should_flush(2, 60));
+
+    state.flush_edit();
+    assert!(!state.should_flush(2, 60));
+  }
+
+  #[test]
+  fn failed_policy_flush_is_retried_test() {
+    let state = CollabEditState::new();
+    state.increment_edit_count();
+    assert!(state.should_flush(1, 60));
+
+    assert_eq!(state.begin_policy_flush(), Some(1));
+    assert!(state.begin_policy_flush().is_none());
+    assert!(!state.should_flush(1, 60));
+    state.end_policy_flush(None);
+    assert!(state.should_flush(1, 60));
+
+    let edit_count = state.begin_policy_flush().unwrap();
+    // The edits made during the flush are left to the next flush
+    state.increment_edit_count();
+    state.end_policy_flush(Some(edit_count));
+    assert!(state.should_flush(1, 60));
+  }
+
+  #[test]
+  fn should_flush_after_max_interval_test() {
+    let state = CollabEditState::new();
+    state.increment_edit_count();
+    assert!(!state.should_flush(100, 60));
+    state
+      .flush_interval
+      .store(chrono::Utc::now().timestamp() - 60, Ordering::SeqCst);
+    assert!(state.should_flush(100, 60));
+  }
 
-    false
+  #[test]
+  fn compaction_does_not_reset_flush_policy_test() {
+    let state = CollabEditState::new();
+    state.increment_edit_count();
+    state.increment_edit_count();
+    state.compact_edit();
+    assert_eq!(state.compact_edit_count(), 2);
+    assert!(state.should_flush(2, 60));
   }
 }
@@ -0,0 +1,338 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The messages over the limit are delayed until they fit in the limit, unless they would be
+/// delayed longer than this. The messages that can be dropped are dropped then.
+const MAX_DELAY: Duration = Duration::from_secs(1);
+/// Number of seconds worth of messages that can be sent at once.
+const BURST_SECS: f64 = 2.0;
+/// The client is told about the limit at most once per interval.
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of collab messages per second the server accepts.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+  /// Messages a user sends from all its devices
+  pub user_per_sec: u32,
+  /// Messages a single device of a user sends
+  pub device_per_sec: u32,
+  /// Messages all the users send to the same collab
+  pub object_per_sec: u32,
+}
+
+impl Default for RateLimitConfig {
+  fn default() -> Self {
+    Self {
+      user_per_sec: 30,
+      device_per_sec: 10,
+      object_per_sec: 100,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitScope {
+  User,
+  Device,
+  Object,
+}
+
+impl RateLimitScope {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      RateLimitScope::User => "user",
+      RateLimitScope::Device => "device",
+      RateLimitScope::Object => "object",
+    }
+  }
+}
+
+/// A message that exceeds one of the limits.
+#[derive(Debug, Clone)]
+pub struct RateLimited {
+  /// The limit with the longest wait
+  pub scope: RateLimitScope,
+  /// The messages per second allowed by the limit
+  pub limit: u32,
+  /// How long the message must wait to fit in the limits. None if the message is dropped.
+  pub delay: Option<Duration>,
+  /// Whether the client should be told to slow down
+  pub notify: bool,
+}
+
+/// Token buckets that limit the collab messages per user, per device and per object. A message
+/// takes a token from each of its buckets, and the buckets are refilled at the configured rate.
+pub struct RealtimeRateLimiter {
+  config: RateLimitConfig,
+  buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+  by_user: HashMap<i64, TokenBucket>,
+  by_device: HashMap<(i64, String), TokenBucket>,
+  by_object: HashMap<String, TokenBucket>,
+  notified_at: HashMap<(i64, String), Instant>,
+}
+
+impl RealtimeRateLimiter {
+  pub fn new(config: RateLimitConfig) -> Self {
+    Self {
+      config,
+      buckets: Default::default(),
+    }
+  }
+
+  /// Take a token for the message from the buckets of the user, the device and the object.
+  /// Returns None if the message fits in the limits.
+  ///
+  /// A message that would wait longer than [MAX_DELAY] is dropped if `can_drop` is true, and
+  /// takes no token then. Otherwise it's delayed as long as needed.
+  pub fn check(
+    &self,
+    uid: i64,
+    device_id: &str,
+    object_id: &str,
+    can_drop: bool,
+  ) -> Option<RateLimited> {
+    let now = Instant::now();
+    let device_key = (uid, device_id.to_string());
+    let mut buckets = self.buckets.lock();
+    let Buckets {
+      by_user,
+      by_device,
+      by_object,
+      notified_at,
+    } = &mut *buckets;
+
+    let user_bucket = by_user
+      .entry(uid)
+      .or_insert_with(|| TokenBucket::new(self.config.user_per_sec, now));
+    let device_bucket = by_device
+      .entry(device_key.clone())
+      .or_insert_with(|| TokenBucket::new(self.config.device_per_sec, now));
+
+    let mut limited: Option<Limit> = None;
+    update_limit(&mut limited, RateLimitScope::User, user_bucket, now);
+    update_limit(&mut limited, RateLimitScope::Device, device_bucket, now);
+    let is_dropped =
+      |limited: &Option<Limit>| can_drop && limited.is_some_and(|(_, _, wait)| wait > MAX_DELAY);
+
+    // The bucket of the object is shared by all the users of the collab, so it's only checked
+    // and charged once the limits of the user and of the device allow the message
+    let mut dropped = is_dropped(&limited);
+    if !dropped {
+      let object_bucket = by_object
+        .entry(object_id.to_string())
+        .or_insert_with(|| TokenBucket::new(self.config.object_per_sec, now));
+      update_limit(&mut limited, RateLimitScope::Object, object_bucket, now);
+      dropped = is_dropped(&limited);
+      if !dropped {
+        // The tokens of a delayed message are reserved, so the next messages wait until it's sent
+        user_bucket.take();
+        device_bucket.take();
+        object_bucket.take();
+      }
+    }
+
+    let (scope, limit, wait) = limited?;
+    let delay = (!dropped).then_some(wait);
+
+    let notify = match notified_at.get(&device_key) {
+      Some(notified_at) if now.duration_since(*notified_at) < NOTIFY_INTERVAL => false,
+      _ => {
+        notified_at.insert(device_key, now);
+        true
+      },
+    };
+
+    Some(RateLimited {
+      scope,
+      limit,
+      delay,
+      notify,
+    })
+  }
+
+  /// Remove the buckets that are full, as their users or objects haven't sent any message for a
+  /// while.
+  pub fn prune(&self) {
+    let now = Instant::now();
+    let mut buckets = self.buckets.lock();
+    buckets.by_user.retain(|_, bucket| !bucket.is_full(now));
+    buckets.by_device.retain(|_, bucket| !bucket.is_full(now));
+    buckets.by_object.retain(|_, bucket| !bucket.is_full(now));
+    buckets
+      .notified_at
+      .retain(|_, notified_at| now.duration_since(*notified_at) < NOTIFY_INTERVAL);
+  }
+}
+
+/// The scope, the rate and the wait of the limit with the longest wait.
+type Limit = (RateLimitScope, u32, Duration);
+
+/// Refill the bucket and keep its limit if the message must wait longer for it than for the
+/// previous limits.
+fn update_limit(
+  limited: &mut Option<Limit>,
+  scope: RateLimitScope,
+  bucket: &mut TokenBucket,
+  now: Instant,
+) {
+  bucket.refill(now);
+  let wait = bucket.wait_time();
+  if !wait.is_zero()
+    && limited
+      .map(|(_, _, max_wait)| wait > max_wait)
+      .unwrap_or(true)
+  {
+    *limited = Some((scope, bucket.rate, wait));
+  }
+}
+
+struct TokenBucket {
+  /// Tokens added per second
+  rate: u32,
+  capacity: f64,
+  /// Negative when the tokens of the delayed messages are reserved
+  tokens: f64,
+  updated_at: Instant,
+}
+
+impl TokenBucket {
+  fn new(rate: u32, now: Instant) -> Self {
+    // A zero rate would never refill the bucket
+    let rate = rate.max(1);
+    let capacity = rate as f64 * BURST_SECS;
+    Self {
+      rate,
+      capacity,
+      tokens: capacity,
+      updated_at: now,
+    }
+  }
+
+  fn refill(&mut self, now: Instant) {
+    let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.capacity);
+    self.updated_at = now;
+  }
+
+  /// How long until a token is available.
+  fn wait_time(&self) -> Duration {
+    if self.tokens >= 1.0 {
+      Duration::ZERO
+    } else {
+      Duration::from_secs_f64((1.0 - self.tokens) / self.rate as f64)
+    }
+  }
+
+  fn take(&mut self) {
+    self.tokens -= 1.0;
+  }
+
+  fn is_full(&self, now: Instant) -> bool {
+    let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+    self.tokens + elapsed * self.rate as f64 >= self.capacity
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn limiter(user_per_sec: u32, device_per_sec: u32, object_per_sec: u32) -> RealtimeRateLimiter {
+    RealtimeRateLimiter::new(RateLimitConfig {
+      user_per_sec,
+      device_per_sec,
+      object_per_sec,
+    })
+  }
+
+  #[test]
+  fn delay_then_drop_test() {
+    let limiter = limiter(100, 1, 100);
+    // The burst of the device is two messages
+    assert!(limiter.check(1, "device", "object", true).is_none());
+    assert!(limiter.check(1, "device", "object", true).is_none());
+
+    let limited = limiter.check(1, "device", "object", true).unwrap();
+    assert_eq!(limited.scope, RateLimitScope::Device);
+    assert_eq!(limited.limit, 1);
+    let delay = limited.delay.unwrap();
+    assert!(delay > Duration::from_millis(900) && delay <= MAX_DELAY);
+    assert!(limited.notify);
+
+    // The token of the delayed message is reserved, so the next one would wait two seconds
+    let limited = limiter.check(1, "device", "object", true).unwrap();
+    assert!(limited.delay.is_none());
+    // The client was just told to slow down
+    assert!(!limited.notify);
+
+    // The messages that can't be dropped are delayed as long as needed
+    let limited = limiter.check(1, "device", "object", false).unwrap();
+    assert!(limited.delay.unwrap() > MAX_DELAY);
+
+    // The other devices of the user are not limited
+    assert!(limiter.check(1, "other device", "object", true).is_none());
+  }
+
+  #[test]
+  fn dropped_message_takes_no_token_test() {
+    let limiter = limiter(100, 1, 100);
+    for _ in 0..3 {
+      limiter.check(1, "device", "object", true);
+    }
+    let dropped = limiter.check(1, "device", "object", true).unwrap();
+    assert!(dropped.delay.is_none());
+    // The dropped message didn't reserve a token, so the next one still waits two seconds
+    let dropped = limiter.check(1, "device", "object", true).unwrap();
+    assert!(dropped.delay.is_none());
+    let delay = limiter
+      .check(1, "device", "object", false)
+      .unwrap()
+      .delay
+      .unwrap();
+    assert!(delay <= Duration::from_secs(2));
+  }
+
+  #[test]
+  fn object_bucket_charged_once_sender_allowed_test() {
+    let limiter = limiter(1, 100, 100);
+    for _ in 0..3 {
+      limiter.check(1, "device", "object", true);
+    }
+    // The message dropped by the limit of the user doesn't reach the bucket of the object
+    let limited = limiter.check(1, "device", "other object", true).unwrap();
+    assert_eq!(limited.scope, RateLimitScope::User);
+    assert!(limited.delay.is_none());
+    let buckets = limiter.buckets.lock();
+    assert!(buckets.by_object.contains_key("object"));
+    assert!(!buckets.by_object.contains_key("other object"));
+  }
+
+  #[test]
+  fn object_limit_shared_by_users_test() {
+    let limiter = limiter(100, 100, 1);
+    assert!(limiter.check(1, "device", "object", true).is_none());
+    assert!(limiter.check(2, "device", "object", true).is_none());
+    let limited = limiter.check(3, "device", "object", true).unwrap();
+    assert_eq!(limited.scope, RateLimitScope::Object);
+    assert!(limited.delay.is_some());
+  }
+
+  #[test]
+  fn prune_full_buckets_test() {
+    let limiter = limiter(1000, 1000, 1);
+    limiter.check(1, "device", "object", true);
+    std::thread::sleep(Duration::from_millis(20));
+
+    // The buckets of the user and the device are refilled, the one of the object isn't yet
+    limiter.prune();
+    let buckets = limiter.buckets.lock();
+    assert!(buckets.by_user.is_empty());
+    assert!(buckets.by_device.is_empty());
+    assert!(buckets.by_object.contains_key("object"));
+  }
+}
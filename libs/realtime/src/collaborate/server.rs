@@ -1,10 +1,11 @@
 use crate::entities::{
   ClientMessage, ClientStreamMessage, Connect, Disconnect, Editing, RealtimeMessage, RealtimeUser,
+  RestartSession, RevokeAccess, Shutdown,
 };
 use crate::error::{RealtimeError, StreamError};
 use anyhow::{anyhow, Result};
 
-use actix::{Actor, Context, Handler, ResponseFuture};
+use actix::{Actor, Context, Handler, Recipient, ResponseFuture};
 use futures_util::future::BoxFuture;
 use parking_lot::Mutex;
 use realtime_entity::collab_msg::CollabMessage;
@@ -12,6 +13,7 @@ use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::pin::Pin;
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -26,11 +28,16 @@ use crate::client::ClientWSSink;
 use crate::collaborate::group::CollabGroupCache;
 use crate::collaborate::permission::CollabAccessControl;
 use crate::collaborate::retry::{CollabUserMessage, SubscribeGroupIfNeed};
-use crate::collaborate::RealtimeMetrics;
+use crate::collaborate::{CollabCluster, RateLimitConfig, RealtimeMetrics, RealtimeRateLimiter};
 use crate::util::channel_ext::UnboundedSenderSink;
 use database::collab::CollabStorage;
 use realtime_entity::message::SystemMessage;
 
+/// The longest the server waits on shutdown for the collabs to be written to the storage. It's
+/// shorter than the grace period that container orchestrators usually give before killing the
+/// process.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(20);
+
 #[derive(Clone)]
 pub struct CollabServer<S, U, AC> {
   #[allow(dead_code)]
@@ -52,8 +59,11 @@ pub struct CollabServer<S, U, AC> {
   /// Keep track of all client streams
   client_stream_by_user: Arc<RwLock<HashMap<U, CollabClientStream>>>,
   access_control: Arc<AC>,
-  #[allow(dead_code)]
   metrics: Arc<RealtimeMetrics>,
+  /// Limits the collab messages sent by the users
+  rate_limiter: Arc<RealtimeRateLimiter>,
+  /// Set when the server receives [Shutdown]. No new connection is accepted afterwards.
+  shutting_down: Arc<AtomicBool>,
 }
 
 impl<S, U, AC> CollabServer<S, U, AC>
@@ -62,15 +72,22 @@ where
   U: RealtimeUser,
   AC: CollabAccessControl,
 {
+  /// Create the server of this node. With a `cluster`, the groups are kept in sync with the
+  /// groups of the other nodes of the cluster.
   pub fn new(
     storage: Arc<S>,
     access_control: AC,
     metrics: Arc<RealtimeMetrics>,
+    cluster: Option<Arc<dyn CollabCluster>>,
+    rate_limit: RateLimitConfig,
   ) -> Result<Self, RealtimeError> {
+    metrics.record_rate_limit(&rate_limit);
+    let rate_limiter = Arc::new(RealtimeRateLimiter::new(rate_limit));
     let access_control = Arc::new(access_control);
     let groups = Arc::new(CollabGroupCache::new(
       storage.clone(),
       access_control.clone(),
+      cluster,
     ));
     let client_stream_by_user: Arc<RwLock<HashMap<U, CollabClientStream>>> = Default::default();
     let editing_collab_by_user = Default::default();
@@ -79,6 +96,7 @@ where
     let cloned_metrics = metrics.clone();
     let cloned_client_stream_by_user = client_stream_by_user.clone();
     let cloned_storage = storage.clone();
+    let cloned_rate_limiter = rate_limiter.clone();
     tokio::spawn(async move {
       let mut interval = interval(Duration::from_secs(60));
       loop {
@@ -99,6 +117,7 @@ where
 
           // Perform groups tick operation
           groups.tick().await;
+          cloned_rate_limiter.prune();
         } else {
           break;
         }
@@ -114,15 +133,20 @@ where
       client_stream_by_user,
       access_control,
       metrics,
+      rate_limiter,
+      shutting_down: Default::default(),
     })
   }
 
+  #[allow(clippy::too_many_arguments)]
   fn process_realtime_message(
     user: U,
     client_stream_by_user: Arc<RwLock<HashMap<U, CollabClientStream>>>,
     groups: Arc<CollabGroupCache<S, U, AC>>,
     edit_collab_by_user: Arc<Mutex<HashMap<U, HashSet<Editing>>>>,
     access_control: Arc<AC>,
+    rate_limiter: Arc<RealtimeRateLimiter>,
+    metrics: Arc<RealtimeMetrics>,
     realtime_msg: RealtimeMessage,
   ) -> Pin<Box<impl Future<Output = Result<(), RealtimeError>>>> {
     Box::pin(async move {
@@ -148,14 +172,44 @@ where
             return Err(RealtimeError::Internal(msg));
           }
 
-          // 2. handle the message sent by the client
+          // 2. limit the rate of the messages sent by the user, by its device and to the collab.
+          // The messages over the limits are delayed. Only the awareness messages are dropped if
+          // they would wait too long, as the next awareness replaces them, while dropping a sync
+          // message would lose the edits of the client without telling it.
+          let can_drop = matches!(collab_message, CollabMessage::AwarenessSync(_));
+          if let Some(limited) = rate_limiter.check(
+            user.uid(),
+            user.device_id(),
+            collab_message.object_id(),
+            can_drop,
+          ) {
+            metrics.record_rate_limited_message(limited.scope, limited.delay.is_none());
+            if limited.notify {
+              if let Some(client_stream) = client_stream_by_user.read().await.get(&user) {
+                client_stream.rate_limit(limited.limit);
+              }
+            }
+            match limited.delay {
+              Some(delay) => tokio::time::sleep(delay).await,
+              None => {
+                trace!(
+                  "drop awareness of client:{} exceeding the {} rate limit",
+                  user.uid(),
+                  limited.scope.as_str()
+                );
+                return Ok(());
+              },
+            }
+          }
+
+          // 3. handle the message sent by the client
           let msg = CollabUserMessage {
             user: &user,
             collab_message: &collab_message,
           };
 
-          // 3.1 create a new group if the user is editing the object for the first time
-          // 3.2 subscribe the user to the group in order to receive changes when the collab object is updated
+          // 4.1 create a new group if the user is editing the object for the first time
+          // 4.2 subscribe the user to the group in order to receive changes when the collab object is updated
           SubscribeGroupIfNeed {
             collab_user_message: &msg,
             groups: &groups,
@@ -166,7 +220,7 @@ where
           .run()
           .await?;
 
-          // 4 send message to the group and then broadcast the message to all connected clients
+          // 5 send message to the group and then broadcast the message to all connected clients
           if groups.contains_group(collab_message.object_id()).await? {
             broadcast_message(&user, collab_message, &client_stream_by_user).await;
           }
@@ -223,8 +277,14 @@ where
   type Result = ResponseFuture<Result<(), RealtimeError>>;
 
   fn handle(&mut self, new_conn: Connect<U>, _ctx: &mut Context<Self>) -> Self::Result {
+    if self.shutting_down.load(Ordering::SeqCst) {
+      // Ask the client to connect to another server
+      new_conn.restart.do_send(RestartSession);
+      return Box::pin(async { Err(RealtimeError::ShuttingDown) });
+    }
+
     // User with the same id and same device will be replaced with the new connection [CollabClientStream]
-    let client_stream = CollabClientStream::new(ClientWSSink(new_conn.socket));
+    let client_stream = CollabClientStream::new(ClientWSSink(new_conn.socket), new_conn.restart);
     let groups = self.groups.clone();
     let user_by_device = self.user_by_device.clone();
     let client_stream_by_user = self.client_stream_by_user.clone();
@@ -316,6 +376,121 @@ where
   }
 }
 
+impl<S, U, AC> Handler<RevokeAccess> for CollabServer<S, U, AC>
+where
+  U: RealtimeUser + Unpin,
+  S: CollabStorage + Unpin,
+  AC: CollabAccessControl + Unpin,
+{
+  type Result = ResponseFuture<()>;
+
+  /// Unsubscribes the devices of the user from the groups of the collabs the user can't access
+  /// anymore, and tells the devices that their access is revoked.
+  fn handle(&mut self, msg: RevokeAccess, _ctx: &mut Context<Self>) -> Self::Result {
+    let groups = self.groups.clone();
+    let client_stream_by_user = self.client_stream_by_user.clone();
+    let editing_collab_by_user = self.editing_collab_by_user.clone();
+
+    Box::pin(async move {
+      let (uid, object_ids) = match msg {
+        RevokeAccess::Collab { uid, object_id } => (uid, vec![object_id]),
+        RevokeAccess::Workspace {
+          uid,
+          workspace_id,
+          kept_object_ids,
+        } => {
+          let object_ids = groups
+            .object_ids_of_workspace(&workspace_id)
+            .await
+            .into_iter()
+            .filter(|object_id| !kept_object_ids.contains(object_id))
+            .collect::<Vec<_>>();
+          (uid, object_ids)
+        },
+      };
+      if object_ids.is_empty() {
+        return;
+      }
+
+      let users = client_stream_by_user
+        .read()
+        .await
+        .keys()
+        .filter(|user| user.uid() == uid)
+        .cloned()
+        .collect::<Vec<_>>();
+      for user in users {
+        let revoked_editing = editing_collab_by_user
+          .lock()
+          .get_mut(&user)
+          .map(|editing_set| {
+            let revoked = editing_set
+              .iter()
+              .filter(|editing| object_ids.contains(&editing.object_id))
+              .map(|editing| editing.object_id.clone())
+              .collect::<Vec<_>>();
+            editing_set.retain(|editing| !object_ids.contains(&editing.object_id));
+            revoked
+          })
+          .unwrap_or_default();
+        if revoked_editing.is_empty() {
+          continue;
+        }
+
+        for object_id in &revoked_editing {
+          info!(
+            "[realtime]: revoke access of {} to collab:{}",
+            user, object_id
+          );
+          if let Err(err) = groups.remove_user(object_id, &user).await {
+            error!("fail to remove {} from group:{}: {}", user, object_id, err);
+          }
+        }
+        if let Some(client_stream) = client_stream_by_user.read().await.get(&user) {
+          client_stream.revoke_access(revoked_editing);
+        }
+      }
+    })
+  }
+}
+
+impl<S, U, AC> Handler<Shutdown> for CollabServer<S, U, AC>
+where
+  U: RealtimeUser + Unpin,
+  S: CollabStorage + Unpin,
+  AC: CollabAccessControl + Unpin,
+{
+  type Result = ResponseFuture<()>;
+
+  /// Drains the server before the process stops:
+  /// 1. Rejects the new connections.
+  /// 2. Asks the connected clients to reconnect, so they are served by another server.
+  /// 3. Flushes all the groups and waits until their collabs are written to the storage, for at
+  ///    most [SHUTDOWN_TIMEOUT].
+  fn handle(&mut self, _msg: Shutdown, _ctx: &mut Context<Self>) -> Self::Result {
+    self.shutting_down.store(true, Ordering::SeqCst);
+    let groups = self.groups.clone();
+    let client_stream_by_user = self.client_stream_by_user.clone();
+
+    Box::pin(async move {
+      let client_streams = client_stream_by_user
+        .write()
+        .await
+        .drain()
+        .collect::<Vec<_>>();
+      info!(
+        "[realtime]: shutting down, disconnect {} users",
+        client_streams.len()
+      );
+      for (_, client_stream) in client_streams {
+        client_stream.reconnect();
+      }
+
+      groups.close_all_groups(SHUTDOWN_TIMEOUT).await;
+    })
+  }
+}
+
 impl<S, U, AC> Handler<ClientMessage<U>> for CollabServer<S, U, AC>
 where
   U: RealtimeUser + Unpin,
@@ -330,12 +505,16 @@ where
     let groups = self.groups.clone();
     let edit_collab_by_user = self.editing_collab_by_user.clone();
     let access_control = self.access_control.clone();
+    let rate_limiter = self.rate_limiter.clone();
+    let metrics = self.metrics.clone();
     Self::process_realtime_message(
       user,
       client_stream_by_user,
       groups,
       edit_collab_by_user,
       access_control,
+      rate_limiter,
+      metrics,
       message,
     )
   }
@@ -360,6 +539,8 @@ where
     let groups = self.groups.clone();
     let edit_collab_by_user = self.editing_collab_by_user.clone();
     let access_control = self.access_control.clone();
+    let rate_limiter = self.rate_limiter.clone();
+    let metrics = self.metrics.clone();
 
     Box::pin(async move {
       if let Some(message) = stream.next().await {
@@ -384,6 +565,8 @@ where
               groups,
               edit_collab_by_user,
               access_control,
+              rate_limiter,
+              metrics,
               message,
             )
             .await
@@ -460,6 +643,7 @@ where
 
 pub struct CollabClientStream {
   sink: ClientWSSink,
+  restart: Recipient<RestartSession>,
   /// Used to receive messages from the collab server. The message will forward to the [CollabBroadcast] which
   /// will broadcast the message to all connected clients.
   ///
@@ -469,10 +653,14 @@ pub struct CollabClientStream {
 }
 
 impl CollabClientStream {
-  pub fn new(sink: ClientWSSink) -> Self {
+  pub fn new(sink: ClientWSSink, restart: Recipient<RestartSession>) -> Self {
     // When receive a new connection, create a new [ClientStream] that holds the connection's websocket
     let (stream_tx, _) = tokio::sync::broadcast::channel(1000);
-    Self { sink, stream_tx }
+    Self {
+      sink,
+      restart,
+      stream_tx,
+    }
   }
 
   /// Returns a [UnboundedSenderSink] and a [ReceiverStream] for the object_id.
@@ -540,6 +728,29 @@ impl CollabClientStream {
       .sink
       .do_send(RealtimeMessage::System(SystemMessage::KickOff));
   }
+
+  /// Ask the client to send at most `limit` messages per second.
+  pub fn rate_limit(&self, limit: u32) {
+    self
+      .sink
+      .do_send(RealtimeMessage::System(SystemMessage::RateLimit(limit)));
+  }
+
+  /// Tell the client it can't access the collabs anymore. The server already stopped sending
+  /// their changes.
+  pub fn revoke_access(&self, object_ids: Vec<String>) {
+    self
+      .sink
+      .do_send(RealtimeMessage::System(SystemMessage::AccessRevoked(
+        object_ids,
+      )));
+  }
+
+  /// Ask the client to connect again, by closing its websocket with
+  /// [CloseCode::Restart](actix_web_actors::ws::CloseCode::Restart).
+  pub fn reconnect(&self) {
+    self.restart.do_send(RestartSession);
+  }
 }
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
@@ -34,10 +34,18 @@ where
 #[rtype(result = "Result<(), RealtimeError>")]
 pub struct Connect<U> {
   pub socket: Recipient<RealtimeMessage>,
+  pub restart: Recipient<RestartSession>,
   pub user: U,
   pub session_id: String,
 }
 
+/// The server is shutting down. The websocket of the client is closed with
+/// [CloseCode::Restart](actix_web_actors::ws::CloseCode::Restart), so the client connects again
+/// and is served by another server.
+#[derive(Debug, Message, Clone)]
+#[rtype(result = "()")]
+pub struct RestartSession;
+
 #[derive(Debug, Message, Clone)]
 #[rtype(result = "Result<(), RealtimeError>")]
 pub struct Disconnect<U> {
@@ -48,6 +56,30 @@ pub struct Disconnect<U> {
 #[derive(Debug, Message, Clone)]
 #[rtype(result = "Result<(), RealtimeError>")]
 pub struct DisconnectByServer;
+
+/// Sent to the [CollabServer](crate::collaborate::CollabServer) when a user loses the access to
+/// collabs. The devices of the user are unsubscribed from the groups of the collabs right away.
+#[derive(Debug, Message, Clone)]
+#[rtype(result = "()")]
+pub enum RevokeAccess {
+  /// The user is no longer a member of the collab
+  Collab { uid: i64, object_id: String },
+  /// The user is no longer a member of the workspace, so it loses the access to all its collabs,
+  /// except the ones in `kept_object_ids` that the user is a member of
+  Workspace {
+    uid: i64,
+    workspace_id: String,
+    kept_object_ids: Vec<String>,
+  },
+}
+
+/// Sent to the [CollabServer](crate::collaborate::CollabServer) when the process is about to stop.
+/// The server stops accepting new connections, asks the connected clients to reconnect and writes
+/// all the opened collabs to the storage.
+#[derive(Debug, Message, Clone)]
+#[rtype(result = "()")]
+pub struct Shutdown;
+
 #[derive(Debug, Clone, Serialize_repr, Deserialize_repr)]
 #[repr(u8)]
 pub enum BusinessID {
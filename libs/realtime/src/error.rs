@@ -14,6 +14,9 @@ pub enum RealtimeError {
   #[error(transparent)]
   SerdeError(#[from] serde_json::Error),
 
+  #[error(transparent)]
+  BincodeError(#[from] bincode::Error),
+
   #[error(transparent)]
   TokioTask(#[from] tokio::task::JoinError),
 
@@ -38,6 +41,9 @@ pub enum RealtimeError {
   #[error("{0}")]
   UserNotFound(String),
 
+  #[error("The server is shutting down")]
+  ShuttingDown,
+
   #[error("Internal failure: {0}")]
   Internal(#[from] anyhow::Error),
 }
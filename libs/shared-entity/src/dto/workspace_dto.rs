@@ -64,6 +64,68 @@ impl WorkspaceMemberChangeset {
 pub struct WorkspaceSpaceUsage {
   pub total_capacity: u64,
   pub consumed_capacity: u64,
+  /// Maximum size of a single blob in bytes
+  #[serde(default)]
+  pub blob_size_limit: u64,
+  /// Maximum size of a single encoded collab in bytes
+  #[serde(default)]
+  pub collab_size_limit: u64,
+}
+
+/// The quota of a workspace. The quota comes from the plan of the workspace unless it's overridden
+/// by the server admin.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WorkspaceQuota {
+  pub workspace_type: i32,
+  pub storage_limit: u64,
+  pub blob_size_limit: u64,
+  pub collab_size_limit: u64,
+}
+
+/// Update the quota of a workspace. The fields that are `None` are left unchanged.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct UpdateWorkspaceQuotaParams {
+  /// Move the workspace to another plan
+  pub workspace_type: Option<i32>,
+  pub storage_limit: Option<u64>,
+  pub blob_size_limit: Option<u64>,
+  pub collab_size_limit: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WorkspacePlan {
+  pub workspace_type: i32,
+  pub name: String,
+  pub storage_limit: u64,
+  pub blob_size_limit: u64,
+  pub collab_size_limit: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RepeatedWorkspacePlan(pub Vec<WorkspacePlan>);
+
+/// Run the blob garbage collector on demand.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BlobGcParams {
+  /// Only collect the orphan objects of this workspace. All the workspaces are collected if it's
+  /// `None`.
+  pub workspace_id: Option<Uuid>,
+  /// Only report the orphan objects without removing them.
+  pub dry_run: bool,
+  /// Move the orphan objects to the quarantine prefix instead of deleting them.
+  pub quarantine: bool,
+  /// Objects modified more recently than this are never collected. Use the server configuration if
+  /// it's `None`.
+  pub min_age_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BlobGcReport {
+  pub scanned_objects: u64,
+  pub orphan_objects: u64,
+  pub orphan_bytes: u64,
+  pub removed_objects: u64,
+  pub failures: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -76,9 +138,84 @@ pub struct BlobMetadata {
   pub file_type: String,
   pub file_size: i64,
   pub modified_at: DateTime<Utc>,
+  /// Dimensions of the image in pixels, if the blob is an image
+  #[serde(default)]
+  pub width: Option<u32>,
+  #[serde(default)]
+  pub height: Option<u32>,
+  /// The collab object that owns the blob, if any
+  #[serde(default)]
+  pub object_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct CreateWorkspaceParam {
   pub workspace_name: Option<String>,
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateBlobUploadParams {
+  pub file_id: String,
+  pub file_type: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatePresignedPutParams {
+  pub file_size: u64,
+}
+
+/// How the client should upload the blob.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum PresignedPutBlob {
+  /// The server doesn't support presigned urls. The blob must be uploaded through the server.
+  Disabled,
+  /// The blob already exists.
+  Completed,
+  /// Upload the blob to `url` with a PUT request, then complete the upload with
+  /// [CompletePresignedPutParams].
+  Upload { upload_id: String, url: String },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CompletePresignedPutParams {
+  pub upload_id: String,
+  pub file_type: String,
+  /// The collab object that owns the blob, if any
+  #[serde(default)]
+  pub object_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CreateBlobPublicLinkParams {
+  /// The link never expires if it's None
+  pub expires_in_secs: Option<u64>,
+}
+
+/// A link that allows anyone to read the blob without signing in.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlobPublicLink {
+  /// The path of the link on the server, including the signature in the query string
+  pub path: String,
+  pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A multipart upload that has been created but not yet completed or aborted.
+#[derive(Serialize, Deserialize)]
+pub struct BlobUpload {
+  pub upload_id: String,
+  pub workspace_id: Uuid,
+  pub file_id: String,
+  pub file_type: String,
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RepeatedBlobUploadPart(pub Vec<BlobUploadPart>);
+
+#[derive(Serialize, Deserialize)]
+pub struct BlobUploadPart {
+  pub part_number: i32,
+  pub e_tag: String,
+  pub part_size: i64,
+  pub modified_at: DateTime<Utc>,
+}
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+
+use collab_document::blocks::DocumentData;
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+use serde_json::{json, Map, Value};
+
+use crate::document::parser::{JsonToDocumentParser, SerdeBlock};
+
+pub struct MarkdownToDocumentParser;
+
+const DELTA: &str = "delta";
+const PAGE: &str = "page";
+const PARAGRAPH: &str = "paragraph";
+const HEADING: &str = "heading";
+const QUOTE: &str = "quote";
+const CODE: &str = "code";
+const BULLETED_LIST: &str = "bulleted_list";
+const NUMBERED_LIST: &str = "numbered_list";
+const TODO_LIST: &str = "todo_list";
+const DIVIDER: &str = "divider";
+pub const IMAGE: &str = "image";
+/// The key of the url in the data of an image block.
+pub const IMAGE_URL: &str = "url";
+
+impl MarkdownToDocumentParser {
+  /// Build a document from the markdown. The images become image blocks that hold the
+  /// destination of the image as it's written in the markdown, so the paths of the local images
+  /// can be replaced by the urls of the uploaded images.
+  pub fn markdown_to_document(markdown: &str) -> DocumentData {
+    let options =
+      Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS | Options::ENABLE_TABLES;
+    let mut builder = BlockTreeBuilder::new();
+    for event in Parser::new_ext(markdown, options) {
+      builder.handle_event(event);
+    }
+    JsonToDocumentParser::serde_block_to_document(&builder.finish())
+  }
+}
+
+/// Builds the tree of blocks from the events of the markdown parser.
+struct BlockTreeBuilder {
+  /// The blocks that are not closed yet, the first one is the page.
+  stack: Vec<SerdeBlock>,
+  /// The lists that hold the current item. The start number of an ordered list is Some.
+  lists: Vec<Option<u64>>,
+  bold: usize,
+  italic: usize,
+  strikethrough: usize,
+  links: Vec<String>,
+  /// Whether the current paragraph is the text of the block that holds it, like the first
+  /// paragraph of a list item.
+  merged_paragraph: bool,
+  /// The alt text of an image is not part of the text of the block.
+  image_depth: usize,
+  /// The images of the current text block, inserted after the block.
+  images: Vec<String>,
+}
+
+impl BlockTreeBuilder {
+  fn new() -> Self {
+    Self {
+      stack: vec![new_block(PAGE, Map::new())],
+      lists: vec![],
+      bold: 0,
+      italic: 0,
+      strikethrough: 0,
+      links: vec![],
+      merged_paragraph: false,
+      image_depth: 0,
+      images: vec![],
+    }
+  }
+
+  fn finish(mut self) -> SerdeBlock {
+    while self.stack.len() > 1 {
+      self.pop_block();
+    }
+    self.flush_images();
+    self
+      .stack
+      .pop()
+      .unwrap_or_else(|| new_block(PAGE, Map::new()))
+  }
+
+  fn handle_event(&mut self, event: Event) {
+    match event {
+      Event::Start(tag) => self.start_tag(tag),
+      Event::End(tag) => self.end_tag(tag),
+      Event::Text(text) => self.push_text(&text, self.attributes()),
+      Event::Code(code) => {
+        let mut attributes = self.attributes();
+        attributes.insert("code".to_string(), Value::Bool(true));
+        self.push_text(&code, attributes);
+      },
+      Event::Html(html) => {
+        if self.top_has_delta() {
+          self.push_text(&html, Map::new());
+        } else {
+          // A html block is kept as a paragraph of its source
+          let html = html.trim_end_matches('\n');
+          if !html.is_empty() {
+            self.append_block(new_text_block(PARAGRAPH, Map::new(), html));
+          }
+        }
+      },
+      Event::FootnoteReference(label) => {
+        self.push_text(&format!("[^{}]", label), self.attributes())
+      },
+      Event::SoftBreak => self.push_text(" ", self.attributes()),
+      Event::HardBreak => self.push_text("\n", self.attributes()),
+      Event::Rule => self.append_block(new_block(DIVIDER, Map::new())),
+      Event::TaskListMarker(checked) => {
+        if let Some(block) = self.stack.last_mut() {
+          block.ty = TODO_LIST.to_string();
+          block
+            .data
+            .insert("checked".to_string(), Value::Bool(checked));
+        }
+      },
+    }
+  }
+
+  fn start_tag(&mut self, tag: Tag) {
+    match tag {
+      Tag::Paragraph => {
+        if self.can_merge_paragraph() {
+          self.merged_paragraph = true;
+        } else {
+          self.stack.push(new_text_block(PARAGRAPH, Map::new(), ""));
+        }
+      },
+      Tag::Heading(level, _, _) => {
+        let data = Map::from_iter([("level".to_string(), json!(level as usize))]);
+        self.stack.push(new_text_block(HEADING, data, ""));
+      },
+      Tag::BlockQuote => self.stack.push(new_text_block(QUOTE, Map::new(), "")),
+      Tag::CodeBlock(kind) => {
+        let language = match kind {
+          CodeBlockKind::Fenced(info) => info.split_whitespace().next().unwrap_or("").to_string(),
+          CodeBlockKind::Indented => String::new(),
+        };
+        let data = Map::from_iter([("language".to_string(), Value::String(language))]);
+        self.stack.push(new_text_block(CODE, data, ""));
+      },
+      Tag::List(start) => self.lists.push(start),
+      Tag::Item => {
+        let ty = match self.lists.last() {
+          Some(Some(_)) => NUMBERED_LIST,
+          _ => BULLETED_LIST,
+        };
+        self.stack.push(new_text_block(ty, Map::new(), ""));
+      },
+      Tag::FootnoteDefinition(label) => {
+        let text = format!("[^{}]: ", label);
+        self
+          .stack
+          .push(new_text_block(PARAGRAPH, Map::new(), &text));
+      },
+      Tag::Table(_) => {},
+      // Each row of a table becomes a paragraph that holds its cells
+      Tag::TableHead | Tag::TableRow => {
+        self.stack.push(new_text_block(PARAGRAPH, Map::new(), ""));
+      },
+      Tag::TableCell => {
+        if !self.top_is_empty() {
+          self.push_text(" | ", Map::new());
+        }
+      },
+      Tag::Emphasis => self.italic += 1,
+      Tag::Strong => self.bold += 1,
+      Tag::Strikethrough => self.strikethrough += 1,
+      Tag::Link(_, url, _) => self.links.push(url.to_string()),
+      Tag::Image(_, url, _) => {
+        self.images.push(url.to_string());
+        self.image_depth += 1;
+      },
+    }
+  }
+
+  fn end_tag(&mut self, tag: Tag) {
+    match tag {
+      Tag::Paragraph => {
+        if self.merged_paragraph {
+          self.merged_paragraph = false;
+          self.flush_images();
+        } else {
+          self.pop_block();
+        }
+      },
+      Tag::CodeBlock(_) => {
+        // The parser ends the code with a line break
+        if let Some(op) = self
+          .stack
+          .last_mut()
+          .and_then(|block| block.data.get_mut(DELTA))
+          .and_then(Value::as_array_mut)
+          .and_then(|ops| ops.last_mut())
+        {
+          if let Some(Value::String(text)) = op.get_mut("insert") {
+            if text.ends_with('\n') {
+              text.pop();
+            }
+          }
+        }
+        self.pop_block();
+      },
+      Tag::Heading(..)
+      | Tag::BlockQuote
+      | Tag::Item
+      | Tag::FootnoteDefinition(_)
+      | Tag::TableHead
+      | Tag::TableRow => self.pop_block(),
+      Tag::List(_) => {
+        self.lists.pop();
+      },
+      Tag::Table(_) | Tag::TableCell => {},
+      Tag::Emphasis => self.italic = self.italic.saturating_sub(1),
+      Tag::Strong => self.bold = self.bold.saturating_sub(1),
+      Tag::Strikethrough => self.strikethrough = self.strikethrough.saturating_sub(1),
+      Tag::Link(..) => {
+        self.links.pop();
+      },
+      Tag::Image(..) => self.image_depth = self.image_depth.saturating_sub(1),
+    }
+  }
+
+  fn attributes(&self) -> Map<String, Value> {
+    let mut attributes = Map::new();
+    for (key, depth) in [
+      ("bold", self.bold),
+      ("italic", self.italic),
+      ("strikethrough", self.strikethrough),
+    ] {
+      if depth > 0 {
+        attributes.insert(key.to_string(), Value::Bool(true));
+      }
+    }
+    if let Some(url) = self.links.last() {
+      attributes.insert("href".to_string(), Value::String(url.clone()));
+    }
+    attributes
+  }
+
+  /// Append the text to the delta of the current block. Consecutive texts with the same
+  /// attributes are merged into one operation.
+  fn push_text(&mut self, text: &str, attributes: Map<String, Value>) {
+    if self.image_depth > 0 || text.is_empty() {
+      return;
+    }
+    if !self.top_has_delta() {
+      self.append_block(new_text_block(PARAGRAPH, Map::new(), text));
+      return;
+    }
+    let ops = self
+      .stack
+      .last_mut()
+      .and_then(|block| block.data.get_mut(DELTA))
+      .and_then(Value::as_array_mut);
+    if let Some(ops) = ops {
+      if let Some(last) = ops.last_mut() {
+        let same_attributes = match last.get("attributes") {
+          Some(Value::Object(last_attributes)) => *last_attributes == attributes,
+          _ => attributes.is_empty(),
+        };
+        if let (true, Some(Value::String(last_text))) = (same_attributes, last.get_mut("insert")) {
+          last_text.push_str(text);
+          return;
+        }
+      }
+      ops.push(delta_op(text, attributes));
+    }
+  }
+
+  fn top_has_delta(&self) -> bool {
+    self
+      .stack
+      .last()
+      .map(|block| block.ty != PAGE && block.data.contains_key(DELTA))
+      .unwrap_or(false)
+  }
+
+  /// Whether the current block has neither text nor children.
+  fn top_is_empty(&self) -> bool {
+    self
+      .stack
+      .last()
+      .map(|block| block.children.is_empty() && !has_text(block))
+      .unwrap_or(true)
+  }
+
+  fn can_merge_paragraph(&self) -> bool {
+    let can_hold_text = self
+      .stack
+      .last()
+      .map(|block| {
+        matches!(
+          block.ty.as_str(),
+          QUOTE | BULLETED_LIST | NUMBERED_LIST | TODO_LIST
+        )
+      })
+      .unwrap_or(false);
+    can_hold_text && self.top_is_empty()
+  }
+
+  fn append_block(&mut self, block: SerdeBlock) {
+    if let Some(parent) = self.stack.last_mut() {
+      parent.children.push(block);
+    }
+  }
+
+  /// Close the current block and add it to the children of its parent. An empty paragraph is
+  /// dropped, like the paragraph that only holds an image.
+  fn pop_block(&mut self) {
+    if self.stack.len() <= 1 {
+      return;
+    }
+    if let Some(block) = self.stack.pop() {
+      if block.ty != PARAGRAPH || has_text(&block) || !block.children.is_empty() {
+        self.append_block(block);
+      }
+    }
+    self.flush_images();
+  }
+
+  fn flush_images(&mut self) {
+    let images = std::mem::take(&mut self.images);
+    for url in images {
+      let data = Map::from_iter([(IMAGE_URL.to_string(), Value::String(url))]);
+      self.append_block(new_block(IMAGE, data));
+    }
+  }
+}
+
+fn new_block(ty: &str, data: Map<String, Value>) -> SerdeBlock {
+  SerdeBlock {
+    ty: ty.to_string(),
+    data: data.into_iter().collect::<HashMap<_, _>>(),
+    children: vec![],
+  }
+}
+
+fn new_text_block(ty: &str, mut data: Map<String, Value>, text: &str) -> SerdeBlock {
+  let ops = if text.is_empty() {
+    vec![]
+  } else {
+    vec![delta_op(text, Map::new())]
+  };
+  data.insert(DELTA.to_string(), Value::Array(ops));
+  new_block(ty, data)
+}
+
+fn delta_op(text: &str, attributes: Map<String, Value>) -> Value {
+  if attributes.is_empty() {
+    json!({ "insert": text })
+  } else {
+    json!({ "insert": text, "attributes": attributes })
+  }
+}
+
+fn has_text(block: &SerdeBlock) -> bool {
+  block
+    .data
+    .get(DELTA)
+    .and_then(Value::as_array)
+    .map(|ops| !ops.is_empty())
+    .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use collab_document::blocks::Block;
+
+  fn children<'a>(document: &'a DocumentData, block: &Block) -> Vec<&'a Block> {
+    document
+      .meta
+      .children_map
+      .get(&block.children)
+      .map(|ids| ids.iter().map(|id| &document.blocks[id]).collect())
+      .unwrap_or_default()
+  }
+
+  fn text(document: &DocumentData, block: &Block) -> Value {
+    let external_id = block.external_id.as_ref().unwrap();
+    let delta = &document.meta.text_map.as_ref().unwrap()[external_id];
+    serde_json::from_str(delta).unwrap()
+  }
+
+  #[test]
+  fn parse_markdown_blocks() {
+    let markdown = "# Title\n\nHello **bold** and [link](https://appflowy.io)\n\n\
+      1. first\n   - [x] done\n2. second\n\n\
+      > quoted\n\n\
+      ```rust\nfn main() {}\n```\n\n\
+      ---\n\n\
+      ![image](images/a.png)\n";
+    let document = MarkdownToDocumentParser::markdown_to_document(markdown);
+    let page = &document.blocks[&document.page_id];
+    let blocks = children(&document, page);
+    let types = blocks.iter().map(|b| b.ty.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+      types,
+      vec![
+        HEADING,
+        PARAGRAPH,
+        NUMBERED_LIST,
+        NUMBERED_LIST,
+        QUOTE,
+        CODE,
+        DIVIDER,
+        IMAGE
+      ]
+    );
+
+    assert_eq!(blocks[0].data["level"], json!(1));
+    assert_eq!(text(&document, blocks[0]), json!([{ "insert": "Title" }]));
+    assert_eq!(
+      text(&document, blocks[1]),
+      json!([
+        { "insert": "Hello " },
+        { "insert": "bold", "attributes": { "bold": true } },
+        { "insert": " and " },
+        { "insert": "link", "attributes": { "href": "https://appflowy.io" } },
+      ])
+    );
+
+    let todos = children(&document, blocks[2]);
+    assert_eq!(todos.len(), 1);
+    assert_eq!(todos[0].ty, TODO_LIST);
+    assert_eq!(todos[0].data["checked"], json!(true));
+    assert_eq!(text(&document, todos[0]), json!([{ "insert": "done" }]));
+
+    assert_eq!(text(&document, blocks[4]), json!([{ "insert": "quoted" }]));
+    assert_eq!(blocks[5].data["language"], json!("rust"));
+    assert_eq!(
+      text(&document, blocks[5]),
+      json!([{ "insert": "fn main() {}" }])
+    );
+    assert_eq!(blocks[7].data[IMAGE_URL], json!("images/a.png"));
+  }
+
+  #[test]
+  fn parse_empty_markdown() {
+    let document = MarkdownToDocumentParser::markdown_to_document("");
+    let page = &document.blocks[&document.page_id];
+    assert!(children(&document, page).is_empty());
+  }
+}
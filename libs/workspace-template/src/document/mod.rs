@@ -1,2 +1,3 @@
 pub mod get_started;
-mod parser;
+pub mod markdown_parser;
+pub mod parser;
@@ -0,0 +1,19 @@
+-- Multipart uploads that have been created but not yet completed or aborted.
+CREATE TABLE IF NOT EXISTS af_blob_upload (
+    upload_id VARCHAR PRIMARY KEY,
+    workspace_id UUID REFERENCES af_workspace(workspace_id) ON DELETE CASCADE NOT NULL,
+    file_id VARCHAR NOT NULL,
+    file_type VARCHAR NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    UNIQUE (workspace_id, file_id)
+);
+
+-- Parts that have been uploaded for a multipart upload.
+CREATE TABLE IF NOT EXISTS af_blob_upload_part (
+    upload_id VARCHAR REFERENCES af_blob_upload(upload_id) ON DELETE CASCADE NOT NULL,
+    part_number INTEGER NOT NULL,
+    e_tag VARCHAR NOT NULL,
+    part_size BIGINT NOT NULL,
+    modified_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (upload_id, part_number)
+);
@@ -0,0 +1,3 @@
+-- Hash of the blob content. It's used as the ETag of the blob.
+-- The column is nullable because the hash of blobs uploaded before this migration is unknown.
+ALTER TABLE af_blob_metadata ADD COLUMN IF NOT EXISTS content_hash VARCHAR;
@@ -0,0 +1,28 @@
+-- Blob objects stored in the bucket, addressed by the hash of their content. Blobs with the same
+-- content in a workspace share one object, and the object is deleted when its last reference is removed.
+CREATE TABLE IF NOT EXISTS af_blob_object (
+    workspace_id UUID REFERENCES af_workspace(workspace_id) ON DELETE CASCADE NOT NULL,
+    content_hash VARCHAR NOT NULL,
+    object_key VARCHAR NOT NULL,
+    file_size BIGINT NOT NULL,
+    ref_count INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (workspace_id, content_hash)
+);
+
+-- Blobs stored before the content hash was recorded keep their original object key.
+-- Each of them is treated as a distinct object.
+UPDATE af_blob_metadata SET content_hash = 'file-' || file_id WHERE content_hash IS NULL;
+ALTER TABLE af_blob_metadata ALTER COLUMN content_hash SET NOT NULL;
+
+INSERT INTO af_blob_object (workspace_id, content_hash, object_key, file_size, ref_count)
+SELECT workspace_id, content_hash, workspace_id || '/' || MIN(file_id), MAX(file_size), COUNT(*)
+FROM af_blob_metadata
+GROUP BY workspace_id, content_hash
+ON CONFLICT DO NOTHING;
+
+-- The object key of the multipart upload. It's independent of the file id so that completing an
+-- upload never overwrites an object that is still referenced by another blob.
+ALTER TABLE af_blob_upload ADD COLUMN IF NOT EXISTS object_key VARCHAR;
+UPDATE af_blob_upload SET object_key = workspace_id || '/' || file_id WHERE object_key IS NULL;
+ALTER TABLE af_blob_upload ALTER COLUMN object_key SET NOT NULL;
@@ -0,0 +1,28 @@
+-- Storage quota of each workspace plan. The plan of a workspace is given by af_workspace.workspace_type.
+CREATE TABLE IF NOT EXISTS af_workspace_plan (
+    workspace_type INTEGER PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    -- Total size of the blobs in bytes
+    storage_limit BIGINT NOT NULL,
+    -- Maximum size of a single blob in bytes
+    blob_size_limit BIGINT NOT NULL,
+    -- Maximum size of a single encoded collab in bytes
+    collab_size_limit BIGINT NOT NULL
+);
+
+INSERT INTO af_workspace_plan (workspace_type, name, storage_limit, blob_size_limit, collab_size_limit)
+VALUES (0, 'Free', 10737418240, 6291456, 67108864)
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE af_workspace
+    ADD CONSTRAINT af_workspace_workspace_type_fkey
+    FOREIGN KEY (workspace_type) REFERENCES af_workspace_plan(workspace_type);
+
+-- Per workspace quota that overrides the plan of the workspace. A NULL limit falls back to the plan.
+CREATE TABLE IF NOT EXISTS af_workspace_quota (
+    workspace_id UUID PRIMARY KEY REFERENCES af_workspace(workspace_id) ON DELETE CASCADE,
+    storage_limit BIGINT,
+    blob_size_limit BIGINT,
+    collab_size_limit BIGINT,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Dimensions of the image blobs in pixels. They are only set once the downscaled variants of the
+-- image have been stored, so the variants can be served whenever the dimensions are known.
+ALTER TABLE af_blob_metadata ADD COLUMN IF NOT EXISTS width INTEGER;
+ALTER TABLE af_blob_metadata ADD COLUMN IF NOT EXISTS height INTEGER;
@@ -0,0 +1,4 @@
+-- The collab object that owns the blob, e.g. the document that embeds the image. Members of the
+-- collab object can read the blob even if they are not members of the workspace.
+ALTER TABLE af_blob_metadata ADD COLUMN IF NOT EXISTS object_id VARCHAR;
+CREATE INDEX IF NOT EXISTS idx_af_blob_metadata_object_id ON af_blob_metadata (object_id);
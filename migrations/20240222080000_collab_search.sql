@@ -0,0 +1,13 @@
+-- Plain text of the documents and database rows, used by the full-text search of a workspace.
+-- The text is extracted from the encoded collab whenever the collab is written.
+CREATE TABLE IF NOT EXISTS af_collab_search (
+    workspace_id UUID NOT NULL REFERENCES af_workspace(workspace_id) ON DELETE CASCADE,
+    oid TEXT NOT NULL,
+    partition_key INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    -- The 'simple' configuration doesn't stem the words, so it works for any language.
+    tsv TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (workspace_id, oid)
+);
+CREATE INDEX IF NOT EXISTS idx_af_collab_search_tsv ON af_collab_search USING GIN (tsv);
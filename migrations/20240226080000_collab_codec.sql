@@ -0,0 +1,5 @@
+-- Format of the blob column: 0 is the encoded collab as is, 1 is zstd and 2 is brotli. The len
+-- column keeps the length of the encoded collab before compression. Existing rows are compressed
+-- in the background by the server.
+ALTER TABLE af_collab ADD COLUMN IF NOT EXISTS codec INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE af_collab_snapshot ADD COLUMN IF NOT EXISTS codec INTEGER NOT NULL DEFAULT 0;
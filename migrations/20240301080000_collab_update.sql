@@ -0,0 +1,12 @@
+-- Append-only log of the yrs updates applied to a collab by the realtime server. The updates are
+-- merged into the blob of the af_collab row when the collab is compacted, and then removed.
+CREATE TABLE IF NOT EXISTS af_collab_update (
+    seq BIGSERIAL PRIMARY KEY,
+    oid TEXT NOT NULL,
+    partition_key INTEGER NOT NULL,
+    workspace_id UUID NOT NULL REFERENCES af_workspace(workspace_id) ON DELETE CASCADE,
+    -- The yrs update, encoded with the v1 encoding
+    blob BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_af_collab_update_oid_seq ON af_collab_update (oid, seq);
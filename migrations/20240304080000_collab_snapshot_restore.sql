@@ -0,0 +1,11 @@
+-- History of the snapshots that were restored into the collabs.
+CREATE TABLE IF NOT EXISTS af_collab_snapshot_restore (
+    restore_id BIGSERIAL PRIMARY KEY,
+    oid TEXT NOT NULL,
+    workspace_id UUID NOT NULL REFERENCES af_workspace(workspace_id) ON DELETE CASCADE,
+    -- Not a foreign key, the history is kept when the snapshot is removed
+    snapshot_id BIGINT NOT NULL,
+    restored_by BIGINT NOT NULL,
+    restored_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_af_collab_snapshot_restore_oid ON af_collab_snapshot_restore (oid, restored_at DESC);
@@ -0,0 +1,19 @@
+-- Pinned snapshots are never removed by the retention policies. A snapshot can be given a name
+-- when it's pinned.
+ALTER TABLE af_collab_snapshot ADD COLUMN IF NOT EXISTS pinned BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE af_collab_snapshot ADD COLUMN IF NOT EXISTS name TEXT;
+
+-- Retention policies of the collab snapshots. A policy applies to the collabs of the given type in
+-- the workspace, or to all the collabs of the workspace when partition_key is NULL.
+CREATE TABLE IF NOT EXISTS af_snapshot_retention_policy (
+    workspace_id UUID NOT NULL REFERENCES af_workspace(workspace_id) ON DELETE CASCADE,
+    partition_key INTEGER,
+    keep_latest INTEGER NOT NULL,
+    hourly_for_hours INTEGER NOT NULL,
+    daily_for_days INTEGER NOT NULL,
+    -- NULL keeps one snapshot per week forever
+    weekly_for_weeks INTEGER,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_af_snapshot_retention_policy_workspace_partition
+    ON af_snapshot_retention_policy (workspace_id, (COALESCE(partition_key, -1)));
@@ -0,0 +1,14 @@
+-- Jobs that export a workspace as an archive. The archive is stored in the bucket once the job is
+-- completed.
+CREATE TABLE IF NOT EXISTS af_workspace_export (
+    job_id UUID PRIMARY KEY,
+    workspace_id UUID NOT NULL REFERENCES af_workspace(workspace_id) ON DELETE CASCADE,
+    created_by BIGINT NOT NULL REFERENCES af_user(uid) ON DELETE CASCADE,
+    -- 0: running, 1: completed, 2: failed
+    status SMALLINT NOT NULL DEFAULT 0,
+    file_size BIGINT,
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+CREATE INDEX IF NOT EXISTS idx_af_workspace_export_workspace_id ON af_workspace_export (workspace_id);
@@ -0,0 +1,146 @@
+use crate::biz::blob_gc::run_blob_gc;
+use crate::biz::workspace::ops;
+use crate::component::auth::jwt::Authorization;
+use crate::config::config::BlobGcAction;
+use crate::state::AppState;
+use actix_web::web::{Data, Json};
+use actix_web::{web, Result, Scope};
+use app_error::AppError;
+use shared_entity::dto::workspace_dto::{
+  BlobGcParams, BlobGcReport, RepeatedWorkspacePlan, UpdateWorkspaceQuotaParams, WorkspacePlan,
+  WorkspaceQuota,
+};
+use shared_entity::response::{AppResponse, AppResponseError, JsonAppResponse};
+use tracing::instrument;
+use uuid::Uuid;
+
+/// The role of the server admin that is created on startup. See `setup_admin_account`.
+const ADMIN_ROLE: &str = "supabase_admin";
+
+/// Endpoints that are only accessible by the server admin.
+///
+/// The workspace id in the path is not named `workspace_id`, because the server admin is usually not
+/// a member of the workspace and the access control middleware would reject the request.
+pub fn admin_scope() -> Scope {
+  web::scope("/api/admin")
+    .service(
+      web::resource("/workspace/{id}/quota")
+        .route(web::get().to(get_workspace_quota_handler))
+        .route(web::put().to(update_workspace_quota_handler))
+        .route(web::delete().to(reset_workspace_quota_handler)),
+    )
+    .service(web::resource("/plan").route(web::get().to(get_workspace_plans_handler)))
+    .service(web::resource("/plan/{workspace_type}").route(web::put().to(update_plan_handler)))
+    .service(web::resource("/blob/gc").route(web::post().to(blob_gc_handler)))
+}
+
+fn check_admin(auth: &Authorization) -> Result<(), AppError> {
+  if auth.claims.role != ADMIN_ROLE {
+    return Err(AppError::NotEnoughPermissions(format!(
+      "user:{:?} is not the server admin",
+      auth.claims.sub
+    )));
+  }
+  Ok(())
+}
+
+#[instrument(skip(state, auth), err)]
+async fn get_workspace_quota_handler(
+  auth: Authorization,
+  state: Data<AppState>,
+  workspace_id: web::Path<Uuid>,
+) -> Result<JsonAppResponse<WorkspaceQuota>> {
+  check_admin(&auth).map_err(AppResponseError::from)?;
+  let quota = ops::get_workspace_quota(&state.pg_pool, &workspace_id)
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(AppResponse::Ok().with_data(quota).into())
+}
+
+#[instrument(skip(state, auth), err)]
+async fn update_workspace_quota_handler(
+  auth: Authorization,
+  state: Data<AppState>,
+  workspace_id: web::Path<Uuid>,
+  params: Json<UpdateWorkspaceQuotaParams>,
+) -> Result<JsonAppResponse<WorkspaceQuota>> {
+  check_admin(&auth).map_err(AppResponseError::from)?;
+  let quota = ops::update_workspace_quota(&state.pg_pool, &workspace_id, params.into_inner())
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(AppResponse::Ok().with_data(quota).into())
+}
+
+#[instrument(skip(state, auth), err)]
+async fn reset_workspace_quota_handler(
+  auth: Authorization,
+  state: Data<AppState>,
+  workspace_id: web::Path<Uuid>,
+) -> Result<JsonAppResponse<WorkspaceQuota>> {
+  check_admin(&auth).map_err(AppResponseError::from)?;
+  let quota = ops::reset_workspace_quota(&state.pg_pool, &workspace_id)
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(AppResponse::Ok().with_data(quota).into())
+}
+
+#[instrument(skip(state, auth), err)]
+async fn get_workspace_plans_handler(
+  auth: Authorization,
+  state: Data<AppState>,
+) -> Result<JsonAppResponse<RepeatedWorkspacePlan>> {
+  check_admin(&auth).map_err(AppResponseError::from)?;
+  let plans = ops::get_workspace_plans(&state.pg_pool)
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(
+    AppResponse::Ok()
+      .with_data(RepeatedWorkspacePlan(plans))
+      .into(),
+  )
+}
+
+#[instrument(skip(state, auth), err)]
+async fn update_plan_handler(
+  auth: Authorization,
+  state: Data<AppState>,
+  workspace_type: web::Path<i32>,
+  plan: Json<WorkspacePlan>,
+) -> Result<JsonAppResponse<()>> {
+  check_admin(&auth).map_err(AppResponseError::from)?;
+  let mut plan = plan.into_inner();
+  plan.workspace_type = workspace_type.into_inner();
+  ops::update_workspace_plan(&state.pg_pool, plan)
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(AppResponse::Ok().into())
+}
+
+/// Run the blob garbage collector on demand, in addition to the periodic background run.
+#[instrument(skip(state, auth), err)]
+async fn blob_gc_handler(
+  auth: Authorization,
+  state: Data<AppState>,
+  params: Json<BlobGcParams>,
+) -> Result<JsonAppResponse<BlobGcReport>> {
+  check_admin(&auth).map_err(AppResponseError::from)?;
+  let params = params.into_inner();
+  let action = if params.dry_run {
+    BlobGcAction::DryRun
+  } else if params.quarantine {
+    BlobGcAction::Quarantine
+  } else {
+    BlobGcAction::Delete
+  };
+  let min_age_secs = params
+    .min_age_secs
+    .unwrap_or(state.config.blob_gc.min_age_secs);
+  let report = run_blob_gc(
+    &state.bucket_storage,
+    params.workspace_id,
+    action,
+    min_age_secs,
+  )
+  .await;
+  Ok(AppResponse::Ok().with_data(report).into())
+}
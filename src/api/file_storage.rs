@@ -1,7 +1,8 @@
 use actix_http::body::BoxBody;
+use actix_web::http::header::HeaderName;
 use actix_web::http::header::{
-  ContentLength, ContentType, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
-  LAST_MODIFIED,
+  ContentLength, ContentType, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_RANGE,
+  CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, LOCATION, RANGE,
 };
 use actix_web::web::{Json, Payload};
 use actix_web::{
@@ -10,28 +11,59 @@ use actix_web::{
 };
 use actix_web::{HttpResponse, Result};
 use app_error::AppError;
-use chrono::DateTime;
-use database::file::{MAX_BLOB_SIZE, MAX_USAGE};
-use database::resource_usage::{get_all_workspace_blob_metadata, get_workspace_usage_size};
-use shared_entity::dto::workspace_dto::{BlobMetadata, RepeatedBlobMetaData, WorkspaceSpaceUsage};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use database::collab::select_workspace_id_of_collab;
+use database::file::{image_variant_content_type, ImageVariant, MAX_UPLOAD_PART_SIZE};
+use database::pg_row::{AFBlobMetadataRow, AFBlobUploadPartRow, AFBlobUploadRow};
+use database::resource_usage::{
+  get_all_workspace_blob_metadata, get_workspace_quota, get_workspace_usage_size,
+};
+use serde::Deserialize;
+use shared_entity::dto::workspace_dto::{
+  BlobMetadata, BlobPublicLink, BlobUpload, BlobUploadPart, CompletePresignedPutParams,
+  CreateBlobPublicLinkParams, CreateBlobUploadParams, CreatePresignedPutParams, PresignedPutBlob,
+  RepeatedBlobMetaData, RepeatedBlobUploadPart, WorkspaceSpaceUsage,
+};
 use shared_entity::response::{AppResponse, AppResponseError, JsonAppResponse};
 use sqlx::types::Uuid;
 use std::pin::Pin;
+use std::str::FromStr;
 use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio_stream::StreamExt;
 use tokio_util::io::StreamReader;
 use tracing::{event, instrument};
 
+use crate::biz::blob_link::{blob_public_link_path, verify_blob_public_link};
 use crate::state::AppState;
 
+/// The routes without `workspace_id` in the path are not checked against the workspace
+/// membership. The collab routes are checked against the collab membership instead, and the
+/// public routes are only checked against the signature of the link.
 pub fn file_storage_scope() -> Scope {
   web::scope("/api/file_storage")
+    .service(
+      web::resource("/collab/{object_id}/blob/{file_id}")
+        .route(web::get().to(get_collab_blob_handler)),
+    )
+    .service(web::resource("/public/{id}/{file_id}").route(web::get().to(get_public_blob_handler)))
     .service(
       web::resource("/{workspace_id}/blob/{file_id}")
         .route(web::put().to(put_blob_handler))
         .route(web::get().to(get_blob_handler))
         .route(web::delete().to(delete_blob_handler)),
     )
+    .service(
+      web::resource("/{workspace_id}/blob/{file_id}/public_link")
+        .route(web::post().to(create_blob_public_link_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/blob/{file_id}/presigned")
+        .route(web::post().to(create_presigned_put_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/blob/{file_id}/presigned/complete")
+        .route(web::post().to(complete_presigned_put_handler)),
+    )
     .service(
       web::resource("/{workspace_id}/metadata/{file_id}")
         .route(web::get().to(get_blob_metadata_handler)),
@@ -43,12 +75,33 @@ pub fn file_storage_scope() -> Scope {
       web::resource("/{workspace_id}/blobs")
         .route(web::get().to(get_all_workspace_blob_metadata_handler)),
     )
+    .service(web::resource("/{workspace_id}/upload").route(web::post().to(create_upload_handler)))
+    .service(
+      web::resource("/{workspace_id}/upload/{upload_id}")
+        .route(web::get().to(get_upload_parts_handler))
+        .route(web::delete().to(abort_upload_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/upload/{upload_id}/part/{part_number}")
+        .route(web::put().to(upload_part_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/upload/{upload_id}/complete")
+        .route(web::post().to(complete_upload_handler)),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct PutBlobQuery {
+  /// The collab object that owns the blob
+  object_id: Option<String>,
 }
 
 #[instrument(skip(state, payload), err)]
 async fn put_blob_handler(
   state: Data<AppState>,
   path: web::Path<(Uuid, String)>,
+  query: web::Query<PutBlobQuery>,
   content_type: web::Header<ContentType>,
   content_length: web::Header<ContentLength>,
   payload: Payload,
@@ -56,36 +109,19 @@ async fn put_blob_handler(
   let (workspace_id, file_id) = path.into_inner();
   let content_length = content_length.into_inner().into_inner();
   let content_type = content_type.into_inner().to_string();
-  let content = {
-    // Check content length, if it's too large, return error.
-    if content_length > MAX_BLOB_SIZE {
-      return Ok(
-        AppResponse::from(AppError::PayloadTooLarge(
-          "The uploading file is too large".to_string(),
-        ))
-        .into(),
-      );
-    }
-    let mut payload_reader = payload_to_async_read(payload);
-    let mut content = vec![0; content_length];
-    let n = payload_reader.read_exact(&mut content).await?;
-    assert_eq!(n, content_length);
-    let res = payload_reader.read_u8().await;
-    match res {
-      Ok(_) => {
-        return Ok(
-          AppResponse::from(AppError::PayloadTooLarge(
-            "Content length is {}, but the actual content is larger".to_string(),
-          ))
-          .into(),
-        );
-      },
-      Err(e) => match e.kind() {
-        std::io::ErrorKind::UnexpectedEof => (),
-        _ => return Err(AppError::Internal(anyhow::anyhow!(e)).into()),
-      },
-    };
-    content
+  let object_id = query.into_inner().object_id;
+  if let Some(object_id) = &object_id {
+    check_blob_owner(&state, &workspace_id, object_id)
+      .await
+      .map_err(AppResponseError::from)?;
+  }
+  let quota = get_workspace_quota(&state.pg_pool, &workspace_id)
+    .await
+    .map_err(AppResponseError::from)?;
+  let content = match read_payload(payload, content_length, quota.blob_size_limit as usize).await {
+    Ok(content) => content,
+    Err(err @ AppError::PayloadTooLarge(_)) => return Ok(AppResponse::from(err).into()),
+    Err(err) => return Err(err.into()),
   };
 
   event!(
@@ -98,13 +134,99 @@ async fn put_blob_handler(
 
   state
     .bucket_storage
-    .put_blob(workspace_id, file_id.to_string(), content, content_type)
+    .put_blob(
+      workspace_id,
+      file_id.to_string(),
+      content,
+      content_type,
+      object_id,
+    )
     .await
     .map_err(AppResponseError::from)?;
 
   Ok(AppResponse::Ok().into())
 }
 
+/// The collab object that owns a blob must be in the same workspace as the blob.
+async fn check_blob_owner(
+  state: &AppState,
+  workspace_id: &Uuid,
+  object_id: &str,
+) -> Result<(), AppError> {
+  let collab_workspace_id = select_workspace_id_of_collab(object_id, &state.pg_pool).await?;
+  if collab_workspace_id.as_ref() != Some(workspace_id) {
+    return Err(AppError::InvalidRequest(format!(
+      "collab:{} is not in workspace:{}",
+      object_id, workspace_id
+    )));
+  }
+  Ok(())
+}
+
+/// Return a presigned url for uploading the blob directly to the bucket, if enabled.
+#[instrument(skip(state), err)]
+async fn create_presigned_put_handler(
+  state: Data<AppState>,
+  path: web::Path<(Uuid, String)>,
+  params: Json<CreatePresignedPutParams>,
+) -> Result<JsonAppResponse<PresignedPutBlob>> {
+  let setting = &state.config.blob_storage;
+  if !setting.presigned_url {
+    return Ok(
+      AppResponse::Ok()
+        .with_data(PresignedPutBlob::Disabled)
+        .into(),
+    );
+  }
+
+  let (workspace_id, file_id) = path.into_inner();
+  let presigned_put = state
+    .bucket_storage
+    .create_presigned_put(
+      &workspace_id,
+      &file_id,
+      params.file_size,
+      setting.presigned_url_expires_secs,
+    )
+    .await
+    .map_err(AppResponseError::from)?;
+  let data = match presigned_put {
+    None => PresignedPutBlob::Completed,
+    Some(presigned_put) => PresignedPutBlob::Upload {
+      upload_id: presigned_put.upload_id,
+      url: presigned_put.url,
+    },
+  };
+  Ok(AppResponse::Ok().with_data(data).into())
+}
+
+#[instrument(skip(state), err)]
+async fn complete_presigned_put_handler(
+  state: Data<AppState>,
+  path: web::Path<(Uuid, String)>,
+  params: Json<CompletePresignedPutParams>,
+) -> Result<JsonAppResponse<()>> {
+  let (workspace_id, file_id) = path.into_inner();
+  let params = params.into_inner();
+  if let Some(object_id) = &params.object_id {
+    check_blob_owner(&state, &workspace_id, object_id)
+      .await
+      .map_err(AppResponseError::from)?;
+  }
+  state
+    .bucket_storage
+    .complete_presigned_put(
+      &workspace_id,
+      &file_id,
+      &params.file_type,
+      &params.upload_id,
+      params.object_id.as_deref(),
+    )
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(AppResponse::Ok().into())
+}
+
 #[instrument(level = "debug", skip(state), err)]
 async fn delete_blob_handler(
   state: Data<AppState>,
@@ -119,55 +241,364 @@ async fn delete_blob_handler(
   Ok(AppResponse::Ok().into())
 }
 
+#[derive(Debug, Deserialize)]
+struct GetBlobQuery {
+  /// One of the [ImageVariant]s. Only available for images.
+  variant: Option<String>,
+}
+
 #[instrument(level = "debug", skip(state), err)]
 async fn get_blob_handler(
   state: Data<AppState>,
   path: web::Path<(Uuid, String)>,
+  query: web::Query<GetBlobQuery>,
   req: HttpRequest,
 ) -> Result<HttpResponse<BoxBody>> {
   let (workspace_id, file_id) = path.into_inner();
+  match get_blob_metadata_or_response(&state, &workspace_id, &file_id).await {
+    Ok(metadata) => blob_response(&state, metadata, query.variant.as_deref(), &req).await,
+    Err(response) => Ok(response),
+  }
+}
 
-  // Get the metadata
-  let result = state
-    .bucket_storage
-    .get_blob_metadata(&workspace_id, &file_id)
-    .await;
-
-  if let Err(err) = result.as_ref() {
-    return if err.is_record_not_found() {
-      Ok(HttpResponse::NotFound().finish())
-    } else {
-      Ok(HttpResponse::InternalServerError().finish())
-    };
-  }
-
-  let metadata = result.unwrap();
-  // Check if the file is modified since the last time
-  if let Some(modified_since) = req
-    .headers()
-    .get(IF_MODIFIED_SINCE)
-    .and_then(|h| h.to_str().ok())
-    .and_then(|s| DateTime::parse_from_rfc2822(s).ok())
+/// Serve the blobs that are owned by the collab object to the members of the collab object, who
+/// might not be members of the workspace.
+#[instrument(level = "debug", skip(state), err)]
+async fn get_collab_blob_handler(
+  state: Data<AppState>,
+  path: web::Path<(String, String)>,
+  query: web::Query<GetBlobQuery>,
+  req: HttpRequest,
+) -> Result<HttpResponse<BoxBody>> {
+  let (object_id, file_id) = path.into_inner();
+  let workspace_id = match select_workspace_id_of_collab(&object_id, &state.pg_pool)
+    .await
+    .map_err(AppResponseError::from)?
   {
-    if metadata.modified_at.naive_utc() <= modified_since.naive_utc() {
-      return Ok(HttpResponse::NotModified().finish());
-    }
+    Some(workspace_id) => workspace_id,
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+  let metadata = match get_blob_metadata_or_response(&state, &workspace_id, &file_id).await {
+    Ok(metadata) => metadata,
+    Err(response) => return Ok(response),
+  };
+  if metadata.object_id.as_deref() != Some(object_id.as_str()) {
+    return Ok(HttpResponse::NotFound().finish());
+  }
+  blob_response(&state, metadata, query.variant.as_deref(), &req).await
+}
+
+#[derive(Debug, Deserialize)]
+struct PublicBlobQuery {
+  /// Unix timestamp in seconds after which the link is no longer valid
+  expires: Option<i64>,
+  signature: String,
+  variant: Option<String>,
+}
+
+/// Serve the blob to anyone who has a public link signed by [create_blob_public_link_handler].
+#[instrument(level = "debug", skip(state), err)]
+async fn get_public_blob_handler(
+  state: Data<AppState>,
+  path: web::Path<(Uuid, String)>,
+  query: web::Query<PublicBlobQuery>,
+  req: HttpRequest,
+) -> Result<HttpResponse<BoxBody>> {
+  let (workspace_id, file_id) = path.into_inner();
+  if let Err(err) = verify_blob_public_link(
+    &state.config.blob_storage.public_link_key,
+    &workspace_id,
+    &file_id,
+    query.expires,
+    &query.signature,
+  ) {
+    event!(tracing::Level::DEBUG, "reject public blob link: {}", err);
+    return Ok(HttpResponse::Forbidden().finish());
   }
-  let blob = state
+  match get_blob_metadata_or_response(&state, &workspace_id, &file_id).await {
+    Ok(metadata) => blob_response(&state, metadata, query.variant.as_deref(), &req).await,
+    Err(response) => Ok(response),
+  }
+}
+
+#[instrument(level = "debug", skip(state), err)]
+async fn create_blob_public_link_handler(
+  state: Data<AppState>,
+  path: web::Path<(Uuid, String)>,
+  params: Json<CreateBlobPublicLinkParams>,
+) -> Result<JsonAppResponse<BlobPublicLink>> {
+  let (workspace_id, file_id) = path.into_inner();
+  // Make sure the blob exists
+  state
     .bucket_storage
-    .get_blob(&workspace_id, &file_id)
+    .get_blob_metadata(&workspace_id, &file_id)
     .await
     .map_err(AppResponseError::from)?;
 
-  let response = HttpResponse::Ok()
-    .append_header((ETAG, file_id))
-    .append_header((CONTENT_TYPE, metadata.file_type))
-    .append_header((LAST_MODIFIED, metadata.modified_at.to_rfc2822()))
-    .append_header((CONTENT_LENGTH, blob.len()))
-    .append_header((CACHE_CONTROL, "public, immutable, max-age=31536000"))// 31536000 seconds = 1 year
-    .body(blob);
+  let expires_at = params
+    .expires_in_secs
+    .map(|secs| Utc::now() + Duration::seconds(secs as i64));
+  let path = blob_public_link_path(
+    &state.config.blob_storage.public_link_key,
+    &workspace_id,
+    &file_id,
+    expires_at,
+  );
+  Ok(
+    AppResponse::Ok()
+      .with_data(BlobPublicLink { path, expires_at })
+      .into(),
+  )
+}
+
+/// Return the metadata of the blob, or the response to send if the metadata can't be read.
+async fn get_blob_metadata_or_response(
+  state: &AppState,
+  workspace_id: &Uuid,
+  file_id: &str,
+) -> Result<AFBlobMetadataRow, HttpResponse<BoxBody>> {
+  state
+    .bucket_storage
+    .get_blob_metadata(workspace_id, file_id)
+    .await
+    .map_err(|err| {
+      if err.is_record_not_found() {
+        HttpResponse::NotFound().finish()
+      } else {
+        HttpResponse::InternalServerError().finish()
+      }
+    })
+}
+
+/// Build the response with the content of the blob, honoring the conditional and range headers
+/// of the request.
+async fn blob_response(
+  state: &AppState,
+  metadata: AFBlobMetadataRow,
+  variant: Option<&str>,
+  req: &HttpRequest,
+) -> Result<HttpResponse<BoxBody>> {
+  let workspace_id = metadata.workspace_id;
+  let file_id = metadata.file_id.clone();
+  let variant = variant
+    .map(ImageVariant::from_str)
+    .transpose()
+    .map_err(AppResponseError::from)?;
+  // The variants are only available once the dimensions of the image are known, and an image that
+  // already fits in the variant is served as is.
+  let variant = match (variant, metadata.width, metadata.height) {
+    (None, _, _) => None,
+    (Some(variant), Some(width), Some(height)) => {
+      Some(variant).filter(|variant| variant.is_generated_for(width as u32, height as u32))
+    },
+    (Some(_), _, _) => return Ok(HttpResponse::NotFound().finish()),
+  };
+  let e_tag = blob_e_tag(&metadata, variant);
+
+  // If-None-Match takes precedence over If-Modified-Since
+  if let Some(if_none_match) = header_str(req, IF_NONE_MATCH) {
+    if if_none_match_matches(if_none_match, &e_tag) {
+      return Ok(
+        HttpResponse::NotModified()
+          .append_header((ETAG, e_tag))
+          .finish(),
+      );
+    }
+  } else if let Some(modified_since) = header_date(req, IF_MODIFIED_SINCE) {
+    // Http dates only have second precision
+    if metadata.modified_at.timestamp() <= modified_since.timestamp() {
+      return Ok(HttpResponse::NotModified().finish());
+    }
+  }
+
+  // Let the client download the content from the bucket directly. The Range header is resent
+  // by the client when following the redirect.
+  let setting = &state.config.blob_storage;
+  if setting.presigned_url {
+    let url = state
+      .bucket_storage
+      .get_blob_presigned_url(
+        &workspace_id,
+        &metadata,
+        variant,
+        setting.presigned_url_expires_secs,
+      )
+      .await
+      .map_err(AppResponseError::from)?;
+    return Ok(
+      HttpResponse::TemporaryRedirect()
+        .append_header((LOCATION, url))
+        .append_header((ETAG, e_tag))
+        .append_header((CACHE_CONTROL, "no-store"))
+        .finish(),
+    );
+  }
+
+  // The variants are small, so range requests are not supported for them
+  if let Some(variant) = variant {
+    let blob = state
+      .bucket_storage
+      .get_blob_variant(&workspace_id, &file_id, variant)
+      .await
+      .map_err(AppResponseError::from)?;
+    let response = HttpResponse::Ok()
+      .append_header((ETAG, e_tag))
+      .append_header((
+        CONTENT_TYPE,
+        image_variant_content_type(&metadata.file_type),
+      ))
+      .append_header((LAST_MODIFIED, metadata.modified_at.to_rfc2822()))
+      .append_header((CONTENT_LENGTH, blob.len()))
+      .append_header((CACHE_CONTROL, "public, immutable, max-age=31536000"))
+      .body(blob);
+    return Ok(response);
+  }
+
+  // The range is ignored if the blob has changed since the client got the If-Range validator
+  let file_size = metadata.file_size as u64;
+  let range = header_str(req, RANGE)
+    .filter(|_| if_range_matches(req, &e_tag, &metadata))
+    .and_then(|range| parse_range(range, file_size));
+
+  match range {
+    None => {
+      let blob = state
+        .bucket_storage
+        .get_blob(&workspace_id, &file_id)
+        .await
+        .map_err(AppResponseError::from)?;
+
+      let response = HttpResponse::Ok()
+        .append_header((ETAG, e_tag))
+        .append_header((CONTENT_TYPE, metadata.file_type))
+        .append_header((LAST_MODIFIED, metadata.modified_at.to_rfc2822()))
+        .append_header((CONTENT_LENGTH, blob.len()))
+        .append_header((ACCEPT_RANGES, "bytes"))
+        .append_header((CACHE_CONTROL, "public, immutable, max-age=31536000"))// 31536000 seconds = 1 year
+        .body(blob);
+      Ok(response)
+    },
+    Some(ByteRange::Unsatisfiable) => Ok(
+      HttpResponse::RangeNotSatisfiable()
+        .append_header((CONTENT_RANGE, format!("bytes */{}", file_size)))
+        .finish(),
+    ),
+    Some(ByteRange::Satisfiable { start, end }) => {
+      let blob = state
+        .bucket_storage
+        .get_blob_range(&workspace_id, &file_id, start, end)
+        .await
+        .map_err(AppResponseError::from)?;
 
-  Ok(response)
+      let response = HttpResponse::PartialContent()
+        .append_header((ETAG, e_tag))
+        .append_header((CONTENT_TYPE, metadata.file_type))
+        .append_header((LAST_MODIFIED, metadata.modified_at.to_rfc2822()))
+        .append_header((CONTENT_LENGTH, blob.len()))
+        .append_header((
+          CONTENT_RANGE,
+          format!("bytes {}-{}/{}", start, end, file_size),
+        ))
+        .append_header((ACCEPT_RANGES, "bytes"))
+        .append_header((CACHE_CONTROL, "public, immutable, max-age=31536000"))
+        .body(blob);
+      Ok(response)
+    },
+  }
+}
+
+/// Use the content hash of the blob as the ETag. Each variant of an image has its own ETag.
+fn blob_e_tag(metadata: &AFBlobMetadataRow, variant: Option<ImageVariant>) -> String {
+  match variant {
+    None => format!("\"{}\"", metadata.content_hash),
+    Some(variant) => format!("\"{}@{}\"", metadata.content_hash, variant.as_str()),
+  }
+}
+
+fn header_str(req: &HttpRequest, name: HeaderName) -> Option<&str> {
+  req.headers().get(name).and_then(|h| h.to_str().ok())
+}
+
+fn header_date(req: &HttpRequest, name: HeaderName) -> Option<DateTime<FixedOffset>> {
+  header_str(req, name).and_then(|s| DateTime::parse_from_rfc2822(s).ok())
+}
+
+/// Return true if any of the entity tags in the If-None-Match header matches the ETag.
+/// Weak comparison is used as defined in RFC 7232.
+fn if_none_match_matches(if_none_match: &str, e_tag: &str) -> bool {
+  if_none_match.trim() == "*"
+    || if_none_match
+      .split(',')
+      .map(|tag| tag.trim().trim_start_matches("W/"))
+      .any(|tag| tag == e_tag)
+}
+
+/// Return true if the Range header should be honored according to the If-Range header. The
+/// If-Range header can be either an ETag or a date.
+fn if_range_matches(req: &HttpRequest, e_tag: &str, metadata: &AFBlobMetadataRow) -> bool {
+  match header_str(req, IF_RANGE) {
+    None => true,
+    Some(if_range) if if_range.trim().starts_with('"') => if_range.trim() == e_tag,
+    Some(_) => match header_date(req, IF_RANGE) {
+      Some(date) => metadata.modified_at.timestamp() <= date.timestamp(),
+      None => false,
+    },
+  }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+  /// The range from `start` to `end`, both inclusive.
+  Satisfiable {
+    start: u64,
+    end: u64,
+  },
+  Unsatisfiable,
+}
+
+/// Parse the Range header, e.g. `bytes=0-99`, `bytes=100-` or `bytes=-100`. Only a single range
+/// is supported. Return None if the header is malformed or contains multiple ranges, in which
+/// case the whole blob is returned.
+fn parse_range(value: &str, size: u64) -> Option<ByteRange> {
+  let spec = value.trim().strip_prefix("bytes=")?;
+  if spec.contains(',') {
+    return None;
+  }
+  let (start, end) = spec.split_once('-')?;
+  let (start, end) = (start.trim(), end.trim());
+  let range = match (start.is_empty(), end.is_empty()) {
+    // bytes=-100 means the last 100 bytes
+    (true, false) => {
+      let suffix = end.parse::<u64>().ok()?;
+      if suffix == 0 || size == 0 {
+        return Some(ByteRange::Unsatisfiable);
+      }
+      ByteRange::Satisfiable {
+        start: size.saturating_sub(suffix),
+        end: size - 1,
+      }
+    },
+    (false, _) => {
+      let start = start.parse::<u64>().ok()?;
+      let end = if end.is_empty() {
+        u64::MAX
+      } else {
+        end.parse::<u64>().ok()?
+      };
+      if end < start {
+        return None;
+      }
+      if start >= size {
+        return Some(ByteRange::Unsatisfiable);
+      }
+      ByteRange::Satisfiable {
+        start,
+        end: end.min(size - 1),
+      }
+    },
+    (true, true) => return None,
+  };
+  Some(range)
 }
 
 #[instrument(level = "debug", skip(state), err)]
@@ -188,6 +619,9 @@ async fn get_blob_metadata_handler(
       file_type: meta.file_type,
       file_size: meta.file_size,
       modified_at: meta.modified_at,
+      width: meta.width.map(|width| width as u32),
+      height: meta.height.map(|height| height as u32),
+      object_id: meta.object_id,
     })
     .map_err(AppResponseError::from)?;
 
@@ -202,9 +636,14 @@ async fn get_workspace_usage_handler(
   let current = get_workspace_usage_size(&state.pg_pool, &workspace_id)
     .await
     .map_err(AppResponseError::from)?;
+  let quota = get_workspace_quota(&state.pg_pool, &workspace_id)
+    .await
+    .map_err(AppResponseError::from)?;
   let usage = WorkspaceSpaceUsage {
     consumed_capacity: current,
-    total_capacity: MAX_USAGE,
+    total_capacity: quota.storage_limit as u64,
+    blob_size_limit: quota.blob_size_limit as u64,
+    collab_size_limit: quota.collab_size_limit as u64,
   };
   Ok(AppResponse::Ok().with_data(usage).into())
 }
@@ -225,6 +664,9 @@ async fn get_all_workspace_blob_metadata_handler(
       file_type: meta.file_type,
       file_size: meta.file_size,
       modified_at: meta.modified_at,
+      width: meta.width.map(|width| width as u32),
+      height: meta.height.map(|height| height as u32),
+      object_id: meta.object_id,
     })
     .collect::<Vec<_>>();
   Ok(
@@ -233,6 +675,148 @@ async fn get_all_workspace_blob_metadata_handler(
       .into(),
   )
 }
+#[instrument(skip(state), err)]
+async fn create_upload_handler(
+  state: Data<AppState>,
+  workspace_id: web::Path<Uuid>,
+  params: Json<CreateBlobUploadParams>,
+) -> Result<JsonAppResponse<BlobUpload>> {
+  let params = params.into_inner();
+  let upload = state
+    .bucket_storage
+    .create_upload(workspace_id.into_inner(), params.file_id, params.file_type)
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(
+    AppResponse::Ok()
+      .with_data(blob_upload_from_row(upload))
+      .into(),
+  )
+}
+
+#[instrument(skip(state, payload), err)]
+async fn upload_part_handler(
+  state: Data<AppState>,
+  path: web::Path<(Uuid, String, i32)>,
+  content_length: web::Header<ContentLength>,
+  payload: Payload,
+) -> Result<JsonAppResponse<BlobUploadPart>> {
+  let (workspace_id, upload_id, part_number) = path.into_inner();
+  let content_length = content_length.into_inner().into_inner();
+  let content = read_payload(payload, content_length, MAX_UPLOAD_PART_SIZE)
+    .await
+    .map_err(AppResponseError::from)?;
+
+  let part = state
+    .bucket_storage
+    .upload_part(&workspace_id, &upload_id, part_number, content)
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(
+    AppResponse::Ok()
+      .with_data(blob_upload_part_from_row(part))
+      .into(),
+  )
+}
+
+#[instrument(level = "debug", skip(state), err)]
+async fn get_upload_parts_handler(
+  state: Data<AppState>,
+  path: web::Path<(Uuid, String)>,
+) -> Result<JsonAppResponse<RepeatedBlobUploadPart>> {
+  let (workspace_id, upload_id) = path.into_inner();
+  let parts = state
+    .bucket_storage
+    .get_upload_parts(&workspace_id, &upload_id)
+    .await
+    .map_err(AppResponseError::from)?
+    .into_iter()
+    .map(blob_upload_part_from_row)
+    .collect::<Vec<_>>();
+  Ok(
+    AppResponse::Ok()
+      .with_data(RepeatedBlobUploadPart(parts))
+      .into(),
+  )
+}
+
+#[instrument(skip(state), err)]
+async fn complete_upload_handler(
+  state: Data<AppState>,
+  path: web::Path<(Uuid, String)>,
+) -> Result<JsonAppResponse<()>> {
+  let (workspace_id, upload_id) = path.into_inner();
+  state
+    .bucket_storage
+    .complete_upload(&workspace_id, &upload_id)
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(AppResponse::Ok().into())
+}
+
+#[instrument(skip(state), err)]
+async fn abort_upload_handler(
+  state: Data<AppState>,
+  path: web::Path<(Uuid, String)>,
+) -> Result<JsonAppResponse<()>> {
+  let (workspace_id, upload_id) = path.into_inner();
+  state
+    .bucket_storage
+    .abort_upload(&workspace_id, &upload_id)
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(AppResponse::Ok().into())
+}
+
+fn blob_upload_from_row(row: AFBlobUploadRow) -> BlobUpload {
+  BlobUpload {
+    upload_id: row.upload_id,
+    workspace_id: row.workspace_id,
+    file_id: row.file_id,
+    file_type: row.file_type,
+    created_at: row.created_at,
+  }
+}
+
+fn blob_upload_part_from_row(row: AFBlobUploadPartRow) -> BlobUploadPart {
+  BlobUploadPart {
+    part_number: row.part_number,
+    e_tag: row.e_tag,
+    part_size: row.part_size,
+    modified_at: row.modified_at,
+  }
+}
+
+/// Read exactly `content_length` bytes from the payload. Return [AppError::PayloadTooLarge] if
+/// the content length exceeds `max_size` or the actual content is larger than the content length.
+async fn read_payload(
+  payload: Payload,
+  content_length: usize,
+  max_size: usize,
+) -> Result<Vec<u8>, AppError> {
+  // Check content length, if it's too large, return error.
+  if content_length > max_size {
+    return Err(AppError::PayloadTooLarge(
+      "The uploading file is too large".to_string(),
+    ));
+  }
+  let mut payload_reader = payload_to_async_read(payload);
+  let mut content = vec![0; content_length];
+  let n = payload_reader.read_exact(&mut content).await?;
+  assert_eq!(n, content_length);
+  let res = payload_reader.read_u8().await;
+  match res {
+    Ok(_) => Err(AppError::PayloadTooLarge(format!(
+      "Content length is {}, but the actual content is larger",
+      content_length
+    ))),
+    Err(e) => match e.kind() {
+      std::io::ErrorKind::UnexpectedEof => Ok(content),
+      _ => Err(AppError::Internal(anyhow::anyhow!(e))),
+    },
+  }
+}
+
 fn payload_to_async_read(payload: Payload) -> Pin<Box<dyn AsyncRead>> {
   let mapped =
     payload.map(|chunk| chunk.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)));
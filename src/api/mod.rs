@@ -1,3 +1,4 @@
+pub mod admin;
 pub mod file_storage;
 pub mod metrics;
 pub mod user;
@@ -3,16 +3,18 @@ use crate::api::ws::CollabServerImpl;
 use crate::biz;
 use crate::biz::workspace;
 use crate::biz::workspace::access_control::WorkspaceAccessControl;
+use crate::biz::workspace::archive::MAX_ARCHIVE_CONTENT_SIZE;
 use crate::component::auth::jwt::UserUuid;
 use crate::domain::compression::{decompress, CompressionType, X_COMPRESSION_TYPE};
 use crate::state::AppState;
 
 use std::time::Duration;
 
+use actix_web::http::header::CONTENT_DISPOSITION;
 use actix_web::web::{Bytes, Payload};
 use actix_web::web::{Data, Json, PayloadConfig};
 use actix_web::{web, Scope};
-use actix_web::{HttpRequest, Result};
+use actix_web::{HttpRequest, HttpResponse, Result};
 use anyhow::{anyhow, Context};
 use app_error::AppError;
 use collab::core::collab_plugin::EncodedCollab;
@@ -32,6 +34,7 @@ use shared_entity::response::{AppResponse, JsonAppResponse};
 use sqlx::types::uuid;
 use tokio::time::{sleep, Instant};
 
+use futures_util::TryStreamExt;
 use tokio_stream::StreamExt;
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{event, instrument};
@@ -40,6 +43,8 @@ use validator::Validate;
 
 pub const WORKSPACE_ID_PATH: &str = "workspace_id";
 pub const COLLAB_OBJECT_ID_PATH: &str = "object_id";
+/// The markdown of an import holds the base64 encoded content of its images.
+const MAX_MARKDOWN_IMPORT_SIZE: usize = 50 * 1024 * 1024;
 
 pub fn workspace_scope() -> Scope {
   web::scope("/api/workspace")
@@ -51,6 +56,10 @@ pub fn workspace_scope() -> Scope {
       .route(web::get().to(list_workspace_handler))
       .route(web::post().to(create_workpace_handler))
     )
+    .service(web::resource("/import")
+      .app_data(PayloadConfig::new(MAX_ARCHIVE_CONTENT_SIZE as usize))
+      .route(web::post().to(import_workspace_handler))
+    )
     .service(web::resource("/{workspace_id}")
       .route(web::delete().to(delete_workspace_handler))
     )
@@ -92,6 +101,25 @@ pub fn workspace_scope() -> Scope {
       web::resource("/{workspace_id}/{object_id}/snapshot/list")
         .route(web::get().to(get_all_collab_snapshot_list_handler)),
     )
+    .service(
+      web::resource("/{workspace_id}/{object_id}/snapshot/restore")
+        .route(web::post().to(restore_collab_snapshot_handler))
+        .route(web::get().to(get_collab_snapshot_restores_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/{object_id}/snapshot/diff")
+        .route(web::get().to(get_document_snapshot_diff_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/{object_id}/snapshot/{snapshot_id}/pin")
+        .route(web::put().to(pin_collab_snapshot_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/snapshot/retention")
+        .route(web::get().to(get_snapshot_retention_policies_handler))
+        .route(web::put().to(set_snapshot_retention_policy_handler))
+        .route(web::delete().to(delete_snapshot_retention_policy_handler)),
+    )
     .service(
       web::resource("/{workspace_id}/collab/{object_id}/member")
         .route(web::post().to(add_collab_member_handler))
@@ -99,6 +127,18 @@ pub fn workspace_scope() -> Scope {
         .route(web::put().to(update_collab_member_handler))
         .route(web::delete().to(remove_collab_member_handler)),
     )
+    .service(
+      web::resource("/{workspace_id}/collab/{object_id}/export")
+        .route(web::get().to(export_document_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/collab/{object_id}/json")
+        .route(web::get().to(get_collab_json_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/collab/{object_id}/csv")
+        .route(web::get().to(export_database_csv_handler)),
+    )
     .service(
       web::resource("/{workspace_id}/collab/{object_id}/member/list")
         .route(web::get().to(get_collab_member_list_handler)),
@@ -106,6 +146,26 @@ pub fn workspace_scope() -> Scope {
     .service(
       web::resource("/{workspace_id}/collab_list").route(web::get().to(batch_get_collab_handler)),
     )
+    .service(
+      web::resource("/{workspace_id}/import/markdown")
+        .app_data(web::JsonConfig::default().limit(MAX_MARKDOWN_IMPORT_SIZE))
+        .route(web::post().to(import_markdown_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/import/csv").route(web::post().to(import_database_csv_handler)),
+    )
+    .service(web::resource("/{workspace_id}/search").route(web::get().to(search_collab_handler)))
+    .service(
+      web::resource("/{workspace_id}/export").route(web::post().to(export_workspace_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/export/{job_id}")
+        .route(web::get().to(get_workspace_export_handler)),
+    )
+    .service(
+      web::resource("/{workspace_id}/export/{job_id}/archive")
+        .route(web::get().to(get_workspace_export_archive_handler)),
+    )
 }
 
 pub fn collab_scope() -> Scope {
@@ -384,24 +444,14 @@ async fn batch_create_collab_handler(
   if collab_params_list.is_empty() {
     return Err(AppError::InvalidRequest("Empty collab params list".to_string()).into());
   }
-  let mut transaction = state
-    .pg_pool
-    .begin()
-    .await
-    .context("acquire transaction to upsert collab")
-    .map_err(AppError::from)?;
-  for params in collab_params_list {
-    state
-      .collab_storage
-      .upsert_collab_with_transaction(&workspace_id, &uid, params, &mut transaction)
-      .await?;
-  }
-
-  transaction
-    .commit()
-    .await
-    .context("fail to commit the transaction to upsert collab")
-    .map_err(AppError::from)?;
+  biz::collab::ops::batch_upsert_collabs(
+    &state.pg_pool,
+    &state.collab_storage,
+    &workspace_id,
+    &uid,
+    collab_params_list,
+  )
+  .await?;
   Ok(Json(AppResponse::Ok()))
 }
 
@@ -548,6 +598,356 @@ async fn get_all_collab_snapshot_list_handler(
   Ok(Json(AppResponse::Ok().with_data(data)))
 }
 
+/// Replace the content of the collab with the content of the given snapshot.
+#[instrument(skip(state), err)]
+async fn restore_collab_snapshot_handler(
+  user_uuid: UserUuid,
+  state: Data<AppState>,
+  path: web::Path<(String, String)>,
+  payload: Json<RestoreSnapshotParams>,
+) -> Result<Json<AppResponse<AFSnapshotRestore>>> {
+  let (workspace_id, object_id) = path.into_inner();
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let restore = biz::collab::restore::restore_collab_snapshot(
+    &state.pg_pool,
+    &state.collab_storage,
+    uid,
+    &workspace_id,
+    &object_id,
+    payload.into_inner(),
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(Json(AppResponse::Ok().with_data(restore)))
+}
+
+#[instrument(skip(state), err)]
+async fn get_collab_snapshot_restores_handler(
+  path: web::Path<(String, String)>,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFSnapshotRestores>>> {
+  let (_, object_id) = path.into_inner();
+  let restores = database::collab::select_snapshot_restores(&state.pg_pool, &object_id)
+    .await
+    .map_err(AppError::from)
+    .map_err(AppResponseError::from)?;
+  Ok(Json(AppResponse::Ok().with_data(restores)))
+}
+
+/// Return the blocks of the document that changed between two of its snapshots, or between a
+/// snapshot and the current state of the document.
+#[instrument(skip(state), err)]
+async fn get_document_snapshot_diff_handler(
+  user_uuid: UserUuid,
+  path: web::Path<(String, String)>,
+  query: web::Query<QuerySnapshotDiffParams>,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFDocumentDiff>>> {
+  let (workspace_id, object_id) = path.into_inner();
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let diff = biz::collab::snapshot_diff::diff_document_snapshots(
+    &state.collab_storage,
+    uid,
+    &workspace_id,
+    &object_id,
+    query.into_inner(),
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(Json(AppResponse::Ok().with_data(diff)))
+}
+
+/// Return the json of the collab, or of one of its snapshots, for the clients that can't decode
+/// the collab themselves.
+#[instrument(skip(state), err)]
+async fn get_collab_json_handler(
+  user_uuid: UserUuid,
+  path: web::Path<(String, String)>,
+  query: web::Query<QueryCollabJsonParams>,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFCollabJson>>> {
+  let (workspace_id, object_id) = path.into_inner();
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let collab_json = biz::collab::json_render::render_collab_json(
+    &state.collab_storage,
+    uid,
+    &workspace_id,
+    &object_id,
+    query.into_inner(),
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(Json(AppResponse::Ok().with_data(collab_json)))
+}
+
+#[instrument(skip(state), err)]
+async fn export_document_handler(
+  user_uuid: UserUuid,
+  path: web::Path<(String, String)>,
+  query: web::Query<ExportDocumentParams>,
+  state: Data<AppState>,
+) -> Result<HttpResponse> {
+  let (workspace_id, object_id) = path.into_inner();
+  let format = query.into_inner().format;
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let content = biz::collab::document_export::export_document(
+    &state.collab_storage,
+    uid,
+    &workspace_id,
+    &object_id,
+    format,
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(
+    HttpResponse::Ok()
+      .content_type(format.content_type())
+      .body(content),
+  )
+}
+
+#[instrument(skip(state, payload), err)]
+async fn import_markdown_handler(
+  user_uuid: UserUuid,
+  workspace_id: web::Path<Uuid>,
+  payload: Json<ImportMarkdownParams>,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFDocumentImport>>> {
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let import = biz::collab::document_import::import_markdown_document(
+    &state,
+    uid,
+    &workspace_id,
+    payload.into_inner(),
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(Json(AppResponse::Ok().with_data(import)))
+}
+
+#[instrument(skip(state), err)]
+async fn export_database_csv_handler(
+  user_uuid: UserUuid,
+  path: web::Path<(String, String)>,
+  query: web::Query<ExportDatabaseParams>,
+  state: Data<AppState>,
+) -> Result<HttpResponse> {
+  let (workspace_id, object_id) = path.into_inner();
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let csv = biz::collab::database_csv::export_database_csv(
+    &state.collab_storage,
+    uid,
+    &workspace_id,
+    &object_id,
+    query.into_inner().view_id,
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(
+    HttpResponse::Ok()
+      .content_type("text/csv; charset=utf-8")
+      .body(csv),
+  )
+}
+
+#[instrument(skip(state, payload), err)]
+async fn import_database_csv_handler(
+  user_uuid: UserUuid,
+  workspace_id: web::Path<Uuid>,
+  payload: Json<ImportCsvParams>,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFDatabaseImport>>> {
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let import = biz::collab::database_csv::import_database_csv(
+    &state.pg_pool,
+    &state.collab_storage,
+    uid,
+    &workspace_id.to_string(),
+    payload.into_inner(),
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(Json(AppResponse::Ok().with_data(import)))
+}
+
+/// Pinned snapshots are never removed by the retention policy of the workspace.
+#[instrument(skip(state), err)]
+async fn pin_collab_snapshot_handler(
+  path: web::Path<(String, String, i64)>,
+  payload: Json<PinSnapshotParams>,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFSnapshotMeta>>> {
+  let (_, object_id, snapshot_id) = path.into_inner();
+  let meta = biz::collab::snapshot_retention::pin_collab_snapshot(
+    &state.pg_pool,
+    &object_id,
+    snapshot_id,
+    payload.into_inner(),
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(Json(AppResponse::Ok().with_data(meta)))
+}
+
+#[instrument(skip(state), err)]
+async fn get_snapshot_retention_policies_handler(
+  workspace_id: web::Path<Uuid>,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFSnapshotRetentionPolicies>>> {
+  let policies =
+    biz::collab::snapshot_retention::get_snapshot_retention_policies(&state.pg_pool, &workspace_id)
+      .await
+      .map_err(AppResponseError::from)?;
+  Ok(Json(
+    AppResponse::Ok().with_data(AFSnapshotRetentionPolicies(policies)),
+  ))
+}
+
+#[instrument(skip(state), err)]
+async fn set_snapshot_retention_policy_handler(
+  workspace_id: web::Path<Uuid>,
+  payload: Json<AFSnapshotRetentionPolicy>,
+  state: Data<AppState>,
+) -> Result<JsonAppResponse<()>> {
+  biz::collab::snapshot_retention::set_snapshot_retention_policy(
+    &state.pg_pool,
+    &workspace_id,
+    payload.into_inner(),
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(AppResponse::Ok().into())
+}
+
+#[instrument(skip(state), err)]
+async fn delete_snapshot_retention_policy_handler(
+  workspace_id: web::Path<Uuid>,
+  payload: Json<DeleteSnapshotRetentionPolicyParams>,
+  state: Data<AppState>,
+) -> Result<JsonAppResponse<()>> {
+  biz::collab::snapshot_retention::remove_snapshot_retention_policy(
+    &state.pg_pool,
+    &workspace_id,
+    payload.collab_type.as_ref(),
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(AppResponse::Ok().into())
+}
+
+/// Start exporting the workspace as an archive. The archive can be downloaded once the returned
+/// job is completed.
+#[instrument(skip(state), err)]
+async fn export_workspace_handler(
+  user_uuid: UserUuid,
+  workspace_id: web::Path<Uuid>,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFWorkspaceExport>>> {
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let export = workspace::export::start_workspace_export(&state, uid, workspace_id.into_inner())
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(Json(AppResponse::Ok().with_data(export)))
+}
+
+#[instrument(skip(state), err)]
+async fn get_workspace_export_handler(
+  user_uuid: UserUuid,
+  path: web::Path<(Uuid, Uuid)>,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFWorkspaceExport>>> {
+  let (workspace_id, job_id) = path.into_inner();
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let export = workspace::export::get_workspace_export(&state, uid, &workspace_id, &job_id)
+    .await
+    .map_err(AppResponseError::from)?;
+  Ok(Json(AppResponse::Ok().with_data(export)))
+}
+
+#[instrument(skip(state), err)]
+async fn get_workspace_export_archive_handler(
+  user_uuid: UserUuid,
+  path: web::Path<(Uuid, Uuid)>,
+  state: Data<AppState>,
+) -> Result<HttpResponse> {
+  let (workspace_id, job_id) = path.into_inner();
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let (file_size, archive) =
+    workspace::export::get_workspace_export_archive(&state, uid, &workspace_id, &job_id)
+      .await
+      .map_err(AppResponseError::from)?;
+  Ok(
+    HttpResponse::Ok()
+      .content_type("application/octet-stream")
+      .insert_header((
+        CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{}.afworkspace\"", workspace_id),
+      ))
+      .no_chunking(file_size)
+      .streaming(archive.map_err(AppResponseError::from)),
+  )
+}
+
+/// Recreate the workspace of the archive, which is the body of the request, as a new workspace
+/// owned by the user.
+#[instrument(skip(payload, state), err)]
+async fn import_workspace_handler(
+  user_uuid: UserUuid,
+  payload: Bytes,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFWorkspaceImport>>> {
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let import =
+    workspace::import::import_workspace_archive(&state, &user_uuid, uid, payload.to_vec())
+      .await
+      .map_err(AppResponseError::from)?;
+  Ok(Json(AppResponse::Ok().with_data(import)))
+}
+
 #[instrument(level = "debug", skip(payload, state), err)]
 async fn batch_get_collab_handler(
   user_uuid: UserUuid,
@@ -568,6 +968,31 @@ async fn batch_get_collab_handler(
   Ok(Json(AppResponse::Ok().with_data(result)))
 }
 
+#[instrument(level = "debug", skip(state), err)]
+async fn search_collab_handler(
+  user_uuid: UserUuid,
+  workspace_id: web::Path<Uuid>,
+  query: web::Query<SearchCollabParams>,
+  state: Data<AppState>,
+) -> Result<Json<AppResponse<AFCollabSearchResults>>> {
+  let uid = state
+    .users
+    .get_user_uid(&user_uuid)
+    .await
+    .map_err(AppResponseError::from)?;
+  let results = biz::collab::search::search_workspace_collab(
+    &state.pg_pool,
+    uid,
+    &workspace_id,
+    query.into_inner(),
+  )
+  .await
+  .map_err(AppResponseError::from)?;
+  Ok(Json(
+    AppResponse::Ok().with_data(AFCollabSearchResults(results)),
+  ))
+}
+
 #[instrument(skip(state, payload), err)]
 async fn update_collab_handler(
   user_uuid: UserUuid,
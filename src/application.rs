@@ -1,7 +1,9 @@
 use crate::api::metrics::{metrics_scope, AppFlowyCloudMetrics};
 use crate::biz::casbin::adapter::PgAdapter;
 use crate::component::auth::HEADER_TOKEN;
-use crate::config::config::{Config, DatabaseSetting, GoTrueSetting, S3Setting};
+use crate::config::config::{
+  BlobStorageBackend, BlobStorageSetting, Config, DatabaseSetting, GoTrueSetting, S3Setting,
+};
 use crate::middleware::request_id::RequestIdMiddleware;
 use crate::self_signed::create_self_signed_certificate;
 use crate::state::{AppState, UserCache};
@@ -23,29 +25,42 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use tokio::sync::RwLock;
-use tracing::info;
+use tracing::{error, info};
 
+use crate::api::admin::admin_scope;
 use crate::api::file_storage::file_storage_scope;
 use crate::api::user::user_scope;
 use crate::api::workspace::{collab_scope, workspace_scope};
-use crate::api::ws::ws_scope;
+use crate::api::ws::{ws_scope, CollabServerImpl};
+use crate::biz::blob_gc::{spawn_blob_gc, BlobGcMetrics};
 use crate::biz::casbin::access_control::{AccessControl, MODEL_CONF};
 use crate::biz::collab::access_control::CollabHttpAccessControl;
+use crate::biz::collab::cluster::RedisCollabCluster;
+use crate::biz::collab::compaction::spawn_collab_compaction;
+use crate::biz::collab::compression::spawn_collab_compression;
+use crate::biz::collab::revoke::spawn_revoke_realtime_access;
+use crate::biz::collab::snapshot_retention::spawn_snapshot_pruner;
 use crate::biz::collab::storage::init_collab_storage;
 use crate::biz::pg_listener::PgListeners;
 use crate::biz::user::RealtimeUserImpl;
 use crate::biz::workspace::access_control::WorkspaceHttpAccessControl;
+use crate::biz::workspace::export::spawn_workspace_export_cleanup;
 use crate::middleware::access_control_mw::WorkspaceAccessControl;
 
 use crate::middleware::metrics_mw::MetricsMiddleware;
 use casbin::CoreApi;
-use database::file::bucket_s3_impl::S3BucketStorage;
+use database::collab::WriteConfig;
+use database::file::bucket_any_impl::{AnyBucketClient, AnyBucketStorage};
+use database::file::bucket_fs_impl::BucketClientFsImpl;
+use database::file::bucket_s3_impl::BucketClientS3Impl;
 use prometheus_client::registry::Registry;
-use realtime::collaborate::{CollabServer, RealtimeMetrics};
+use realtime::collaborate::{CollabCluster, CollabServer, RealtimeMetrics};
+use realtime::entities::Shutdown;
 
 pub struct Application {
   port: u16,
   server: Server,
+  collab_server: CollabServerImpl,
 }
 
 impl Application {
@@ -53,12 +68,29 @@ impl Application {
     let address = format!("{}:{}", config.application.host, config.application.port);
     let listener = TcpListener::bind(&address)?;
     let port = listener.local_addr().unwrap().port();
-    let server = run(listener, state, config).await?;
+    let (server, collab_server) = run(listener, state, config).await?;
 
-    Ok(Self { port, server })
+    Ok(Self {
+      port,
+      server,
+      collab_server,
+    })
   }
 
+  /// Run the server until it receives a stop signal. The realtime server is drained before the
+  /// http server stops, so the clients are asked to reconnect while their websockets are still
+  /// open and the opened collabs are written to the storage.
   pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+    let server_handle = self.server.handle();
+    let collab_server = self.collab_server;
+    actix_rt::spawn(async move {
+      shutdown_signal().await;
+      info!("shutting down, draining the realtime server");
+      if let Err(err) = collab_server.send(Shutdown).await {
+        error!("fail to drain the realtime server: {}", err);
+      }
+      server_handle.stop(true).await;
+    });
     self.server.await
   }
 
@@ -67,11 +99,33 @@ impl Application {
   }
 }
 
+async fn shutdown_signal() {
+  #[cfg(unix)]
+  {
+    use actix_rt::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+      Ok(mut terminate) => {
+        tokio::select! {
+          _ = actix_rt::signal::ctrl_c() => {},
+          _ = terminate.recv() => {},
+        }
+      },
+      Err(err) => {
+        error!("fail to listen to SIGTERM: {}", err);
+        let _ = actix_rt::signal::ctrl_c().await;
+      },
+    }
+  }
+
+  #[cfg(not(unix))]
+  let _ = actix_rt::signal::ctrl_c().await;
+}
+
 pub async fn run(
   listener: TcpListener,
   state: AppState,
   config: Config,
-) -> Result<Server, anyhow::Error> {
+) -> Result<(Server, CollabServerImpl), anyhow::Error> {
   let redis_store = RedisSessionStore::new(config.redis_uri.expose_secret())
     .await
     .map_err(|e| {
@@ -102,19 +156,49 @@ pub async fn run(
   let mut registry = Registry::default();
   let af_cloud_metric = AppFlowyCloudMetrics::register(&mut registry);
   let af_realtime_metric = RealtimeMetrics::register(&mut registry);
+  let blob_gc_metric = Arc::new(BlobGcMetrics::register(&mut registry));
+  spawn_blob_gc(
+    state.pg_pool.clone(),
+    state.bucket_storage.clone(),
+    config.blob_gc.clone(),
+    blob_gc_metric,
+  );
+  spawn_collab_compression(state.pg_pool.clone(), config.collab.clone());
+  spawn_collab_compaction(
+    state.pg_pool.clone(),
+    storage.clone(),
+    config.collab.clone(),
+  );
+  spawn_snapshot_pruner(state.pg_pool.clone(), config.collab.clone());
+  spawn_workspace_export_cleanup(state.clone());
 
   let registry_arc = Arc::new(registry);
   let af_cloud_metric_arc = Arc::new(af_cloud_metric);
   let af_realtime_metric_arc = Arc::new(af_realtime_metric);
 
+  let cluster: Option<Arc<dyn CollabCluster>> = if config.collab.cluster_enabled {
+    let cluster =
+      RedisCollabCluster::new(config.redis_uri.expose_secret(), state.redis_client.clone()).await?;
+    Some(Arc::new(cluster))
+  } else {
+    None
+  };
   let collab_server = CollabServer::<_, Arc<RealtimeUserImpl>, _>::new(
     storage.clone(),
     state.collab_access_control.clone(),
     af_realtime_metric_arc.clone(),
+    cluster,
+    config.websocket.rate_limit.clone(),
   )
   .unwrap()
   .start();
+  spawn_revoke_realtime_access(
+    state.pg_pool.clone(),
+    &state.pg_listeners,
+    collab_server.clone().recipient(),
+  );
 
+  let cloned_collab_server = collab_server.clone();
   let mut server = HttpServer::new(move || {
     App::new()
        // Middleware is registered for each App, scope, or Resource and executed in opposite order as registration
@@ -135,6 +219,7 @@ pub async fn run(
       .service(ws_scope())
       .service(file_storage_scope())
       .service(metrics_scope())
+      .service(admin_scope())
       .app_data(Data::new(af_cloud_metric_arc.clone()))
       .app_data(Data::new(af_realtime_metric_arc.clone()))
       .app_data(Data::new(registry_arc.clone()))
@@ -150,7 +235,9 @@ pub async fn run(
     },
   };
 
-  Ok(server.run())
+  // The stop signals are handled by [Application::run_until_stopped], which drains the realtime
+  // server first.
+  Ok((server.disable_signals().run(), cloned_collab_server))
 }
 
 fn get_certificate_and_server_key(config: &Config) -> Option<(Secret<String>, Secret<String>)> {
@@ -168,9 +255,8 @@ pub async fn init_state(config: &Config) -> Result<AppState, Error> {
   migrate(&pg_pool).await?;
 
   // Bucket storage
-  info!("Setting up S3 bucket...");
-  let s3_bucket = get_aws_s3_bucket(&config.s3).await?;
-  let bucket_storage = Arc::new(S3BucketStorage::from_s3_bucket(s3_bucket, pg_pool.clone()));
+  let bucket_client = get_bucket_client(&config.blob_storage, &config.s3).await?;
+  let bucket_storage = Arc::new(AnyBucketStorage::new(bucket_client, pg_pool.clone()));
 
   // Gotrue
   info!("Connecting to GoTrue...");
@@ -207,6 +293,11 @@ pub async fn init_state(config: &Config) -> Result<AppState, Error> {
       redis_client.clone(),
       collab_access_control.clone(),
       workspace_access_control.clone(),
+      config.collab.compression,
+      WriteConfig {
+        flush_policy: config.collab.flush_policy.clone(),
+        ..Default::default()
+      },
     )
     .await,
   );
@@ -297,6 +388,34 @@ async fn get_redis_client(redis_uri: &str) -> Result<redis::aio::ConnectionManag
   Ok(manager)
 }
 
+async fn get_bucket_client(
+  blob_storage_setting: &BlobStorageSetting,
+  s3_setting: &S3Setting,
+) -> Result<AnyBucketClient, Error> {
+  match blob_storage_setting.backend {
+    BlobStorageBackend::S3 => {
+      info!("Setting up S3 bucket...");
+      let s3_bucket = get_aws_s3_bucket(s3_setting).await?;
+      Ok(BucketClientS3Impl::new(s3_bucket).into())
+    },
+    BlobStorageBackend::FileSystem => {
+      if blob_storage_setting.presigned_url {
+        return Err(anyhow::anyhow!(
+          "presigned url is not supported by the file system blob storage"
+        ));
+      }
+      info!(
+        "Setting up file system blob storage at: {}",
+        blob_storage_setting.fs_root
+      );
+      tokio::fs::create_dir_all(&blob_storage_setting.fs_root)
+        .await
+        .context("failed to create blob storage root directory")?;
+      Ok(BucketClientFsImpl::new(&blob_storage_setting.fs_root).into())
+    },
+  }
+}
+
 async fn get_aws_s3_bucket(s3_setting: &S3Setting) -> Result<s3::Bucket, Error> {
   info!("Connecting to S3 bucket with setting: {:?}", &s3_setting);
   let region = {
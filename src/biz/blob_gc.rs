@@ -0,0 +1,222 @@
+use crate::config::config::{BlobGcAction, BlobGcSetting};
+use database::file::bucket_any_impl::AnyBucketStorage;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Registry;
+use shared_entity::dto::workspace_dto::BlobGcReport;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+use tracing::{error, info, trace};
+use uuid::Uuid;
+
+/// Key of the advisory lock held by the server that is running the periodic blob gc.
+const BLOB_GC_LOCK_KEY: i64 = 0x626c_6f62_5f67_63;
+
+/// Periodically remove the objects in the bucket that are not referenced by any blob or pending
+/// upload, e.g. the objects of deleted workspaces or the objects uploaded by a transaction that
+/// failed to commit. When several servers enable it, the run is skipped by the servers that find
+/// another server already running it.
+pub fn spawn_blob_gc(
+  pg_pool: PgPool,
+  storage: Arc<AnyBucketStorage>,
+  setting: BlobGcSetting,
+  metrics: Arc<BlobGcMetrics>,
+) {
+  if !setting.enabled {
+    info!("blob gc is disabled");
+    return;
+  }
+
+  tokio::spawn(async move {
+    let period = Duration::from_secs(setting.interval_secs);
+    let mut interval = interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+      interval.tick().await;
+      match run_blob_gc_exclusively(&pg_pool, &storage, &setting).await {
+        Ok(Some(report)) => {
+          info!("blob gc finished: {:?}", report);
+          metrics.record(&report);
+        },
+        Ok(None) => info!("blob gc is skipped, another server is running it"),
+        Err(err) => error!("[blob gc] failed to acquire the blob gc lock: {}", err),
+      }
+    }
+  });
+}
+
+/// Run the blob gc while holding a session advisory lock, or return None if another server holds
+/// it. The lock is released when the connection is closed, so a crashed server never keeps it.
+async fn run_blob_gc_exclusively(
+  pg_pool: &PgPool,
+  storage: &AnyBucketStorage,
+  setting: &BlobGcSetting,
+) -> Result<Option<BlobGcReport>, sqlx::Error> {
+  let mut conn = pg_pool.acquire().await?;
+  let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+    .bind(BLOB_GC_LOCK_KEY)
+    .fetch_one(&mut *conn)
+    .await?;
+  if !locked {
+    return Ok(None);
+  }
+
+  let report = run_blob_gc(storage, None, setting.action, setting.min_age_secs).await;
+  let unlocked = sqlx::query("SELECT pg_advisory_unlock($1)")
+    .bind(BLOB_GC_LOCK_KEY)
+    .execute(&mut *conn)
+    .await;
+  if let Err(err) = unlocked {
+    // Closing the connection releases the lock, instead of returning it to the pool with the lock
+    error!("[blob gc] failed to release the blob gc lock: {}", err);
+    drop(conn.detach());
+  }
+  Ok(Some(report))
+}
+
+/// Collect the orphan objects of the given workspace, or of all the workspaces in the bucket if
+/// `workspace_id` is `None`. Errors are logged and counted in the report instead of aborting the
+/// run, so one broken workspace doesn't block the others.
+pub async fn run_blob_gc(
+  storage: &AnyBucketStorage,
+  workspace_id: Option<Uuid>,
+  action: BlobGcAction,
+  min_age_secs: u64,
+) -> BlobGcReport {
+  let mut report = BlobGcReport::default();
+  let workspace_ids = match workspace_id {
+    Some(workspace_id) => vec![workspace_id],
+    None => match storage.list_bucket_workspaces().await {
+      Ok(workspace_ids) => workspace_ids,
+      Err(err) => {
+        error!(
+          "[blob gc] failed to list the workspaces of the bucket: {}",
+          err
+        );
+        report.failures += 1;
+        return report;
+      },
+    },
+  };
+
+  let modified_before = chrono::Utc::now() - chrono::Duration::seconds(min_age_secs as i64);
+  for workspace_id in workspace_ids {
+    let scan = match storage
+      .find_orphan_objects(&workspace_id, modified_before)
+      .await
+    {
+      Ok(scan) => scan,
+      Err(err) => {
+        error!(
+          "[blob gc] failed to scan the objects of workspace:{}: {}",
+          workspace_id, err
+        );
+        report.failures += 1;
+        continue;
+      },
+    };
+
+    report.scanned_objects += scan.scanned as u64;
+    for object in scan.orphans {
+      report.orphan_objects += 1;
+      report.orphan_bytes += object.size;
+      if action == BlobGcAction::DryRun {
+        info!(
+          "[blob gc] found orphan object: {}, size: {}",
+          object.key, object.size
+        );
+        continue;
+      }
+
+      let quarantine = action == BlobGcAction::Quarantine;
+      match storage
+        .remove_orphan_object(&workspace_id, &object, quarantine)
+        .await
+      {
+        Ok(true) => {
+          trace!("[blob gc] removed orphan object: {}", object.key);
+          report.removed_objects += 1;
+        },
+        Ok(false) => trace!("[blob gc] object: {} is referenced again", object.key),
+        Err(err) => {
+          error!(
+            "[blob gc] failed to remove orphan object: {}: {}",
+            object.key, err
+          );
+          report.failures += 1;
+        },
+      }
+    }
+  }
+  report
+}
+
+#[derive(Clone)]
+pub struct BlobGcMetrics {
+  scanned_objects: Counter,
+  orphan_objects: Counter,
+  orphan_bytes: Counter,
+  removed_objects: Counter,
+  failures: Counter,
+  last_run_timestamp: Gauge,
+}
+
+impl BlobGcMetrics {
+  fn init() -> Self {
+    Self {
+      scanned_objects: Counter::default(),
+      orphan_objects: Counter::default(),
+      orphan_bytes: Counter::default(),
+      removed_objects: Counter::default(),
+      failures: Counter::default(),
+      last_run_timestamp: Gauge::default(),
+    }
+  }
+
+  pub fn register(registry: &mut Registry) -> Self {
+    let metrics = Self::init();
+    let gc_registry = registry.sub_registry_with_prefix("blob_gc");
+    gc_registry.register(
+      "scanned_objects",
+      "number of objects scanned by the blob gc",
+      metrics.scanned_objects.clone(),
+    );
+    gc_registry.register(
+      "orphan_objects",
+      "number of orphan objects found by the blob gc",
+      metrics.orphan_objects.clone(),
+    );
+    gc_registry.register(
+      "orphan_bytes",
+      "size of the orphan objects found by the blob gc in bytes",
+      metrics.orphan_bytes.clone(),
+    );
+    gc_registry.register(
+      "removed_objects",
+      "number of orphan objects deleted or quarantined by the blob gc",
+      metrics.removed_objects.clone(),
+    );
+    gc_registry.register(
+      "failures",
+      "number of errors that occurred during the blob gc",
+      metrics.failures.clone(),
+    );
+    gc_registry.register(
+      "last_run_timestamp",
+      "unix timestamp of the last blob gc run",
+      metrics.last_run_timestamp.clone(),
+    );
+    metrics
+  }
+
+  pub fn record(&self, report: &BlobGcReport) {
+    self.scanned_objects.inc_by(report.scanned_objects);
+    self.orphan_objects.inc_by(report.orphan_objects);
+    self.orphan_bytes.inc_by(report.orphan_bytes);
+    self.removed_objects.inc_by(report.removed_objects);
+    self.failures.inc_by(report.failures);
+    self.last_run_timestamp.set(chrono::Utc::now().timestamp());
+  }
+}
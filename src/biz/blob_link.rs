@@ -0,0 +1,128 @@
+use app_error::AppError;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Return the path of a public link to the blob. The link is signed with the link key, so it
+/// can't be forged or altered, and it stays valid until `expires_at` or forever if it's None.
+pub fn blob_public_link_path(
+  link_key: &Secret<String>,
+  workspace_id: &Uuid,
+  file_id: &str,
+  expires_at: Option<DateTime<Utc>>,
+) -> String {
+  let expires = expires_at.map(|time| time.timestamp());
+  let signature = URL_SAFE_NO_PAD.encode(
+    blob_link_mac(link_key, workspace_id, file_id, expires)
+      .finalize()
+      .into_bytes(),
+  );
+  match expires {
+    None => format!(
+      "/api/file_storage/public/{}/{}?signature={}",
+      workspace_id, file_id, signature
+    ),
+    Some(expires) => format!(
+      "/api/file_storage/public/{}/{}?expires={}&signature={}",
+      workspace_id, file_id, expires, signature
+    ),
+  }
+}
+
+/// Check that the public link was signed by this server and hasn't expired.
+pub fn verify_blob_public_link(
+  link_key: &Secret<String>,
+  workspace_id: &Uuid,
+  file_id: &str,
+  expires: Option<i64>,
+  signature: &str,
+) -> Result<(), AppError> {
+  let signature = URL_SAFE_NO_PAD
+    .decode(signature)
+    .map_err(|_| AppError::NotEnoughPermissions("invalid blob link signature".to_string()))?;
+  blob_link_mac(link_key, workspace_id, file_id, expires)
+    .verify_slice(&signature)
+    .map_err(|_| AppError::NotEnoughPermissions("invalid blob link signature".to_string()))?;
+
+  if let Some(expires) = expires {
+    if Utc::now().timestamp() > expires {
+      return Err(AppError::NotEnoughPermissions(
+        "the blob link has expired".to_string(),
+      ));
+    }
+  }
+  Ok(())
+}
+
+fn blob_link_mac(
+  link_key: &Secret<String>,
+  workspace_id: &Uuid,
+  file_id: &str,
+  expires: Option<i64>,
+) -> HmacSha256 {
+  // HMAC accepts keys of any length, so creating it never fails
+  let mut mac = HmacSha256::new_from_slice(link_key.expose_secret().as_bytes())
+    .expect("HMAC can take key of any size");
+  // The prefix prevents the signature from being valid for anything else signed with the key
+  let expires = expires
+    .map(|expires| expires.to_string())
+    .unwrap_or_default();
+  mac.update(format!("blob_link:{}/{}/{}", workspace_id, file_id, expires).as_bytes());
+  mac
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::Duration;
+
+  fn parse_link(path: &str) -> (Option<i64>, String) {
+    let query = path.split_once('?').unwrap().1;
+    let mut expires = None;
+    let mut signature = String::new();
+    for pair in query.split('&') {
+      match pair.split_once('=').unwrap() {
+        ("expires", value) => expires = Some(value.parse().unwrap()),
+        ("signature", value) => signature = value.to_string(),
+        _ => {},
+      }
+    }
+    (expires, signature)
+  }
+
+  #[test]
+  fn verify_signed_link() {
+    let key = Secret::new("link_key".to_string());
+    let workspace_id = Uuid::new_v4();
+    let path = blob_public_link_path(&key, &workspace_id, "file", None);
+    let (expires, signature) = parse_link(&path);
+    assert!(verify_blob_public_link(&key, &workspace_id, "file", expires, &signature).is_ok());
+
+    // The signature is bound to the file and the key
+    assert!(verify_blob_public_link(&key, &workspace_id, "other", expires, &signature).is_err());
+    let other_key = Secret::new("other_key".to_string());
+    assert!(
+      verify_blob_public_link(&other_key, &workspace_id, "file", expires, &signature).is_err()
+    );
+  }
+
+  #[test]
+  fn reject_expired_or_extended_link() {
+    let key = Secret::new("link_key".to_string());
+    let workspace_id = Uuid::new_v4();
+    let expires_at = Utc::now() - Duration::seconds(10);
+    let path = blob_public_link_path(&key, &workspace_id, "file", Some(expires_at));
+    let (expires, signature) = parse_link(&path);
+    assert!(verify_blob_public_link(&key, &workspace_id, "file", expires, &signature).is_err());
+
+    let extended = expires.map(|expires| expires + 3600);
+    assert!(verify_blob_public_link(&key, &workspace_id, "file", extended, &signature).is_err());
+    assert!(verify_blob_public_link(&key, &workspace_id, "file", None, &signature).is_err());
+  }
+}
@@ -0,0 +1,334 @@
+use crate::state::RedisClient;
+use anyhow::{anyhow, Context};
+use app_error::AppError;
+use async_trait::async_trait;
+use chrono::Utc;
+use dashmap::DashMap;
+use futures_util::StreamExt;
+use realtime::collaborate::{ClusterMessage, ClusterSubscription, CollabCluster};
+use redis::{AsyncCommands, Script};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Notify};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// The key that holds the id of the node that owns the collab.
+const OWNER_KEY_PREFIX: &str = "af_collab_owner:";
+/// The sorted set of the nodes that subscribed to the collab, scored by the expiration of their
+/// subscription.
+const SUBSCRIBER_KEY_PREFIX: &str = "af_collab_subscribers:";
+/// Each node receives the messages of the collabs it subscribed to on the channel
+/// `{CLUSTER_CHANNEL_PREFIX}{node_id}`.
+const CLUSTER_CHANNEL_PREFIX: &str = "af_collab_cluster:";
+/// The ownership of a collab expires if its owner doesn't renew it within this time.
+const OWNERSHIP_TTL: Duration = Duration::from_secs(30);
+/// The subscription of a node expires if the node doesn't renew it within this time, so the
+/// messages are no longer published to a node that stopped.
+const SUBSCRIPTION_TTL: Duration = Duration::from_secs(30);
+const SUBSCRIPTION_RENEW_INTERVAL: Duration = Duration::from_secs(10);
+/// The messages of a collab that are not applied yet. Once full, the next messages are dropped
+/// and the collab is synced again.
+const SUBSCRIPTION_BUFFER_SIZE: usize = 100;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Sets the owner of the collab if it has none, or renews the ownership if the node owns it.
+const CLAIM_OWNERSHIP_SCRIPT: &str = r#"
+local owner = redis.call('GET', KEYS[1])
+if not owner then
+  redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+  return 1
+end
+if owner == ARGV[1] then
+  redis.call('PEXPIRE', KEYS[1], ARGV[2])
+  return 1
+end
+return 0
+"#;
+
+const RELEASE_OWNERSHIP_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+  return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// A [CollabCluster] whose nodes share the same Redis. The ownership of a collab is a key that
+/// expires.
+///
+/// The nodes that hold a group of a collab are listed in the subscribers of the collab, and the
+/// changes of the collab are only published to them, on the pub/sub channel of each node. The
+/// messages received by a node are then dispatched to the subscription of their collab. The
+/// channels are published to by the client rather than by a script, so it also works on a Redis
+/// Cluster.
+pub struct RedisCollabCluster {
+  node_id: String,
+  redis_client: RedisClient,
+  subscriptions: Arc<Subscriptions>,
+}
+
+type Subscriptions = DashMap<String, SubscriptionSender>;
+
+struct SubscriptionSender {
+  sender: mpsc::Sender<ClusterMessage>,
+  missed: Arc<Notify>,
+}
+
+impl SubscriptionSender {
+  fn send(&self, message: ClusterMessage) {
+    match self.sender.try_send(message) {
+      Ok(_) => {},
+      Err(TrySendError::Full(_)) => self.missed.notify_one(),
+      // The group was closed
+      Err(TrySendError::Closed(_)) => {},
+    }
+  }
+}
+
+impl RedisCollabCluster {
+  pub async fn new(redis_uri: &str, redis_client: RedisClient) -> Result<Self, anyhow::Error> {
+    let node_id = Uuid::new_v4().to_string();
+    let client = redis::Client::open(redis_uri).context("failed to connect to redis")?;
+    let subscriptions = Arc::new(Subscriptions::new());
+
+    // Fail early if the pub/sub connection can't be opened
+    let pubsub = subscribe_node_channel(&client, &node_id).await?;
+    tokio::spawn(receive_messages(
+      client,
+      pubsub,
+      node_id.clone(),
+      Arc::downgrade(&subscriptions),
+    ));
+    tokio::spawn(renew_subscriptions(
+      redis_client.clone(),
+      node_id.clone(),
+      Arc::downgrade(&subscriptions),
+    ));
+    info!("join the collab cluster as node:{}", node_id);
+    Ok(Self {
+      node_id,
+      redis_client,
+      subscriptions,
+    })
+  }
+}
+
+async fn subscribe_node_channel(
+  client: &redis::Client,
+  node_id: &str,
+) -> Result<redis::aio::PubSub, anyhow::Error> {
+  let mut pubsub = client
+    .get_async_connection()
+    .await
+    .context("failed to open the pub/sub connection")?
+    .into_pubsub();
+  pubsub
+    .subscribe(node_channel(node_id))
+    .await
+    .context("failed to subscribe the collab cluster channel")?;
+  Ok(pubsub)
+}
+
+/// Dispatch the messages published to this node to the subscriptions of their collabs. The
+/// pub/sub connection is opened again if it's closed, and all the collabs are synced again as
+/// they may have missed some messages meanwhile.
+async fn receive_messages(
+  client: redis::Client,
+  mut pubsub: redis::aio::PubSub,
+  node_id: String,
+  subscriptions: Weak<Subscriptions>,
+) {
+  loop {
+    let mut messages = Box::pin(pubsub.into_on_message());
+    while let Some(msg) = messages.next().await {
+      let subscriptions = match subscriptions.upgrade() {
+        Some(subscriptions) => subscriptions,
+        None => return,
+      };
+      match ClusterMessage::decode(msg.get_payload_bytes()) {
+        Ok(message) => {
+          if let Some(subscription) = subscriptions.get(&message.object_id) {
+            subscription.send(message);
+          }
+        },
+        Err(err) => warn!("invalid message of the collab cluster: {}", err),
+      }
+    }
+    drop(messages);
+
+    error!("the pub/sub connection of the collab cluster is closed, reconnecting");
+    pubsub = loop {
+      tokio::time::sleep(RECONNECT_INTERVAL).await;
+      match subscribe_node_channel(&client, &node_id).await {
+        Ok(pubsub) => break pubsub,
+        Err(err) => error!("fail to reconnect to the collab cluster: {:?}", err),
+      }
+    };
+    match subscriptions.upgrade() {
+      Some(subscriptions) => subscriptions
+        .iter()
+        .for_each(|subscription| subscription.missed.notify_one()),
+      None => return,
+    }
+  }
+}
+
+/// Renew the subscriptions of this node before they expire. The expired subscriptions of the other
+/// nodes are removed at the same time.
+async fn renew_subscriptions(
+  mut redis_client: RedisClient,
+  node_id: String,
+  subscriptions: Weak<Subscriptions>,
+) {
+  let mut interval = tokio::time::interval(SUBSCRIPTION_RENEW_INTERVAL);
+  loop {
+    interval.tick().await;
+    let object_ids = match subscriptions.upgrade() {
+      Some(subscriptions) => subscriptions
+        .iter()
+        .map(|subscription| subscription.key().clone())
+        .collect::<Vec<_>>(),
+      None => break,
+    };
+    if object_ids.is_empty() {
+      continue;
+    }
+    let mut pipe = redis::pipe();
+    for object_id in &object_ids {
+      add_subscriber(&mut pipe, object_id, &node_id);
+    }
+    if let Err(err) = pipe.query_async::<_, ()>(&mut redis_client).await {
+      error!(
+        "fail to renew the subscriptions of the collab cluster: {}",
+        err
+      );
+    }
+  }
+}
+
+fn owner_key(object_id: &str) -> String {
+  format!("{}{}", OWNER_KEY_PREFIX, object_id)
+}
+
+fn subscriber_key(object_id: &str) -> String {
+  format!("{}{}", SUBSCRIBER_KEY_PREFIX, object_id)
+}
+
+fn node_channel(node_id: &str) -> String {
+  format!("{}{}", CLUSTER_CHANNEL_PREFIX, node_id)
+}
+
+fn add_subscriber(pipe: &mut redis::Pipeline, object_id: &str, node_id: &str) {
+  let key = subscriber_key(object_id);
+  let now = Utc::now().timestamp_millis();
+  let expires_at = now + SUBSCRIPTION_TTL.as_millis() as i64;
+  pipe
+    .zrembyscore(&key, "-inf", now)
+    .ignore()
+    .zadd(&key, node_id, expires_at)
+    .ignore()
+    .pexpire(&key, SUBSCRIPTION_TTL.as_millis() as usize)
+    .ignore();
+}
+
+#[async_trait]
+impl CollabCluster for RedisCollabCluster {
+  fn node_id(&self) -> &str {
+    &self.node_id
+  }
+
+  async fn claim_ownership(&self, object_id: &str) -> Result<bool, AppError> {
+    let mut redis_client = self.redis_client.clone();
+    let owned: i32 = Script::new(CLAIM_OWNERSHIP_SCRIPT)
+      .key(owner_key(object_id))
+      .arg(&self.node_id)
+      .arg(OWNERSHIP_TTL.as_millis() as u64)
+      .invoke_async(&mut redis_client)
+      .await
+      .map_err(|err| AppError::Internal(anyhow!("fail to claim the ownership: {}", err)))?;
+    Ok(owned == 1)
+  }
+
+  async fn release_ownership(&self, object_id: &str) -> Result<(), AppError> {
+    let mut redis_client = self.redis_client.clone();
+    let _: i32 = Script::new(RELEASE_OWNERSHIP_SCRIPT)
+      .key(owner_key(object_id))
+      .arg(&self.node_id)
+      .invoke_async(&mut redis_client)
+      .await
+      .map_err(|err| AppError::Internal(anyhow!("fail to release the ownership: {}", err)))?;
+    Ok(())
+  }
+
+  async fn publish(&self, message: ClusterMessage) -> Result<(), AppError> {
+    let payload = message
+      .encode()
+      .map_err(|err| AppError::Internal(anyhow!("fail to encode the message: {}", err)))?;
+    let mut redis_client = self.redis_client.clone();
+    // The subscriptions that expired are ignored, and removed when the subscriptions are renewed
+    let nodes: Vec<String> = redis_client
+      .zrangebyscore(
+        subscriber_key(&message.object_id),
+        format!("({}", Utc::now().timestamp_millis()),
+        "+inf",
+      )
+      .await
+      .map_err(|err| AppError::Internal(anyhow!("fail to get the subscribers: {}", err)))?;
+
+    let nodes = nodes
+      .into_iter()
+      .filter(|node| *node != self.node_id)
+      .collect::<Vec<_>>();
+    if nodes.is_empty() {
+      return Ok(());
+    }
+    let mut pipe = redis::pipe();
+    for node in &nodes {
+      pipe.publish(node_channel(node), &payload).ignore();
+    }
+    pipe
+      .query_async::<_, ()>(&mut redis_client)
+      .await
+      .map_err(|err| AppError::Internal(anyhow!("fail to publish the message: {}", err)))?;
+    Ok(())
+  }
+
+  async fn subscribe(&self, object_id: &str) -> Result<ClusterSubscription, AppError> {
+    // The subscription is registered before the other nodes publish to this node, so the first
+    // messages are not lost
+    let (sender, messages) = mpsc::channel(SUBSCRIPTION_BUFFER_SIZE);
+    let missed = Arc::new(Notify::new());
+    self.subscriptions.insert(
+      object_id.to_string(),
+      SubscriptionSender {
+        sender,
+        missed: missed.clone(),
+      },
+    );
+
+    let mut pipe = redis::pipe();
+    add_subscriber(&mut pipe, object_id, &self.node_id);
+    let mut redis_client = self.redis_client.clone();
+    if let Err(err) = pipe.query_async::<_, ()>(&mut redis_client).await {
+      self.subscriptions.remove(object_id);
+      return Err(AppError::Internal(anyhow!(
+        "fail to subscribe the collab: {}",
+        err
+      )));
+    }
+    Ok(ClusterSubscription { messages, missed })
+  }
+
+  async fn unsubscribe(&self, object_id: &str) -> Result<(), AppError> {
+    self.subscriptions.remove(object_id);
+    let mut redis_client = self.redis_client.clone();
+    redis::cmd("ZREM")
+      .arg(subscriber_key(object_id))
+      .arg(&self.node_id)
+      .query_async::<_, ()>(&mut redis_client)
+      .await
+      .map_err(|err| AppError::Internal(anyhow!("fail to unsubscribe the collab: {}", err)))?;
+    Ok(())
+  }
+}
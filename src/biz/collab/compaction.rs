@@ -0,0 +1,85 @@
+use crate::biz::collab::storage::CollabPostgresDBStorage;
+use crate::config::config::CollabSetting;
+use database::collab::{select_collabs_to_compact, CollabStorage};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+use tracing::{error, info};
+
+/// Number of collabs selected at once.
+const COMPACTION_BATCH_SIZE: i64 = 500;
+
+/// Periodically merge the update logs of the collabs into the collabs. A collab is compacted once
+/// its log holds [CollabSetting::compaction_min_updates] updates, or once its oldest update is older
+/// than [CollabSetting::compaction_interval_secs]. The collabs that are being edited are also
+/// compacted by the realtime server, so this mostly catches the collabs that are not opened anymore.
+pub fn spawn_collab_compaction(
+  pg_pool: PgPool,
+  storage: Arc<CollabPostgresDBStorage>,
+  setting: CollabSetting,
+) {
+  if setting.compaction_interval_secs == 0 {
+    info!("collab compaction is disabled");
+    return;
+  }
+
+  tokio::spawn(async move {
+    let period = Duration::from_secs(setting.compaction_interval_secs);
+    let mut interval = interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+      interval.tick().await;
+      let written_before =
+        chrono::Utc::now() - chrono::Duration::seconds(setting.compaction_interval_secs as i64);
+
+      // All the collabs to compact are visited, in batches, so the collabs that fail to be
+      // compacted don't keep the others from being compacted.
+      let mut total = 0;
+      let mut failed = 0;
+      let mut cursor = None;
+      loop {
+        let (collabs, next_cursor) = match select_collabs_to_compact(
+          &pg_pool,
+          setting.compaction_min_updates,
+          written_before,
+          cursor.as_ref(),
+          COMPACTION_BATCH_SIZE,
+        )
+        .await
+        {
+          Ok(result) => result,
+          Err(err) => {
+            error!("[collab compaction] failed to select the collabs: {}", err);
+            break;
+          },
+        };
+
+        total += collabs.len();
+        for (object_id, collab_type) in collabs {
+          if let Err(err) = storage
+            .compact_collab_updates(&object_id, &collab_type)
+            .await
+          {
+            failed += 1;
+            error!(
+              "[collab compaction] failed to compact collab:{}: {}",
+              object_id, err
+            );
+          }
+        }
+        match next_cursor {
+          None => break,
+          Some(next_cursor) => cursor = Some(next_cursor),
+        }
+      }
+      if total > 0 {
+        info!(
+          "[collab compaction] compacted {} collabs, {} failed",
+          total - failed,
+          failed
+        );
+      }
+    }
+  });
+}
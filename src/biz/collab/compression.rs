@@ -0,0 +1,69 @@
+use crate::config::config::CollabSetting;
+use database::collab::{recompress_collab_batch, recompress_snapshot_batch};
+use sqlx::PgPool;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Pause between two batches, so the background compression doesn't starve the other queries.
+const BATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Compress the collabs and snapshots that were written before the compression was enabled, or
+/// with another codec, using the configured codec. It runs once, in the background, when the
+/// server starts. Rows that are written in the meantime already use the configured codec.
+pub fn spawn_collab_compression(pg_pool: PgPool, setting: CollabSetting) {
+  if setting.compression_migration_batch_size <= 0 {
+    info!("collab compression migration is disabled");
+    return;
+  }
+
+  tokio::spawn(async move {
+    let codec = setting.compression;
+    let batch_size = setting.compression_migration_batch_size;
+
+    let mut total = 0;
+    let mut after_oid = String::new();
+    loop {
+      match recompress_collab_batch(&pg_pool, codec, &after_oid, batch_size).await {
+        Ok((count, next_oid)) => {
+          total += count;
+          match next_oid {
+            None => break,
+            Some(next_oid) => after_oid = next_oid,
+          }
+        },
+        Err(err) => {
+          error!("[collab compression] failed to compress collabs: {}", err);
+          return;
+        },
+      }
+      tokio::time::sleep(BATCH_INTERVAL).await;
+    }
+    info!(
+      "[collab compression] compressed {} collabs with {:?}",
+      total, codec
+    );
+
+    let mut total = 0;
+    let mut after_sid = 0;
+    loop {
+      match recompress_snapshot_batch(&pg_pool, codec, after_sid, batch_size).await {
+        Ok((count, next_sid)) => {
+          total += count;
+          match next_sid {
+            None => break,
+            Some(next_sid) => after_sid = next_sid,
+          }
+        },
+        Err(err) => {
+          error!("[collab compression] failed to compress snapshots: {}", err);
+          return;
+        },
+      }
+      tokio::time::sleep(BATCH_INTERVAL).await;
+    }
+    info!(
+      "[collab compression] compressed {} snapshots with {:?}",
+      total, codec
+    );
+  });
+}
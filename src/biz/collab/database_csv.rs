@@ -0,0 +1,729 @@
+use crate::biz::collab::ops::batch_upsert_collabs;
+use crate::biz::collab::snapshot_diff::collab_json;
+use crate::biz::collab::storage::CollabPostgresDBStorage;
+use anyhow::anyhow;
+use app_error::AppError;
+use chrono::{TimeZone, Utc};
+use collab::core::collab_plugin::EncodedCollab;
+use collab_entity::CollabType;
+use database::collab::CollabStorage;
+use database_entity::dto::{
+  AFDatabaseImport, CollabParams, ImportCsvParams, QueryCollab, QueryCollabParams,
+  QueryCollabResult,
+};
+use serde_json::{json, Map, Value};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{instrument, warn};
+use uuid::Uuid;
+use yrs::updates::encoder::Encode;
+use yrs::{Any, ArrayPrelim, Doc, Map as _, MapPrelim, MapRef, ReadTxn, StateVector, Transact};
+
+/// Name of the root map of a database collab.
+const DATABASE: &str = "database";
+/// Name of the root map of a database row collab.
+const ROW_DATA: &str = "data";
+const RICH_TEXT_FIELD_TYPE: i64 = 0;
+const SINGLE_SELECT_FIELD_TYPE: i64 = 3;
+const MULTI_SELECT_FIELD_TYPE: i64 = 4;
+const DATE_TIME_FIELD_TYPE: i64 = 2;
+const CHECKLIST_FIELD_TYPE: i64 = 7;
+const LAST_EDITED_TIME_FIELD_TYPE: i64 = 8;
+const CREATED_TIME_FIELD_TYPE: i64 = 9;
+const GRID_LAYOUT: i64 = 0;
+const FIELD_ALWAYS_HIDDEN: i64 = 2;
+const DEFAULT_ROW_HEIGHT: i64 = 60;
+const DEFAULT_FIELD_WIDTH: i64 = 150;
+
+/// Export a view of the database as csv. The first line holds the names of the visible fields of
+/// the view, followed by one line per row of the view.
+#[instrument(level = "debug", skip(storage), err)]
+pub async fn export_database_csv(
+  storage: &CollabPostgresDBStorage,
+  uid: i64,
+  workspace_id: &str,
+  object_id: &str,
+  view_id: Option<String>,
+) -> Result<String, AppError> {
+  let query = QueryCollabParams::new(object_id, CollabType::Database, workspace_id);
+  let encoded_collab = storage
+    .get_collab_encoded(&uid, query)
+    .await?
+    .encode_to_bytes()
+    .map_err(|err| AppError::Internal(anyhow!("fail to encode collab: {:?}", err)))?;
+  let view = read_database_view(storage, uid, object_id, encoded_collab, view_id).await?;
+  tokio::task::spawn_blocking(move || {
+    let mut csv = String::new();
+    write_csv_record(&mut csv, view.fields.iter().map(|field| field.name.clone()));
+    for row in &view.rows {
+      write_csv_record(
+        &mut csv,
+        view.fields.iter().map(|field| cell_text(field, row)),
+      );
+    }
+    csv
+  })
+  .await
+  .map_err(AppError::from)
+}
+
+/// Read the visible fields and the rows of a view of the encoded database. The rows are always
+/// read from their current state, even if the database is a snapshot.
+pub(crate) async fn read_database_view(
+  storage: &CollabPostgresDBStorage,
+  uid: i64,
+  database_id: &str,
+  encoded_collab_v1: Vec<u8>,
+  view_id: Option<String>,
+) -> Result<DatabaseView, AppError> {
+  let cloned_database_id = database_id.to_string();
+  let table = tokio::task::spawn_blocking(move || {
+    database_view_table(&cloned_database_id, &encoded_collab_v1, view_id.as_deref())
+  })
+  .await??;
+
+  let queries = table
+    .row_ids
+    .iter()
+    .map(|row_id| QueryCollab {
+      object_id: row_id.clone(),
+      collab_type: CollabType::DatabaseRow,
+    })
+    .collect();
+  let mut rows = storage.batch_get_collab(&uid, queries).await;
+  let database_id = database_id.to_string();
+  tokio::task::spawn_blocking(move || {
+    let mut database_rows = Vec::with_capacity(table.row_ids.len());
+    for row_id in table.row_ids {
+      match rows.remove(&row_id) {
+        Some(QueryCollabResult::Success { encode_collab_v1 }) => {
+          database_rows.push(database_row(&row_id, &encode_collab_v1)?);
+        },
+        Some(QueryCollabResult::Failed { error }) => {
+          warn!("skip reading the database row:{}: {}", row_id, error);
+        },
+        None => {},
+      }
+    }
+    Ok::<_, AppError>(DatabaseView {
+      database_id,
+      view_id: table.view_id,
+      fields: table.fields,
+      rows: database_rows,
+    })
+  })
+  .await?
+}
+
+/// Return the json of the view, with the cells of the rows as displayed texts keyed by field id.
+pub(crate) fn database_view_json(view: &DatabaseView) -> Value {
+  let fields = view
+    .fields
+    .iter()
+    .map(|field| {
+      json!({
+        "id": field.id,
+        "name": field.name,
+        "field_type": field.field_type,
+      })
+    })
+    .collect::<Vec<_>>();
+  let rows = view
+    .rows
+    .iter()
+    .map(|row| {
+      let cells = view
+        .fields
+        .iter()
+        .map(|field| (field.id.clone(), Value::String(cell_text(field, row))))
+        .collect::<Map<_, _>>();
+      json!({
+        "id": row.id,
+        "created_at": row.created_at,
+        "last_modified": row.last_modified,
+        "cells": cells,
+      })
+    })
+    .collect::<Vec<_>>();
+  json!({
+    "database_id": view.database_id,
+    "view_id": view.view_id,
+    "fields": fields,
+    "rows": rows,
+  })
+}
+
+/// Create a database from the csv, with a grid view that shows a text field per column and a row
+/// per line after the header. The database and its rows are created in one transaction.
+#[instrument(level = "debug", skip(pg_pool, storage, params), err)]
+pub async fn import_database_csv(
+  pg_pool: &PgPool,
+  storage: &CollabPostgresDBStorage,
+  uid: i64,
+  workspace_id: &str,
+  params: ImportCsvParams,
+) -> Result<AFDatabaseImport, AppError> {
+  let database_id = Uuid::new_v4().to_string();
+  let view_id = Uuid::new_v4().to_string();
+  let (database_id, view_id, field_count, params_list) = tokio::task::spawn_blocking(move || {
+    let mut records = parse_csv(&params.csv)?.into_iter();
+    let header = records.next().ok_or_else(|| {
+      AppError::InvalidRequest("the csv must start with the names of the fields".to_string())
+    })?;
+    let fields = header
+      .into_iter()
+      .enumerate()
+      .map(|(index, name)| {
+        let name = if name.trim().is_empty() {
+          format!("Field {}", index + 1)
+        } else {
+          name
+        };
+        (Uuid::new_v4().to_string(), name)
+      })
+      .collect::<Vec<_>>();
+
+    let now = Utc::now().timestamp();
+    let mut row_ids = vec![];
+    let mut params_list = vec![];
+    for record in records {
+      let row_id = Uuid::new_v4().to_string();
+      let cells = fields
+        .iter()
+        .map(|(field_id, _)| field_id.as_str())
+        .zip(record)
+        .collect::<Vec<_>>();
+      let row = database_row_collab(&row_id, &database_id, &cells, now)?;
+      params_list.push(CollabParams::new(&row_id, CollabType::DatabaseRow, row));
+      row_ids.push(row_id);
+    }
+    let database = database_collab(&database_id, &view_id, &params.name, &fields, &row_ids, now)?;
+    params_list.insert(
+      0,
+      CollabParams::new(&database_id, CollabType::Database, database),
+    );
+    Ok::<_, AppError>((database_id, view_id, fields.len(), params_list))
+  })
+  .await??;
+
+  let row_count = params_list.len() - 1;
+  batch_upsert_collabs(pg_pool, storage, workspace_id, &uid, params_list).await?;
+  Ok(AFDatabaseImport {
+    database_id,
+    view_id,
+    field_count,
+    row_count,
+  })
+}
+
+struct DatabaseField {
+  id: String,
+  name: String,
+  field_type: i64,
+  type_options: Value,
+}
+
+/// The fields and the row ids of a database view.
+struct DatabaseTable {
+  view_id: String,
+  fields: Vec<DatabaseField>,
+  row_ids: Vec<String>,
+}
+
+/// The visible fields and the rows of a database view.
+pub(crate) struct DatabaseView {
+  database_id: String,
+  view_id: String,
+  fields: Vec<DatabaseField>,
+  rows: Vec<DatabaseRow>,
+}
+
+struct DatabaseRow {
+  id: String,
+  cells: Map<String, Value>,
+  created_at: Option<i64>,
+  last_modified: Option<i64>,
+}
+
+fn database_view_table(
+  database_id: &str,
+  encoded_collab_v1: &[u8],
+  view_id: Option<&str>,
+) -> Result<DatabaseTable, AppError> {
+  let json = collab_json(database_id, encoded_collab_v1)?;
+  let database = json
+    .get(DATABASE)
+    .filter(|database| database.is_object())
+    .ok_or_else(|| AppError::InvalidRequest(format!("collab:{} is not a database", database_id)))?;
+  let views = database
+    .get("views")
+    .and_then(Value::as_object)
+    .cloned()
+    .unwrap_or_default();
+  let view_id = view_id
+    .or_else(|| database.pointer("/metas/iid").and_then(Value::as_str))
+    .or_else(|| views.keys().next().map(String::as_str))
+    .unwrap_or_default();
+  let view = views.get(view_id).ok_or_else(|| {
+    AppError::RecordNotFound(format!(
+      "Can't find the view:{} of database:{}",
+      view_id, database_id
+    ))
+  })?;
+
+  let empty = Map::new();
+  let fields = database
+    .get("fields")
+    .and_then(Value::as_object)
+    .unwrap_or(&empty);
+  let field_settings = view
+    .get("field_settings")
+    .and_then(Value::as_object)
+    .unwrap_or(&empty);
+  let fields = order_ids(view, "field_orders")
+    .into_iter()
+    .filter(|field_id| {
+      let visibility = field_settings
+        .get(field_id)
+        .and_then(|settings| settings.get("visibility"))
+        .and_then(Value::as_i64);
+      visibility != Some(FIELD_ALWAYS_HIDDEN)
+    })
+    .filter_map(|field_id| {
+      let field = fields.get(&field_id)?;
+      Some(DatabaseField {
+        name: field
+          .get("name")
+          .and_then(Value::as_str)
+          .unwrap_or_default()
+          .to_string(),
+        field_type: field
+          .get("ty")
+          .and_then(Value::as_i64)
+          .unwrap_or(RICH_TEXT_FIELD_TYPE),
+        type_options: field.get("type_option").cloned().unwrap_or(Value::Null),
+        id: field_id,
+      })
+    })
+    .collect();
+  Ok(DatabaseTable {
+    view_id: view_id.to_string(),
+    fields,
+    row_ids: order_ids(view, "row_orders"),
+  })
+}
+
+/// The ids of the row or field orders of a view.
+fn order_ids(view: &Value, key: &str) -> Vec<String> {
+  view
+    .get(key)
+    .and_then(Value::as_array)
+    .map(|orders| {
+      orders
+        .iter()
+        .filter_map(|order| order.get("id")?.as_str().map(str::to_string))
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn database_row(row_id: &str, encoded_collab_v1: &[u8]) -> Result<DatabaseRow, AppError> {
+  let json = collab_json(row_id, encoded_collab_v1)?;
+  let data = json.get(ROW_DATA);
+  let timestamp = |key: &str| data.and_then(|data| data.get(key)).and_then(Value::as_i64);
+  Ok(DatabaseRow {
+    id: row_id.to_string(),
+    cells: data
+      .and_then(|data| data.get("cells"))
+      .and_then(Value::as_object)
+      .cloned()
+      .unwrap_or_default(),
+    created_at: timestamp("created_at"),
+    last_modified: timestamp("last_modified"),
+  })
+}
+
+/// The text of the cell as it's displayed, like the names of the selected options instead of
+/// their ids.
+fn cell_text(field: &DatabaseField, row: &DatabaseRow) -> String {
+  match field.field_type {
+    CREATED_TIME_FIELD_TYPE => return row.created_at.map(format_time).unwrap_or_default(),
+    LAST_EDITED_TIME_FIELD_TYPE => return row.last_modified.map(format_time).unwrap_or_default(),
+    _ => {},
+  }
+  let cell = match row.cells.get(&field.id) {
+    Some(cell) => cell,
+    None => return String::new(),
+  };
+  let data = match cell.get("data") {
+    Some(Value::String(data)) => data.clone(),
+    Some(Value::Null) | None => String::new(),
+    Some(data) => data.to_string(),
+  };
+  match field.field_type {
+    SINGLE_SELECT_FIELD_TYPE | MULTI_SELECT_FIELD_TYPE => {
+      let options = select_options(&field.type_options, field.field_type);
+      data
+        .split(',')
+        .filter(|id| !id.is_empty())
+        .map(|id| options.get(id).cloned().unwrap_or_else(|| id.to_string()))
+        .collect::<Vec<_>>()
+        .join(",")
+    },
+    CHECKLIST_FIELD_TYPE => {
+      let checklist = serde_json::from_str::<Value>(&data).unwrap_or(Value::Null);
+      let names = options_by_id(&checklist);
+      checklist
+        .get("selected_option_ids")
+        .and_then(Value::as_array)
+        .map(|ids| {
+          ids
+            .iter()
+            .filter_map(Value::as_str)
+            .filter_map(|id| names.get(id).cloned())
+            .collect::<Vec<_>>()
+            .join(",")
+        })
+        .unwrap_or_default()
+    },
+    DATE_TIME_FIELD_TYPE => match data.parse::<i64>() {
+      Ok(timestamp) => format_time(timestamp),
+      Err(_) => data,
+    },
+    _ => data,
+  }
+}
+
+/// The names of the options of a select field, keyed by option id. The options are stored as a
+/// json string in the type option of the field.
+fn select_options(type_options: &Value, field_type: i64) -> HashMap<String, String> {
+  type_options
+    .get(field_type.to_string())
+    .and_then(|type_option| type_option.get("content"))
+    .and_then(Value::as_str)
+    .and_then(|content| serde_json::from_str::<Value>(content).ok())
+    .map(|content| options_by_id(&content))
+    .unwrap_or_default()
+}
+
+fn options_by_id(value: &Value) -> HashMap<String, String> {
+  value
+    .get("options")
+    .and_then(Value::as_array)
+    .map(|options| {
+      options
+        .iter()
+        .filter_map(|option| {
+          let id = option.get("id")?.as_str()?;
+          let name = option.get("name")?.as_str()?;
+          Some((id.to_string(), name.to_string()))
+        })
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn format_time(timestamp: i64) -> String {
+  match Utc.timestamp_opt(timestamp, 0).single() {
+    Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+    None => timestamp.to_string(),
+  }
+}
+
+/// Encode a database collab with a grid view that shows the text fields and the rows.
+fn database_collab(
+  database_id: &str,
+  view_id: &str,
+  name: &str,
+  fields: &[(String, String)],
+  row_ids: &[String],
+  now: i64,
+) -> Result<Vec<u8>, AppError> {
+  let doc = Doc::new();
+  let database = doc.get_or_insert_map(DATABASE);
+  {
+    let mut txn = doc.transact_mut();
+    database.insert(&mut txn, "id", any_str(database_id));
+
+    let field_map = insert_map(&mut txn, &database, "fields");
+    for (index, (field_id, field_name)) in fields.iter().enumerate() {
+      let field = insert_map(&mut txn, &field_map, field_id);
+      field.insert(&mut txn, "id", any_str(field_id));
+      field.insert(&mut txn, "name", any_str(field_name));
+      field.insert(&mut txn, "ty", Any::BigInt(RICH_TEXT_FIELD_TYPE));
+      field.insert(&mut txn, "is_primary", Any::Bool(index == 0));
+      let type_options = insert_map(&mut txn, &field, "type_option");
+      insert_map(&mut txn, &type_options, &RICH_TEXT_FIELD_TYPE.to_string());
+    }
+
+    let view_map = insert_map(&mut txn, &database, "views");
+    let view = insert_map(&mut txn, &view_map, view_id);
+    view.insert(&mut txn, "id", any_str(view_id));
+    view.insert(&mut txn, "database_id", any_str(database_id));
+    view.insert(&mut txn, "name", any_str(name));
+    view.insert(&mut txn, "layout", Any::BigInt(GRID_LAYOUT));
+    view.insert(&mut txn, "created_at", Any::BigInt(now));
+    view.insert(&mut txn, "modified_at", Any::BigInt(now));
+    insert_map(&mut txn, &view, "layout_settings");
+    for key in ["filters", "groups", "sorts"] {
+      view.insert(&mut txn, key, ArrayPrelim::<Vec<Any>, Any>::from(vec![]));
+    }
+    let field_settings = insert_map(&mut txn, &view, "field_settings");
+    for (field_id, _) in fields {
+      let settings = insert_map(&mut txn, &field_settings, field_id);
+      settings.insert(&mut txn, "visibility", Any::BigInt(0));
+      settings.insert(&mut txn, "width", Any::BigInt(DEFAULT_FIELD_WIDTH));
+    }
+    let field_orders = fields
+      .iter()
+      .map(|(field_id, _)| any_map([("id", any_str(field_id))]))
+      .collect::<Vec<_>>();
+    view.insert(
+      &mut txn,
+      "field_orders",
+      ArrayPrelim::<Vec<Any>, Any>::from(field_orders),
+    );
+    let row_orders = row_ids
+      .iter()
+      .map(|row_id| {
+        any_map([
+          ("id", any_str(row_id)),
+          ("height", Any::BigInt(DEFAULT_ROW_HEIGHT)),
+        ])
+      })
+      .collect::<Vec<_>>();
+    view.insert(
+      &mut txn,
+      "row_orders",
+      ArrayPrelim::<Vec<Any>, Any>::from(row_orders),
+    );
+
+    let metas = insert_map(&mut txn, &database, "metas");
+    metas.insert(&mut txn, "iid", any_str(view_id));
+  }
+  encode_doc(&doc)
+}
+
+/// Encode a database row collab whose cells are texts.
+fn database_row_collab(
+  row_id: &str,
+  database_id: &str,
+  cells: &[(&str, String)],
+  now: i64,
+) -> Result<Vec<u8>, AppError> {
+  let doc = Doc::new();
+  let data = doc.get_or_insert_map(ROW_DATA);
+  {
+    let mut txn = doc.transact_mut();
+    data.insert(&mut txn, "id", any_str(row_id));
+    data.insert(&mut txn, "database_id", any_str(database_id));
+    data.insert(&mut txn, "height", Any::BigInt(DEFAULT_ROW_HEIGHT));
+    data.insert(&mut txn, "visibility", Any::Bool(true));
+    data.insert(&mut txn, "created_at", Any::BigInt(now));
+    data.insert(&mut txn, "last_modified", Any::BigInt(now));
+    let cell_map = insert_map(&mut txn, &data, "cells");
+    for (field_id, text) in cells {
+      let cell = insert_map(&mut txn, &cell_map, field_id);
+      cell.insert(&mut txn, "data", any_str(text));
+      cell.insert(&mut txn, "field_type", Any::BigInt(RICH_TEXT_FIELD_TYPE));
+      cell.insert(&mut txn, "created_at", Any::BigInt(now));
+      cell.insert(&mut txn, "last_modified", Any::BigInt(now));
+    }
+  }
+  encode_doc(&doc)
+}
+
+fn insert_map(txn: &mut yrs::TransactionMut, map: &MapRef, key: &str) -> MapRef {
+  map.insert(txn, key, MapPrelim::<Any>::from(HashMap::new()))
+}
+
+fn any_str(value: &str) -> Any {
+  Any::String(value.into())
+}
+
+fn any_map<const N: usize>(entries: [(&str, Any); N]) -> Any {
+  Any::Map(Arc::new(
+    entries
+      .into_iter()
+      .map(|(key, value)| (key.to_string(), value))
+      .collect(),
+  ))
+}
+
+fn encode_doc(doc: &Doc) -> Result<Vec<u8>, AppError> {
+  let txn = doc.transact();
+  EncodedCollab::new_v1(
+    txn.state_vector().encode_v1(),
+    txn.encode_state_as_update_v1(&StateVector::default()),
+  )
+  .encode_to_bytes()
+  .map_err(|err| AppError::Internal(anyhow!("fail to encode collab: {:?}", err)))
+}
+
+/// Parse the records of the csv. The fields can be quoted, and a quoted field can hold commas,
+/// line breaks and escaped quotes (`""`).
+fn parse_csv(csv: &str) -> Result<Vec<Vec<String>>, AppError> {
+  let csv = csv.strip_prefix('\u{feff}').unwrap_or(csv);
+  let mut records = vec![];
+  let mut record = vec![];
+  let mut field = String::new();
+  let mut in_quotes = false;
+  // Whether the current line has any content, so the empty lines are skipped
+  let mut in_record = false;
+  let mut chars = csv.chars().peekable();
+  while let Some(c) = chars.next() {
+    if in_quotes {
+      match c {
+        '"' if chars.peek() == Some(&'"') => {
+          chars.next();
+          field.push('"');
+        },
+        '"' => in_quotes = false,
+        c => field.push(c),
+      }
+      continue;
+    }
+    match c {
+      '"' => {
+        in_quotes = true;
+        in_record = true;
+      },
+      ',' => {
+        record.push(std::mem::take(&mut field));
+        in_record = true;
+      },
+      '\r' if chars.peek() == Some(&'\n') => {},
+      '\n' | '\r' => {
+        if in_record {
+          record.push(std::mem::take(&mut field));
+          records.push(std::mem::take(&mut record));
+        }
+        in_record = false;
+      },
+      c => {
+        field.push(c);
+        in_record = true;
+      },
+    }
+  }
+  if in_quotes {
+    return Err(AppError::InvalidRequest(
+      "invalid csv: a quoted field is not closed".to_string(),
+    ));
+  }
+  if in_record {
+    record.push(field);
+    records.push(record);
+  }
+  Ok(records)
+}
+
+fn write_csv_record<I>(csv: &mut String, fields: I)
+where
+  I: IntoIterator<Item = String>,
+{
+  for (index, field) in fields.into_iter().enumerate() {
+    if index > 0 {
+      csv.push(',');
+    }
+    if field.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+      csv.push('"');
+      csv.push_str(&field.replace('"', "\"\""));
+      csv.push('"');
+    } else {
+      csv.push_str(&field);
+    }
+  }
+  csv.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_and_write_csv() {
+    let csv = "\u{feff}name,note\r\na,\"b, \"\"c\"\"\nd\"\n\nlast,\n";
+    let records = parse_csv(csv).unwrap();
+    assert_eq!(
+      records,
+      vec![
+        vec!["name".to_string(), "note".to_string()],
+        vec!["a".to_string(), "b, \"c\"\nd".to_string()],
+        vec!["last".to_string(), "".to_string()],
+      ]
+    );
+
+    let mut written = String::new();
+    for record in records.clone() {
+      write_csv_record(&mut written, record);
+    }
+    assert_eq!(parse_csv(&written).unwrap(), records);
+    assert!(parse_csv("\"not closed").is_err());
+  }
+
+  #[test]
+  fn read_created_database() {
+    let fields = vec![
+      ("f1".to_string(), "Name".to_string()),
+      ("f2".to_string(), "Note".to_string()),
+    ];
+    let row_ids = vec!["r1".to_string()];
+    let database = database_collab("db", "v1", "Grid", &fields, &row_ids, 0).unwrap();
+    let table = database_view_table("db", &database, None).unwrap();
+    assert_eq!(
+      table
+        .fields
+        .iter()
+        .map(|field| field.name.as_str())
+        .collect::<Vec<_>>(),
+      vec!["Name", "Note"]
+    );
+    assert_eq!(table.row_ids, row_ids);
+    assert!(database_view_table("db", &database, Some("v2")).is_err());
+
+    let cells = vec![("f1", "a".to_string()), ("f2", "b".to_string())];
+    let row = database_row_collab("r1", "db", &cells, 0).unwrap();
+    let row = database_row("r1", &row).unwrap();
+    let texts = table
+      .fields
+      .iter()
+      .map(|field| cell_text(field, &row))
+      .collect::<Vec<_>>();
+    assert_eq!(texts, vec!["a", "b"]);
+  }
+
+  #[test]
+  fn select_option_cell_text() {
+    let content = json!({
+      "options": [{ "id": "o1", "name": "Done" }, { "id": "o2", "name": "Todo" }],
+    });
+    let field = DatabaseField {
+      id: "f1".to_string(),
+      name: "Status".to_string(),
+      field_type: MULTI_SELECT_FIELD_TYPE,
+      type_options: json!({ "4": { "content": content.to_string() } }),
+    };
+    let row = DatabaseRow {
+      id: "r1".to_string(),
+      cells: json!({ "f1": { "data": "o2,o1" } })
+        .as_object()
+        .cloned()
+        .unwrap(),
+      created_at: None,
+      last_modified: None,
+    };
+    assert_eq!(cell_text(&field, &row), "Todo,Done");
+
+    let view = DatabaseView {
+      database_id: "db".to_string(),
+      view_id: "v1".to_string(),
+      fields: vec![field],
+      rows: vec![row],
+    };
+    let json = database_view_json(&view);
+    assert_eq!(json["fields"][0]["name"], "Status");
+    assert_eq!(json["rows"][0]["cells"]["f1"], "Todo,Done");
+  }
+}
@@ -0,0 +1,847 @@
+use crate::biz::collab::restore::{doc_from_encoded_collab, DATA_SECTION};
+use crate::biz::collab::search::block_data;
+use crate::biz::collab::snapshot_diff::document_json;
+use crate::biz::collab::storage::CollabPostgresDBStorage;
+use anyhow::anyhow;
+use app_error::AppError;
+use collab::core::collab_plugin::EncodedCollab;
+use collab_entity::CollabType;
+use database::collab::CollabStorage;
+use database_entity::dto::{DocumentExportFormat, QueryCollabParams};
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use tracing::instrument;
+use yrs::types::text::YChange;
+use yrs::types::Value as YValue;
+use yrs::{Any, Doc, Map as _, MapRef, ReadTxn, Text as _, Transact};
+
+/// The blocks that are nested deeper than this are not rendered, so a malformed document can't
+/// overflow the stack.
+const MAX_BLOCK_DEPTH: usize = 64;
+
+/// Render the document as Markdown or HTML.
+#[instrument(level = "debug", skip(storage), err)]
+pub async fn export_document(
+  storage: &CollabPostgresDBStorage,
+  uid: i64,
+  workspace_id: &str,
+  object_id: &str,
+  format: DocumentExportFormat,
+) -> Result<String, AppError> {
+  let query = QueryCollabParams::new(object_id, CollabType::Document, workspace_id);
+  let encoded_collab = storage
+    .get_collab_encoded(&uid, query)
+    .await?
+    .encode_to_bytes()
+    .map_err(|err| AppError::Internal(anyhow!("fail to encode collab: {:?}", err)))?;
+
+  // Decoding the collab is CPU bound
+  let object_id = object_id.to_string();
+  tokio::task::spawn_blocking(move || {
+    let document = document_json_with_deltas(&object_id, &encoded_collab)?;
+    Ok(render_document(&document, format))
+  })
+  .await?
+}
+
+/// Return the json of the document stored in the encoded collab, with the formatting of its texts.
+///
+/// The texts of the documents edited by the clients are `Y.Text`s, whose json only keeps the
+/// plain text, so their deltas are read from the doc and replace them in the text map.
+pub(crate) fn document_json_with_deltas(
+  object_id: &str,
+  encoded_collab_v1: &[u8],
+) -> Result<Value, AppError> {
+  let mut document = document_json(object_id, encoded_collab_v1)?;
+  let encoded_collab = EncodedCollab::decode_from_bytes(encoded_collab_v1)
+    .map_err(|err| AppError::Internal(anyhow!("invalid encoded collab: {}", err)))?;
+  let deltas = text_deltas(&doc_from_encoded_collab(&encoded_collab)?);
+  if let Some(text_map) = document
+    .pointer_mut("/meta/text_map")
+    .and_then(Value::as_object_mut)
+  {
+    text_map.extend(deltas);
+  }
+  Ok(document)
+}
+
+/// The operations of the delta of every `Y.Text` in the text map of the document.
+fn text_deltas(doc: &Doc) -> Map<String, Value> {
+  let data = doc.get_or_insert_map(DATA_SECTION);
+  let txn = doc.transact();
+  let text_map = child_map(&txn, &data, "document")
+    .and_then(|document| child_map(&txn, &document, "meta"))
+    .and_then(|meta| child_map(&txn, &meta, "text_map"));
+  let text_map = match text_map {
+    Some(text_map) => text_map,
+    None => return Map::new(),
+  };
+  text_map
+    .iter(&txn)
+    .filter_map(|(text_id, value)| match value {
+      YValue::YText(text) => {
+        let ops = text
+          .diff(&txn, YChange::identity)
+          .into_iter()
+          .filter_map(|diff| {
+            let insert = match diff.insert {
+              YValue::Any(Any::String(insert)) => insert.to_string(),
+              _ => return None,
+            };
+            let mut op = Map::from_iter([("insert".to_string(), Value::String(insert))]);
+            if let Some(attributes) = diff.attributes {
+              let attributes = attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), any_json(value)))
+                .collect();
+              op.insert("attributes".to_string(), Value::Object(attributes));
+            }
+            Some(Value::Object(op))
+          })
+          .collect();
+        Some((text_id.to_string(), Value::Array(ops)))
+      },
+      _ => None,
+    })
+    .collect()
+}
+
+fn child_map<T: ReadTxn>(txn: &T, map: &MapRef, key: &str) -> Option<MapRef> {
+  match map.get(txn, key)? {
+    YValue::YMap(map) => Some(map),
+    _ => None,
+  }
+}
+
+fn any_json(any: &Any) -> Value {
+  let mut json = String::new();
+  any.to_json(&mut json);
+  serde_json::from_str(&json).unwrap_or(Value::Null)
+}
+
+/// Render the json of a document.
+pub fn render_document(document: &Value, format: DocumentExportFormat) -> String {
+  let tree = match DocumentTree::new(document) {
+    Some(tree) => tree,
+    None => return String::new(),
+  };
+  let mut visited = HashSet::from([tree.page_id.to_string()]);
+  let children = tree
+    .blocks
+    .get(tree.page_id)
+    .map(|page| tree.children(page))
+    .unwrap_or_default();
+  match format {
+    DocumentExportFormat::Markdown => {
+      let mut markdown = markdown_blocks(&tree, &children, 0, &mut visited);
+      if !markdown.is_empty() {
+        markdown.push('\n');
+      }
+      markdown
+    },
+    DocumentExportFormat::Html => format!(
+      "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n</head>\n<body>\n{}</body>\n</html>\n",
+      html_blocks(&tree, &children, 0, &mut visited)
+    ),
+  }
+}
+
+/// Return the blocks of the document as a tree whose root is the page. The text of a block is
+/// given as the operations of its delta.
+pub(crate) fn document_block_tree(document: &Value) -> Value {
+  let tree = match DocumentTree::new(document) {
+    Some(tree) => tree,
+    None => return Value::Null,
+  };
+  let mut visited = HashSet::from([tree.page_id.to_string()]);
+  match tree.block(tree.page_id) {
+    Some(page) => block_json(&tree, page, 0, &mut visited),
+    None => Value::Null,
+  }
+}
+
+struct DocumentTree<'a> {
+  page_id: &'a str,
+  blocks: &'a Map<String, Value>,
+  children_map: &'a Map<String, Value>,
+  text_map: Option<&'a Map<String, Value>>,
+}
+
+struct Block<'a> {
+  id: &'a str,
+  ty: &'a str,
+  data: Value,
+  ops: Vec<InlineOp>,
+  children: Vec<&'a str>,
+}
+
+/// A run of text with the same formatting.
+struct InlineOp {
+  text: String,
+  attributes: Map<String, Value>,
+}
+
+impl<'a> DocumentTree<'a> {
+  fn new(document: &'a Value) -> Option<Self> {
+    let meta = document.get("meta")?;
+    Some(Self {
+      page_id: document.get("page_id")?.as_str()?,
+      blocks: document.get("blocks")?.as_object()?,
+      children_map: meta.get("children_map")?.as_object()?,
+      text_map: meta.get("text_map").and_then(Value::as_object),
+    })
+  }
+
+  fn children(&self, block: &'a Value) -> Vec<&'a str> {
+    block
+      .get("children")
+      .and_then(Value::as_str)
+      .and_then(|children_id| self.children_map.get(children_id))
+      .and_then(Value::as_array)
+      .map(|children| children.iter().filter_map(Value::as_str).collect())
+      .unwrap_or_default()
+  }
+
+  fn block(&self, block_id: &str) -> Option<Block<'a>> {
+    let (id, block) = self.blocks.get_key_value(block_id)?;
+    let mut data = block_data(block).unwrap_or(Value::Null);
+    let data_delta = data.as_object_mut().and_then(|data| data.remove("delta"));
+    let delta = block
+      .get("external_id")
+      .and_then(Value::as_str)
+      .and_then(|external_id| self.text_map?.get(external_id))
+      .cloned()
+      .or(data_delta);
+    Some(Block {
+      id,
+      ty: block.get("ty").and_then(Value::as_str).unwrap_or_default(),
+      data,
+      ops: delta.as_ref().map(delta_ops).unwrap_or_default(),
+      children: self.children(block),
+    })
+  }
+}
+
+impl Block<'_> {
+  fn plain_text(&self) -> String {
+    self.ops.iter().map(|op| op.text.as_str()).collect()
+  }
+
+  fn data_str(&self, key: &str) -> &str {
+    self
+      .data
+      .get(key)
+      .and_then(Value::as_str)
+      .unwrap_or_default()
+  }
+
+  fn checked(&self) -> bool {
+    self
+      .data
+      .get("checked")
+      .and_then(Value::as_bool)
+      .unwrap_or(false)
+  }
+
+  fn heading_level(&self) -> usize {
+    self
+      .data
+      .get("level")
+      .and_then(Value::as_u64)
+      .unwrap_or(1)
+      .clamp(1, 6) as usize
+  }
+}
+
+/// The delta is either the plain text itself, a json string of the operations of the delta or the
+/// operations.
+fn delta_ops(delta: &Value) -> Vec<InlineOp> {
+  match delta {
+    Value::String(text) => match serde_json::from_str::<Value>(text) {
+      Ok(ops @ Value::Array(_)) => delta_ops(&ops),
+      _ => vec![InlineOp {
+        text: text.clone(),
+        attributes: Map::new(),
+      }],
+    },
+    Value::Array(ops) => ops
+      .iter()
+      .filter_map(|op| {
+        Some(InlineOp {
+          text: op.get("insert")?.as_str()?.to_string(),
+          attributes: op
+            .get("attributes")
+            .and_then(Value::as_object)
+            .cloned()
+            .unwrap_or_default(),
+        })
+      })
+      .collect(),
+    _ => vec![],
+  }
+}
+
+fn is_set(attributes: &Map<String, Value>, key: &str) -> bool {
+  attributes
+    .get(key)
+    .and_then(Value::as_bool)
+    .unwrap_or(false)
+}
+
+fn href(attributes: &Map<String, Value>) -> Option<&str> {
+  attributes
+    .get("href")
+    .and_then(Value::as_str)
+    .filter(|href| !href.is_empty())
+}
+
+/// Iterate over the blocks that were not rendered yet, so a block that appears twice in the tree
+/// is only rendered once.
+fn unvisited_blocks<'a>(
+  tree: &DocumentTree<'a>,
+  block_ids: &[&str],
+  visited: &mut HashSet<String>,
+) -> Vec<Block<'a>> {
+  block_ids
+    .iter()
+    .filter(|block_id| visited.insert(block_id.to_string()))
+    .filter_map(|block_id| tree.block(block_id))
+    .collect()
+}
+
+fn block_json(
+  tree: &DocumentTree,
+  block: Block,
+  depth: usize,
+  visited: &mut HashSet<String>,
+) -> Value {
+  let children = if depth + 1 >= MAX_BLOCK_DEPTH {
+    vec![]
+  } else {
+    unvisited_blocks(tree, &block.children, visited)
+      .into_iter()
+      .map(|child| block_json(tree, child, depth + 1, visited))
+      .collect()
+  };
+  let delta = block
+    .ops
+    .into_iter()
+    .map(|op| {
+      let mut op_json = Map::from_iter([("insert".to_string(), Value::String(op.text))]);
+      if !op.attributes.is_empty() {
+        op_json.insert("attributes".to_string(), Value::Object(op.attributes));
+      }
+      Value::Object(op_json)
+    })
+    .collect::<Vec<_>>();
+  json!({
+    "id": block.id,
+    "type": block.ty,
+    "data": block.data,
+    "delta": delta,
+    "children": children,
+  })
+}
+
+fn markdown_blocks(
+  tree: &DocumentTree,
+  block_ids: &[&str],
+  depth: usize,
+  visited: &mut HashSet<String>,
+) -> String {
+  if depth >= MAX_BLOCK_DEPTH {
+    return String::new();
+  }
+  let mut chunks = vec![];
+  let mut number = 0;
+  for block in unvisited_blocks(tree, block_ids, visited) {
+    number = if block.ty == "numbered_list" {
+      number + 1
+    } else {
+      0
+    };
+    let chunk = markdown_block(tree, &block, number, depth, visited);
+    if !chunk.is_empty() {
+      chunks.push(chunk);
+    }
+  }
+  chunks.join("\n\n")
+}
+
+fn markdown_block(
+  tree: &DocumentTree,
+  block: &Block,
+  number: usize,
+  depth: usize,
+  visited: &mut HashSet<String>,
+) -> String {
+  let text = markdown_inline(&block.ops);
+  let children = markdown_blocks(tree, &block.children, depth + 1, visited);
+  // The children of a list item are indented by the width of its marker
+  let (head, indent) = match block.ty {
+    "heading" => (
+      format!("{} {}", "#".repeat(block.heading_level()), text),
+      String::new(),
+    ),
+    "bulleted_list" | "toggle_list" => (format!("- {}", text), "  ".to_string()),
+    "todo_list" => {
+      let checked = if block.checked() { "x" } else { " " };
+      (format!("- [{}] {}", checked, text), "  ".to_string())
+    },
+    "numbered_list" => {
+      let marker = format!("{}. ", number);
+      let indent = " ".repeat(marker.len());
+      (format!("{}{}", marker, text), indent)
+    },
+    "quote" | "callout" => {
+      let icon = block.data_str("icon");
+      let text = if icon.is_empty() {
+        text
+      } else {
+        format!("{} {}", icon, text)
+      };
+      let content = join_chunks(text, children);
+      return prefix_lines(&content, ">", " ");
+    },
+    "code" => {
+      let code = block.plain_text();
+      let fence = code_fence(&code);
+      (
+        format!(
+          "{}{}\n{}\n{}",
+          fence,
+          block.data_str("language"),
+          code,
+          fence
+        ),
+        String::new(),
+      )
+    },
+    "image" => {
+      let url = block.data_str("url");
+      if url.is_empty() {
+        (String::new(), String::new())
+      } else {
+        (format!("![]({})", markdown_url(url)), String::new())
+      }
+    },
+    "divider" => ("---".to_string(), String::new()),
+    "math_equation" => (
+      format!("$$\n{}\n$$", block.data_str("formula")),
+      String::new(),
+    ),
+    _ => (text, String::new()),
+  };
+  join_chunks(head, prefix_lines(&children, "", &indent))
+}
+
+fn join_chunks(head: String, children: String) -> String {
+  match (head.is_empty(), children.is_empty()) {
+    (_, true) => head,
+    (true, false) => children,
+    (false, false) => format!("{}\n\n{}", head, children),
+  }
+}
+
+/// Prefix every line with `prefix` followed by `separator`, which is omitted on empty lines.
+fn prefix_lines(content: &str, prefix: &str, separator: &str) -> String {
+  if content.is_empty() || (prefix.is_empty() && separator.is_empty()) {
+    return content.to_string();
+  }
+  content
+    .split('\n')
+    .map(|line| {
+      if line.is_empty() {
+        prefix.to_string()
+      } else {
+        format!("{}{}{}", prefix, separator, line)
+      }
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+fn markdown_inline(ops: &[InlineOp]) -> String {
+  ops
+    .iter()
+    .map(|op| {
+      let attributes = &op.attributes;
+      let mut text = if is_set(attributes, "code") {
+        code_span(&op.text)
+      } else {
+        let mut text = escape_markdown(&op.text);
+        if is_set(attributes, "strikethrough") {
+          text = wrap_trimmed(&text, "~~");
+        }
+        if is_set(attributes, "italic") {
+          text = wrap_trimmed(&text, "_");
+        }
+        if is_set(attributes, "bold") {
+          text = wrap_trimmed(&text, "**");
+        }
+        text
+      };
+      if let Some(href) = href(attributes) {
+        text = format!("[{}]({})", text, markdown_url(href));
+      }
+      text
+    })
+    .collect()
+}
+
+/// Wrap the text with the emphasis marker, leaving out the surrounding whitespaces because an
+/// emphasis can't start or end with a whitespace.
+fn wrap_trimmed(text: &str, marker: &str) -> String {
+  let trimmed = text.trim();
+  if trimmed.is_empty() {
+    return text.to_string();
+  }
+  let start = text.len() - text.trim_start().len();
+  let end = start + trimmed.len();
+  format!(
+    "{}{}{}{}{}",
+    &text[..start],
+    marker,
+    trimmed,
+    marker,
+    &text[end..]
+  )
+}
+
+fn escape_markdown(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    if matches!(
+      c,
+      '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '~' | '#' | '|'
+    ) {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+fn code_span(text: &str) -> String {
+  if text.contains('`') {
+    format!("`` {} ``", text)
+  } else {
+    format!("`{}`", text)
+  }
+}
+
+/// A fence that is longer than any run of backticks in the code.
+fn code_fence(code: &str) -> String {
+  let longest_run = code
+    .split(|c: char| c != '`')
+    .map(str::len)
+    .max()
+    .unwrap_or(0);
+  "`".repeat(longest_run.max(2) + 1)
+}
+
+fn markdown_url(url: &str) -> String {
+  if url.contains(|c: char| c.is_whitespace() || c == '(' || c == ')') {
+    format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+  } else {
+    url.to_string()
+  }
+}
+
+fn html_blocks(
+  tree: &DocumentTree,
+  block_ids: &[&str],
+  depth: usize,
+  visited: &mut HashSet<String>,
+) -> String {
+  if depth >= MAX_BLOCK_DEPTH {
+    return String::new();
+  }
+  let mut html = String::new();
+  // The consecutive list items are grouped in a list
+  let mut open_list = None;
+  for block in unvisited_blocks(tree, block_ids, visited) {
+    let list = match block.ty {
+      "bulleted_list" | "todo_list" => Some("ul"),
+      "numbered_list" => Some("ol"),
+      _ => None,
+    };
+    if open_list != list {
+      if let Some(tag) = open_list {
+        html.push_str(&format!("</{}>\n", tag));
+      }
+      if let Some(tag) = list {
+        html.push_str(&format!("<{}>\n", tag));
+      }
+      open_list = list;
+    }
+    html.push_str(&html_block(tree, &block, depth, visited));
+  }
+  if let Some(tag) = open_list {
+    html.push_str(&format!("</{}>\n", tag));
+  }
+  html
+}
+
+fn html_block(
+  tree: &DocumentTree,
+  block: &Block,
+  depth: usize,
+  visited: &mut HashSet<String>,
+) -> String {
+  let text = html_inline(&block.ops);
+  let children = html_blocks(tree, &block.children, depth + 1, visited);
+  match block.ty {
+    "heading" => format!(
+      "<h{0}>{1}</h{0}>\n{2}",
+      block.heading_level(),
+      text,
+      children
+    ),
+    "bulleted_list" | "numbered_list" => format!("<li>{}\n{}</li>\n", text, children),
+    "todo_list" => {
+      let checked = if block.checked() { " checked" } else { "" };
+      format!(
+        "<li><input type=\"checkbox\" disabled{}> {}\n{}</li>\n",
+        checked, text, children
+      )
+    },
+    "toggle_list" => format!(
+      "<details>\n<summary>{}</summary>\n{}</details>\n",
+      text, children
+    ),
+    "quote" | "callout" => {
+      let icon = escape_html(block.data_str("icon"));
+      let text = if icon.is_empty() {
+        text
+      } else {
+        format!("{} {}", icon, text)
+      };
+      format!("<blockquote>\n<p>{}</p>\n{}</blockquote>\n", text, children)
+    },
+    "code" => {
+      let language = block.data_str("language");
+      let class = if language.is_empty() {
+        String::new()
+      } else {
+        format!(" class=\"language-{}\"", escape_html(language))
+      };
+      format!(
+        "<pre><code{}>{}</code></pre>\n{}",
+        class,
+        escape_html(&block.plain_text()),
+        children
+      )
+    },
+    "image" => {
+      let url = block.data_str("url");
+      if url.is_empty() {
+        children
+      } else {
+        format!("<img src=\"{}\">\n{}", escape_html(url), children)
+      }
+    },
+    "divider" => format!("<hr>\n{}", children),
+    "math_equation" => format!(
+      "<pre class=\"math\">{}</pre>\n{}",
+      escape_html(block.data_str("formula")),
+      children
+    ),
+    _ if text.is_empty() => children,
+    _ => format!("<p>{}</p>\n{}", text, children),
+  }
+}
+
+fn html_inline(ops: &[InlineOp]) -> String {
+  ops
+    .iter()
+    .map(|op| {
+      let attributes = &op.attributes;
+      let mut html = escape_html(&op.text).replace('\n', "<br>");
+      for (key, tag) in [
+        ("code", "code"),
+        ("strikethrough", "s"),
+        ("underline", "u"),
+        ("italic", "em"),
+        ("bold", "strong"),
+      ] {
+        if is_set(attributes, key) {
+          html = format!("<{1}>{0}</{1}>", html, tag);
+        }
+      }
+      if let Some(href) = href(attributes) {
+        html = format!("<a href=\"{}\">{}</a>", escape_html(href), html);
+      }
+      html
+    })
+    .collect()
+}
+
+fn escape_html(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&#39;"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use collab::core::collab::MutexCollab;
+  use collab::core::origin::CollabOrigin;
+  use collab_document::document::Document;
+  use std::sync::Arc;
+  use workspace_template::document::parser::JsonToDocumentParser;
+
+  fn document() -> Value {
+    let text = |ops: Value| Value::String(ops.to_string());
+    json!({
+      "page_id": "page",
+      "blocks": {
+        "page": { "id": "page", "ty": "page", "children": "c_page", "data": "{}" },
+        "h": { "id": "h", "ty": "heading", "children": "c_h", "external_id": "t_h", "data": "{\"level\":2}" },
+        "p": { "id": "p", "ty": "paragraph", "children": "c_p", "external_id": "t_p", "data": "{}" },
+        "l1": { "id": "l1", "ty": "numbered_list", "children": "c_l1", "external_id": "t_l1", "data": "{}" },
+        "l2": { "id": "l2", "ty": "numbered_list", "children": "c_l2", "external_id": "t_l2", "data": "{}" },
+        "todo": { "id": "todo", "ty": "todo_list", "children": "c_todo", "external_id": "t_todo", "data": "{\"checked\":true}" },
+        "code": { "id": "code", "ty": "code", "children": "c_code", "external_id": "t_code", "data": "{\"language\":\"rust\"}" },
+        "quote": { "id": "quote", "ty": "quote", "children": "c_quote", "external_id": "t_quote", "data": "{}" },
+        "img": { "id": "img", "ty": "image", "children": "c_img", "data": "{\"url\":\"https://appflowy.io/a.png\"}" },
+      },
+      "meta": {
+        "children_map": {
+          "c_page": ["h", "p", "l1", "l2", "code", "quote", "img"],
+          "c_h": [], "c_p": [], "c_l1": ["todo"], "c_l2": [], "c_todo": [], "c_code": [],
+          "c_quote": [], "c_img": [],
+        },
+        "text_map": {
+          "t_h": text(json!([{ "insert": "Title" }])),
+          "t_p": text(json!([
+            { "insert": "a " },
+            { "insert": "bold", "attributes": { "bold": true } },
+            { "insert": " and " },
+            { "insert": "link", "attributes": { "href": "https://appflowy.io" } },
+            { "insert": " <x>" },
+          ])),
+          "t_l1": text(json!([{ "insert": "first" }])),
+          "t_l2": text(json!([{ "insert": "second" }])),
+          "t_todo": text(json!([{ "insert": "done" }])),
+          "t_code": text(json!([{ "insert": "let a = 1;" }])),
+          "t_quote": text(json!([{ "insert": "quoted" }])),
+        }
+      }
+    })
+  }
+
+  #[test]
+  fn block_tree() {
+    let tree = document_block_tree(&document());
+    assert_eq!(tree["id"], "page");
+    let children = tree["children"].as_array().unwrap();
+    assert_eq!(children.len(), 7);
+    assert_eq!(children[0]["type"], "heading");
+    assert_eq!(children[0]["data"]["level"], 2);
+    assert_eq!(children[1]["delta"][1]["insert"], "bold");
+    assert_eq!(children[1]["delta"][1]["attributes"]["bold"], true);
+    assert!(children[1]["delta"][0].get("attributes").is_none());
+    assert_eq!(children[2]["children"][0]["id"], "todo");
+  }
+
+  #[test]
+  fn render_markdown() {
+    let markdown = render_document(&document(), DocumentExportFormat::Markdown);
+    assert_eq!(
+      markdown,
+      "## Title\n\n\
+       a **bold** and [link](https://appflowy.io) \\<x\\>\n\n\
+       1. first\n\n   - [x] done\n\n\
+       2. second\n\n\
+       ```rust\nlet a = 1;\n```\n\n\
+       > quoted\n\n\
+       ![](https://appflowy.io/a.png)\n"
+    );
+  }
+
+  #[test]
+  fn render_html() {
+    let html = render_document(&document(), DocumentExportFormat::Html);
+    assert!(html.contains("<h2>Title</h2>"));
+    assert!(html.contains(
+      "<p>a <strong>bold</strong> and <a href=\"https://appflowy.io\">link</a> &lt;x&gt;</p>"
+    ));
+    assert!(html.contains(
+      "<ol>\n<li>first\n<ul>\n<li><input type=\"checkbox\" disabled checked> done\n</li>\n</ul>\n</li>\n<li>second\n</li>\n</ol>"
+    ));
+    assert!(html.contains("<pre><code class=\"language-rust\">let a = 1;</code></pre>"));
+    assert!(html.contains("<blockquote>\n<p>quoted</p>\n</blockquote>"));
+    assert!(html.contains("<img src=\"https://appflowy.io/a.png\">"));
+  }
+
+  #[test]
+  fn render_document_collab() {
+    let blocks = json!({
+      "type": "page",
+      "data": { "delta": [] },
+      "children": [
+        { "type": "heading", "data": { "level": 1, "delta": [{ "insert": "Title" }] } },
+        {
+          "type": "paragraph",
+          "data": {
+            "delta": [
+              { "insert": "a " },
+              { "insert": "bold", "attributes": { "bold": true } },
+              { "insert": " and " },
+              { "insert": "link", "attributes": { "href": "https://appflowy.io" } },
+            ]
+          }
+        },
+        {
+          "type": "numbered_list",
+          "data": { "delta": [{ "insert": "first" }] },
+          "children": [
+            { "type": "todo_list", "data": { "checked": true, "delta": [{ "insert": "done" }] } }
+          ]
+        },
+        { "type": "code", "data": { "language": "rust", "delta": [{ "insert": "let a = 1;" }] } },
+      ]
+    });
+    let document_data = JsonToDocumentParser::json_str_to_document(&blocks.to_string()).unwrap();
+    let collab = Arc::new(MutexCollab::new(CollabOrigin::Empty, "doc", vec![]));
+    let document = Document::create_with_data(collab, document_data).unwrap();
+    let encoded_collab_v1 = document
+      .get_collab()
+      .encode_collab_v1()
+      .encode_to_bytes()
+      .unwrap();
+
+    let document = document_json_with_deltas("doc", &encoded_collab_v1).unwrap();
+    assert_eq!(
+      render_document(&document, DocumentExportFormat::Markdown),
+      "# Title\n\n\
+       a **bold** and [link](https://appflowy.io)\n\n\
+       1. first\n\n   - [x] done\n\n\
+       ```rust\nlet a = 1;\n```\n"
+    );
+    let html = render_document(&document, DocumentExportFormat::Html);
+    assert!(html.contains("<h1>Title</h1>"));
+    assert!(
+      html.contains("<p>a <strong>bold</strong> and <a href=\"https://appflowy.io\">link</a></p>")
+    );
+  }
+
+  #[test]
+  fn escape_inline_markdown() {
+    assert_eq!(wrap_trimmed(" bold ", "**"), " **bold** ");
+    assert_eq!(code_span("a`b"), "`` a`b ``");
+    assert_eq!(code_fence("```"), "````");
+    assert_eq!(escape_markdown("*a_b*"), "\\*a\\_b\\*");
+  }
+}
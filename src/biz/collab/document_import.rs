@@ -0,0 +1,337 @@
+use crate::biz::collab::restore::{apply_server_update, doc_from_encoded_collab};
+use crate::biz::collab::storage::CollabPostgresDBStorage;
+use crate::state::AppState;
+use anyhow::anyhow;
+use app_error::AppError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use collab::core::collab::MutexCollab;
+use collab::core::collab_plugin::EncodedCollab;
+use collab::core::origin::CollabOrigin;
+use collab_document::document::Document;
+use collab_entity::CollabType;
+use collab_folder::{timestamp, Folder, RepeatedViewIdentifier, View, ViewLayout};
+use database::collab::CollabStorage;
+use database_entity::dto::{
+  AFDocumentImport, CreateCollabParams, ImportMarkdownParams, QueryCollabParams,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{error, instrument};
+use uuid::Uuid;
+use workspace_template::document::markdown_parser::{MarkdownToDocumentParser, IMAGE, IMAGE_URL};
+use workspace_template::gen_view_id;
+use yrs::{ReadTxn, Transact};
+
+/// Create a document from the markdown and add its view to the folder of the workspace. The local
+/// images of the markdown are uploaded as blobs of the document, and the image blocks point at the
+/// blobs with a path relative to the server. The uploaded blobs are deleted if the import fails.
+#[instrument(level = "debug", skip(state, params), err)]
+pub async fn import_markdown_document(
+  state: &AppState,
+  uid: i64,
+  workspace_id: &Uuid,
+  params: ImportMarkdownParams,
+) -> Result<AFDocumentImport, AppError> {
+  let ImportMarkdownParams {
+    name,
+    markdown,
+    parent_view_id,
+    images,
+  } = params;
+  let parent_view_id = parent_view_id.unwrap_or_else(|| workspace_id.to_string());
+  // Nothing is uploaded when the document can't be added to the folder
+  check_parent_view(&state.collab_storage, uid, workspace_id, &parent_view_id).await?;
+
+  let view_id = gen_view_id();
+  let mut uploaded_files = vec![];
+  let result = async {
+    let mut document_data = tokio::task::spawn_blocking(move || {
+      MarkdownToDocumentParser::markdown_to_document(&markdown)
+    })
+    .await?;
+
+    let images = images
+      .into_iter()
+      .map(|(path, content)| (normalize_image_path(&path).to_string(), content))
+      .collect::<HashMap<_, _>>();
+    let mut uploaded_urls = HashMap::new();
+    let mut missing_images = vec![];
+    for block in document_data
+      .blocks
+      .values_mut()
+      .filter(|block| block.ty == IMAGE)
+    {
+      let path = match block.data.get(IMAGE_URL).and_then(Value::as_str) {
+        Some(path) if is_local_image(path) => normalize_image_path(path).to_string(),
+        _ => continue,
+      };
+      if !uploaded_urls.contains_key(&path) {
+        let content = match images.get(&path) {
+          Some(content) => content,
+          None => {
+            if !missing_images.contains(&path) {
+              missing_images.push(path);
+            }
+            continue;
+          },
+        };
+        let data = STANDARD.decode(content).map_err(|err| {
+          AppError::InvalidRequest(format!("invalid content of the image:{}: {}", path, err))
+        })?;
+        let extension = Path::new(&path)
+          .extension()
+          .and_then(|extension| extension.to_str())
+          .map(|extension| extension.to_ascii_lowercase());
+        let file_id = match &extension {
+          Some(extension) => format!("{}.{}", Uuid::new_v4(), extension),
+          None => Uuid::new_v4().to_string(),
+        };
+        state
+          .bucket_storage
+          .put_blob(
+            *workspace_id,
+            file_id.clone(),
+            data,
+            image_content_type(extension.as_deref()).to_string(),
+            Some(view_id.clone()),
+          )
+          .await?;
+        uploaded_files.push(file_id.clone());
+        let url = format!("/api/file_storage/{}/blob/{}", workspace_id, file_id);
+        uploaded_urls.insert(path.clone(), url);
+      }
+      block.data.insert(
+        IMAGE_URL.to_string(),
+        Value::String(uploaded_urls[&path].clone()),
+      );
+    }
+
+    let object_id = view_id.clone();
+    let encoded_collab_v1 = tokio::task::spawn_blocking(move || {
+      let collab = Arc::new(MutexCollab::new(CollabOrigin::Empty, &object_id, vec![]));
+      let document = Document::create_with_data(collab, document_data)
+        .map_err(|err| AppError::Internal(anyhow!("fail to create document: {:?}", err)))?;
+      document
+        .get_collab()
+        .encode_collab_v1()
+        .encode_to_bytes()
+        .map_err(|err| AppError::Internal(anyhow!("fail to encode document: {:?}", err)))
+    })
+    .await??;
+
+    let workspace_id = workspace_id.to_string();
+    state
+      .collab_storage
+      .upsert_collab(
+        &uid,
+        CreateCollabParams {
+          object_id: view_id.clone(),
+          encoded_collab_v1,
+          collab_type: CollabType::Document,
+          override_if_exist: false,
+          workspace_id: workspace_id.clone(),
+        },
+      )
+      .await?;
+
+    let view = View {
+      id: view_id.clone(),
+      parent_view_id,
+      name,
+      desc: String::new(),
+      created_at: timestamp(),
+      is_favorite: false,
+      layout: ViewLayout::Document,
+      icon: None,
+      created_by: Some(uid),
+      last_edited_time: timestamp(),
+      children: RepeatedViewIdentifier::new(vec![]),
+      last_edited_by: Some(uid),
+    };
+    if let Err(err) = insert_folder_view(&state.collab_storage, uid, &workspace_id, view).await {
+      // Without a view the document can't be reached
+      if let Err(delete_err) = state.collab_storage.delete_collab(&uid, &view_id).await {
+        error!(
+          "failed to delete the document:{} of the failed import: {}",
+          view_id, delete_err
+        );
+      }
+      return Err(err);
+    }
+
+    Ok(AFDocumentImport {
+      view_id: view_id.clone(),
+      image_count: uploaded_urls.len(),
+      missing_images,
+    })
+  }
+  .await;
+
+  if result.is_err() {
+    for file_id in uploaded_files {
+      if let Err(err) = state
+        .bucket_storage
+        .delete_blob(workspace_id, &file_id)
+        .await
+      {
+        error!(
+          "failed to delete the image:{} of the failed import: {}",
+          file_id, err
+        );
+      }
+    }
+  }
+  result
+}
+
+/// Return an error if the parent view isn't in the folder of the workspace. The workspace itself
+/// is the parent of its top level views.
+async fn check_parent_view(
+  storage: &CollabPostgresDBStorage,
+  uid: i64,
+  workspace_id: &Uuid,
+  parent_view_id: &str,
+) -> Result<(), AppError> {
+  let workspace_id = workspace_id.to_string();
+  if parent_view_id == workspace_id {
+    return Ok(());
+  }
+  let folder = match storage.get_opened_collab(&workspace_id).await {
+    Some(collab) => tokio::task::spawn_blocking(move || collab.lock().encode_collab_v1()).await?,
+    None => {
+      let query = QueryCollabParams::new(&workspace_id, CollabType::Folder, &workspace_id);
+      storage.get_collab_encoded(&uid, query).await?
+    },
+  };
+  let parent_view_id = parent_view_id.to_string();
+  tokio::task::spawn_blocking(move || {
+    let folder = open_folder(uid, &workspace_id, &folder)?;
+    check_folder_view(&folder, &parent_view_id)
+  })
+  .await?
+}
+
+/// Add the view to the folder of the workspace. Like a snapshot restore, the view is inserted as
+/// an update, so the clients that hold the folder receive it.
+async fn insert_folder_view(
+  storage: &CollabPostgresDBStorage,
+  uid: i64,
+  workspace_id: &str,
+  view: View,
+) -> Result<(), AppError> {
+  let folder_id = workspace_id.to_string();
+  match storage.get_opened_collab(workspace_id).await {
+    Some(collab) => {
+      tokio::task::spawn_blocking(move || {
+        let mut collab = collab.lock();
+        let update = insert_view_update(uid, &folder_id, &collab.encode_collab_v1(), view)?;
+        apply_server_update(&mut collab, &update)
+      })
+      .await??;
+    },
+    None => {
+      let query = QueryCollabParams::new(workspace_id, CollabType::Folder, workspace_id);
+      let current = storage.get_collab_encoded(&uid, query).await?;
+      let update =
+        tokio::task::spawn_blocking(move || insert_view_update(uid, &folder_id, &current, view))
+          .await??;
+      storage
+        .append_collab_updates(
+          workspace_id,
+          workspace_id,
+          &CollabType::Folder,
+          vec![update],
+        )
+        .await?;
+      storage
+        .compact_collab_updates(workspace_id, &CollabType::Folder)
+        .await?;
+    },
+  }
+  Ok(())
+}
+
+/// Return the update that inserts the view into the `current` folder.
+fn insert_view_update(
+  uid: i64,
+  workspace_id: &str,
+  current: &EncodedCollab,
+  view: View,
+) -> Result<Vec<u8>, AppError> {
+  let state_vector = {
+    let doc = doc_from_encoded_collab(current)?;
+    let txn = doc.transact();
+    txn.state_vector()
+  };
+  let folder = open_folder(uid, workspace_id, current)?;
+  // The parent view may have been deleted since the import started
+  if view.parent_view_id != workspace_id {
+    check_folder_view(&folder, &view.parent_view_id)?;
+  }
+  folder.insert_view(view, None);
+
+  let doc = doc_from_encoded_collab(&folder.encode_collab_v1())?;
+  let update = doc.transact().encode_state_as_update_v1(&state_vector);
+  Ok(update)
+}
+
+fn open_folder(uid: i64, workspace_id: &str, folder: &EncodedCollab) -> Result<Folder, AppError> {
+  Folder::from_collab_doc_state(
+    uid,
+    CollabOrigin::Server,
+    folder.doc_state.to_vec(),
+    workspace_id,
+    vec![],
+  )
+  .map_err(|err| AppError::Internal(anyhow!("fail to open folder: {:?}", err)))
+}
+
+fn check_folder_view(folder: &Folder, view_id: &str) -> Result<(), AppError> {
+  match folder.views.get_view(view_id) {
+    Some(_) => Ok(()),
+    None => Err(AppError::InvalidRequest(format!(
+      "the parent view:{} doesn't exist",
+      view_id
+    ))),
+  }
+}
+
+/// Whether the image is a file next to the markdown rather than a remote or inline image.
+fn is_local_image(url: &str) -> bool {
+  !url.is_empty() && !url.contains("://") && !url.starts_with("data:")
+}
+
+fn normalize_image_path(path: &str) -> &str {
+  path.trim_start_matches("./")
+}
+
+fn image_content_type(extension: Option<&str>) -> &'static str {
+  match extension {
+    Some("png") => "image/png",
+    Some("jpg") | Some("jpeg") => "image/jpeg",
+    Some("gif") => "image/gif",
+    Some("webp") => "image/webp",
+    Some("svg") => "image/svg+xml",
+    Some("bmp") => "image/bmp",
+    _ => "application/octet-stream",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn local_image_paths() {
+    assert!(is_local_image("images/a.png"));
+    assert!(is_local_image("./a.png"));
+    assert!(!is_local_image("https://appflowy.io/a.png"));
+    assert!(!is_local_image("data:image/png;base64,AAAA"));
+    assert_eq!(normalize_image_path("./images/a.png"), "images/a.png");
+    assert_eq!(image_content_type(Some("jpeg")), "image/jpeg");
+    assert_eq!(image_content_type(None), "application/octet-stream");
+  }
+}
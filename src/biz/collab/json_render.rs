@@ -0,0 +1,136 @@
+use crate::biz::collab::database_csv::{database_view_json, read_database_view};
+use crate::biz::collab::document_export::document_block_tree;
+use crate::biz::collab::snapshot_diff::{collab_json, document_json, snapshot_encoded_collab};
+use crate::biz::collab::storage::CollabPostgresDBStorage;
+use anyhow::anyhow;
+use app_error::AppError;
+use collab::core::collab_plugin::EncodedCollab;
+use collab::core::origin::CollabOrigin;
+use collab_entity::CollabType;
+use collab_folder::Folder;
+use database::collab::CollabStorage;
+use database_entity::dto::{AFCollabJson, QueryCollabJsonParams, QueryCollabParams};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use tracing::instrument;
+
+/// The views that are nested deeper than this are not rendered, so a malformed folder can't
+/// overflow the stack.
+const MAX_VIEW_DEPTH: usize = 64;
+
+/// Return the json of the collab, or of one of its snapshots. Documents are rendered as a tree of
+/// blocks, databases as the fields and rows of their inline view and folders as a tree of views.
+/// The other collabs are rendered as the raw json of their content.
+#[instrument(level = "debug", skip(storage), err)]
+pub async fn render_collab_json(
+  storage: &CollabPostgresDBStorage,
+  uid: i64,
+  workspace_id: &str,
+  object_id: &str,
+  params: QueryCollabJsonParams,
+) -> Result<AFCollabJson, AppError> {
+  let QueryCollabJsonParams {
+    collab_type,
+    snapshot_id,
+  } = params;
+  let encoded_collab_v1 = match snapshot_id {
+    Some(snapshot_id) => {
+      snapshot_encoded_collab(storage, workspace_id, object_id, snapshot_id).await?
+    },
+    None => {
+      let query = QueryCollabParams::new(object_id, collab_type.clone(), workspace_id);
+      storage
+        .get_collab_encoded(&uid, query)
+        .await?
+        .encode_to_bytes()
+        .map_err(|err| AppError::Internal(anyhow!("fail to encode collab: {:?}", err)))?
+    },
+  };
+
+  let content = match collab_type {
+    CollabType::Database => {
+      let view = read_database_view(storage, uid, object_id, encoded_collab_v1, None).await?;
+      database_view_json(&view)
+    },
+    _ => {
+      // Decoding the collab is CPU bound
+      let collab_type = collab_type.clone();
+      let object_id = object_id.to_string();
+      let workspace_id = workspace_id.to_string();
+      tokio::task::spawn_blocking(move || match collab_type {
+        CollabType::Document => {
+          let document = document_json(&object_id, &encoded_collab_v1)?;
+          Ok(document_block_tree(&document))
+        },
+        CollabType::Folder => folder_view_tree(uid, &workspace_id, &encoded_collab_v1),
+        _ => collab_json(&object_id, &encoded_collab_v1),
+      })
+      .await??
+    },
+  };
+
+  Ok(AFCollabJson {
+    object_id: object_id.to_string(),
+    collab_type,
+    snapshot_id,
+    content,
+  })
+}
+
+/// Return the views of the folder as a tree whose roots are the views of the workspace.
+fn folder_view_tree(
+  uid: i64,
+  workspace_id: &str,
+  encoded_collab_v1: &[u8],
+) -> Result<Value, AppError> {
+  let encoded_collab = EncodedCollab::decode_from_bytes(encoded_collab_v1)
+    .map_err(|err| AppError::Internal(anyhow!("invalid encoded collab: {}", err)))?;
+  let folder = Folder::from_collab_doc_state(
+    uid,
+    CollabOrigin::Server,
+    encoded_collab.doc_state.to_vec(),
+    workspace_id,
+    vec![],
+  )
+  .map_err(|err| {
+    AppError::InvalidRequest(format!(
+      "collab:{} is not a folder: {:?}",
+      workspace_id, err
+    ))
+  })?;
+
+  let mut visited = HashSet::from([workspace_id.to_string()]);
+  Ok(json!({
+    "workspace_id": workspace_id,
+    "views": child_views_json(&folder, workspace_id, 0, &mut visited),
+  }))
+}
+
+fn child_views_json(
+  folder: &Folder,
+  parent_view_id: &str,
+  depth: usize,
+  visited: &mut HashSet<String>,
+) -> Vec<Value> {
+  if depth >= MAX_VIEW_DEPTH {
+    return vec![];
+  }
+  let mut views = vec![];
+  for view in folder.views.get_views_belong_to(parent_view_id) {
+    if !visited.insert(view.id.clone()) {
+      continue;
+    }
+    let mut view_json = match serde_json::to_value(&*view) {
+      Ok(view_json) => view_json,
+      Err(_) => continue,
+    };
+    if let Some(view_json) = view_json.as_object_mut() {
+      view_json.insert(
+        "children".to_string(),
+        Value::Array(child_views_json(folder, &view.id, depth + 1, visited)),
+      );
+    }
+    views.push(view_json);
+  }
+  views
+}
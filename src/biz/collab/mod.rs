@@ -1,4 +1,16 @@
 pub mod access_control;
+pub mod cluster;
+pub mod compaction;
+pub mod compression;
+pub mod database_csv;
+pub mod document_export;
+pub mod document_import;
+pub mod json_render;
 mod mem_cache;
 pub mod ops;
+pub mod restore;
+pub mod revoke;
+pub mod search;
+pub mod snapshot_diff;
+pub mod snapshot_retention;
 pub mod storage;
@@ -1,10 +1,12 @@
+use crate::biz::collab::storage::CollabPostgresDBStorage;
 use anyhow::Context;
 
 use std::ops::DerefMut;
 
 use app_error::AppError;
+use database::collab::CollabStorage;
 use database_entity::dto::{
-  AFCollabMember, CollabMemberIdentify, DeleteCollabParams, InsertCollabMemberParams,
+  AFCollabMember, CollabMemberIdentify, CollabParams, DeleteCollabParams, InsertCollabMemberParams,
   QueryCollabMembers, UpdateCollabMemberParams,
 };
 
@@ -12,6 +14,30 @@ use sqlx::{types::Uuid, PgPool};
 use tracing::{event, trace};
 use validator::Validate;
 
+/// Create or update the collabs in one transaction, so either all of them are saved or none.
+pub async fn batch_upsert_collabs(
+  pg_pool: &PgPool,
+  storage: &CollabPostgresDBStorage,
+  workspace_id: &str,
+  uid: &i64,
+  params_list: Vec<CollabParams>,
+) -> Result<(), AppError> {
+  let mut transaction = pg_pool
+    .begin()
+    .await
+    .context("acquire transaction to upsert collab")?;
+  for params in params_list {
+    storage
+      .upsert_collab_with_transaction(workspace_id, uid, params, &mut transaction)
+      .await?;
+  }
+  transaction
+    .commit()
+    .await
+    .context("fail to commit the transaction to upsert collab")?;
+  Ok(())
+}
+
 pub async fn delete_collab(
   pg_pool: &PgPool,
   _user_uuid: &Uuid,
@@ -19,6 +45,8 @@ pub async fn delete_collab(
 ) -> Result<(), AppError> {
   params.validate()?;
   database::collab::delete_collab(pg_pool, &params.object_id).await?;
+  database::collab::delete_collab_search_content(pg_pool, &params.object_id).await?;
+  database::collab::delete_collab_updates(pg_pool, &params.object_id).await?;
   Ok(())
 }
 
@@ -0,0 +1,301 @@
+use crate::biz::collab::storage::CollabPostgresDBStorage;
+use anyhow::anyhow;
+use app_error::AppError;
+use collab::core::collab::TransactionMutExt;
+use collab::core::collab_plugin::EncodedCollab;
+use collab::core::origin::CollabOrigin;
+use collab::core::transaction::TransactionRetry;
+use collab::preclude::Collab;
+use database::collab::{insert_snapshot_restore, CollabStorage};
+use database_entity::dto::{AFSnapshotRestore, QueryCollabParams, RestoreSnapshotParams};
+use sqlx::PgPool;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{instrument, warn};
+use uuid::Uuid;
+use yrs::types::text::YChange;
+use yrs::types::Value;
+use yrs::updates::decoder::Decode;
+use yrs::{
+  Any, Array, ArrayPrelim, ArrayRef, Doc, Map, MapPrelim, MapRef, ReadTxn, Text, TextPrelim,
+  TextRef, Transact, TransactionMut, Update,
+};
+
+/// Name of the root map that holds the content of a collab.
+pub(crate) const DATA_SECTION: &str = "data";
+
+/// Replace the content of the collab with the content of one of its snapshots.
+///
+/// The snapshot is restored as a new update on top of the current collab instead of overwriting
+/// it, otherwise the clients that hold the current collab would sync the removed content back.
+/// If the collab is opened by the realtime server, the update is applied to the opened collab, so
+/// it's broadcast to the connected clients right away.
+#[instrument(level = "debug", skip(pg_pool, storage), err)]
+pub async fn restore_collab_snapshot(
+  pg_pool: &PgPool,
+  storage: &CollabPostgresDBStorage,
+  uid: i64,
+  workspace_id: &str,
+  object_id: &str,
+  params: RestoreSnapshotParams,
+) -> Result<AFSnapshotRestore, AppError> {
+  let snapshot = storage.get_collab_snapshot(&params.snapshot_id).await?;
+  if snapshot.object_id != object_id || snapshot.workspace_id != workspace_id {
+    return Err(AppError::RecordNotFound(format!(
+      "Can't find the snapshot with id:{} of collab:{}",
+      params.snapshot_id, object_id
+    )));
+  }
+  let snapshot_collab =
+    EncodedCollab::decode_from_bytes(&snapshot.encoded_collab_v1).map_err(|err| {
+      AppError::Internal(anyhow!(
+        "fail to decode snapshot to EncodedCollab: {:?}",
+        err
+      ))
+    })?;
+
+  match storage.get_opened_collab(object_id).await {
+    Some(collab) => {
+      tokio::task::spawn_blocking(move || {
+        let mut collab = collab.lock();
+        let update = snapshot_restore_update(&collab.encode_collab_v1(), &snapshot_collab)?;
+        apply_server_update(&mut collab, &update)
+      })
+      .await??;
+    },
+    None => {
+      let query = QueryCollabParams::new(object_id, params.collab_type.clone(), workspace_id);
+      let current = storage.get_collab_encoded(&uid, query).await?;
+      let update =
+        tokio::task::spawn_blocking(move || snapshot_restore_update(&current, &snapshot_collab))
+          .await??;
+      // Going through the update log keeps the restore if the collab is opened in the meantime
+      storage
+        .append_collab_updates(workspace_id, object_id, &params.collab_type, vec![update])
+        .await?;
+      storage
+        .compact_collab_updates(object_id, &params.collab_type)
+        .await?;
+    },
+  }
+
+  let workspace_id = Uuid::from_str(workspace_id)?;
+  let restore =
+    insert_snapshot_restore(pg_pool, object_id, &workspace_id, params.snapshot_id, uid).await?;
+  Ok(restore)
+}
+
+/// Apply the update to the collab opened by the realtime server, which broadcasts it to the
+/// connected clients.
+pub(crate) fn apply_server_update(collab: &mut Collab, update: &[u8]) -> Result<(), AppError> {
+  let update = Update::decode_v1(update)
+    .map_err(|err| AppError::Internal(anyhow!("fail to decode update: {:?}", err)))?;
+  let mut retry_txn = TransactionRetry::new(collab.get_mut_awareness().doc());
+  let mut txn = retry_txn
+    .try_get_write_txn_with(CollabOrigin::Server)
+    .map_err(|err| AppError::Internal(anyhow!("fail to acquire transaction: {}", err)))?;
+  txn
+    .try_apply_update(update)
+    .map_err(|err| AppError::Internal(anyhow!("fail to apply update: {}", err)))?;
+  Ok(())
+}
+
+/// Return the update that turns the content of the `current` collab into the content of the
+/// `snapshot`. The content of the current collab is removed and the content of the snapshot is
+/// inserted again.
+pub fn snapshot_restore_update(
+  current: &EncodedCollab,
+  snapshot: &EncodedCollab,
+) -> Result<Vec<u8>, AppError> {
+  let doc = doc_from_encoded_collab(current)?;
+  let snapshot_doc = doc_from_encoded_collab(snapshot)?;
+  let data = doc.get_or_insert_map(DATA_SECTION);
+  let snapshot_data = snapshot_doc.get_or_insert_map(DATA_SECTION);
+
+  let state_vector = doc.transact().state_vector();
+  {
+    let snapshot_txn = snapshot_doc.transact();
+    let mut txn = doc.transact_mut();
+    data.clear(&mut txn);
+    copy_map(&snapshot_txn, &snapshot_data, &mut txn, &data, &unchanged);
+  }
+  let update = doc.transact().encode_state_as_update_v1(&state_vector);
+  Ok(update)
+}
+
+pub(crate) fn doc_from_encoded_collab(encoded_collab: &EncodedCollab) -> Result<Doc, AppError> {
+  let doc = Doc::new();
+  let update = Update::decode_v1(&encoded_collab.doc_state)
+    .map_err(|err| AppError::Internal(anyhow!("fail to decode doc state: {:?}", err)))?;
+  doc
+    .transact_mut()
+    .try_apply_update(update)
+    .map_err(|err| AppError::Internal(anyhow!("fail to apply doc state: {}", err)))?;
+  Ok(doc)
+}
+
+/// Copy the content of the `source` map into the `target` map. Every string of the content,
+/// including the keys of the maps, goes through `map_str`.
+pub(crate) fn copy_map<T, F>(
+  source_txn: &T,
+  source: &MapRef,
+  txn: &mut TransactionMut,
+  target: &MapRef,
+  map_str: &F,
+) where
+  T: ReadTxn,
+  F: Fn(&str) -> Cow<'_, str>,
+{
+  for (key, value) in source.iter(source_txn) {
+    let key = map_str(key).into_owned();
+    match value {
+      Value::Any(any) => {
+        target.insert(txn, key, map_any(any, map_str));
+      },
+      Value::YMap(map) => {
+        let new_map = target.insert(txn, key, MapPrelim::<Any>::from(HashMap::new()));
+        copy_map(source_txn, &map, txn, &new_map, map_str);
+      },
+      Value::YArray(array) => {
+        let new_array = target.insert(txn, key, ArrayPrelim::<Vec<Any>, Any>::from(vec![]));
+        copy_array(source_txn, &array, txn, &new_array, map_str);
+      },
+      Value::YText(text) => {
+        let new_text = target.insert(txn, key, TextPrelim::new(""));
+        copy_text(source_txn, &text, txn, &new_text, map_str);
+      },
+      _ => warn!("skip copying unsupported value of key:{}", key),
+    }
+  }
+}
+
+fn copy_array<T, F>(
+  source_txn: &T,
+  source: &ArrayRef,
+  txn: &mut TransactionMut,
+  target: &ArrayRef,
+  map_str: &F,
+) where
+  T: ReadTxn,
+  F: Fn(&str) -> Cow<'_, str>,
+{
+  for value in source.iter(source_txn) {
+    match value {
+      Value::Any(any) => {
+        target.push_back(txn, map_any(any, map_str));
+      },
+      Value::YMap(map) => {
+        let new_map = target.push_back(txn, MapPrelim::<Any>::from(HashMap::new()));
+        copy_map(source_txn, &map, txn, &new_map, map_str);
+      },
+      Value::YArray(array) => {
+        let new_array = target.push_back(txn, ArrayPrelim::<Vec<Any>, Any>::from(vec![]));
+        copy_array(source_txn, &array, txn, &new_array, map_str);
+      },
+      Value::YText(text) => {
+        let new_text = target.push_back(txn, TextPrelim::new(""));
+        copy_text(source_txn, &text, txn, &new_text, map_str);
+      },
+      _ => warn!("skip copying unsupported array item"),
+    }
+  }
+}
+
+/// Copy the text with its formatting attributes. The embeds are not used by the collabs, so they
+/// are skipped.
+fn copy_text<T, F>(
+  source_txn: &T,
+  source: &TextRef,
+  txn: &mut TransactionMut,
+  target: &TextRef,
+  map_str: &F,
+) where
+  T: ReadTxn,
+  F: Fn(&str) -> Cow<'_, str>,
+{
+  for diff in source.diff(source_txn, YChange::identity) {
+    if let Value::Any(Any::String(chunk)) = diff.insert {
+      let index = target.len(&*txn);
+      let chunk = map_str(chunk.as_ref());
+      match diff.attributes {
+        Some(attributes) => {
+          let attributes = (*attributes)
+            .into_iter()
+            .map(|(key, value)| (key, map_any(value, map_str)))
+            .collect();
+          target.insert_with_attributes(txn, index, &chunk, attributes)
+        },
+        None => target.insert(txn, index, &chunk),
+      }
+    }
+  }
+}
+
+fn unchanged(s: &str) -> Cow<'_, str> {
+  Cow::Borrowed(s)
+}
+
+fn map_any<F>(any: Any, map_str: &F) -> Any
+where
+  F: Fn(&str) -> Cow<'_, str>,
+{
+  match any {
+    Any::String(value) => match map_str(value.as_ref()) {
+      Cow::Borrowed(_) => Any::String(value.clone()),
+      Cow::Owned(mapped) => Any::String(mapped.into()),
+    },
+    Any::Array(items) => Any::Array(
+      items
+        .iter()
+        .map(|item| map_any(item.clone(), map_str))
+        .collect(),
+    ),
+    Any::Map(map) => Any::Map(Arc::new(
+      map
+        .iter()
+        .map(|(key, value)| {
+          let key = map_str(key.as_str()).into_owned();
+          (key, map_any(value.clone(), map_str))
+        })
+        .collect(),
+    )),
+    any => any,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use collab::preclude::Collab;
+  use database::collab::merge_collab_updates;
+  use serde_json::json;
+
+  #[test]
+  fn restore_snapshot_content() {
+    let collab = Collab::new(1, "object", "device", vec![]);
+    collab.with_origin_transact_mut(|txn| {
+      collab.insert_with_txn(txn, "0", "a");
+      collab.insert_with_txn(txn, "1", "b");
+    });
+    let snapshot = collab.encode_collab_v1();
+
+    collab.with_origin_transact_mut(|txn| {
+      collab.insert_with_txn(txn, "0", "changed");
+      collab.insert_with_txn(txn, "2", "c");
+    });
+    let current = collab.encode_collab_v1();
+
+    let update = snapshot_restore_update(&current, &snapshot).unwrap();
+    let restored = merge_collab_updates(&current.encode_to_bytes().unwrap(), &[update]).unwrap();
+    let restored = Collab::new_with_doc_state(
+      CollabOrigin::Empty,
+      "object",
+      restored.doc_state.to_vec(),
+      vec![],
+    )
+    .unwrap();
+    assert_eq!(restored.to_json_value(), json!({ "0": "a", "1": "b" }));
+  }
+}
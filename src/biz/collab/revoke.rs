@@ -0,0 +1,115 @@
+use crate::biz::casbin::pg_listen::{
+  CollabMemberAction, CollabMemberNotification, WorkspaceMemberAction, WorkspaceMemberNotification,
+};
+use crate::biz::pg_listener::PgListeners;
+use actix::Recipient;
+use app_error::AppError;
+use database::collab::select_member_collab_ids_of_workspace;
+use database::workspace::select_workspace_member;
+use realtime::entities::RevokeAccess;
+use sqlx::PgPool;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{trace, warn};
+
+/// Unsubscribe the users from the realtime groups of the collabs as soon as they are removed from
+/// the members of the collabs or of their workspaces.
+///
+/// The access that remains is read from the database rather than from the access control, whose
+/// cache is updated from the same notifications and may not have applied the removal yet.
+pub fn spawn_revoke_realtime_access(
+  pg_pool: PgPool,
+  pg_listeners: &PgListeners,
+  collab_server: Recipient<RevokeAccess>,
+) {
+  tokio::spawn(listen_on_collab_member_removal(
+    pg_listeners.subscribe_collab_member_change(),
+    collab_server.clone(),
+  ));
+  tokio::spawn(listen_on_workspace_member_removal(
+    pg_pool,
+    pg_listeners.subscribe_workspace_member_change(),
+    collab_server,
+  ));
+}
+
+async fn listen_on_collab_member_removal(
+  mut listener: broadcast::Receiver<CollabMemberNotification>,
+  collab_server: Recipient<RevokeAccess>,
+) {
+  loop {
+    let change = match listener.recv().await {
+      Ok(change) => change,
+      Err(RecvError::Lagged(count)) => {
+        warn!("missed {} collab member changes", count);
+        continue;
+      },
+      Err(RecvError::Closed) => break,
+    };
+    if !matches!(change.action_type, CollabMemberAction::DELETE) {
+      continue;
+    }
+    if let Some(member) = change.old {
+      trace!(
+        "revoke access of user:{} to collab:{}",
+        member.uid,
+        member.oid
+      );
+      collab_server.do_send(RevokeAccess::Collab {
+        uid: member.uid,
+        object_id: member.oid,
+      });
+    }
+  }
+}
+
+async fn listen_on_workspace_member_removal(
+  pg_pool: PgPool,
+  mut listener: broadcast::Receiver<WorkspaceMemberNotification>,
+  collab_server: Recipient<RevokeAccess>,
+) {
+  loop {
+    let change = match listener.recv().await {
+      Ok(change) => change,
+      Err(RecvError::Lagged(count)) => {
+        warn!("missed {} workspace member changes", count);
+        continue;
+      },
+      Err(RecvError::Closed) => break,
+    };
+    if !matches!(change.action_type, WorkspaceMemberAction::DELETE) {
+      continue;
+    }
+    if let Some(member) = change.old {
+      // The user may have been added back to the workspace meanwhile
+      match select_workspace_member(&pg_pool, &member.uid, &member.workspace_id).await {
+        Ok(_) => continue,
+        Err(AppError::RecordNotFound(_)) => {},
+        Err(err) => warn!(
+          "fail to check the membership of user:{} in workspace:{}: {}",
+          member.uid, member.workspace_id, err
+        ),
+      }
+      let kept_object_ids =
+        select_member_collab_ids_of_workspace(&pg_pool, member.uid, &member.workspace_id)
+          .await
+          .unwrap_or_else(|err| {
+            warn!(
+              "fail to get the collabs of user:{} in workspace:{}: {}",
+              member.uid, member.workspace_id, err
+            );
+            vec![]
+          });
+      trace!(
+        "revoke access of user:{} to workspace:{}",
+        member.uid,
+        member.workspace_id
+      );
+      collab_server.do_send(RevokeAccess::Workspace {
+        uid: member.uid,
+        workspace_id: member.workspace_id.to_string(),
+        kept_object_ids,
+      });
+    }
+  }
+}
@@ -0,0 +1,321 @@
+use app_error::AppError;
+use collab::core::collab_plugin::EncodedCollab;
+use collab::core::origin::CollabOrigin;
+use collab::preclude::Collab;
+use collab_entity::CollabType;
+use database::collab::{search_collab, upsert_collab_search_content};
+use database_entity::dto::{AFAccessLevel, AFCollabSearchResult, SearchCollabParams};
+use serde_json::Value;
+use sqlx::{PgPool, Transaction};
+use std::collections::HashSet;
+use std::ops::DerefMut;
+use std::str::FromStr;
+use tracing::{event, instrument};
+use uuid::Uuid;
+
+const DEFAULT_SEARCH_LIMIT: u32 = 20;
+const MAX_SEARCH_LIMIT: u32 = 100;
+
+/// Only the documents and the database rows hold text that is worth searching.
+pub fn is_searchable(collab_type: &CollabType) -> bool {
+  matches!(collab_type, CollabType::Document | CollabType::DatabaseRow)
+}
+
+/// Update the search content of the collab object with the text extracted from its encoded data.
+/// It's called whenever a collab is written, either through the http api or when the realtime
+/// server flushes the collab.
+///
+/// A collab whose text can't be extracted is not indexed, but it's still saved, so the error is
+/// only logged.
+pub async fn update_collab_search_content(
+  workspace_id: &str,
+  object_id: &str,
+  collab_type: &CollabType,
+  encoded_collab_v1: Vec<u8>,
+  transaction: &mut Transaction<'_, sqlx::Postgres>,
+) -> Result<(), AppError> {
+  if !is_searchable(collab_type) {
+    return Ok(());
+  }
+
+  let cloned_object_id = object_id.to_string();
+  let cloned_collab_type = collab_type.clone();
+  // Decoding the collab is CPU bound
+  let content = tokio::task::spawn_blocking(move || {
+    collab_plain_text(&cloned_object_id, &cloned_collab_type, &encoded_collab_v1)
+  })
+  .await?;
+  let content = match content {
+    Ok(content) => content,
+    Err(err) => {
+      event!(
+        tracing::Level::WARN,
+        "skip indexing collab:{}: {}",
+        object_id,
+        err
+      );
+      return Ok(());
+    },
+  };
+
+  let workspace_id = Uuid::from_str(workspace_id)?;
+  upsert_collab_search_content(
+    transaction.deref_mut(),
+    &workspace_id,
+    object_id,
+    collab_type.value(),
+    &content,
+  )
+  .await
+}
+
+#[instrument(level = "debug", skip(pg_pool), err)]
+pub async fn search_workspace_collab(
+  pg_pool: &PgPool,
+  uid: i64,
+  workspace_id: &Uuid,
+  params: SearchCollabParams,
+) -> Result<Vec<AFCollabSearchResult>, AppError> {
+  let query = params.query.trim();
+  if query.is_empty() {
+    return Err(AppError::InvalidRequest(
+      "search query must not be empty".to_string(),
+    ));
+  }
+  let limit = params
+    .limit
+    .unwrap_or(DEFAULT_SEARCH_LIMIT)
+    .clamp(1, MAX_SEARCH_LIMIT);
+
+  let rows = search_collab(
+    pg_pool,
+    workspace_id,
+    uid,
+    query,
+    AFAccessLevel::ReadOnly as i32,
+    limit as i64,
+  )
+  .await?;
+  let results = rows
+    .into_iter()
+    .map(|row| AFCollabSearchResult {
+      object_id: row.oid,
+      collab_type: if row.partition_key == CollabType::DatabaseRow.value() {
+        CollabType::DatabaseRow
+      } else {
+        CollabType::Document
+      },
+      snippet: row.snippet,
+      rank: row.rank,
+      updated_at: row.updated_at,
+    })
+    .collect();
+  Ok(results)
+}
+
+/// Extract the plain text of a document or a database row from its encoded data.
+pub fn collab_plain_text(
+  object_id: &str,
+  collab_type: &CollabType,
+  encoded_collab_v1: &[u8],
+) -> Result<String, AppError> {
+  let encoded_collab = EncodedCollab::decode_from_bytes(encoded_collab_v1)
+    .map_err(|err| AppError::InvalidRequest(format!("invalid encoded collab: {}", err)))?;
+  let collab = Collab::new_with_doc_state(
+    CollabOrigin::Empty,
+    object_id,
+    encoded_collab.doc_state.to_vec(),
+    vec![],
+  )
+  .map_err(|err| AppError::InvalidRequest(format!("invalid collab doc state: {}", err)))?;
+  let json = collab.to_json_value();
+
+  let lines = match collab_type {
+    CollabType::Document => document_text(&json),
+    CollabType::DatabaseRow => database_row_text(&json),
+    _ => vec![],
+  };
+  Ok(lines.join("\n"))
+}
+
+/// Return the text of the blocks of the document, in the order they appear in the document.
+fn document_text(json: &Value) -> Vec<String> {
+  let document = match json.get("document") {
+    Some(document) => document,
+    None => return vec![],
+  };
+  let blocks = document.get("blocks").and_then(Value::as_object);
+  let meta = document.get("meta");
+  let children_map = meta
+    .and_then(|meta| meta.get("children_map"))
+    .and_then(Value::as_object);
+  let text_map = meta
+    .and_then(|meta| meta.get("text_map"))
+    .and_then(Value::as_object);
+
+  let mut lines = vec![];
+  let (blocks, children_map, page_id) = match (
+    blocks,
+    children_map,
+    document.get("page_id").and_then(Value::as_str),
+  ) {
+    (Some(blocks), Some(children_map), Some(page_id)) => (blocks, children_map, page_id),
+    // Without the tree of blocks, the order of the text is unknown
+    _ => {
+      if let Some(text_map) = text_map {
+        lines.extend(text_map.values().filter_map(delta_text));
+      }
+      return lines;
+    },
+  };
+
+  // Depth-first traversal of the blocks. The visited set guards against a malformed tree
+  let mut visited = HashSet::new();
+  let mut stack = vec![page_id.to_string()];
+  while let Some(block_id) = stack.pop() {
+    if !visited.insert(block_id.clone()) {
+      continue;
+    }
+    let block = match blocks.get(&block_id) {
+      Some(block) => block,
+      None => continue,
+    };
+
+    let text = block
+      .get("external_id")
+      .and_then(Value::as_str)
+      .and_then(|external_id| text_map.and_then(|text_map| text_map.get(external_id)))
+      .and_then(delta_text);
+    let text = match text {
+      Some(text) => Some(text),
+      // Older documents keep the delta in the data of the block
+      None => block_data(block)
+        .and_then(|data| data.get("delta").cloned())
+        .and_then(|delta| delta_text(&delta)),
+    };
+    if let Some(text) = text.filter(|text| !text.is_empty()) {
+      lines.push(text);
+    }
+
+    let children = block
+      .get("children")
+      .and_then(Value::as_str)
+      .and_then(|children_id| children_map.get(children_id))
+      .and_then(Value::as_array);
+    if let Some(children) = children {
+      // Push in reverse order, so the first child is visited first
+      stack.extend(
+        children
+          .iter()
+          .rev()
+          .filter_map(Value::as_str)
+          .map(|child| child.to_string()),
+      );
+    }
+  }
+  lines
+}
+
+/// The data of a block is stored as a json string.
+pub(crate) fn block_data(block: &Value) -> Option<Value> {
+  match block.get("data")? {
+    Value::String(data) => serde_json::from_str(data).ok(),
+    data => Some(data.clone()),
+  }
+}
+
+/// Return the text of a delta. The delta is either the plain text itself, a json string of the
+/// operations of the delta or the operations.
+pub(crate) fn delta_text(delta: &Value) -> Option<String> {
+  match delta {
+    Value::String(text) => match serde_json::from_str::<Value>(text) {
+      Ok(ops @ Value::Array(_)) => delta_text(&ops),
+      _ => Some(text.clone()),
+    },
+    Value::Array(ops) => Some(
+      ops
+        .iter()
+        .filter_map(|op| op.get("insert").and_then(Value::as_str))
+        .collect(),
+    ),
+    _ => None,
+  }
+}
+
+/// Return the text of the cells of the database row.
+fn database_row_text(json: &Value) -> Vec<String> {
+  let cells = match find_object(json, "cells") {
+    Some(cells) => cells,
+    None => return vec![],
+  };
+  cells
+    .values()
+    .filter_map(|cell| cell.get("data").and_then(Value::as_str))
+    .filter(|text| !text.is_empty())
+    .map(|text| text.to_string())
+    .collect()
+}
+
+fn find_object<'a>(json: &'a Value, key: &str) -> Option<&'a serde_json::Map<String, Value>> {
+  let object = json.as_object()?;
+  if let Some(value) = object.get(key).and_then(Value::as_object) {
+    return Some(value);
+  }
+  object.values().find_map(|value| find_object(value, key))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn document_text_in_block_order() {
+    let json = json!({
+      "document": {
+        "page_id": "page",
+        "blocks": {
+          "page": { "id": "page", "children": "page_children", "data": "{}" },
+          "b1": { "id": "b1", "children": "b1_children", "external_id": "t1", "data": "{}" },
+          "b2": { "id": "b2", "children": "b2_children", "external_id": "t2", "data": "{}" },
+          "b3": {
+            "id": "b3",
+            "children": "b3_children",
+            "data": "{\"delta\":[{\"insert\":\"third \"},{\"insert\":\"block\"}]}"
+          }
+        },
+        "meta": {
+          "children_map": {
+            "page_children": ["b1", "b3"],
+            "b1_children": ["b2"],
+            "b2_children": [],
+            "b3_children": []
+          },
+          "text_map": {
+            "t1": "[{\"insert\":\"first\"}]",
+            "t2": "nested block"
+          }
+        }
+      }
+    });
+    assert_eq!(
+      document_text(&json),
+      vec!["first", "nested block", "third block"]
+    );
+  }
+
+  #[test]
+  fn database_row_text_from_cells() {
+    let json = json!({
+      "data": {
+        "id": "row",
+        "cells": {
+          "f1": { "data": "hello", "field_type": 0 },
+          "f2": { "data": "", "field_type": 0 },
+          "f3": { "field_type": 5 }
+        }
+      }
+    });
+    assert_eq!(database_row_text(&json), vec!["hello"]);
+  }
+}
@@ -0,0 +1,472 @@
+use crate::biz::collab::search::{block_data, delta_text};
+use crate::biz::collab::storage::CollabPostgresDBStorage;
+use anyhow::anyhow;
+use app_error::AppError;
+use collab::core::collab_plugin::EncodedCollab;
+use collab::core::origin::CollabOrigin;
+use collab::preclude::Collab;
+use collab_entity::CollabType;
+use database::collab::CollabStorage;
+use database_entity::dto::{
+  AFBlockDiff, AFDocumentDiff, BlockChangeType, QueryCollabParams, QuerySnapshotDiffParams,
+  TextDeltaOp,
+};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use tracing::instrument;
+
+/// Above this number of compared characters, the changed part of a text is replaced as a whole
+/// instead of being diffed character by character.
+const MAX_TEXT_DIFF_CELLS: usize = 1_000_000;
+
+/// Return the blocks of the document that changed between the `from` snapshot and the `to`
+/// snapshot, or the current state of the document if there's no `to` snapshot.
+#[instrument(level = "debug", skip(storage), err)]
+pub async fn diff_document_snapshots(
+  storage: &CollabPostgresDBStorage,
+  uid: i64,
+  workspace_id: &str,
+  object_id: &str,
+  params: QuerySnapshotDiffParams,
+) -> Result<AFDocumentDiff, AppError> {
+  let from =
+    snapshot_encoded_collab(storage, workspace_id, object_id, params.from_snapshot_id).await?;
+  let to = match params.to_snapshot_id {
+    Some(snapshot_id) => {
+      snapshot_encoded_collab(storage, workspace_id, object_id, snapshot_id).await?
+    },
+    None => {
+      let query = QueryCollabParams::new(object_id, CollabType::Document, workspace_id);
+      storage
+        .get_collab_encoded(&uid, query)
+        .await?
+        .encode_to_bytes()
+        .map_err(|err| AppError::Internal(anyhow!("fail to encode collab: {:?}", err)))?
+    },
+  };
+
+  // Decoding the collabs is CPU bound
+  let cloned_object_id = object_id.to_string();
+  let blocks = tokio::task::spawn_blocking(move || {
+    let from = document_json(&cloned_object_id, &from)?;
+    let to = document_json(&cloned_object_id, &to)?;
+    Ok::<_, AppError>(document_diff(&from, &to))
+  })
+  .await??;
+
+  Ok(AFDocumentDiff {
+    from_snapshot_id: params.from_snapshot_id,
+    to_snapshot_id: params.to_snapshot_id,
+    blocks,
+  })
+}
+
+pub(crate) async fn snapshot_encoded_collab(
+  storage: &CollabPostgresDBStorage,
+  workspace_id: &str,
+  object_id: &str,
+  snapshot_id: i64,
+) -> Result<Vec<u8>, AppError> {
+  let snapshot = storage.get_collab_snapshot(&snapshot_id).await?;
+  if snapshot.object_id != object_id || snapshot.workspace_id != workspace_id {
+    return Err(AppError::RecordNotFound(format!(
+      "Can't find the snapshot with id:{} of collab:{}",
+      snapshot_id, object_id
+    )));
+  }
+  Ok(snapshot.encoded_collab_v1)
+}
+
+/// Return the json of the content of the encoded collab.
+pub(crate) fn collab_json(object_id: &str, encoded_collab_v1: &[u8]) -> Result<Value, AppError> {
+  let encoded_collab = EncodedCollab::decode_from_bytes(encoded_collab_v1)
+    .map_err(|err| AppError::Internal(anyhow!("invalid encoded collab: {}", err)))?;
+  let collab = Collab::new_with_doc_state(
+    CollabOrigin::Empty,
+    object_id,
+    encoded_collab.doc_state.to_vec(),
+    vec![],
+  )
+  .map_err(|err| AppError::Internal(anyhow!("invalid collab doc state: {}", err)))?;
+  Ok(collab.to_json_value())
+}
+
+/// Return the json of the document stored in the encoded collab.
+pub(crate) fn document_json(object_id: &str, encoded_collab_v1: &[u8]) -> Result<Value, AppError> {
+  let mut json = collab_json(object_id, encoded_collab_v1)?;
+  match json.get_mut("document").map(Value::take) {
+    Some(document) if document.is_object() => Ok(document),
+    _ => Err(AppError::InvalidRequest(format!(
+      "collab:{} is not a document",
+      object_id
+    ))),
+  }
+}
+
+struct DocumentBlock {
+  id: String,
+  ty: String,
+  parent: String,
+  text: String,
+  /// The delta of the text, which also holds the formatting of the text
+  delta: Option<Value>,
+  /// The data of the block without its delta
+  data: Value,
+}
+
+/// Return the blocks of the document, in the order they appear in the document.
+fn document_blocks(document: &Value) -> Vec<DocumentBlock> {
+  let blocks = document.get("blocks").and_then(Value::as_object);
+  let meta = document.get("meta");
+  let children_map = meta
+    .and_then(|meta| meta.get("children_map"))
+    .and_then(Value::as_object);
+  let text_map = meta
+    .and_then(|meta| meta.get("text_map"))
+    .and_then(Value::as_object);
+  let (blocks, children_map, page_id) = match (
+    blocks,
+    children_map,
+    document.get("page_id").and_then(Value::as_str),
+  ) {
+    (Some(blocks), Some(children_map), Some(page_id)) => (blocks, children_map, page_id),
+    _ => return vec![],
+  };
+
+  // Depth-first traversal of the blocks, like the text extraction of the search
+  let mut result = vec![];
+  let mut visited = HashSet::new();
+  let mut stack = vec![page_id.to_string()];
+  while let Some(block_id) = stack.pop() {
+    if !visited.insert(block_id.clone()) {
+      continue;
+    }
+    let block = match blocks.get(&block_id) {
+      Some(block) => block,
+      None => continue,
+    };
+
+    let mut data = block_data(block).unwrap_or(Value::Null);
+    let data_delta = data.as_object_mut().and_then(|data| data.remove("delta"));
+    let delta = block
+      .get("external_id")
+      .and_then(Value::as_str)
+      .and_then(|external_id| text_map.and_then(|text_map| text_map.get(external_id)))
+      .cloned()
+      .or(data_delta);
+    let text = delta.as_ref().and_then(delta_text).unwrap_or_default();
+    result.push(DocumentBlock {
+      id: block_id.clone(),
+      ty: string_field(block, "ty"),
+      parent: string_field(block, "parent"),
+      text,
+      delta,
+      data,
+    });
+
+    let children = block
+      .get("children")
+      .and_then(Value::as_str)
+      .and_then(|children_id| children_map.get(children_id))
+      .and_then(Value::as_array);
+    if let Some(children) = children {
+      stack.extend(
+        children
+          .iter()
+          .rev()
+          .filter_map(Value::as_str)
+          .map(|child| child.to_string()),
+      );
+    }
+  }
+  result
+}
+
+fn string_field(block: &Value, key: &str) -> String {
+  block
+    .get(key)
+    .and_then(Value::as_str)
+    .unwrap_or_default()
+    .to_string()
+}
+
+/// Return the blocks that were added, removed or changed between the two versions of the document.
+pub fn document_diff(from: &Value, to: &Value) -> Vec<AFBlockDiff> {
+  let from_blocks = document_blocks(from);
+  let to_blocks = document_blocks(to);
+  let from_by_id = from_blocks
+    .iter()
+    .map(|block| (block.id.as_str(), block))
+    .collect::<HashMap<_, _>>();
+  let to_ids = to_blocks
+    .iter()
+    .map(|block| block.id.as_str())
+    .collect::<HashSet<_>>();
+
+  let mut diffs = vec![];
+  for block in &to_blocks {
+    let diff = match from_by_id.get(block.id.as_str()) {
+      None => AFBlockDiff {
+        block_id: block.id.clone(),
+        block_type: block.ty.clone(),
+        change_type: BlockChangeType::Added,
+        text_delta: text_delta("", &block.text),
+        data_changed: false,
+        moved: false,
+      },
+      Some(old) => {
+        let text_changed = old.delta != block.delta;
+        let data_changed = old.ty != block.ty || old.data != block.data;
+        let moved = old.parent != block.parent;
+        if !text_changed && !data_changed && !moved {
+          continue;
+        }
+        AFBlockDiff {
+          block_id: block.id.clone(),
+          block_type: block.ty.clone(),
+          change_type: BlockChangeType::Changed,
+          text_delta: text_delta(&old.text, &block.text),
+          data_changed,
+          moved,
+        }
+      },
+    };
+    diffs.push(diff);
+  }
+
+  diffs.extend(
+    from_blocks
+      .iter()
+      .filter(|block| !to_ids.contains(block.id.as_str()))
+      .map(|block| AFBlockDiff {
+        block_id: block.id.clone(),
+        block_type: block.ty.clone(),
+        change_type: BlockChangeType::Removed,
+        text_delta: text_delta(&block.text, ""),
+        data_changed: false,
+        moved: false,
+      }),
+  );
+  diffs
+}
+
+/// Return the delta that turns `old` into `new`. The trailing retain is omitted, like in the
+/// deltas of the collabs.
+pub fn text_delta(old: &str, new: &str) -> Vec<TextDeltaOp> {
+  let old = old.chars().collect::<Vec<_>>();
+  let new = new.chars().collect::<Vec<_>>();
+  let prefix = old
+    .iter()
+    .zip(new.iter())
+    .take_while(|(a, b)| a == b)
+    .count();
+  let suffix = old[prefix..]
+    .iter()
+    .rev()
+    .zip(new[prefix..].iter().rev())
+    .take_while(|(a, b)| a == b)
+    .count();
+  let old_middle = &old[prefix..old.len() - suffix];
+  let new_middle = &new[prefix..new.len() - suffix];
+
+  let mut ops = DeltaBuilder::default();
+  ops.retain(&old[..prefix]);
+  if old_middle.len().saturating_mul(new_middle.len()) > MAX_TEXT_DIFF_CELLS {
+    ops.delete(old_middle);
+    ops.insert(new_middle);
+  } else {
+    diff_chars(old_middle, new_middle, &mut ops);
+  }
+  ops.finish()
+}
+
+/// Diff the characters with their longest common subsequence.
+fn diff_chars(old: &[char], new: &[char], ops: &mut DeltaBuilder) {
+  // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+  let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+  for i in (0..old.len()).rev() {
+    for j in (0..new.len()).rev() {
+      lcs[i][j] = if old[i] == new[j] {
+        lcs[i + 1][j + 1] + 1
+      } else {
+        lcs[i + 1][j].max(lcs[i][j + 1])
+      };
+    }
+  }
+
+  let (mut i, mut j) = (0, 0);
+  while i < old.len() && j < new.len() {
+    if old[i] == new[j] {
+      ops.retain(&old[i..i + 1]);
+      i += 1;
+      j += 1;
+    } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+      ops.delete(&old[i..i + 1]);
+      i += 1;
+    } else {
+      ops.insert(&new[j..j + 1]);
+      j += 1;
+    }
+  }
+  ops.delete(&old[i..]);
+  ops.insert(&new[j..]);
+}
+
+/// Build a delta, merging the consecutive operations of the same kind.
+#[derive(Default)]
+struct DeltaBuilder {
+  ops: Vec<TextDeltaOp>,
+}
+
+impl DeltaBuilder {
+  fn retain(&mut self, chars: &[char]) {
+    let len = utf16_len(chars);
+    if len == 0 {
+      return;
+    }
+    match self.ops.last_mut() {
+      Some(TextDeltaOp::Retain { retain }) => *retain += len,
+      _ => self.ops.push(TextDeltaOp::Retain { retain: len }),
+    }
+  }
+
+  fn delete(&mut self, chars: &[char]) {
+    let len = utf16_len(chars);
+    if len == 0 {
+      return;
+    }
+    match self.ops.last_mut() {
+      Some(TextDeltaOp::Delete { delete }) => *delete += len,
+      _ => self.ops.push(TextDeltaOp::Delete { delete: len }),
+    }
+  }
+
+  fn insert(&mut self, chars: &[char]) {
+    if chars.is_empty() {
+      return;
+    }
+    match self.ops.last_mut() {
+      Some(TextDeltaOp::Insert { insert }) => insert.extend(chars),
+      _ => self.ops.push(TextDeltaOp::Insert {
+        insert: chars.iter().collect(),
+      }),
+    }
+  }
+
+  fn finish(mut self) -> Vec<TextDeltaOp> {
+    if matches!(self.ops.last(), Some(TextDeltaOp::Retain { .. })) {
+      self.ops.pop();
+    }
+    self.ops
+  }
+}
+
+fn utf16_len(chars: &[char]) -> usize {
+  chars.iter().map(|c| c.len_utf16()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn document(blocks: Value, children_map: Value, text_map: Value) -> Value {
+    json!({
+      "page_id": "page",
+      "blocks": blocks,
+      "meta": { "children_map": children_map, "text_map": text_map }
+    })
+  }
+
+  #[test]
+  fn text_delta_of_changed_text() {
+    assert_eq!(text_delta("hello", "hello"), vec![]);
+    assert_eq!(
+      text_delta("hello world", "hello brave world!"),
+      vec![
+        TextDeltaOp::Retain { retain: 6 },
+        TextDeltaOp::Insert {
+          insert: "brave ".to_string()
+        },
+        TextDeltaOp::Retain { retain: 5 },
+        TextDeltaOp::Insert {
+          insert: "!".to_string()
+        },
+      ]
+    );
+    assert_eq!(
+      text_delta("a😀b", "ab"),
+      vec![
+        TextDeltaOp::Retain { retain: 1 },
+        TextDeltaOp::Delete { delete: 2 },
+      ]
+    );
+  }
+
+  #[test]
+  fn diff_added_removed_and_changed_blocks() {
+    let from = document(
+      json!({
+        "page": { "id": "page", "ty": "page", "parent": "", "children": "c_page", "data": "{}" },
+        "b1": { "id": "b1", "ty": "paragraph", "parent": "page", "children": "c_b1", "external_id": "t1", "data": "{}" },
+        "b2": { "id": "b2", "ty": "heading", "parent": "page", "children": "c_b2", "external_id": "t2", "data": "{\"level\":1}" },
+        "b3": { "id": "b3", "ty": "paragraph", "parent": "page", "children": "c_b3", "external_id": "t3", "data": "{}" }
+      }),
+      json!({ "c_page": ["b1", "b2", "b3"], "c_b1": [], "c_b2": [], "c_b3": [] }),
+      json!({
+        "t1": "[{\"insert\":\"unchanged\"}]",
+        "t2": "[{\"insert\":\"title\"}]",
+        "t3": "[{\"insert\":\"removed\"}]"
+      }),
+    );
+    let to = document(
+      json!({
+        "page": { "id": "page", "ty": "page", "parent": "", "children": "c_page", "data": "{}" },
+        "b1": { "id": "b1", "ty": "paragraph", "parent": "page", "children": "c_b1", "external_id": "t1", "data": "{}" },
+        "b2": { "id": "b2", "ty": "heading", "parent": "page", "children": "c_b2", "external_id": "t2", "data": "{\"level\":2}" },
+        "b4": { "id": "b4", "ty": "paragraph", "parent": "page", "children": "c_b4", "external_id": "t4", "data": "{}" }
+      }),
+      json!({ "c_page": ["b1", "b2", "b4"], "c_b1": [], "c_b2": [], "c_b4": [] }),
+      json!({
+        "t1": "[{\"insert\":\"unchanged\"}]",
+        "t2": "[{\"insert\":\"new title\"}]",
+        "t4": "[{\"insert\":\"added\"}]"
+      }),
+    );
+
+    let diffs = document_diff(&from, &to);
+    assert_eq!(
+      diffs,
+      vec![
+        AFBlockDiff {
+          block_id: "b2".to_string(),
+          block_type: "heading".to_string(),
+          change_type: BlockChangeType::Changed,
+          text_delta: vec![TextDeltaOp::Insert {
+            insert: "new ".to_string()
+          }],
+          data_changed: true,
+          moved: false,
+        },
+        AFBlockDiff {
+          block_id: "b4".to_string(),
+          block_type: "paragraph".to_string(),
+          change_type: BlockChangeType::Added,
+          text_delta: vec![TextDeltaOp::Insert {
+            insert: "added".to_string()
+          }],
+          data_changed: false,
+          moved: false,
+        },
+        AFBlockDiff {
+          block_id: "b3".to_string(),
+          block_type: "paragraph".to_string(),
+          change_type: BlockChangeType::Removed,
+          text_delta: vec![TextDeltaOp::Delete { delete: 7 }],
+          data_changed: false,
+          moved: false,
+        },
+      ]
+    );
+  }
+}
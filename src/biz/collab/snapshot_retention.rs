@@ -0,0 +1,145 @@
+use crate::config::config::CollabSetting;
+use app_error::AppError;
+use collab_entity::CollabType;
+use database::collab::{
+  delete_snapshot_retention_policy, prune_collab_snapshots, select_collabs_with_snapshots,
+  select_snapshot_retention_policies, update_snapshot_pin, upsert_snapshot_retention_policy,
+};
+use database_entity::dto::{AFSnapshotMeta, AFSnapshotRetentionPolicy, PinSnapshotParams};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+use tracing::{error, info, instrument};
+use uuid::Uuid;
+
+/// Number of collabs whose snapshots are pruned at a time by the periodic pruning.
+const PRUNE_BATCH_SIZE: i64 = 200;
+const MAX_SNAPSHOT_NAME_LEN: usize = 256;
+
+#[instrument(level = "debug", skip(pg_pool), err)]
+pub async fn pin_collab_snapshot(
+  pg_pool: &PgPool,
+  object_id: &str,
+  snapshot_id: i64,
+  params: PinSnapshotParams,
+) -> Result<AFSnapshotMeta, AppError> {
+  let name = params
+    .name
+    .as_deref()
+    .map(str::trim)
+    .filter(|name| !name.is_empty());
+  if name.unwrap_or_default().len() > MAX_SNAPSHOT_NAME_LEN {
+    return Err(AppError::InvalidRequest(format!(
+      "snapshot name must not be longer than {} bytes",
+      MAX_SNAPSHOT_NAME_LEN
+    )));
+  }
+
+  update_snapshot_pin(pg_pool, object_id, snapshot_id, params.pinned, name)
+    .await?
+    .ok_or_else(|| {
+      AppError::RecordNotFound(format!(
+        "Can't find the snapshot with id:{} of collab:{}",
+        snapshot_id, object_id
+      ))
+    })
+}
+
+pub async fn get_snapshot_retention_policies(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+) -> Result<Vec<AFSnapshotRetentionPolicy>, AppError> {
+  let policies = select_snapshot_retention_policies(pg_pool, workspace_id).await?;
+  Ok(policies)
+}
+
+#[instrument(level = "debug", skip(pg_pool), err)]
+pub async fn set_snapshot_retention_policy(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+  params: AFSnapshotRetentionPolicy,
+) -> Result<(), AppError> {
+  let policy = &params.policy;
+  // Otherwise a collab could lose all its snapshots
+  if policy.keep_latest == 0 {
+    return Err(AppError::InvalidRequest(
+      "the retention policy must keep at least the latest snapshot".to_string(),
+    ));
+  }
+  let values = [
+    Some(policy.keep_latest),
+    Some(policy.hourly_for_hours),
+    Some(policy.daily_for_days),
+    policy.weekly_for_weeks,
+  ];
+  if values
+    .into_iter()
+    .flatten()
+    .any(|value| value > i32::MAX as u32)
+  {
+    return Err(AppError::InvalidRequest(
+      "the values of the retention policy are too large".to_string(),
+    ));
+  }
+
+  upsert_snapshot_retention_policy(pg_pool, workspace_id, params.collab_type.as_ref(), policy)
+    .await?;
+  Ok(())
+}
+
+pub async fn remove_snapshot_retention_policy(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+  collab_type: Option<&CollabType>,
+) -> Result<(), AppError> {
+  delete_snapshot_retention_policy(pg_pool, workspace_id, collab_type).await?;
+  Ok(())
+}
+
+/// Periodically prune the snapshots of all the collabs. The snapshots of a collab are already
+/// pruned when a snapshot is created, but the snapshots that move out of the hourly or daily
+/// windows of the policy, and the policies that are changed, are only applied here.
+pub fn spawn_snapshot_pruner(pg_pool: PgPool, setting: CollabSetting) {
+  if setting.snapshot_prune_interval_secs == 0 {
+    info!("snapshot pruning is disabled");
+    return;
+  }
+
+  tokio::spawn(async move {
+    let period = Duration::from_secs(setting.snapshot_prune_interval_secs);
+    let mut interval = interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+      interval.tick().await;
+      let mut after_object_id = String::new();
+      let mut pruned = 0;
+      loop {
+        let object_ids =
+          match select_collabs_with_snapshots(&pg_pool, &after_object_id, PRUNE_BATCH_SIZE).await {
+            Ok(object_ids) => object_ids,
+            Err(err) => {
+              error!("[snapshot pruner] failed to select the collabs: {}", err);
+              break;
+            },
+          };
+        let last_object_id = match object_ids.last() {
+          Some(object_id) => object_id.clone(),
+          None => break,
+        };
+        for object_id in object_ids {
+          match prune_collab_snapshots(&pg_pool, &object_id).await {
+            Ok(count) => pruned += count,
+            Err(err) => error!(
+              "[snapshot pruner] failed to prune the snapshots of collab:{}: {}",
+              object_id, err
+            ),
+          }
+        }
+        after_object_id = last_object_id;
+      }
+      if pruned > 0 {
+        info!("[snapshot pruner] removed {} snapshots", pruned);
+      }
+    }
+  });
+}
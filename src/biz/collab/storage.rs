@@ -1,9 +1,10 @@
 use async_trait::async_trait;
 use collab::core::collab::MutexCollab;
 use database::collab::{
-  is_collab_exists, CollabStorage, CollabStorageAccessControl, CollabStoragePgImpl, DatabaseResult,
-  WriteConfig,
+  has_collab_updates, is_collab_exists, CollabCodec, CollabStorage, CollabStorageAccessControl,
+  CollabStoragePgImpl, DatabaseResult, WriteConfig,
 };
+use database::resource_usage::get_workspace_quota;
 use database_entity::dto::{
   AFAccessLevel, AFSnapshotMeta, AFSnapshotMetas, CollabParams, CreateCollabParams,
   InsertSnapshotParams, QueryCollab, QueryCollabParams, QueryCollabResult, SnapshotData,
@@ -13,18 +14,22 @@ use itertools::{Either, Itertools};
 use crate::biz::casbin::{CollabAccessControlImpl, WorkspaceAccessControlImpl};
 use crate::biz::collab::access_control::CollabStorageAccessControlImpl;
 use crate::biz::collab::mem_cache::CollabMemCache;
+use crate::biz::collab::search::update_collab_search_content;
 use crate::state::RedisClient;
 use anyhow::Context;
 use app_error::AppError;
 use collab::core::collab_plugin::EncodedCollab;
+use collab_entity::CollabType;
 use sqlx::{PgPool, Transaction};
 use std::ops::DerefMut;
+use std::str::FromStr;
 use std::{
   collections::HashMap,
   sync::{Arc, Weak},
 };
 use tokio::sync::RwLock;
 use tracing::{event, instrument};
+use uuid::Uuid;
 use validator::Validate;
 
 pub type CollabPostgresDBStorage = CollabStorageController<
@@ -36,12 +41,14 @@ pub async fn init_collab_storage(
   redis_client: RedisClient,
   collab_access_control: CollabAccessControlImpl,
   workspace_access_control: WorkspaceAccessControlImpl,
+  codec: CollabCodec,
+  write_config: WriteConfig,
 ) -> CollabPostgresDBStorage {
   let access_control = CollabStorageAccessControlImpl {
     collab_access_control: collab_access_control.into(),
     workspace_access_control: workspace_access_control.into(),
   };
-  let disk_cache = CollabStoragePgImpl::new(pg_pool);
+  let disk_cache = CollabStoragePgImpl::new(pg_pool, codec, write_config);
   let mem_cache = CollabMemCache::new(redis_client);
   CollabStorageController::new(disk_cache, mem_cache, access_control)
 }
@@ -74,6 +81,29 @@ where
     }
   }
 
+  /// Return the latest state of the collab without checking the access level of the user, for
+  /// the jobs that already checked the role of the user in the workspace.
+  pub async fn get_latest_collab_encoded(
+    &self,
+    uid: &i64,
+    params: QueryCollabParams,
+  ) -> Result<EncodedCollab, AppError> {
+    if let Some(collab) = self.get_opened_collab(&params.object_id).await {
+      return Ok(collab.encode_collab_v1());
+    }
+    self.disk_cache.get_collab_encoded(uid, params).await
+  }
+
+  /// Return the collab if it's opened by the realtime server.
+  pub async fn get_opened_collab(&self, object_id: &str) -> Option<Arc<MutexCollab>> {
+    self
+      .opened_collab_by_object_id
+      .read()
+      .await
+      .get(object_id)
+      .and_then(|collab| collab.upgrade())
+  }
+
   async fn check_collab_permission(
     &self,
     workspace_id: &str,
@@ -129,6 +159,25 @@ where
   }
 }
 
+/// Reject the collab if its encoded size exceeds the collab size limit of the workspace quota.
+async fn check_collab_size_limit(
+  workspace_id: &str,
+  params: &CollabParams,
+  transaction: &mut Transaction<'_, sqlx::Postgres>,
+) -> Result<(), AppError> {
+  let workspace_id = Uuid::from_str(workspace_id)?;
+  let quota = get_workspace_quota(transaction.deref_mut(), &workspace_id).await?;
+  if params.encoded_collab_v1.len() as i64 > quota.collab_size_limit {
+    return Err(AppError::PayloadTooLarge(format!(
+      "collab:{} with size:{} exceeds the limit of {} bytes",
+      params.object_id,
+      params.encoded_collab_v1.len(),
+      quota.collab_size_limit
+    )));
+  }
+  Ok(())
+}
+
 #[async_trait]
 impl<AC> CollabStorage for CollabStorageController<AC>
 where
@@ -194,12 +243,22 @@ where
     self
       .check_collab_permission(workspace_id, uid, &params, transaction)
       .await?;
+    check_collab_size_limit(workspace_id, &params, transaction).await?;
     let object_id = params.object_id.clone();
+    let collab_type = params.collab_type.clone();
     let encoded_collab = params.encoded_collab_v1.clone();
     self
       .disk_cache
       .upsert_collab_with_transaction(workspace_id, uid, params, transaction)
       .await?;
+    update_collab_search_content(
+      workspace_id,
+      &object_id,
+      &collab_type,
+      encoded_collab.clone(),
+      transaction,
+    )
+    .await?;
 
     self
       .mem_cache
@@ -236,30 +295,41 @@ where
       return Ok(data);
     }
 
-    // Attempt to retrieve from memory cache if not found then try
-    // to retrieve from disk cache
-    match self
-      .mem_cache
-      .get_encoded_collab(&params.inner.object_id)
-      .await
-    {
-      Some(encoded_collab) => {
+    // The cached collab doesn't include the updates in the update log of the collab, which the
+    // realtime server of any node may append to, so it's only used while the log is empty
+    let has_updates = has_collab_updates(
+      &self.disk_cache.pg_pool,
+      &params.object_id,
+      &params.collab_type,
+    )
+    .await?;
+    if !has_updates {
+      // Attempt to retrieve from memory cache if not found then try
+      // to retrieve from disk cache
+      if let Some(encoded_collab) = self
+        .mem_cache
+        .get_encoded_collab(&params.inner.object_id)
+        .await
+      {
         event!(
           tracing::Level::DEBUG,
           "Get encoded collab:{} from redis",
           params.object_id
         );
-        Ok(encoded_collab)
-      },
-      None => {
-        // Fallback to disk cache if not in memory cache
-        let encoded_collab = self.disk_cache.get_collab_encoded(uid, params).await?;
-        self
-          .mem_cache
-          .cache_encoded_collab(object_id, &encoded_collab);
-        Ok(encoded_collab)
-      },
+        return Ok(encoded_collab);
+      }
     }
+
+    // Fallback to disk cache if not in memory cache
+    let encoded_collab = self.disk_cache.get_collab_encoded(uid, params).await?;
+    if has_updates {
+      self.mem_cache.remove_encoded_collab(&object_id).await;
+    } else {
+      self
+        .mem_cache
+        .cache_encoded_collab(object_id, &encoded_collab);
+    }
+    Ok(encoded_collab)
   }
 
   async fn batch_get_collab(
@@ -305,6 +375,60 @@ where
     results
   }
 
+  async fn append_collab_updates(
+    &self,
+    workspace_id: &str,
+    object_id: &str,
+    collab_type: &CollabType,
+    updates: Vec<Vec<u8>>,
+  ) -> DatabaseResult<()> {
+    self
+      .disk_cache
+      .append_collab_updates(workspace_id, object_id, collab_type, updates)
+      .await?;
+    // The cached collab doesn't include the appended updates
+    self.mem_cache.remove_encoded_collab(object_id).await;
+    Ok(())
+  }
+
+  #[instrument(level = "debug", skip(self), err)]
+  async fn compact_collab_updates(
+    &self,
+    object_id: &str,
+    collab_type: &CollabType,
+  ) -> DatabaseResult<()> {
+    let mut transaction = self
+      .disk_cache
+      .pg_pool
+      .begin()
+      .await
+      .context("acquire transaction to compact collab updates")
+      .map_err(AppError::from)?;
+    let compacted = self
+      .disk_cache
+      .compact_collab_updates_with_transaction(object_id, collab_type, &mut transaction)
+      .await?;
+    if let Some(compacted) = compacted {
+      update_collab_search_content(
+        &compacted.workspace_id.to_string(),
+        object_id,
+        collab_type,
+        compacted.encoded_collab_v1,
+        &mut transaction,
+      )
+      .await?;
+    }
+    transaction
+      .commit()
+      .await
+      .context("fail to commit the transaction to compact collab updates")
+      .map_err(AppError::from)?;
+
+    // The cached collab doesn't include the updates that were just compacted
+    self.mem_cache.remove_encoded_collab(object_id).await;
+    Ok(())
+  }
+
   async fn delete_collab(&self, uid: &i64, object_id: &str) -> DatabaseResult<()> {
     if !self
       .access_control
@@ -1,3 +1,5 @@
+pub mod blob_gc;
+pub mod blob_link;
 pub mod casbin;
 pub mod collab;
 pub mod pg_listener;
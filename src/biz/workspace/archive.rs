@@ -0,0 +1,331 @@
+use app_error::AppError;
+use chrono::{DateTime, Utc};
+use collab_entity::CollabType;
+use database_entity::dto::AFRole;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use uuid::Uuid;
+
+/// The first bytes of a workspace archive.
+const ARCHIVE_MAGIC: &[u8; 8] = b"AFWSARCH";
+/// Version of the archive format. The archives of a newer version can't be imported.
+pub const ARCHIVE_VERSION: u16 = 1;
+const MANIFEST_ENTRY: &str = "manifest.json";
+const COLLAB_ENTRY_PREFIX: &str = "collab/";
+const BLOB_ENTRY_PREFIX: &str = "blob/";
+const MAX_ENTRY_NAME_LEN: u32 = 1024;
+/// Maximum size of the decompressed entries of an archive, so a small archive can't exhaust the
+/// memory of the server.
+pub const MAX_ARCHIVE_CONTENT_SIZE: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+  pub version: u16,
+  pub workspace_id: Uuid,
+  pub workspace_name: String,
+  /// The object id of the workspace database collab
+  pub database_storage_id: Uuid,
+  pub exported_at: DateTime<Utc>,
+  pub members: Vec<ArchiveMember>,
+  pub collabs: Vec<ArchiveCollab>,
+  pub blobs: Vec<ArchiveBlob>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveMember {
+  pub email: String,
+  pub role: AFRole,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveCollab {
+  pub object_id: String,
+  pub collab_type: CollabType,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveBlob {
+  pub file_id: String,
+  pub file_type: String,
+  /// The collab object that owns the blob, if any
+  pub object_id: Option<String>,
+}
+
+/// The content of a workspace archive. The collabs are encoded with
+/// [collab::core::collab_plugin::EncodedCollab::encode_to_bytes] and keyed by object id, the blobs
+/// are keyed by file id.
+pub struct WorkspaceArchive {
+  pub manifest: ArchiveManifest,
+  pub collabs: HashMap<String, Vec<u8>>,
+  pub blobs: HashMap<String, Vec<u8>>,
+}
+
+/// Writes a workspace archive entry by entry, so the content of the workspace doesn't have to be
+/// held in memory while the archive is built.
+///
+/// The archive starts with [ARCHIVE_MAGIC] and the version of the format, followed by the zstd
+/// compressed entries. Each entry is the length of its name (u32), the name, the length of its
+/// data (u64) and the data, in big endian. The manifest is the first entry.
+pub struct ArchiveWriter<W: Write> {
+  encoder: zstd::Encoder<'static, W>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+  pub fn new(mut writer: W, manifest: &ArchiveManifest) -> Result<Self, AppError> {
+    writer.write_all(ARCHIVE_MAGIC)?;
+    writer.write_all(&manifest.version.to_be_bytes())?;
+    let mut encoder = zstd::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+    let manifest = serde_json::to_vec(manifest)?;
+    write_entry(&mut encoder, MANIFEST_ENTRY, &manifest)?;
+    Ok(Self { encoder })
+  }
+
+  pub fn write_collab(&mut self, object_id: &str, data: &[u8]) -> Result<(), AppError> {
+    let name = format!("{}{}", COLLAB_ENTRY_PREFIX, object_id);
+    write_entry(&mut self.encoder, &name, data)?;
+    Ok(())
+  }
+
+  pub fn write_blob(&mut self, file_id: &str, data: &[u8]) -> Result<(), AppError> {
+    let name = format!("{}{}", BLOB_ENTRY_PREFIX, file_id);
+    write_entry(&mut self.encoder, &name, data)?;
+    Ok(())
+  }
+
+  /// The underlying writer, which holds the part of the archive that was compressed so far.
+  pub fn get_mut(&mut self) -> &mut W {
+    self.encoder.get_mut()
+  }
+
+  pub fn finish(self) -> Result<W, AppError> {
+    let writer = self.encoder.finish()?;
+    Ok(writer)
+  }
+}
+
+/// Decode the archive and check that it holds the data of every collab and blob of its manifest.
+pub fn decode_archive(data: &[u8]) -> Result<WorkspaceArchive, AppError> {
+  let header_len = ARCHIVE_MAGIC.len() + 2;
+  if data.len() < header_len || &data[..ARCHIVE_MAGIC.len()] != ARCHIVE_MAGIC {
+    return Err(AppError::InvalidRequest(
+      "the file is not a workspace archive".to_string(),
+    ));
+  }
+  let version = u16::from_be_bytes([data[ARCHIVE_MAGIC.len()], data[ARCHIVE_MAGIC.len() + 1]]);
+  if version > ARCHIVE_VERSION {
+    return Err(AppError::InvalidRequest(format!(
+      "the version {} of the workspace archive is not supported",
+      version
+    )));
+  }
+
+  let decoder = zstd::Decoder::new(&data[header_len..]).map_err(invalid_archive)?;
+  let mut reader = decoder.take(MAX_ARCHIVE_CONTENT_SIZE + 1);
+  let mut manifest = None;
+  let mut collabs = HashMap::new();
+  let mut blobs = HashMap::new();
+  loop {
+    let (name, data) = match read_entry(&mut reader) {
+      Ok(Some(entry)) => entry,
+      Ok(None) => break,
+      Err(_) if reader.limit() == 0 => return Err(archive_too_large()),
+      Err(err) => return Err(invalid_archive(err)),
+    };
+    if name == MANIFEST_ENTRY {
+      manifest = Some(serde_json::from_slice::<ArchiveManifest>(&data).map_err(invalid_archive)?);
+    } else if let Some(object_id) = name.strip_prefix(COLLAB_ENTRY_PREFIX) {
+      collabs.insert(object_id.to_string(), data);
+    } else if let Some(file_id) = name.strip_prefix(BLOB_ENTRY_PREFIX) {
+      blobs.insert(file_id.to_string(), data);
+    }
+  }
+  if reader.limit() == 0 {
+    return Err(archive_too_large());
+  }
+
+  let manifest = manifest.ok_or_else(|| invalid_archive("missing manifest"))?;
+  if let Some(collab) = manifest
+    .collabs
+    .iter()
+    .find(|collab| !collabs.contains_key(&collab.object_id))
+  {
+    return Err(invalid_archive(format!(
+      "missing data of collab:{}",
+      collab.object_id
+    )));
+  }
+  if let Some(blob) = manifest
+    .blobs
+    .iter()
+    .find(|blob| !blobs.contains_key(&blob.file_id))
+  {
+    return Err(invalid_archive(format!(
+      "missing data of blob:{}",
+      blob.file_id
+    )));
+  }
+  Ok(WorkspaceArchive {
+    manifest,
+    collabs,
+    blobs,
+  })
+}
+
+fn archive_too_large() -> AppError {
+  AppError::PayloadTooLarge(format!(
+    "the content of the workspace archive is larger than {} bytes",
+    MAX_ARCHIVE_CONTENT_SIZE
+  ))
+}
+
+fn invalid_archive<E: ToString>(err: E) -> AppError {
+  AppError::InvalidRequest(format!("invalid workspace archive: {}", err.to_string()))
+}
+
+fn write_entry<W: Write>(writer: &mut W, name: &str, data: &[u8]) -> io::Result<()> {
+  writer.write_all(&(name.len() as u32).to_be_bytes())?;
+  writer.write_all(name.as_bytes())?;
+  writer.write_all(&(data.len() as u64).to_be_bytes())?;
+  writer.write_all(data)
+}
+
+/// Read the next entry, or return None at the end of the archive.
+fn read_entry<R: Read>(reader: &mut R) -> io::Result<Option<(String, Vec<u8>)>> {
+  let mut name_len = [0u8; 4];
+  let mut read = 0;
+  while read < name_len.len() {
+    match reader.read(&mut name_len[read..]) {
+      Ok(0) if read == 0 => return Ok(None),
+      Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+      Ok(n) => read += n,
+      Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+      Err(err) => return Err(err),
+    }
+  }
+  let name_len = u32::from_be_bytes(name_len);
+  if name_len > MAX_ENTRY_NAME_LEN {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "the name of the entry is too long",
+    ));
+  }
+  let mut name = vec![0u8; name_len as usize];
+  reader.read_exact(&mut name)?;
+  let name =
+    String::from_utf8(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+  let mut data_len = [0u8; 8];
+  reader.read_exact(&mut data_len)?;
+  let data_len = u64::from_be_bytes(data_len);
+  if data_len > MAX_ARCHIVE_CONTENT_SIZE {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "the entry is too large",
+    ));
+  }
+  // The length is not trusted to allocate the buffer, the data might be shorter
+  let mut data = Vec::new();
+  reader.by_ref().take(data_len).read_to_end(&mut data)?;
+  if data.len() as u64 != data_len {
+    return Err(io::ErrorKind::UnexpectedEof.into());
+  }
+  Ok(Some((name, data)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::anyhow;
+
+  fn encode_archive(archive: &WorkspaceArchive) -> Result<Vec<u8>, AppError> {
+    let mut writer = ArchiveWriter::new(Vec::new(), &archive.manifest)?;
+    for collab in &archive.manifest.collabs {
+      let data = archive.collabs.get(&collab.object_id).ok_or_else(|| {
+        AppError::Internal(anyhow!("missing data of collab:{}", collab.object_id))
+      })?;
+      writer.write_collab(&collab.object_id, data)?;
+    }
+    for blob in &archive.manifest.blobs {
+      let data = archive
+        .blobs
+        .get(&blob.file_id)
+        .ok_or_else(|| AppError::Internal(anyhow!("missing data of blob:{}", blob.file_id)))?;
+      writer.write_blob(&blob.file_id, data)?;
+    }
+    writer.finish()
+  }
+
+  #[test]
+  fn write_archive_in_parts() {
+    let archive = test_archive();
+    let mut writer = ArchiveWriter::new(Vec::new(), &archive.manifest).unwrap();
+    writer
+      .write_collab("collab_1", &archive.collabs["collab_1"])
+      .unwrap();
+    // The part written so far is taken, like when it's uploaded while the archive is written
+    let mut data = std::mem::take(writer.get_mut());
+    writer
+      .write_blob("file_1", &archive.blobs["file_1"])
+      .unwrap();
+    data.extend(writer.finish().unwrap());
+
+    let decoded = decode_archive(&data).unwrap();
+    assert_eq!(decoded.collabs, archive.collabs);
+    assert_eq!(decoded.blobs, archive.blobs);
+  }
+
+  fn test_archive() -> WorkspaceArchive {
+    let manifest = ArchiveManifest {
+      version: ARCHIVE_VERSION,
+      workspace_id: Uuid::new_v4(),
+      workspace_name: "my workspace".to_string(),
+      database_storage_id: Uuid::new_v4(),
+      exported_at: Utc::now(),
+      members: vec![ArchiveMember {
+        email: "owner@appflowy.io".to_string(),
+        role: AFRole::Owner,
+      }],
+      collabs: vec![ArchiveCollab {
+        object_id: "collab_1".to_string(),
+        collab_type: CollabType::Document,
+      }],
+      blobs: vec![ArchiveBlob {
+        file_id: "file_1".to_string(),
+        file_type: "image/png".to_string(),
+        object_id: Some("collab_1".to_string()),
+      }],
+    };
+    WorkspaceArchive {
+      manifest,
+      collabs: HashMap::from([("collab_1".to_string(), vec![1, 2, 3])]),
+      blobs: HashMap::from([("file_1".to_string(), vec![4; 1024])]),
+    }
+  }
+
+  #[test]
+  fn encode_and_decode_archive() {
+    let archive = test_archive();
+    let data = encode_archive(&archive).unwrap();
+    let decoded = decode_archive(&data).unwrap();
+    assert_eq!(decoded.manifest.workspace_id, archive.manifest.workspace_id);
+    assert_eq!(decoded.manifest.members.len(), 1);
+    assert_eq!(decoded.collabs, archive.collabs);
+    assert_eq!(decoded.blobs, archive.blobs);
+  }
+
+  #[test]
+  fn reject_invalid_archive() {
+    assert!(decode_archive(b"not an archive").is_err());
+
+    // A newer version
+    let mut archive = test_archive();
+    archive.manifest.version = ARCHIVE_VERSION + 1;
+    let data = encode_archive(&archive).unwrap();
+    assert!(decode_archive(&data).is_err());
+
+    // Truncated
+    let data = encode_archive(&test_archive()).unwrap();
+    assert!(decode_archive(&data[..data.len() - 8]).is_err());
+  }
+}
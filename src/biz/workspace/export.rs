@@ -0,0 +1,374 @@
+use crate::biz::workspace::archive::{
+  ArchiveBlob, ArchiveCollab, ArchiveManifest, ArchiveMember, ArchiveWriter, ARCHIVE_VERSION,
+};
+use crate::state::AppState;
+use anyhow::anyhow;
+use app_error::AppError;
+use bytes::Bytes;
+use chrono::Utc;
+use collab_entity::CollabType;
+use database::collab::select_workspace_collabs;
+use database::file::{CompletedPart, MIN_UPLOAD_PART_SIZE};
+use database::resource_usage::get_all_workspace_blob_metadata;
+use database::workspace::{
+  delete_expired_workspace_exports, fail_interrupted_workspace_exports, insert_workspace_export,
+  select_workspace, select_workspace_export, select_workspace_member_list,
+  update_workspace_export_result,
+};
+use database_entity::dto::{
+  AFWorkspace, AFWorkspaceExport, QueryCollabParams, WorkspaceExportStatus,
+};
+use futures_util::{stream, Stream};
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{error, info, instrument};
+use uuid::Uuid;
+
+/// An export job that takes longer is failed. A job that is still running after it was started
+/// for longer was interrupted, by a restart of the server for example, and is failed too.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+/// The archives are removed, with their jobs, once their jobs were started for longer.
+const EXPORT_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const EXPORT_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Size of the chunks in which the archive is read from the bucket when it's downloaded.
+const DOWNLOAD_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Start a job that exports the workspace as an archive. The archive is built in the background
+/// and stored in the bucket, so the job is returned right away and can be polled with
+/// [get_workspace_export].
+#[instrument(level = "debug", skip(state), err)]
+pub async fn start_workspace_export(
+  state: &AppState,
+  uid: i64,
+  workspace_id: Uuid,
+) -> Result<AFWorkspaceExport, AppError> {
+  let job_id = Uuid::new_v4();
+  let row = insert_workspace_export(&state.pg_pool, &job_id, &workspace_id, uid).await?;
+
+  let state = state.clone();
+  tokio::spawn(async move {
+    let result = export_workspace_archive(&state, uid, &workspace_id, &job_id).await;
+    let result = match result {
+      Ok(file_size) => {
+        info!(
+          "exported workspace:{} with job:{}, {} bytes",
+          workspace_id, job_id, file_size
+        );
+        Ok(file_size)
+      },
+      Err(err) => {
+        error!(
+          "failed to export workspace:{} with job:{}: {}",
+          workspace_id, job_id, err
+        );
+        Err(err.to_string())
+      },
+    };
+    let result = result.as_ref().map(|file_size| *file_size);
+    if let Err(err) =
+      update_workspace_export_result(&state.pg_pool, &job_id, result.map_err(String::as_str)).await
+    {
+      error!("failed to update the export job:{}: {}", job_id, err);
+    }
+  });
+  Ok(row.into())
+}
+
+/// Return the export job. Only the user who started the job can see it, because the archive holds
+/// the whole content of the workspace.
+pub async fn get_workspace_export(
+  state: &AppState,
+  uid: i64,
+  workspace_id: &Uuid,
+  job_id: &Uuid,
+) -> Result<AFWorkspaceExport, AppError> {
+  let row = select_workspace_export(&state.pg_pool, workspace_id, job_id).await?;
+  if row.created_by != uid {
+    return Err(AppError::NotEnoughPermissions(format!(
+      "user:{} can't access the export job:{}",
+      uid, job_id
+    )));
+  }
+  Ok(row.into())
+}
+
+/// Return the size of the archive of the completed export job, and the stream of its content. The
+/// archive is read from the bucket chunk by chunk, as it's sent.
+pub async fn get_workspace_export_archive(
+  state: &AppState,
+  uid: i64,
+  workspace_id: &Uuid,
+  job_id: &Uuid,
+) -> Result<(u64, impl Stream<Item = Result<Bytes, AppError>>), AppError> {
+  let export = get_workspace_export(state, uid, workspace_id, job_id).await?;
+  let file_size = match (export.status, export.file_size) {
+    (WorkspaceExportStatus::Completed, Some(file_size)) => file_size as u64,
+    _ => {
+      return Err(AppError::InvalidRequest(format!(
+        "the export job:{} is not completed",
+        job_id
+      )))
+    },
+  };
+
+  let bucket_storage = state.bucket_storage.clone();
+  let (workspace_id, job_id) = (*workspace_id, *job_id);
+  let chunks = stream::try_unfold(0u64, move |start| {
+    let bucket_storage = bucket_storage.clone();
+    async move {
+      if start >= file_size {
+        return Ok(None);
+      }
+      let end = (start + DOWNLOAD_CHUNK_SIZE).min(file_size) - 1;
+      let chunk = bucket_storage
+        .get_workspace_export_range(&workspace_id, &job_id, start, end)
+        .await?;
+      Ok(Some((Bytes::from(chunk), end + 1)))
+    }
+  });
+  Ok((file_size, chunks))
+}
+
+/// Build the archive of the workspace and upload it to the bucket, in parts, while it's written.
+/// Return the size of the archive.
+async fn export_workspace_archive(
+  state: &AppState,
+  uid: i64,
+  workspace_id: &Uuid,
+  job_id: &Uuid,
+) -> Result<i64, AppError> {
+  let upload_id = state
+    .bucket_storage
+    .create_workspace_export_upload(workspace_id, job_id)
+    .await?;
+  let mut upload = ArchiveUpload {
+    state,
+    workspace_id,
+    job_id,
+    upload_id: &upload_id,
+    parts: vec![],
+    size: 0,
+  };
+  let result = match tokio::time::timeout(
+    EXPORT_TIMEOUT,
+    write_workspace_archive(state, uid, workspace_id, &mut upload),
+  )
+  .await
+  {
+    Ok(result) => result,
+    Err(_) => Err(AppError::Internal(anyhow!(
+      "the export took longer than {:?}",
+      EXPORT_TIMEOUT
+    ))),
+  };
+
+  match result {
+    Ok(()) => {
+      let size = upload.size;
+      state
+        .bucket_storage
+        .complete_workspace_export_upload(workspace_id, job_id, &upload_id, upload.parts)
+        .await?;
+      Ok(size)
+    },
+    Err(err) => {
+      if let Err(abort_err) = state
+        .bucket_storage
+        .abort_workspace_export_upload(workspace_id, job_id, &upload_id)
+        .await
+      {
+        error!(
+          "failed to abort the upload of the export job:{}: {}",
+          job_id, abort_err
+        );
+      }
+      Err(err)
+    },
+  }
+}
+
+/// The upload of the archive of an export job.
+struct ArchiveUpload<'a> {
+  state: &'a AppState,
+  workspace_id: &'a Uuid,
+  job_id: &'a Uuid,
+  upload_id: &'a str,
+  parts: Vec<CompletedPart>,
+  /// Number of bytes uploaded so far
+  size: i64,
+}
+
+impl ArchiveUpload<'_> {
+  async fn upload_part(&mut self, content: Vec<u8>) -> Result<(), AppError> {
+    let part_number = self.parts.len() as i32 + 1;
+    let size = content.len() as i64;
+    let part = self
+      .state
+      .bucket_storage
+      .upload_workspace_export_part(
+        self.workspace_id,
+        self.job_id,
+        self.upload_id,
+        part_number,
+        content,
+      )
+      .await?;
+    self.parts.push(part);
+    self.size += size;
+    Ok(())
+  }
+
+  /// Upload the part of the archive that was written so far, once it's large enough to be a part.
+  async fn upload_written(&mut self, written: &mut Vec<u8>) -> Result<(), AppError> {
+    if written.len() >= MIN_UPLOAD_PART_SIZE {
+      self.upload_part(std::mem::take(written)).await?;
+    }
+    Ok(())
+  }
+}
+
+/// Write the archive entry by entry, so only one collab or blob is held in memory at a time.
+async fn write_workspace_archive(
+  state: &AppState,
+  uid: i64,
+  workspace_id: &Uuid,
+  upload: &mut ArchiveUpload<'_>,
+) -> Result<(), AppError> {
+  let workspace = AFWorkspace::try_from(select_workspace(&state.pg_pool, workspace_id).await?)?;
+  let members = select_workspace_member_list(&state.pg_pool, workspace_id)
+    .await?
+    .into_iter()
+    .map(|member| ArchiveMember {
+      email: member.email,
+      role: member.role,
+    })
+    .collect();
+
+  // The awareness of a user is not part of the content of the workspace
+  let collabs = select_workspace_collabs(&state.pg_pool, workspace_id)
+    .await?
+    .into_iter()
+    .filter(|(_, collab_type)| *collab_type != CollabType::UserAwareness)
+    .map(|(object_id, collab_type)| ArchiveCollab {
+      object_id,
+      collab_type,
+    })
+    .collect::<Vec<_>>();
+  let blobs = get_all_workspace_blob_metadata(&state.pg_pool, workspace_id)
+    .await?
+    .into_iter()
+    .map(|metadata| ArchiveBlob {
+      file_id: metadata.file_id,
+      file_type: metadata.file_type,
+      object_id: metadata.object_id,
+    })
+    .collect::<Vec<_>>();
+  let manifest = ArchiveManifest {
+    version: ARCHIVE_VERSION,
+    workspace_id: *workspace_id,
+    workspace_name: workspace.workspace_name,
+    database_storage_id: workspace.database_storage_id,
+    exported_at: Utc::now(),
+    members,
+    collabs,
+    blobs,
+  };
+
+  let mut writer = ArchiveWriter::new(Vec::new(), &manifest)?;
+  for collab in manifest.collabs {
+    let params = QueryCollabParams::new(&collab.object_id, collab.collab_type, workspace_id);
+    let data = state
+      .collab_storage
+      .get_latest_collab_encoded(&uid, params)
+      .await?
+      .encode_to_bytes()
+      .map_err(|err| {
+        AppError::Internal(anyhow!(
+          "fail to encode collab:{}: {:?}",
+          collab.object_id,
+          err
+        ))
+      })?;
+    // Compressing the entry is CPU bound
+    writer = tokio::task::spawn_blocking(move || {
+      writer.write_collab(&collab.object_id, &data)?;
+      Ok::<_, AppError>(writer)
+    })
+    .await??;
+    upload.upload_written(writer.get_mut()).await?;
+  }
+  for blob in manifest.blobs {
+    let data = state
+      .bucket_storage
+      .get_blob(workspace_id, &blob.file_id)
+      .await?;
+    writer = tokio::task::spawn_blocking(move || {
+      writer.write_blob(&blob.file_id, &data)?;
+      Ok::<_, AppError>(writer)
+    })
+    .await??;
+    upload.upload_written(writer.get_mut()).await?;
+  }
+
+  // The last part can be smaller than the minimum size of a part
+  let rest = tokio::task::spawn_blocking(move || writer.finish()).await??;
+  upload.upload_part(rest).await?;
+  Ok(())
+}
+
+/// Periodically fail the export jobs that were interrupted, and remove the expired archives with
+/// their jobs. It runs on every server: a job is deleted by a single server, which then removes
+/// its archive.
+pub fn spawn_workspace_export_cleanup(state: AppState) {
+  tokio::spawn(async move {
+    let mut interval = interval(EXPORT_CLEANUP_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+      interval.tick().await;
+      cleanup_workspace_exports(&state).await;
+    }
+  });
+}
+
+async fn cleanup_workspace_exports(state: &AppState) {
+  let pg_pool = &state.pg_pool;
+  // A running job never outlives the timeout, so it was interrupted if it's still running
+  let started_before = Utc::now() - chrono::Duration::seconds(EXPORT_TIMEOUT.as_secs() as i64);
+  match fail_interrupted_workspace_exports(pg_pool, started_before, "the export was interrupted")
+    .await
+  {
+    Ok(0) => {},
+    Ok(count) => info!("failed {} interrupted workspace export jobs", count),
+    Err(err) => error!(
+      "failed to fail the interrupted workspace export jobs: {}",
+      err
+    ),
+  }
+
+  let started_before = Utc::now() - chrono::Duration::seconds(EXPORT_TTL.as_secs() as i64);
+  let jobs = match delete_expired_workspace_exports(pg_pool, started_before).await {
+    Ok(jobs) => jobs,
+    Err(err) => {
+      error!(
+        "failed to delete the expired workspace export jobs: {}",
+        err
+      );
+      return;
+    },
+  };
+  for (workspace_id, job_id) in jobs {
+    // The archive of a failed job may not exist
+    if let Err(err) = state
+      .bucket_storage
+      .delete_workspace_export(&workspace_id, &job_id)
+      .await
+    {
+      if !err.is_record_not_found() {
+        error!(
+          "failed to remove the archive of the export job:{}: {}",
+          job_id, err
+        );
+      }
+    }
+  }
+}
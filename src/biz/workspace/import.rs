@@ -0,0 +1,309 @@
+use crate::biz::collab::restore::{copy_map, doc_from_encoded_collab, DATA_SECTION};
+use crate::biz::workspace::access_control::WorkspaceAccessControl;
+use crate::biz::workspace::archive::{decode_archive, WorkspaceArchive};
+use crate::biz::workspace::ops::add_workspace_members;
+use crate::state::AppState;
+use anyhow::{anyhow, Context};
+use app_error::AppError;
+use collab::core::collab_plugin::EncodedCollab;
+use database::collab::CollabStorage;
+use database::user::select_uid_from_email;
+use database::workspace::{delete_from_workspace, insert_user_workspace};
+use database_entity::dto::{AFRole, AFWorkspace, AFWorkspaceImport, CollabParams};
+use shared_entity::dto::workspace_dto::CreateWorkspaceMember;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use tracing::{error, instrument};
+use uuid::Uuid;
+use yrs::updates::encoder::Encode;
+use yrs::{Doc, ReadTxn, StateVector, Transact};
+
+const UUID_LEN: usize = 36;
+
+/// Recreate the workspace of the archive as a new workspace owned by the user. Every collab is
+/// given a new object id, and the references to the old ids in the content of the collabs, like
+/// the views of the folder or the rows of a database, are replaced by the new ids. The members of
+/// the exported workspace who have an account on this server are added to the new workspace as
+/// members, whatever their role in the exported workspace: the importing user is the only owner.
+#[instrument(level = "debug", skip(state, data), err)]
+pub async fn import_workspace_archive(
+  state: &AppState,
+  user_uuid: &Uuid,
+  uid: i64,
+  data: Vec<u8>,
+) -> Result<AFWorkspaceImport, AppError> {
+  let archive = tokio::task::spawn_blocking(move || decode_archive(&data)).await??;
+  let row =
+    insert_user_workspace(&state.pg_pool, user_uuid, &archive.manifest.workspace_name).await?;
+  let workspace = AFWorkspace::try_from(row)?;
+  let workspace_id = workspace.workspace_id;
+
+  let result = async {
+    state
+      .workspace_access_control
+      .insert_workspace_role(&uid, &workspace_id, AFRole::Owner)
+      .await?;
+    import_into_workspace(state, user_uuid, uid, &workspace, archive).await
+  }
+  .await;
+  match result {
+    Ok((collab_count, blob_count, skipped_members)) => Ok(AFWorkspaceImport {
+      workspace,
+      collab_count,
+      blob_count,
+      skipped_members,
+    }),
+    Err(err) => {
+      // The blobs that were already uploaded are removed by the blob gc with the workspace
+      if let Err(delete_err) = delete_from_workspace(&state.pg_pool, &workspace_id).await {
+        error!(
+          "failed to delete the workspace:{} of the failed import: {}",
+          workspace_id, delete_err
+        );
+      }
+      Err(err)
+    },
+  }
+}
+
+async fn import_into_workspace(
+  state: &AppState,
+  user_uuid: &Uuid,
+  uid: i64,
+  workspace: &AFWorkspace,
+  archive: WorkspaceArchive,
+) -> Result<(usize, usize, Vec<String>), AppError> {
+  let WorkspaceArchive {
+    manifest,
+    collabs,
+    blobs,
+  } = archive;
+  let mut ids = HashMap::new();
+  ids.insert(
+    manifest.workspace_id.to_string(),
+    workspace.workspace_id.to_string(),
+  );
+  ids.insert(
+    manifest.database_storage_id.to_string(),
+    workspace.database_storage_id.to_string(),
+  );
+  for collab in &manifest.collabs {
+    ids
+      .entry(collab.object_id.clone())
+      .or_insert_with(|| Uuid::new_v4().to_string());
+  }
+
+  let workspace_id = workspace.workspace_id.to_string();
+  let collab_count = manifest.collabs.len();
+  let (ids, params_list) = tokio::task::spawn_blocking(move || {
+    let mut params_list = Vec::with_capacity(manifest.collabs.len());
+    for collab in manifest.collabs {
+      let data = &collabs[&collab.object_id];
+      let encoded_collab = remap_collab(data, &ids).map_err(|err| {
+        AppError::InvalidRequest(format!(
+          "invalid collab:{} in the workspace archive: {}",
+          collab.object_id, err
+        ))
+      })?;
+      params_list.push(CollabParams::new(
+        &ids[&collab.object_id],
+        collab.collab_type,
+        encoded_collab,
+      ));
+    }
+    Ok::<_, AppError>((ids, params_list))
+  })
+  .await??;
+
+  let mut txn = state
+    .pg_pool
+    .begin()
+    .await
+    .context("Begin transaction to import workspace collabs")?;
+  for params in params_list {
+    state
+      .collab_storage
+      .upsert_collab_with_transaction(&workspace_id, &uid, params, &mut txn)
+      .await?;
+  }
+  txn
+    .commit()
+    .await
+    .context("Commit transaction to import workspace collabs")?;
+
+  let blob_count = manifest.blobs.len();
+  let mut blobs = blobs;
+  for blob in manifest.blobs {
+    let data = blobs.remove(&blob.file_id).unwrap_or_default();
+    let object_id = blob
+      .object_id
+      .map(|object_id| ids.get(&object_id).cloned().unwrap_or(object_id));
+    state
+      .bucket_storage
+      .put_blob(
+        workspace.workspace_id,
+        blob.file_id,
+        data,
+        blob.file_type,
+        object_id,
+      )
+      .await?;
+  }
+
+  let mut members = vec![];
+  let mut skipped_members = vec![];
+  for member in manifest.members {
+    // The user who imports the archive is already the owner of the workspace
+    match select_uid_from_email(&state.pg_pool, &member.email).await {
+      Ok(member_uid) if member_uid == uid => {},
+      Ok(_) => members.push(CreateWorkspaceMember {
+        email: member.email,
+        role: AFRole::Member,
+      }),
+      Err(AppError::RecordNotFound(_)) => skipped_members.push(member.email),
+      Err(err) => return Err(err),
+    }
+  }
+  let role_by_uid =
+    add_workspace_members(&state.pg_pool, user_uuid, &workspace.workspace_id, members).await?;
+  for (member_uid, role) in role_by_uid {
+    state
+      .workspace_access_control
+      .insert_workspace_role(&member_uid, &workspace.workspace_id, role)
+      .await?;
+  }
+  Ok((collab_count, blob_count, skipped_members))
+}
+
+/// Return the collab with its content copied into a new document, where the ids are replaced
+/// according to `ids`.
+fn remap_collab(data: &[u8], ids: &HashMap<String, String>) -> Result<Vec<u8>, AppError> {
+  let encoded_collab = EncodedCollab::decode_from_bytes(data)
+    .map_err(|err| anyhow!("fail to decode collab: {:?}", err))?;
+  let source = doc_from_encoded_collab(&encoded_collab)?;
+  let source_data = source.get_or_insert_map(DATA_SECTION);
+  let doc = Doc::new();
+  let data = doc.get_or_insert_map(DATA_SECTION);
+  {
+    let source_txn = source.transact();
+    let mut txn = doc.transact_mut();
+    copy_map(&source_txn, &source_data, &mut txn, &data, &|s| {
+      remap_ids(s, ids)
+    });
+  }
+
+  let txn = doc.transact();
+  let encoded_collab = EncodedCollab::new_v1(
+    txn.state_vector().encode_v1(),
+    txn.encode_state_as_update_v1(&StateVector::default()),
+  );
+  let data = encoded_collab
+    .encode_to_bytes()
+    .map_err(|err| anyhow!("fail to encode collab: {:?}", err))?;
+  Ok(data)
+}
+
+/// Replace the ids that appear in the string. A string that is an id is replaced as a whole,
+/// otherwise the UUIDs in the string, like the ones of a url, are replaced.
+fn remap_ids<'a>(s: &'a str, ids: &HashMap<String, String>) -> Cow<'a, str> {
+  if let Some(new_id) = ids.get(s) {
+    return Cow::Owned(new_id.clone());
+  }
+  if s.len() <= UUID_LEN {
+    return Cow::Borrowed(s);
+  }
+
+  let bytes = s.as_bytes();
+  let mut result: Option<String> = None;
+  // Start of the part of the string that is not copied to the result yet
+  let mut copied = 0;
+  let mut i = 0;
+  while i + UUID_LEN <= bytes.len() {
+    // A UUID only holds ascii characters, so its bounds are char boundaries
+    if is_uuid(&bytes[i..i + UUID_LEN]) {
+      if let Some(new_id) = ids.get(&s[i..i + UUID_LEN]) {
+        let result = result.get_or_insert_with(|| String::with_capacity(s.len()));
+        result.push_str(&s[copied..i]);
+        result.push_str(new_id);
+        i += UUID_LEN;
+        copied = i;
+        continue;
+      }
+    }
+    i += 1;
+  }
+  match result {
+    Some(mut result) => {
+      result.push_str(&s[copied..]);
+      Cow::Owned(result)
+    },
+    None => Cow::Borrowed(s),
+  }
+}
+
+/// Whether the bytes are a hyphenated UUID.
+fn is_uuid(bytes: &[u8]) -> bool {
+  bytes.len() == UUID_LEN
+    && bytes.iter().enumerate().all(|(i, b)| match i {
+      8 | 13 | 18 | 23 => *b == b'-',
+      _ => b.is_ascii_hexdigit(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use collab::core::origin::CollabOrigin;
+  use collab::preclude::Collab;
+  use serde_json::json;
+
+  #[test]
+  fn remap_ids_in_string() {
+    let old_id = Uuid::new_v4().to_string();
+    let new_id = Uuid::new_v4().to_string();
+    let ids = HashMap::from([(old_id.clone(), new_id.clone()), ("a".into(), "b".into())]);
+    assert_eq!(remap_ids(&old_id, &ids), new_id);
+    assert_eq!(remap_ids("a", &ids), "b");
+    assert_eq!(
+      remap_ids(&format!("/api/file_storage/{}/file", old_id), &ids),
+      format!("/api/file_storage/{}/file", new_id)
+    );
+    assert_eq!(
+      remap_ids(&format!("{}é{}", old_id, old_id), &ids),
+      format!("{}é{}", new_id, new_id)
+    );
+
+    let other_id = Uuid::new_v4().to_string();
+    assert!(matches!(remap_ids(&other_id, &ids), Cow::Borrowed(_)));
+    assert!(matches!(
+      remap_ids(&format!("[{}]", other_id), &ids),
+      Cow::Borrowed(_)
+    ));
+  }
+
+  #[test]
+  fn remap_ids_in_collab() {
+    let view_id = Uuid::new_v4().to_string();
+    let new_view_id = Uuid::new_v4().to_string();
+    let collab = Collab::new(1, "folder", "device", vec![]);
+    collab.with_origin_transact_mut(|txn| {
+      collab.insert_with_txn(txn, "current_view", view_id.clone());
+      collab.insert_with_txn(txn, view_id.clone(), "view");
+    });
+    let data = collab.encode_collab_v1().encode_to_bytes().unwrap();
+
+    let ids = HashMap::from([(view_id, new_view_id.clone())]);
+    let data = remap_collab(&data, &ids).unwrap();
+    let encoded_collab = EncodedCollab::decode_from_bytes(&data).unwrap();
+    let collab = Collab::new_with_doc_state(
+      CollabOrigin::Empty,
+      "folder",
+      encoded_collab.doc_state.to_vec(),
+      vec![],
+    )
+    .unwrap();
+    let mut expected = json!({ "current_view": new_view_id });
+    expected[&new_view_id] = json!("view");
+    assert_eq!(collab.to_json_value(), expected);
+  }
+}
@@ -1,2 +1,5 @@
 pub mod access_control;
+pub mod archive;
+pub mod export;
+pub mod import;
 pub mod ops;
@@ -1,7 +1,13 @@
 use anyhow::Context;
 use app_error::AppError;
 use database::collab::upsert_collab_member_with_txn;
-use database::pg_row::{AFWorkspaceMemberRow, AFWorkspaceRow};
+use database::pg_row::{
+  AFWorkspaceMemberRow, AFWorkspacePlanRow, AFWorkspaceQuotaRow, AFWorkspaceRow,
+};
+use database::resource_usage::{
+  delete_workspace_quota, select_workspace_plans, update_workspace_type, upsert_workspace_plan,
+  upsert_workspace_quota,
+};
 use database::user::select_uid_from_email;
 use database::workspace::{
   delete_from_workspace, delete_workspace_members, insert_user_workspace,
@@ -9,7 +15,10 @@ use database::workspace::{
   select_workspace_member_list, update_updated_at_of_workspace, upsert_workspace_member,
 };
 use database_entity::dto::{AFAccessLevel, AFRole, AFWorkspace};
-use shared_entity::dto::workspace_dto::{CreateWorkspaceMember, WorkspaceMemberChangeset};
+use shared_entity::dto::workspace_dto::{
+  CreateWorkspaceMember, UpdateWorkspaceQuotaParams, WorkspaceMemberChangeset, WorkspacePlan,
+  WorkspaceQuota,
+};
 use shared_entity::response::AppResponseError;
 use sqlx::{types::uuid, PgPool};
 use std::collections::HashMap;
@@ -163,3 +172,89 @@ pub async fn update_workspace_member(
   .await?;
   Ok(())
 }
+
+pub async fn get_workspace_quota(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+) -> Result<WorkspaceQuota, AppError> {
+  let row = database::resource_usage::get_workspace_quota(pg_pool, workspace_id).await?;
+  Ok(workspace_quota_from_row(row))
+}
+
+/// Move the workspace to another plan and/or override the limits of its plan.
+pub async fn update_workspace_quota(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+  params: UpdateWorkspaceQuotaParams,
+) -> Result<WorkspaceQuota, AppError> {
+  let mut txn = pg_pool
+    .begin()
+    .await
+    .context("Begin transaction to update workspace quota")?;
+  if let Some(workspace_type) = params.workspace_type {
+    update_workspace_type(&mut txn, workspace_id, workspace_type).await?;
+  }
+  upsert_workspace_quota(
+    &mut txn,
+    workspace_id,
+    params.storage_limit.map(limit_to_i64).transpose()?,
+    params.blob_size_limit.map(limit_to_i64).transpose()?,
+    params.collab_size_limit.map(limit_to_i64).transpose()?,
+  )
+  .await?;
+  let row = database::resource_usage::get_workspace_quota(txn.deref_mut(), workspace_id).await?;
+  txn
+    .commit()
+    .await
+    .context("Commit transaction to update workspace quota")?;
+  Ok(workspace_quota_from_row(row))
+}
+
+/// Remove the overridden limits, so the workspace uses the limits of its plan again.
+pub async fn reset_workspace_quota(
+  pg_pool: &PgPool,
+  workspace_id: &Uuid,
+) -> Result<WorkspaceQuota, AppError> {
+  delete_workspace_quota(pg_pool, workspace_id).await?;
+  get_workspace_quota(pg_pool, workspace_id).await
+}
+
+pub async fn get_workspace_plans(pg_pool: &PgPool) -> Result<Vec<WorkspacePlan>, AppError> {
+  let plans = select_workspace_plans(pg_pool)
+    .await?
+    .into_iter()
+    .map(|row| WorkspacePlan {
+      workspace_type: row.workspace_type,
+      name: row.name,
+      storage_limit: row.storage_limit as u64,
+      blob_size_limit: row.blob_size_limit as u64,
+      collab_size_limit: row.collab_size_limit as u64,
+    })
+    .collect();
+  Ok(plans)
+}
+
+pub async fn update_workspace_plan(pg_pool: &PgPool, plan: WorkspacePlan) -> Result<(), AppError> {
+  let row = AFWorkspacePlanRow {
+    workspace_type: plan.workspace_type,
+    name: plan.name,
+    storage_limit: limit_to_i64(plan.storage_limit)?,
+    blob_size_limit: limit_to_i64(plan.blob_size_limit)?,
+    collab_size_limit: limit_to_i64(plan.collab_size_limit)?,
+  };
+  upsert_workspace_plan(pg_pool, &row).await
+}
+
+fn limit_to_i64(limit: u64) -> Result<i64, AppError> {
+  i64::try_from(limit)
+    .map_err(|_| AppError::InvalidRequest(format!("the limit:{} is out of range", limit)))
+}
+
+fn workspace_quota_from_row(row: AFWorkspaceQuotaRow) -> WorkspaceQuota {
+  WorkspaceQuota {
+    workspace_type: row.workspace_type,
+    storage_limit: row.storage_limit as u64,
+    blob_size_limit: row.blob_size_limit as u64,
+    collab_size_limit: row.collab_size_limit as u64,
+  }
+}
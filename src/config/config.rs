@@ -1,4 +1,8 @@
 use anyhow::Context;
+use database::collab::{CollabCodec, CollabFlushPolicy, FlushPolicyConfig};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use realtime::collaborate::RateLimitConfig;
 use secrecy::Secret;
 use serde::Deserialize;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
@@ -12,15 +16,120 @@ pub struct Config {
   pub application: ApplicationSetting,
   pub websocket: WebsocketSetting,
   pub redis_uri: Secret<String>,
+  pub blob_storage: BlobStorageSetting,
+  pub blob_gc: BlobGcSetting,
+  pub collab: CollabSetting,
   pub s3: S3Setting,
   pub casbin: CasbinSetting,
 }
 
+#[derive(Clone, Debug)]
+pub struct CollabSetting {
+  /// Codec used to compress the collabs and snapshots that are written to the database.
+  pub compression: CollabCodec,
+  /// Number of rows rewritten at a time by the background job that compresses the existing rows
+  /// with [CollabSetting::compression]. The job is disabled if it's 0.
+  pub compression_migration_batch_size: i64,
+  /// How often the update logs of the collabs are compacted into the collabs. The background
+  /// compaction is disabled if it's 0.
+  pub compaction_interval_secs: u64,
+  /// Number of updates in the update log of a collab that triggers its compaction.
+  pub compaction_min_updates: i64,
+  /// How often the snapshots of all the collabs are pruned according to their retention policy.
+  /// The snapshots of a collab are also pruned whenever a snapshot is created. The periodic
+  /// pruning is disabled if it's 0.
+  pub snapshot_prune_interval_secs: u64,
+  /// Whether the server runs on several nodes that share the same Redis. The nodes then keep the
+  /// collabs that are edited on more than one node in sync, and only the node that owns a collab
+  /// writes it.
+  pub cluster_enabled: bool,
+  /// When the realtime server merges the update logs of the collabs that are being edited into
+  /// the collabs. The policies are checked every minute.
+  pub flush_policy: FlushPolicyConfig,
+}
+
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct CasbinSetting {
   pub pool_size: u32,
 }
 
+#[derive(Clone, Debug)]
+pub struct BlobStorageSetting {
+  pub backend: BlobStorageBackend,
+  /// Root directory of the blobs when using [BlobStorageBackend::FileSystem].
+  pub fs_root: String,
+  /// Let the clients download and upload the blobs directly from the bucket with presigned urls,
+  /// instead of proxying the content through the server. Only supported by [BlobStorageBackend::S3].
+  pub presigned_url: bool,
+  /// How long the presigned urls stay valid.
+  pub presigned_url_expires_secs: u32,
+  /// Key of the signatures of the public blob links.
+  pub public_link_key: Secret<String>,
+}
+
+/// Where the uploaded blobs are stored.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum BlobStorageBackend {
+  /// S3 or any S3 compatible object store such as MinIO. See [S3Setting].
+  S3,
+  /// Local file system. Suitable for small installs and CI.
+  FileSystem,
+}
+
+impl FromStr for BlobStorageBackend {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "s3" => Ok(Self::S3),
+      "fs" | "filesystem" => Ok(Self::FileSystem),
+      other => anyhow::bail!(
+        "{} is not a supported blob storage backend. Use either `s3` or `fs`.",
+        other
+      ),
+    }
+  }
+}
+
+/// The background job that removes the objects in the bucket that are no longer referenced by any
+/// blob, e.g. the objects of deleted workspaces.
+#[derive(Clone, Debug)]
+pub struct BlobGcSetting {
+  /// Disabled by default. The servers that enable it take turns, see [crate::biz::blob_gc].
+  pub enabled: bool,
+  pub interval_secs: u64,
+  /// Objects modified more recently than this are never collected, because the transaction that
+  /// references them might still be in progress.
+  pub min_age_secs: u64,
+  pub action: BlobGcAction,
+}
+
+/// What to do with the orphan objects found by the blob garbage collector.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum BlobGcAction {
+  /// Only report the orphan objects through the logs and metrics.
+  DryRun,
+  /// Move the orphan objects to the quarantine prefix of the bucket.
+  Quarantine,
+  Delete,
+}
+
+impl FromStr for BlobGcAction {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "dry_run" | "dry-run" => Ok(Self::DryRun),
+      "quarantine" => Ok(Self::Quarantine),
+      "delete" => Ok(Self::Delete),
+      other => anyhow::bail!(
+        "{} is not a supported blob gc action. Use either `dry_run`, `quarantine` or `delete`.",
+        other
+      ),
+    }
+  }
+}
+
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct S3Setting {
   pub use_minio: bool,
@@ -117,8 +226,76 @@ pub fn get_configuration() -> Result<Config, anyhow::Error> {
     websocket: WebsocketSetting {
       heartbeat_interval: get_env_var("APPFLOWY_WEBSOCKET_HEARTBEAT_INTERVAL", "6").parse()?,
       client_timeout: get_env_var("APPFLOWY_WEBSOCKET_CLIENT_TIMEOUT", "60").parse()?,
+      rate_limit: RateLimitConfig {
+        user_per_sec: get_env_var("APPFLOWY_WEBSOCKET_RATE_LIMIT_USER_PER_SEC", "30")
+          .parse()
+          .context("fail to get APPFLOWY_WEBSOCKET_RATE_LIMIT_USER_PER_SEC")?,
+        device_per_sec: get_env_var("APPFLOWY_WEBSOCKET_RATE_LIMIT_DEVICE_PER_SEC", "10")
+          .parse()
+          .context("fail to get APPFLOWY_WEBSOCKET_RATE_LIMIT_DEVICE_PER_SEC")?,
+        object_per_sec: get_env_var("APPFLOWY_WEBSOCKET_RATE_LIMIT_OBJECT_PER_SEC", "100")
+          .parse()
+          .context("fail to get APPFLOWY_WEBSOCKET_RATE_LIMIT_OBJECT_PER_SEC")?,
+      },
     },
     redis_uri: get_env_var("APPFLOWY_REDIS_URI", "redis://localhost:6379").into(),
+    blob_storage: BlobStorageSetting {
+      backend: get_env_var("APPFLOWY_BLOB_STORAGE_BACKEND", "s3")
+        .parse()
+        .context("fail to get APPFLOWY_BLOB_STORAGE_BACKEND")?,
+      fs_root: get_env_var("APPFLOWY_BLOB_STORAGE_FS_ROOT", "./data/blob"),
+      presigned_url: get_env_var("APPFLOWY_BLOB_STORAGE_PRESIGNED_URL", "false")
+        .parse()
+        .context("fail to get APPFLOWY_BLOB_STORAGE_PRESIGNED_URL")?,
+      presigned_url_expires_secs: get_env_var(
+        "APPFLOWY_BLOB_STORAGE_PRESIGNED_URL_EXPIRES_SECS",
+        "300",
+      )
+      .parse()
+      .context("fail to get APPFLOWY_BLOB_STORAGE_PRESIGNED_URL_EXPIRES_SECS")?,
+      public_link_key: get_blob_public_link_key(),
+    },
+    blob_gc: BlobGcSetting {
+      enabled: get_env_var("APPFLOWY_BLOB_GC_ENABLED", "false")
+        .parse()
+        .context("fail to get APPFLOWY_BLOB_GC_ENABLED")?,
+      interval_secs: get_env_var("APPFLOWY_BLOB_GC_INTERVAL_SECS", "86400")
+        .parse()
+        .context("fail to get APPFLOWY_BLOB_GC_INTERVAL_SECS")?,
+      min_age_secs: get_env_var("APPFLOWY_BLOB_GC_MIN_AGE_SECS", "86400")
+        .parse()
+        .context("fail to get APPFLOWY_BLOB_GC_MIN_AGE_SECS")?,
+      action: get_env_var("APPFLOWY_BLOB_GC_ACTION", "dry_run")
+        .parse()
+        .context("fail to get APPFLOWY_BLOB_GC_ACTION")?,
+    },
+    collab: CollabSetting {
+      compression: get_env_var("APPFLOWY_COLLAB_COMPRESSION", "zstd")
+        .parse()
+        .context("fail to get APPFLOWY_COLLAB_COMPRESSION")?,
+      compression_migration_batch_size: get_env_var(
+        "APPFLOWY_COLLAB_COMPRESSION_MIGRATION_BATCH_SIZE",
+        "100",
+      )
+      .parse()
+      .context("fail to get APPFLOWY_COLLAB_COMPRESSION_MIGRATION_BATCH_SIZE")?,
+      compaction_interval_secs: get_env_var("APPFLOWY_COLLAB_COMPACTION_INTERVAL_SECS", "300")
+        .parse()
+        .context("fail to get APPFLOWY_COLLAB_COMPACTION_INTERVAL_SECS")?,
+      compaction_min_updates: get_env_var("APPFLOWY_COLLAB_COMPACTION_MIN_UPDATES", "100")
+        .parse()
+        .context("fail to get APPFLOWY_COLLAB_COMPACTION_MIN_UPDATES")?,
+      snapshot_prune_interval_secs: get_env_var(
+        "APPFLOWY_COLLAB_SNAPSHOT_PRUNE_INTERVAL_SECS",
+        "3600",
+      )
+      .parse()
+      .context("fail to get APPFLOWY_COLLAB_SNAPSHOT_PRUNE_INTERVAL_SECS")?,
+      cluster_enabled: get_env_var("APPFLOWY_COLLAB_CLUSTER_ENABLED", "false")
+        .parse()
+        .context("fail to get APPFLOWY_COLLAB_CLUSTER_ENABLED")?,
+      flush_policy: get_flush_policy_config()?,
+    },
     s3: S3Setting {
       use_minio: get_env_var("APPFLOWY_S3_USE_MINIO", "true")
         .parse()
@@ -136,6 +313,53 @@ pub fn get_configuration() -> Result<Config, anyhow::Error> {
   Ok(config)
 }
 
+/// Read the key from `APPFLOWY_BLOB_PUBLIC_LINK_KEY`, or generate a random key if it's not set.
+/// The links signed with a random key stop working when the server restarts, and are rejected by
+/// the other servers.
+fn get_blob_public_link_key() -> Secret<String> {
+  match std::env::var("APPFLOWY_BLOB_PUBLIC_LINK_KEY") {
+    Ok(key) if !key.is_empty() => key.into(),
+    _ => {
+      tracing::warn!(
+        "APPFLOWY_BLOB_PUBLIC_LINK_KEY is not set, the public blob links are signed with a random key"
+      );
+      rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect::<String>()
+        .into()
+    },
+  }
+}
+
+fn get_flush_policy_config() -> Result<FlushPolicyConfig, anyhow::Error> {
+  let default = FlushPolicyConfig::default();
+  Ok(FlushPolicyConfig {
+    document: get_flush_policy("DOCUMENT", default.document)?,
+    database: get_flush_policy("DATABASE", default.database)?,
+    workspace: get_flush_policy("WORKSPACE", default.workspace)?,
+  })
+}
+
+/// Read the policy from `APPFLOWY_COLLAB_{kind}_FLUSH_EDIT_COUNT` and
+/// `APPFLOWY_COLLAB_{kind}_FLUSH_INTERVAL_SECS`.
+fn get_flush_policy(
+  kind: &str,
+  default: CollabFlushPolicy,
+) -> Result<CollabFlushPolicy, anyhow::Error> {
+  let edit_count_key = format!("APPFLOWY_COLLAB_{}_FLUSH_EDIT_COUNT", kind);
+  let interval_key = format!("APPFLOWY_COLLAB_{}_FLUSH_INTERVAL_SECS", kind);
+  Ok(CollabFlushPolicy {
+    max_edit_count: get_env_var(&edit_count_key, &default.max_edit_count.to_string())
+      .parse()
+      .with_context(|| format!("fail to get {}", edit_count_key))?,
+    max_interval_secs: get_env_var(&interval_key, &default.max_interval_secs.to_string())
+      .parse()
+      .with_context(|| format!("fail to get {}", interval_key))?,
+  })
+}
+
 fn get_env_var(key: &str, default: &str) -> String {
   std::env::var(key).unwrap_or_else(|e| {
     tracing::warn!(
@@ -182,4 +406,6 @@ impl FromStr for Environment {
 pub struct WebsocketSetting {
   pub heartbeat_interval: u8,
   pub client_timeout: u8,
+  /// Number of collab messages per second accepted from each user, device and collab
+  pub rate_limit: RateLimitConfig,
 }
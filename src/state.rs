@@ -6,7 +6,7 @@ use crate::config::config::Config;
 use app_error::AppError;
 
 use crate::biz::casbin::access_control::AccessControl;
-use database::file::bucket_s3_impl::S3BucketStorage;
+use database::file::bucket_any_impl::AnyBucketStorage;
 use database::user::select_uid_from_uuid;
 use snowflake::Snowflake;
 use sqlx::PgPool;
@@ -28,7 +28,7 @@ pub struct AppState {
   pub collab_storage: Arc<CollabPostgresDBStorage>,
   pub collab_access_control: CollabAccessControlImpl,
   pub workspace_access_control: WorkspaceAccessControlImpl,
-  pub bucket_storage: Arc<S3BucketStorage>,
+  pub bucket_storage: Arc<AnyBucketStorage>,
   pub pg_listeners: Arc<PgListeners>,
   pub access_control: AccessControl,
 }
@@ -0,0 +1,113 @@
+use appflowy_cloud::biz::collab::cluster::RedisCollabCluster;
+use collab::core::collab::MutexCollab;
+use collab::core::origin::CollabOrigin;
+use realtime::collaborate::{ClusterMessage, ClusterPayload, ClusterSync, CollabCluster};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+async fn cluster_node() -> Arc<RedisCollabCluster> {
+  let redis_uri =
+    std::env::var("APPFLOWY_REDIS_URI").unwrap_or_else(|_| "redis://localhost:6379".to_string());
+  let redis_client = redis::Client::open(redis_uri.as_str())
+    .unwrap()
+    .get_tokio_connection_manager()
+    .await
+    .unwrap();
+  Arc::new(
+    RedisCollabCluster::new(&redis_uri, redis_client)
+      .await
+      .unwrap(),
+  )
+}
+
+#[tokio::test]
+async fn cluster_ownership_handoff_test() {
+  let node_1 = cluster_node().await;
+  let node_2 = cluster_node().await;
+  let object_id = Uuid::new_v4().to_string();
+
+  assert!(node_1.claim_ownership(&object_id).await.unwrap());
+  assert!(!node_2.claim_ownership(&object_id).await.unwrap());
+  // The owner renews its ownership
+  assert!(node_1.claim_ownership(&object_id).await.unwrap());
+
+  // Only the owner can release the ownership
+  node_2.release_ownership(&object_id).await.unwrap();
+  assert!(!node_2.claim_ownership(&object_id).await.unwrap());
+
+  node_1.release_ownership(&object_id).await.unwrap();
+  assert!(node_2.claim_ownership(&object_id).await.unwrap());
+  assert!(!node_1.claim_ownership(&object_id).await.unwrap());
+}
+
+#[tokio::test]
+async fn cluster_publish_to_subscribed_nodes_test() {
+  let node_1 = cluster_node().await;
+  let node_2 = cluster_node().await;
+  let node_3 = cluster_node().await;
+  let object_id = Uuid::new_v4().to_string();
+  let mut subscription_1 = node_1.subscribe(&object_id).await.unwrap();
+  let mut subscription_2 = node_2.subscribe(&object_id).await.unwrap();
+  // The node only receives the messages of the collabs it subscribed to
+  let mut subscription_3 = node_3.subscribe(&Uuid::new_v4().to_string()).await.unwrap();
+
+  node_1
+    .publish(ClusterMessage {
+      node_id: node_1.node_id().to_string(),
+      object_id: object_id.clone(),
+      payload: ClusterPayload::Released,
+    })
+    .await
+    .unwrap();
+  let message = timeout(Duration::from_secs(5), subscription_2.messages.recv())
+    .await
+    .unwrap()
+    .unwrap();
+  assert_eq!(message.node_id, node_1.node_id());
+  assert!(matches!(message.payload, ClusterPayload::Released));
+  // The publisher doesn't receive its own messages
+  assert!(subscription_1.messages.try_recv().is_err());
+  assert!(subscription_3.messages.try_recv().is_err());
+
+  // No message is received once unsubscribed
+  node_2.unsubscribe(&object_id).await.unwrap();
+  node_1
+    .publish(ClusterMessage {
+      node_id: node_1.node_id().to_string(),
+      object_id: object_id.clone(),
+      payload: ClusterPayload::Released,
+    })
+    .await
+    .unwrap();
+  assert!(
+    timeout(Duration::from_secs(5), subscription_2.messages.recv())
+      .await
+      .unwrap()
+      .is_none()
+  );
+}
+
+#[tokio::test]
+async fn cluster_sync_collab_across_nodes_test() {
+  let node_1 = cluster_node().await;
+  let node_2 = cluster_node().await;
+  let object_id = Uuid::new_v4().to_string();
+  let collab_1 = MutexCollab::new(CollabOrigin::Server, &object_id, vec![]);
+  let collab_2 = MutexCollab::new(CollabOrigin::Server, &object_id, vec![]);
+  let _subscription_1 = node_1.subscribe(&object_id).await.unwrap();
+  let mut subscription_2 = node_2.subscribe(&object_id).await.unwrap();
+  let _sync_1 = ClusterSync::new(&object_id, &collab_1, node_1.clone()).unwrap();
+  let sync_2 = ClusterSync::new(&object_id, &collab_2, node_2.clone()).unwrap();
+
+  // The edits of the collab on the first node are applied to the replica of the second node
+  collab_1.lock().insert("title", "hello".to_string());
+  let message = timeout(Duration::from_secs(5), subscription_2.messages.recv())
+    .await
+    .unwrap()
+    .unwrap();
+  assert!(matches!(message.payload, ClusterPayload::Update(_)));
+  sync_2.apply(&collab_2, message.payload).unwrap();
+  assert_eq!(collab_2.lock().to_json_value()["title"], "hello");
+}
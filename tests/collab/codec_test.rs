@@ -0,0 +1,92 @@
+use crate::casbin::{create_user, setup_db};
+use anyhow::anyhow;
+use collab_entity::CollabType;
+use database::collab::{
+  create_snapshot, decompress_collab_blob, get_all_collab_snapshot_meta, insert_into_af_collab,
+  recompress_collab_batch, recompress_snapshot_batch, select_blob_from_af_collab, select_snapshot,
+  CollabCodec, CompressedCollab,
+};
+use database_entity::dto::CollabParams;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[sqlx::test(migrations = false)]
+async fn recompress_collabs_and_snapshots_test(pool: PgPool) -> anyhow::Result<()> {
+  setup_db(&pool).await?;
+  let user = create_user(&pool).await?;
+  let workspace = database::workspace::select_user_workspace(&pool, &user.uuid)
+    .await?
+    .into_iter()
+    .next()
+    .ok_or(anyhow!("workspace should be created"))?;
+  let workspace_id = workspace.workspace_id;
+
+  // Written before the compression was enabled
+  let mut collabs = vec![];
+  for i in 0..3 {
+    let object_id = Uuid::new_v4().to_string();
+    let data = format!("collab {} ", i).repeat(100).into_bytes();
+    let params = CollabParams::new(&object_id, CollabType::Document, data.clone());
+    let compressed = CompressedCollab::compress(CollabCodec::Raw, data.clone()).await?;
+    let mut txn = pool.begin().await?;
+    insert_into_af_collab(
+      &mut txn,
+      &user.uid,
+      &workspace_id.to_string(),
+      &params,
+      &compressed,
+    )
+    .await?;
+    txn.commit().await?;
+    create_snapshot(&pool, &object_id, &compressed, &workspace_id).await?;
+    collabs.push((object_id, data));
+  }
+
+  for codec in [CollabCodec::Zstd, CollabCodec::Brotli] {
+    // A batch of one row, so the cursor is used
+    let mut total = 0;
+    let mut after_oid = String::new();
+    loop {
+      let (count, next_oid) = recompress_collab_batch(&pool, codec, &after_oid, 1).await?;
+      total += count;
+      match next_oid {
+        None => break,
+        Some(next_oid) => after_oid = next_oid,
+      }
+    }
+    assert_eq!(total, collabs.len());
+
+    let mut total = 0;
+    let mut after_sid = 0;
+    loop {
+      let (count, next_sid) = recompress_snapshot_batch(&pool, codec, after_sid, 1).await?;
+      total += count;
+      match next_sid {
+        None => break,
+        Some(next_sid) => after_sid = next_sid,
+      }
+    }
+    assert_eq!(total, collabs.len());
+
+    // Nothing left to compress
+    let (count, _) = recompress_collab_batch(&pool, codec, "", 10).await?;
+    assert_eq!(count, 0);
+
+    for (object_id, data) in &collabs {
+      let blob = select_blob_from_af_collab(&pool, &CollabType::Document, object_id).await?;
+      assert_eq!(&blob, data);
+
+      let metas = get_all_collab_snapshot_meta(&pool, object_id).await?;
+      assert_eq!(metas.0.len(), 1);
+      let snapshot = select_snapshot(&pool, &metas.0[0].snapshot_id)
+        .await?
+        .ok_or(anyhow!("snapshot should exist"))?;
+      assert_eq!(snapshot.codec, codec.value());
+      assert_eq!(
+        &decompress_collab_blob(snapshot.codec, snapshot.blob)?,
+        data
+      );
+    }
+  }
+  Ok(())
+}
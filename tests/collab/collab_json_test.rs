@@ -0,0 +1,115 @@
+use crate::collab::util::document_collab_from_blocks;
+use client_api_test_util::{generate_unique_registered_user_client, workspace_id_from_client};
+use collab_entity::CollabType;
+use database_entity::dto::{CreateCollabParams, ImportCsvParams};
+use serde_json::json;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn get_document_json_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let object_id = Uuid::new_v4().to_string();
+  c.create_collab(CreateCollabParams {
+    object_id: object_id.clone(),
+    encoded_collab_v1: document_collab_from_blocks(&object_id, &document("Hello")),
+    collab_type: CollabType::Document,
+    override_if_exist: false,
+    workspace_id: workspace_id.clone(),
+  })
+  .await
+  .unwrap();
+  let snapshot = c
+    .create_snapshot(&workspace_id, &object_id, CollabType::Document)
+    .await
+    .unwrap();
+  c.update_collab(CreateCollabParams {
+    object_id: object_id.clone(),
+    encoded_collab_v1: document_collab_from_blocks(&object_id, &document("World")),
+    collab_type: CollabType::Document,
+    override_if_exist: true,
+    workspace_id: workspace_id.clone(),
+  })
+  .await
+  .unwrap();
+
+  let current = c
+    .get_collab_json(&workspace_id, &object_id, CollabType::Document, None)
+    .await
+    .unwrap();
+  assert_eq!(current.snapshot_id, None);
+  assert_eq!(current.content["type"], "page");
+  assert_eq!(current.content["children"][0]["type"], "paragraph");
+  assert_eq!(
+    current.content["children"][0]["delta"][0]["insert"],
+    "World"
+  );
+
+  let old = c
+    .get_collab_json(
+      &workspace_id,
+      &object_id,
+      CollabType::Document,
+      Some(snapshot.snapshot_id),
+    )
+    .await
+    .unwrap();
+  assert_eq!(old.snapshot_id, Some(snapshot.snapshot_id));
+  assert_eq!(old.content["children"][0]["delta"][0]["insert"], "Hello");
+}
+
+#[tokio::test]
+async fn get_database_json_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let import = c
+    .import_csv(
+      &workspace_id,
+      ImportCsvParams {
+        name: "Grid".to_string(),
+        csv: "Name,Done\nfirst,yes\nsecond,no\n".to_string(),
+      },
+    )
+    .await
+    .unwrap();
+
+  let database = c
+    .get_collab_json(
+      &workspace_id,
+      &import.database_id,
+      CollabType::Database,
+      None,
+    )
+    .await
+    .unwrap();
+  let fields = database.content["fields"].as_array().unwrap();
+  let rows = database.content["rows"].as_array().unwrap();
+  assert_eq!(database.content["view_id"], import.view_id.as_str());
+  assert_eq!(fields.len(), 2);
+  assert_eq!(fields[0]["name"], "Name");
+  assert_eq!(rows.len(), 2);
+  let name_field_id = fields[0]["id"].as_str().unwrap();
+  assert_eq!(rows[1]["cells"][name_field_id], "second");
+}
+
+#[tokio::test]
+async fn get_folder_json_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let folder = c
+    .get_collab_json(&workspace_id, &workspace_id, CollabType::Folder, None)
+    .await
+    .unwrap();
+  assert_eq!(folder.content["workspace_id"], workspace_id.as_str());
+  let views = folder.content["views"].as_array().unwrap();
+  assert!(!views.is_empty());
+  assert!(views.iter().all(|view| view["children"].is_array()));
+}
+
+fn document(text: &str) -> String {
+  json!({
+    "type": "page",
+    "children": [{ "type": "paragraph", "data": { "delta": [{ "insert": text }] } }]
+  })
+  .to_string()
+}
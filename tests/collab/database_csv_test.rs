@@ -0,0 +1,91 @@
+use app_error::ErrorCode;
+use client_api_test_util::{generate_unique_registered_user_client, workspace_id_from_client};
+use collab_entity::CollabType;
+use database_entity::dto::{ImportCsvParams, QueryCollabParams};
+
+#[tokio::test]
+async fn import_and_export_csv_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let csv = "Name,Note\nfirst,\"a, \"\"quoted\"\" note\"\nsecond,\n";
+  let import = c
+    .import_csv(
+      &workspace_id,
+      ImportCsvParams {
+        name: "Grid".to_string(),
+        csv: csv.to_string(),
+      },
+    )
+    .await
+    .unwrap();
+  assert_eq!(import.field_count, 2);
+  assert_eq!(import.row_count, 2);
+
+  // The database and its rows are stored as collabs
+  c.get_collab(QueryCollabParams::new(
+    &import.database_id,
+    CollabType::Database,
+    &workspace_id,
+  ))
+  .await
+  .unwrap();
+
+  let exported = c
+    .export_database_csv(&workspace_id, &import.database_id, None)
+    .await
+    .unwrap();
+  assert_eq!(exported, csv);
+  let exported = c
+    .export_database_csv(
+      &workspace_id,
+      &import.database_id,
+      Some(import.view_id.clone()),
+    )
+    .await
+    .unwrap();
+  assert_eq!(exported, csv);
+}
+
+#[tokio::test]
+async fn import_invalid_csv_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  for csv in ["", "Name\n\"not closed\n"] {
+    let error = c
+      .import_csv(
+        &workspace_id,
+        ImportCsvParams {
+          name: "Grid".to_string(),
+          csv: csv.to_string(),
+        },
+      )
+      .await
+      .unwrap_err();
+    assert_eq!(error.code, ErrorCode::InvalidRequest);
+  }
+}
+
+#[tokio::test]
+async fn export_unknown_view_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let import = c
+    .import_csv(
+      &workspace_id,
+      ImportCsvParams {
+        name: "Grid".to_string(),
+        csv: "Name\nfirst\n".to_string(),
+      },
+    )
+    .await
+    .unwrap();
+  let error = c
+    .export_database_csv(
+      &workspace_id,
+      &import.database_id,
+      Some("unknown".to_string()),
+    )
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::RecordNotFound);
+}
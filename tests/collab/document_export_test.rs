@@ -0,0 +1,97 @@
+use crate::collab::util::document_collab_from_blocks;
+use app_error::ErrorCode;
+use client_api_test_util::{generate_unique_registered_user_client, workspace_id_from_client};
+use collab_entity::CollabType;
+use database_entity::dto::{CreateCollabParams, DocumentExportFormat};
+use serde_json::json;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn export_document_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let object_id = Uuid::new_v4().to_string();
+  let blocks = json!({
+    "type": "page",
+    "data": { "delta": [] },
+    "children": [
+      { "type": "heading", "data": { "level": 1, "delta": [{ "insert": "Hello" }] } },
+      {
+        "type": "bulleted_list",
+        "data": { "delta": [{ "insert": "world", "attributes": { "bold": true } }] }
+      },
+    ]
+  });
+  c.create_collab(CreateCollabParams {
+    object_id: object_id.clone(),
+    encoded_collab_v1: document_collab_from_blocks(&object_id, &blocks.to_string()),
+    collab_type: CollabType::Document,
+    override_if_exist: false,
+    workspace_id: workspace_id.clone(),
+  })
+  .await
+  .unwrap();
+
+  let markdown = c
+    .export_document(&workspace_id, &object_id, DocumentExportFormat::Markdown)
+    .await
+    .unwrap();
+  assert_eq!(markdown, "# Hello\n\n- **world**\n");
+
+  let html = c
+    .export_document(&workspace_id, &object_id, DocumentExportFormat::Html)
+    .await
+    .unwrap();
+  assert!(html.contains("<h1>Hello</h1>"));
+  assert!(html.contains("<ul>\n<li><strong>world</strong>\n</li>\n</ul>"));
+}
+
+#[tokio::test]
+async fn export_template_document_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let object_id = Uuid::new_v4().to_string();
+  c.create_collab(CreateCollabParams {
+    object_id: object_id.clone(),
+    encoded_collab_v1: document_collab_from_blocks(
+      &object_id,
+      include_str!("../../libs/workspace-template/assets/read_me.json"),
+    ),
+    collab_type: CollabType::Document,
+    override_if_exist: false,
+    workspace_id: workspace_id.clone(),
+  })
+  .await
+  .unwrap();
+
+  let markdown = c
+    .export_document(&workspace_id, &object_id, DocumentExportFormat::Markdown)
+    .await
+    .unwrap();
+  assert!(markdown.starts_with(
+    "# Welcome to AppFlowy!\n\n## Here are the basics\n\n- [ ] Click anywhere and just start typing.\n"
+  ));
+  assert!(markdown.contains("to _style_ **your**"));
+
+  let html = c
+    .export_document(&workspace_id, &object_id, DocumentExportFormat::Html)
+    .await
+    .unwrap();
+  assert!(html.contains("<h1>Welcome to AppFlowy!</h1>"));
+  assert!(html.contains("<em>style</em> <strong>your</strong>"));
+}
+
+#[tokio::test]
+async fn export_non_existent_document_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let error = c
+    .export_document(
+      &workspace_id,
+      &Uuid::new_v4().to_string(),
+      DocumentExportFormat::Markdown,
+    )
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::RecordNotFound);
+}
@@ -0,0 +1,80 @@
+use app_error::ErrorCode;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use client_api_test_util::TestClient;
+use database_entity::dto::{DocumentExportFormat, ImportMarkdownParams};
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn import_markdown_test() {
+  let c = TestClient::new_user_without_ws_conn().await;
+  let workspace_id = c.workspace_id().await;
+  let image = vec![1u8; 64];
+  let import = c
+    .api_client
+    .import_markdown(
+      &workspace_id,
+      ImportMarkdownParams {
+        name: "Imported".to_string(),
+        markdown: "# Hello\n\n- **world**\n\n![](./images/a.png)\n\n![](missing.png)\n".to_string(),
+        parent_view_id: None,
+        images: HashMap::from([("images/a.png".to_string(), STANDARD.encode(&image))]),
+      },
+    )
+    .await
+    .unwrap();
+  assert_eq!(import.image_count, 1);
+  assert_eq!(import.missing_images, vec!["missing.png".to_string()]);
+
+  let folder = c.get_user_folder().await;
+  assert!(folder
+    .get_workspace_views()
+    .iter()
+    .any(|view| view.id == import.view_id && view.name == "Imported"));
+
+  let markdown = c
+    .api_client
+    .export_document(
+      &workspace_id,
+      &import.view_id,
+      DocumentExportFormat::Markdown,
+    )
+    .await
+    .unwrap();
+  assert!(markdown.starts_with("# Hello\n\n- **world**\n\n![]("));
+  assert!(markdown.contains("![](missing.png)"));
+
+  // The local image is uploaded as a blob of the workspace
+  let url = markdown
+    .split("![](")
+    .nth(1)
+    .and_then(|rest| rest.split(')').next())
+    .unwrap();
+  assert!(url.starts_with(&format!("/api/file_storage/{}/blob/", workspace_id)));
+  let url = format!("{}{}", c.api_client.base_url(), url);
+  let (_, blob) = c.api_client.get_blob(&url).await.unwrap();
+  assert_eq!(blob, image);
+}
+
+#[tokio::test]
+async fn import_markdown_into_non_existent_view_test() {
+  let c = TestClient::new_user_without_ws_conn().await;
+  let workspace_id = c.workspace_id().await;
+  let error = c
+    .api_client
+    .import_markdown(
+      &workspace_id,
+      ImportMarkdownParams {
+        name: "Imported".to_string(),
+        markdown: "Hello\n\n![](a.png)\n".to_string(),
+        parent_view_id: Some(uuid::Uuid::new_v4().to_string()),
+        images: HashMap::from([("a.png".to_string(), STANDARD.encode([1u8; 64]))]),
+      },
+    )
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::InvalidRequest);
+  assert_eq!(c.get_user_folder().await.get_workspace_views().len(), 1);
+  // The image isn't uploaded
+  assert_eq!(c.get_workspace_usage().await.consumed_capacity, 0);
+}
@@ -363,3 +363,70 @@ async fn edit_collab_with_full_access_then_readonly_permission() {
   )
   .await;
 }
+
+#[tokio::test]
+async fn stop_recv_updates_after_collab_member_removed_test() {
+  let collab_type = CollabType::Document;
+  let mut client_1 = TestClient::new_user().await;
+  let mut client_2 = TestClient::new_user().await;
+
+  let workspace_id = client_1.workspace_id().await;
+  let object_id = client_1
+    .create_and_edit_collab(&workspace_id, collab_type.clone())
+    .await;
+  client_1
+    .add_client_as_collab_member(
+      &workspace_id,
+      &object_id,
+      &client_2,
+      AFAccessLevel::ReadOnly,
+    )
+    .await;
+  client_2
+    .open_collab(&workspace_id, &object_id, collab_type.clone())
+    .await;
+
+  client_1
+    .collab_by_object_id
+    .get_mut(&object_id)
+    .unwrap()
+    .collab
+    .lock()
+    .insert("name", "AppFlowy");
+  client_1.wait_object_sync_complete(&object_id).await;
+  let expected = json!({
+    "name": "AppFlowy"
+  });
+  assert_client_collab(&mut client_2, &object_id, "name", expected, 10).await;
+
+  // Once client 2 is removed from the members of the collab, the server unsubscribes it from the
+  // collab and tells it, so it doesn't receive the subsequent updates.
+  let mut access_revoked = client_2.ws_client.subscribe_access_revoked();
+  client_1
+    .remove_client_as_collab_member(&workspace_id, &object_id, &client_2)
+    .await;
+  let revoked_object_ids =
+    tokio::time::timeout(std::time::Duration::from_secs(10), access_revoked.recv())
+      .await
+      .expect("client 2 should be told its access is revoked")
+      .unwrap();
+  assert_eq!(revoked_object_ids, vec![object_id.clone()]);
+
+  client_1
+    .collab_by_object_id
+    .get_mut(&object_id)
+    .unwrap()
+    .collab
+    .lock()
+    .insert("title", "hello world");
+  client_1.wait_object_sync_complete(&object_id).await;
+  let json = client_2
+    .collab_by_object_id
+    .get(&object_id)
+    .unwrap()
+    .collab
+    .lock()
+    .to_json_value();
+  assert_eq!(json["name"], "AppFlowy");
+  assert!(json.get("title").is_none());
+}
@@ -1,9 +1,18 @@
+mod cluster_test;
+mod codec_test;
 mod collab_curd_test;
+mod collab_json_test;
+mod database_csv_test;
+mod document_export_test;
+mod document_import_test;
 mod edit_permission;
 mod edit_workspace;
 mod member_crud;
 mod multi_devices_edit;
+mod search_test;
 mod single_device_edit;
+mod snapshot_diff_test;
 mod snapshot_test;
 mod storage_test;
+mod update_log_test;
 mod util;
@@ -0,0 +1,84 @@
+use app_error::ErrorCode;
+use client_api_test_util::{
+  generate_unique_registered_user_client, workspace_id_from_client, TestClient,
+};
+use collab_entity::CollabType;
+use database_entity::dto::{AFRole, DeleteCollabParams};
+
+#[tokio::test]
+async fn search_get_started_document_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+
+  // The workspace of a new user contains the getting started document
+  let results = c
+    .search_collab(&workspace_id, "welcome appflowy", None)
+    .await
+    .unwrap();
+  assert_eq!(results.len(), 1);
+  assert!(matches!(results[0].collab_type, CollabType::Document));
+  assert!(results[0].snippet.contains("Welcome"));
+  let object_id = results[0].object_id.clone();
+
+  let results = c
+    .search_collab(&workspace_id, "welcome -appflowy", None)
+    .await
+    .unwrap();
+  assert!(results.is_empty());
+
+  // Deleted documents are not searchable
+  c.delete_collab(DeleteCollabParams {
+    object_id,
+    workspace_id: workspace_id.clone(),
+  })
+  .await
+  .unwrap();
+  let results = c
+    .search_collab(&workspace_id, "welcome", None)
+    .await
+    .unwrap();
+  assert!(results.is_empty());
+}
+
+#[tokio::test]
+async fn search_document_created_by_other_member_test() {
+  let c1 = TestClient::new_user_without_ws_conn().await;
+  let c2 = TestClient::new_user_without_ws_conn().await;
+  let workspace_id = c1.workspace_id().await;
+
+  // The getting started document is created by the owner of the workspace, so the other member
+  // finds it through the role in the workspace rather than as a member of the document
+  c1.add_workspace_member(&workspace_id, &c2, AFRole::Member)
+    .await;
+  let results = c2
+    .api_client
+    .search_collab(&workspace_id, "welcome appflowy", None)
+    .await
+    .unwrap();
+  assert_eq!(results.len(), 1);
+  assert!(matches!(results[0].collab_type, CollabType::Document));
+}
+
+#[tokio::test]
+async fn search_other_workspace_test() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let (c2, _user2) = generate_unique_registered_user_client().await;
+  let workspace_id_1 = workspace_id_from_client(&c1).await;
+
+  let err = c2
+    .search_collab(&workspace_id_1, "welcome", None)
+    .await
+    .unwrap_err();
+  assert_eq!(err.code, ErrorCode::NotEnoughPermissions);
+}
+
+#[tokio::test]
+async fn search_with_empty_query_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let err = c
+    .search_collab(&workspace_id, "  ", None)
+    .await
+    .unwrap_err();
+  assert_eq!(err.code, ErrorCode::InvalidRequest);
+}
@@ -0,0 +1,157 @@
+use app_error::ErrorCode;
+use client_api_test_util::{generate_unique_registered_user_client, workspace_id_from_client};
+use collab::preclude::Collab;
+use collab_entity::CollabType;
+use database_entity::dto::{BlockChangeType, CreateCollabParams, TextDeltaOp};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+use yrs::Any;
+
+#[tokio::test]
+async fn diff_document_snapshots_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let object_id = Uuid::new_v4().to_string();
+  c.create_collab(CreateCollabParams {
+    object_id: object_id.clone(),
+    encoded_collab_v1: document_collab(&object_id, "hello", "removed"),
+    collab_type: CollabType::Document,
+    override_if_exist: false,
+    workspace_id: workspace_id.clone(),
+  })
+  .await
+  .unwrap();
+  let from = c
+    .create_snapshot(&workspace_id, &object_id, CollabType::Document)
+    .await
+    .unwrap();
+
+  c.update_collab(CreateCollabParams {
+    object_id: object_id.clone(),
+    encoded_collab_v1: document_collab(&object_id, "hello world", ""),
+    collab_type: CollabType::Document,
+    override_if_exist: true,
+    workspace_id: workspace_id.clone(),
+  })
+  .await
+  .unwrap();
+
+  // Compare with the current state of the document
+  let diff = c
+    .get_snapshot_diff(&workspace_id, &object_id, from.snapshot_id, None)
+    .await
+    .unwrap();
+  assert_eq!(diff.blocks.len(), 2);
+  assert_eq!(diff.blocks[0].block_id, "b1");
+  assert_eq!(diff.blocks[0].change_type, BlockChangeType::Changed);
+  assert_eq!(
+    diff.blocks[0].text_delta,
+    vec![
+      TextDeltaOp::Retain { retain: 5 },
+      TextDeltaOp::Insert {
+        insert: " world".to_string()
+      }
+    ]
+  );
+  assert_eq!(diff.blocks[1].block_id, "b2");
+  assert_eq!(diff.blocks[1].change_type, BlockChangeType::Removed);
+
+  // Compare two snapshots
+  let to = c
+    .create_snapshot(&workspace_id, &object_id, CollabType::Document)
+    .await
+    .unwrap();
+  let snapshot_diff = c
+    .get_snapshot_diff(
+      &workspace_id,
+      &object_id,
+      from.snapshot_id,
+      Some(to.snapshot_id),
+    )
+    .await
+    .unwrap();
+  assert_eq!(snapshot_diff.blocks, diff.blocks);
+
+  let diff = c
+    .get_snapshot_diff(&workspace_id, &object_id, to.snapshot_id, None)
+    .await
+    .unwrap();
+  assert!(diff.blocks.is_empty());
+}
+
+#[tokio::test]
+async fn diff_snapshots_of_other_collab_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let mut object_ids = vec![];
+  for _ in 0..2 {
+    let object_id = Uuid::new_v4().to_string();
+    c.create_collab(CreateCollabParams {
+      object_id: object_id.clone(),
+      encoded_collab_v1: document_collab(&object_id, "hello", ""),
+      collab_type: CollabType::Document,
+      override_if_exist: false,
+      workspace_id: workspace_id.clone(),
+    })
+    .await
+    .unwrap();
+    object_ids.push(object_id);
+  }
+  let snapshot = c
+    .create_snapshot(&workspace_id, &object_ids[0], CollabType::Document)
+    .await
+    .unwrap();
+
+  let error = c
+    .get_snapshot_diff(&workspace_id, &object_ids[1], snapshot.snapshot_id, None)
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::RecordNotFound);
+}
+
+/// A document with a paragraph of the given text, followed by a second paragraph if its text is
+/// not empty.
+fn document_collab(object_id: &str, text_1: &str, text_2: &str) -> Vec<u8> {
+  let mut children = vec!["b1"];
+  let mut blocks = json!({
+    "page": { "id": "page", "ty": "page", "parent": "", "children": "c_page", "data": "{}" },
+    "b1": { "id": "b1", "ty": "paragraph", "parent": "page", "children": "c_b1", "external_id": "t1", "data": "{}" },
+  });
+  let mut text_map = json!({ "t1": json!([{ "insert": text_1 }]).to_string() });
+  if !text_2.is_empty() {
+    children.push("b2");
+    blocks["b2"] = json!({ "id": "b2", "ty": "paragraph", "parent": "page", "children": "c_b2", "external_id": "t2", "data": "{}" });
+    text_map["t2"] = Value::String(json!([{ "insert": text_2 }]).to_string());
+  }
+  let document = json!({
+    "page_id": "page",
+    "blocks": blocks,
+    "meta": {
+      "children_map": { "c_page": children, "c_b1": [], "c_b2": [] },
+      "text_map": text_map,
+    }
+  });
+
+  let collab = Collab::new(1, object_id, "fake_device_id", vec![]);
+  collab.with_origin_transact_mut(|txn| {
+    collab.insert_with_txn(txn, "document", json_to_any(document));
+  });
+  collab.encode_collab_v1().encode_to_bytes().unwrap()
+}
+
+fn json_to_any(value: Value) -> Any {
+  match value {
+    Value::Null => Any::Null,
+    Value::Bool(value) => Any::Bool(value),
+    Value::Number(value) => Any::Number(value.as_f64().unwrap()),
+    Value::String(value) => Any::String(value.into()),
+    Value::Array(values) => Any::Array(values.into_iter().map(json_to_any).collect()),
+    Value::Object(map) => Any::Map(Arc::new(
+      map
+        .into_iter()
+        .map(|(key, value)| (key, json_to_any(value)))
+        .collect(),
+    )),
+  }
+}
@@ -4,8 +4,13 @@ use collab_entity::CollabType;
 use serde_json::{json, Value};
 use std::time::Duration;
 
+use app_error::ErrorCode;
 use client_api_test_util::*;
 use database::collab::COLLAB_SNAPSHOT_LIMIT;
+use database_entity::dto::{
+  AFSnapshotMeta, AFSnapshotRetentionPolicy, PinSnapshotParams, RestoreSnapshotParams,
+  SnapshotRetentionPolicy,
+};
 use uuid::Uuid;
 
 #[tokio::test]
@@ -106,7 +111,7 @@ async fn snapshot_limit_test() {
     .await;
 
   // When a new snapshot is created that surpasses the preset limit, older snapshots
-  // will be deleted to maintain the limit
+  // will be deleted in the background to maintain the limit
   for _ in 0..(2 * COLLAB_SNAPSHOT_LIMIT) {
     let _ = test_client
       .create_snapshot(&workspace_id, &object_id, collab_type.clone())
@@ -114,12 +119,267 @@ async fn snapshot_limit_test() {
       .unwrap();
   }
 
-  let list = test_client
-    .get_snapshot_list(&workspace_id, &object_id)
+  let list = wait_snapshot_count(
+    &test_client,
+    &workspace_id,
+    &object_id,
+    COLLAB_SNAPSHOT_LIMIT as usize,
+  )
+  .await;
+  assert_eq!(list.len() as i64, COLLAB_SNAPSHOT_LIMIT);
+}
+
+#[tokio::test]
+async fn pinned_snapshot_is_kept_test() {
+  let mut test_client = TestClient::new_user().await;
+  let workspace_id = test_client.workspace_id().await;
+  let collab_type = CollabType::Document;
+  let object_id = test_client
+    .create_and_edit_collab(&workspace_id, collab_type.clone())
+    .await;
+
+  let pinned = test_client
+    .create_snapshot(&workspace_id, &object_id, collab_type.clone())
+    .await
+    .unwrap();
+  let meta = test_client
+    .api_client
+    .pin_snapshot(
+      &workspace_id,
+      &object_id,
+      pinned.snapshot_id,
+      PinSnapshotParams {
+        pinned: true,
+        name: Some("before cleanup".to_string()),
+      },
+    )
+    .await
+    .unwrap();
+  assert!(meta.pinned);
+  assert_eq!(meta.name.as_deref(), Some("before cleanup"));
+
+  for _ in 0..(2 * COLLAB_SNAPSHOT_LIMIT) {
+    test_client
+      .create_snapshot(&workspace_id, &object_id, collab_type.clone())
+      .await
+      .unwrap();
+  }
+
+  // The pinned snapshot is not counted in the snapshots kept by the retention policy
+  let list = wait_snapshot_count(
+    &test_client,
+    &workspace_id,
+    &object_id,
+    COLLAB_SNAPSHOT_LIMIT as usize + 1,
+  )
+  .await;
+  let meta = list
+    .iter()
+    .find(|meta| meta.snapshot_id == pinned.snapshot_id)
+    .unwrap();
+  assert!(meta.pinned);
+  assert_eq!(meta.name.as_deref(), Some("before cleanup"));
+}
+
+#[tokio::test]
+async fn snapshot_retention_policy_test() {
+  let mut test_client = TestClient::new_user().await;
+  let workspace_id = test_client.workspace_id().await;
+  let collab_type = CollabType::Document;
+  let object_id = test_client
+    .create_and_edit_collab(&workspace_id, collab_type.clone())
+    .await;
+
+  let policy = SnapshotRetentionPolicy {
+    keep_latest: 2,
+    hourly_for_hours: 0,
+    daily_for_days: 0,
+    weekly_for_weeks: Some(0),
+  };
+  test_client
+    .api_client
+    .set_snapshot_retention_policy(
+      &workspace_id,
+      AFSnapshotRetentionPolicy {
+        collab_type: Some(collab_type.clone()),
+        policy: policy.clone(),
+      },
+    )
+    .await
+    .unwrap();
+  let policies = test_client
+    .api_client
+    .get_snapshot_retention_policies(&workspace_id)
     .await
     .unwrap()
     .0;
-  assert_eq!(list.len() as i64, COLLAB_SNAPSHOT_LIMIT);
+  assert_eq!(policies.len(), 1);
+  assert_eq!(policies[0].collab_type, Some(collab_type.clone()));
+  assert_eq!(policies[0].policy, policy);
+
+  for _ in 0..5 {
+    test_client
+      .create_snapshot(&workspace_id, &object_id, collab_type.clone())
+      .await
+      .unwrap();
+  }
+  wait_snapshot_count(&test_client, &workspace_id, &object_id, 2).await;
+
+  // A policy must keep at least the latest snapshot
+  let error = test_client
+    .api_client
+    .set_snapshot_retention_policy(
+      &workspace_id,
+      AFSnapshotRetentionPolicy {
+        collab_type: None,
+        policy: SnapshotRetentionPolicy {
+          keep_latest: 0,
+          ..policy
+        },
+      },
+    )
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::InvalidRequest);
+
+  test_client
+    .api_client
+    .delete_snapshot_retention_policy(&workspace_id, Some(collab_type))
+    .await
+    .unwrap();
+  let policies = test_client
+    .api_client
+    .get_snapshot_retention_policies(&workspace_id)
+    .await
+    .unwrap()
+    .0;
+  assert!(policies.is_empty());
+}
+
+/// The snapshots are pruned in the background, so wait until the collab has the expected number
+/// of snapshots.
+async fn wait_snapshot_count(
+  test_client: &TestClient,
+  workspace_id: &str,
+  object_id: &str,
+  expected: usize,
+) -> Vec<AFSnapshotMeta> {
+  let timeout = tokio::time::Instant::now() + Duration::from_secs(10);
+  loop {
+    let list = test_client
+      .get_snapshot_list(workspace_id, object_id)
+      .await
+      .unwrap()
+      .0;
+    if list.len() == expected || tokio::time::Instant::now() > timeout {
+      assert_eq!(list.len(), expected);
+      return list;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+  }
+}
+
+#[tokio::test]
+async fn restore_snapshot_test() {
+  let mut test_client = TestClient::new_user().await;
+  let workspace_id = test_client.workspace_id().await;
+
+  let collab_type = CollabType::Document;
+  let object_id = Uuid::new_v4().to_string();
+  let (data, expected) = test_collab_data(test_client.uid().await, &object_id);
+
+  test_client
+    .create_and_edit_collab_with_data(
+      object_id.clone(),
+      &workspace_id,
+      collab_type.clone(),
+      Some(data),
+    )
+    .await;
+  let meta = test_client
+    .create_snapshot(&workspace_id, &object_id, collab_type.clone())
+    .await
+    .unwrap();
+
+  {
+    let collab = test_client
+      .collab_by_object_id
+      .get_mut(&object_id)
+      .unwrap()
+      .collab
+      .lock();
+    collab.insert("0", "changed");
+    collab.insert("3", "d");
+  }
+  test_client.wait_object_sync_complete(&object_id).await;
+
+  let restore = test_client
+    .api_client
+    .restore_snapshot(
+      &workspace_id,
+      &object_id,
+      RestoreSnapshotParams {
+        snapshot_id: meta.snapshot_id,
+        collab_type: collab_type.clone(),
+      },
+    )
+    .await
+    .unwrap();
+  assert_eq!(restore.snapshot_id, meta.snapshot_id);
+  assert_eq!(restore.restored_by, test_client.uid().await);
+
+  // The connected client receives the restored content
+  assert_client_collab(&mut test_client, &object_id, "0", expected.clone(), 30).await;
+  assert_client_collab(&mut test_client, &object_id, "3", expected.clone(), 30).await;
+  assert_server_collab(
+    &workspace_id,
+    &mut test_client.api_client,
+    &object_id,
+    &collab_type,
+    10,
+    expected,
+  )
+  .await;
+
+  let restores = test_client
+    .api_client
+    .get_snapshot_restores(&workspace_id, &object_id)
+    .await
+    .unwrap()
+    .0;
+  assert_eq!(restores.len(), 1);
+  assert_eq!(restores[0].restore_id, restore.restore_id);
+}
+
+#[tokio::test]
+async fn restore_snapshot_of_other_collab_test() {
+  let mut test_client = TestClient::new_user().await;
+  let workspace_id = test_client.workspace_id().await;
+  let collab_type = CollabType::Document;
+  let object_id_1 = test_client
+    .create_and_edit_collab(&workspace_id, collab_type.clone())
+    .await;
+  let object_id_2 = test_client
+    .create_and_edit_collab(&workspace_id, collab_type.clone())
+    .await;
+  let meta = test_client
+    .create_snapshot(&workspace_id, &object_id_1, collab_type.clone())
+    .await
+    .unwrap();
+
+  let error = test_client
+    .api_client
+    .restore_snapshot(
+      &workspace_id,
+      &object_id_2,
+      RestoreSnapshotParams {
+        snapshot_id: meta.snapshot_id,
+        collab_type,
+      },
+    )
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::RecordNotFound);
 }
 
 fn test_collab_data(uid: i64, oid: &str) -> (EncodedCollab, Value) {
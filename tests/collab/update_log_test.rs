@@ -0,0 +1,157 @@
+use crate::casbin::{create_user, setup_db, User};
+use anyhow::anyhow;
+use collab::core::collab::TransactionMutExt;
+use collab::core::collab_plugin::EncodedCollab;
+use collab::core::transaction::DocTransactionExtension;
+use collab_entity::CollabType;
+use database::collab::{
+  compact_collab_updates, delete_collab, insert_collab_updates, insert_into_af_collab,
+  merge_collab_updates, select_blob_from_af_collab, select_collab_updates,
+  select_collabs_to_compact, CollabCodec, CompressedCollab,
+};
+use database_entity::dto::CollabParams;
+use sqlx::PgPool;
+use uuid::Uuid;
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, GetString, ReadTxn, Text, Transact, Update};
+
+#[sqlx::test(migrations = false)]
+async fn append_compact_and_read_collab_updates_test(pool: PgPool) -> anyhow::Result<()> {
+  setup_db(&pool).await?;
+  let user = create_user(&pool).await?;
+  let workspace_id = user_workspace_id(&pool, &user).await?;
+  let object_id = Uuid::new_v4().to_string();
+
+  let doc = Doc::new();
+  let text = doc.get_or_insert_text("text");
+  text.insert(&mut doc.transact_mut(), 0, "hello");
+  insert_collab(&pool, &user, &workspace_id, &object_id, &doc).await?;
+
+  let mut updates = vec![];
+  for chunk in [" world", "!"] {
+    let state_vector = doc.transact().state_vector();
+    let len = text.len(&doc.transact());
+    text.insert(&mut doc.transact_mut(), len, chunk);
+    updates.push(doc.transact().encode_state_as_update_v1(&state_vector));
+  }
+  insert_collab_updates(
+    &pool,
+    &workspace_id,
+    &object_id,
+    &CollabType::Document,
+    &updates,
+  )
+  .await?;
+
+  // The updates are merged into the collab when it's read
+  assert_eq!(read_text(&pool, &object_id).await?, "hello world!");
+
+  // Two updates are enough to be compacted
+  let (collabs, cursor) = select_collabs_to_compact(&pool, 2, chrono::Utc::now(), None, 10).await?;
+  assert_eq!(collabs, vec![(object_id.clone(), CollabType::Document)]);
+  assert!(cursor.is_none());
+
+  let mut txn = pool.begin().await?;
+  let compacted = compact_collab_updates(
+    &mut txn,
+    CollabCodec::Zstd,
+    &object_id,
+    &CollabType::Document,
+  )
+  .await?;
+  txn.commit().await?;
+  assert!(compacted.is_some());
+
+  let updates = select_collab_updates(&pool, &object_id, &CollabType::Document).await?;
+  assert!(updates.is_empty());
+  assert_eq!(read_text(&pool, &object_id).await?, "hello world!");
+  Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn compact_updates_of_deleted_collab_test(pool: PgPool) -> anyhow::Result<()> {
+  setup_db(&pool).await?;
+  let user = create_user(&pool).await?;
+  let workspace_id = user_workspace_id(&pool, &user).await?;
+  let object_id = Uuid::new_v4().to_string();
+
+  let doc = Doc::new();
+  let text = doc.get_or_insert_text("text");
+  text.insert(&mut doc.transact_mut(), 0, "hello");
+  insert_collab(&pool, &user, &workspace_id, &object_id, &doc).await?;
+  delete_collab(&pool, &object_id).await?;
+
+  // The realtime server may still append the updates of a collab that is open
+  let state_vector = doc.transact().state_vector();
+  text.insert(&mut doc.transact_mut(), 5, "!");
+  let update = doc.transact().encode_state_as_update_v1(&state_vector);
+  insert_collab_updates(
+    &pool,
+    &workspace_id,
+    &object_id,
+    &CollabType::Document,
+    &[update],
+  )
+  .await?;
+
+  let mut txn = pool.begin().await?;
+  let compacted = compact_collab_updates(
+    &mut txn,
+    CollabCodec::Zstd,
+    &object_id,
+    &CollabType::Document,
+  )
+  .await?;
+  txn.commit().await?;
+  assert!(compacted.is_none());
+
+  let updates = select_collab_updates(&pool, &object_id, &CollabType::Document).await?;
+  assert!(updates.is_empty());
+  Ok(())
+}
+
+async fn user_workspace_id(pool: &PgPool, user: &User) -> anyhow::Result<Uuid> {
+  let workspace = database::workspace::select_user_workspace(pool, &user.uuid)
+    .await?
+    .into_iter()
+    .next()
+    .ok_or(anyhow!("workspace should be created"))?;
+  Ok(workspace.workspace_id)
+}
+
+async fn insert_collab(
+  pool: &PgPool,
+  user: &User,
+  workspace_id: &Uuid,
+  object_id: &str,
+  doc: &Doc,
+) -> anyhow::Result<()> {
+  let encoded_collab_v1 = doc.get_encoded_collab_v1().encode_to_bytes()?;
+  let params = CollabParams::new(object_id, CollabType::Document, encoded_collab_v1.clone());
+  let compressed = CompressedCollab::compress(CollabCodec::Raw, encoded_collab_v1).await?;
+  let mut txn = pool.begin().await?;
+  insert_into_af_collab(
+    &mut txn,
+    &user.uid,
+    &workspace_id.to_string(),
+    &params,
+    &compressed,
+  )
+  .await?;
+  txn.commit().await?;
+  Ok(())
+}
+
+async fn read_text(pool: &PgPool, object_id: &str) -> anyhow::Result<String> {
+  let updates = select_collab_updates(pool, object_id, &CollabType::Document).await?;
+  let blob = select_blob_from_af_collab(pool, &CollabType::Document, object_id).await?;
+  let encoded_collab: EncodedCollab = merge_collab_updates(&blob, &updates)?;
+  let doc = Doc::new();
+  doc
+    .transact_mut()
+    .try_apply_update(Update::decode_v1(&encoded_collab.doc_state)?)
+    .map_err(|err| anyhow!("fail to apply update: {:?}", err))?;
+  let text = doc.get_or_insert_text("text");
+  let content = text.get_string(&doc.transact());
+  Ok(content)
+}
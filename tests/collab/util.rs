@@ -1,7 +1,11 @@
+use collab::core::collab::MutexCollab;
 use collab::core::origin::CollabOrigin;
 use collab::preclude::Collab;
+use collab_document::document::Document;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use std::sync::Arc;
+use workspace_template::document::parser::JsonToDocumentParser;
 
 pub fn generate_random_bytes(size: usize) -> Vec<u8> {
   let s: String = thread_rng()
@@ -27,3 +31,15 @@ pub fn make_big_collab_doc_state(object_id: &str, key: &str, value: String) -> V
   collab.insert(key, value);
   collab.encode_collab_v1().doc_state.to_vec()
 }
+
+/// Create a document collab from the json of its blocks, in the format of the AppFlowy templates.
+pub fn document_collab_from_blocks(object_id: &str, json_str: &str) -> Vec<u8> {
+  let document_data = JsonToDocumentParser::json_str_to_document(json_str).unwrap();
+  let collab = Arc::new(MutexCollab::new(CollabOrigin::Empty, object_id, vec![]));
+  let document = Document::create_with_data(collab, document_data).unwrap();
+  document
+    .get_collab()
+    .encode_collab_v1()
+    .encode_to_bytes()
+    .unwrap()
+}
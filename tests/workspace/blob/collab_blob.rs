@@ -0,0 +1,111 @@
+use client_api_test_util::{generate_unique_registered_user_client, workspace_id_from_client};
+use collab_entity::CollabType;
+use database_entity::dto::{AFAccessLevel, CreateCollabParams, InsertCollabMemberParams};
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn collab_member_get_collab_blob_test() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let object_id = Uuid::new_v4().to_string();
+  c1.create_collab(CreateCollabParams {
+    object_id: object_id.clone(),
+    encoded_collab_v1: vec![0; 10],
+    collab_type: CollabType::Document,
+    override_if_exist: false,
+    workspace_id: workspace_id.clone(),
+  })
+  .await
+  .unwrap();
+
+  let file_id = Uuid::new_v4().to_string();
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  c1.put_collab_blob(&url, &object_id, "hello world", &mime::TEXT_PLAIN_UTF_8)
+    .await
+    .unwrap();
+
+  let metadata_url = c1.get_blob_metadata_url(&workspace_id, &file_id);
+  let metadata = c1.get_blob_metadata(&metadata_url).await.unwrap();
+  assert_eq!(metadata.object_id, Some(object_id.clone()));
+
+  // The guest is not a member of the workspace, so it can only read the blob from the collab url
+  let (c2, _user2) = generate_unique_registered_user_client().await;
+  let uid_2 = c2.get_profile().await.unwrap().uid;
+  let collab_blob_url = c2.get_collab_blob_url(&object_id, &file_id);
+  assert!(c2.get_blob(&collab_blob_url).await.is_err());
+
+  c1.add_collab_member(InsertCollabMemberParams {
+    uid: uid_2,
+    workspace_id: workspace_id.clone(),
+    object_id: object_id.clone(),
+    access_level: AFAccessLevel::ReadOnly,
+  })
+  .await
+  .unwrap();
+  let (_, content) = c2.get_blob(&collab_blob_url).await.unwrap();
+  assert_eq!(content, b"hello world");
+  assert!(c2.get_blob(&url).await.is_err());
+
+  // A blob that is not owned by the collab can't be read from the collab url
+  let other_file_id = Uuid::new_v4().to_string();
+  let other_url = c1.get_blob_url(&workspace_id, &other_file_id);
+  c1.put_blob(&other_url, "other", &mime::TEXT_PLAIN_UTF_8)
+    .await
+    .unwrap();
+  let other_collab_blob_url = c2.get_collab_blob_url(&object_id, &other_file_id);
+  assert!(c2.get_blob(&other_collab_blob_url).await.is_err());
+}
+
+#[tokio::test]
+async fn put_blob_with_collab_of_other_workspace_test() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let (c2, _user2) = generate_unique_registered_user_client().await;
+  let workspace_id_2 = workspace_id_from_client(&c2).await;
+  let object_id = Uuid::new_v4().to_string();
+  c2.create_collab(CreateCollabParams {
+    object_id: object_id.clone(),
+    encoded_collab_v1: vec![0; 10],
+    collab_type: CollabType::Document,
+    override_if_exist: false,
+    workspace_id: workspace_id_2,
+  })
+  .await
+  .unwrap();
+
+  let workspace_id_1 = workspace_id_from_client(&c1).await;
+  let url = c1.get_blob_url(&workspace_id_1, &Uuid::new_v4().to_string());
+  let result = c1
+    .put_collab_blob(&url, &object_id, "hello world", &mime::TEXT_PLAIN_UTF_8)
+    .await;
+  assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn get_blob_from_public_link_test() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let file_id = Uuid::new_v4().to_string();
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  c1.put_blob(&url, "hello world", &mime::TEXT_PLAIN_UTF_8)
+    .await
+    .unwrap();
+
+  let link = c1
+    .create_blob_public_link(&workspace_id, &file_id, Some(3600))
+    .await
+    .unwrap();
+  let resp = reqwest::get(&link).await.unwrap();
+  assert_eq!(resp.status(), StatusCode::OK);
+  assert_eq!(resp.bytes().await.unwrap().as_ref(), b"hello world");
+
+  // The signature doesn't match the expiration time anymore
+  let tampered = link.replace("expires=", "expires=1");
+  let resp = reqwest::get(&tampered).await.unwrap();
+  assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+  let err = c1
+    .create_blob_public_link(&workspace_id, &Uuid::new_v4().to_string(), None)
+    .await;
+  assert!(err.is_err());
+}
@@ -0,0 +1,90 @@
+use client_api_test_util::{admin_user_client, generate_unique_registered_user_client};
+use shared_entity::dto::workspace_dto::{BlobGcParams, CreateWorkspaceParam};
+
+#[tokio::test]
+async fn collect_blobs_of_deleted_workspace_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = c
+    .create_workspace(CreateWorkspaceParam {
+      workspace_name: Some("gc_workspace".to_string()),
+    })
+    .await
+    .unwrap()
+    .workspace_id;
+  let mime = mime::TEXT_PLAIN_UTF_8;
+  let url = c.get_blob_url(&workspace_id.to_string(), &uuid::Uuid::new_v4().to_string());
+  c.put_blob(&url, "hello world", &mime).await.unwrap();
+
+  let admin_client = admin_user_client().await;
+  let mut params = BlobGcParams {
+    workspace_id: Some(workspace_id),
+    dry_run: true,
+    min_age_secs: Some(0),
+    ..Default::default()
+  };
+
+  // the blob is still referenced by the workspace
+  let report = admin_client.run_blob_gc(&params).await.unwrap();
+  assert_eq!(report.scanned_objects, 1);
+  assert_eq!(report.orphan_objects, 0);
+
+  // the objects are left behind after the workspace is deleted
+  c.delete_workspace(&workspace_id.to_string()).await.unwrap();
+  let report = admin_client.run_blob_gc(&params).await.unwrap();
+  assert_eq!(report.orphan_objects, 1);
+  assert_eq!(report.orphan_bytes, 11);
+  assert_eq!(report.removed_objects, 0);
+
+  params.dry_run = false;
+  let report = admin_client.run_blob_gc(&params).await.unwrap();
+  assert_eq!(report.removed_objects, 1);
+
+  params.dry_run = true;
+  let report = admin_client.run_blob_gc(&params).await.unwrap();
+  assert_eq!(report.scanned_objects, 0);
+}
+
+#[tokio::test]
+async fn quarantine_blobs_of_deleted_workspace_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = c
+    .create_workspace(CreateWorkspaceParam {
+      workspace_name: Some("gc_quarantine_workspace".to_string()),
+    })
+    .await
+    .unwrap()
+    .workspace_id;
+  let mime = mime::TEXT_PLAIN_UTF_8;
+  let url = c.get_blob_url(&workspace_id.to_string(), &uuid::Uuid::new_v4().to_string());
+  c.put_blob(&url, "hello world", &mime).await.unwrap();
+  c.delete_workspace(&workspace_id.to_string()).await.unwrap();
+
+  let admin_client = admin_user_client().await;
+  let mut params = BlobGcParams {
+    workspace_id: Some(workspace_id),
+    quarantine: true,
+    min_age_secs: Some(0),
+    ..Default::default()
+  };
+  let report = admin_client.run_blob_gc(&params).await.unwrap();
+  assert_eq!(report.orphan_objects, 1);
+  assert_eq!(report.removed_objects, 1);
+
+  // the quarantined object is moved out of the objects of the workspace
+  params.dry_run = true;
+  let report = admin_client.run_blob_gc(&params).await.unwrap();
+  assert_eq!(report.scanned_objects, 0);
+}
+
+#[tokio::test]
+async fn only_admin_can_run_blob_gc_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let error = c
+    .run_blob_gc(&BlobGcParams {
+      dry_run: true,
+      ..Default::default()
+    })
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, app_error::ErrorCode::NotEnoughPermissions);
+}
@@ -0,0 +1,66 @@
+use app_error::ErrorCode;
+use client_api_test_util::{generate_unique_registered_user_client, workspace_id_from_client};
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Rgb};
+
+fn png_image(width: u32, height: u32) -> Vec<u8> {
+  let image = ImageBuffer::from_pixel(width, height, Rgb([0u8, 128, 255]));
+  let mut data = vec![];
+  DynamicImage::ImageRgb8(image)
+    .write_to(&mut data, ImageOutputFormat::Png)
+    .unwrap();
+  data
+}
+
+#[tokio::test]
+async fn get_image_variant_test() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let data = png_image(800, 400);
+  let file_id = uuid::Uuid::new_v4().to_string();
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  c1.put_blob(&url, data.clone(), &mime::IMAGE_PNG)
+    .await
+    .unwrap();
+
+  let metadata_url = c1.get_blob_metadata_url(&workspace_id, &file_id);
+  let metadata = c1.get_blob_metadata(&metadata_url).await.unwrap();
+  assert_eq!(metadata.width, Some(800));
+  assert_eq!(metadata.height, Some(400));
+
+  let small_url = c1.get_blob_variant_url(&workspace_id, &file_id, "small");
+  let (mime, small) = c1.get_blob(&small_url).await.unwrap();
+  assert_eq!(mime, mime::IMAGE_PNG);
+  let small = image::load_from_memory(&small).unwrap();
+  assert_eq!((small.width(), small.height()), (128, 64));
+
+  // The image already fits in the large variant, so the original is returned
+  let large_url = c1.get_blob_variant_url(&workspace_id, &file_id, "large");
+  let (_, large) = c1.get_blob(&large_url).await.unwrap();
+  assert_eq!(large, data);
+
+  let invalid_url = c1.get_blob_variant_url(&workspace_id, &file_id, "huge");
+  assert!(c1.get_blob(&invalid_url).await.is_err());
+
+  c1.delete_blob(&url).await.unwrap();
+  let err = c1.get_blob(&small_url).await.unwrap_err();
+  assert_eq!(err.code, ErrorCode::RecordNotFound);
+}
+
+#[tokio::test]
+async fn no_variant_for_non_image_blob_test() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let file_id = uuid::Uuid::new_v4().to_string();
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  c1.put_blob(&url, "hello world", &mime::TEXT_PLAIN_UTF_8)
+    .await
+    .unwrap();
+
+  let metadata_url = c1.get_blob_metadata_url(&workspace_id, &file_id);
+  let metadata = c1.get_blob_metadata(&metadata_url).await.unwrap();
+  assert_eq!(metadata.width, None);
+
+  let small_url = c1.get_blob_variant_url(&workspace_id, &file_id, "small");
+  let err = c1.get_blob(&small_url).await.unwrap_err();
+  assert_eq!(err.code, ErrorCode::RecordNotFound);
+}
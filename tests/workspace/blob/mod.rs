@@ -1,2 +1,7 @@
+mod collab_blob;
+mod gc;
+mod image_variant;
+mod multipart;
 mod put_and_get;
+mod range;
 mod usage;
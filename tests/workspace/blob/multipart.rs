@@ -0,0 +1,181 @@
+use app_error::ErrorCode;
+use client_api_test_util::{
+  admin_user_client, generate_unique_registered_user_client, workspace_id_from_client,
+};
+use shared_entity::dto::workspace_dto::UpdateWorkspaceQuotaParams;
+
+const PART_SIZE: usize = 5 * 1024 * 1024;
+
+#[tokio::test]
+async fn multipart_upload_and_get() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let mime = mime::APPLICATION_OCTET_STREAM;
+  let file_id = uuid::Uuid::new_v4().to_string();
+  let part_1 = vec![1u8; PART_SIZE];
+  let part_2 = vec![2u8; 1024];
+
+  let upload = c1
+    .create_upload(&workspace_id, &file_id, &mime)
+    .await
+    .unwrap();
+  c1.upload_part(&workspace_id, &upload.upload_id, 1, part_1.clone())
+    .await
+    .unwrap();
+  c1.upload_part(&workspace_id, &upload.upload_id, 2, part_2.clone())
+    .await
+    .unwrap();
+  c1.complete_upload(&workspace_id, &upload.upload_id)
+    .await
+    .unwrap();
+
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  let (got_mime, got_data) = c1.get_blob(&url).await.unwrap();
+  assert_eq!(got_mime, mime);
+  assert_eq!(got_data, [part_1, part_2].concat());
+
+  let metadata_url = c1.get_blob_metadata_url(&workspace_id, &file_id);
+  let metadata = c1.get_blob_metadata(&metadata_url).await.unwrap();
+  assert_eq!(metadata.file_size as usize, PART_SIZE + 1024);
+  c1.delete_blob(&url).await.unwrap();
+}
+
+#[tokio::test]
+async fn resume_multipart_upload() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let mime = mime::APPLICATION_OCTET_STREAM;
+  let file_id = uuid::Uuid::new_v4().to_string();
+
+  let upload = c1
+    .create_upload(&workspace_id, &file_id, &mime)
+    .await
+    .unwrap();
+  c1.upload_part(&workspace_id, &upload.upload_id, 1, vec![1u8; PART_SIZE])
+    .await
+    .unwrap();
+
+  // Creating the upload again returns the pending upload with its uploaded parts
+  let resumed = c1
+    .create_upload(&workspace_id, &file_id, &mime)
+    .await
+    .unwrap();
+  assert_eq!(resumed.upload_id, upload.upload_id);
+  let parts = c1
+    .get_upload_parts(&workspace_id, &resumed.upload_id)
+    .await
+    .unwrap()
+    .0;
+  assert_eq!(parts.len(), 1);
+  assert_eq!(parts[0].part_number, 1);
+  assert_eq!(parts[0].part_size as usize, PART_SIZE);
+
+  c1.upload_part(&workspace_id, &resumed.upload_id, 2, vec![2u8; 10])
+    .await
+    .unwrap();
+  c1.complete_upload(&workspace_id, &resumed.upload_id)
+    .await
+    .unwrap();
+
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  let (_, got_data) = c1.get_blob(&url).await.unwrap();
+  assert_eq!(got_data.len(), PART_SIZE + 10);
+  c1.delete_blob(&url).await.unwrap();
+}
+
+#[tokio::test]
+async fn abort_multipart_upload() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let mime = mime::TEXT_PLAIN_UTF_8;
+  let file_id = uuid::Uuid::new_v4().to_string();
+
+  let upload = c1
+    .create_upload(&workspace_id, &file_id, &mime)
+    .await
+    .unwrap();
+  c1.upload_part(&workspace_id, &upload.upload_id, 1, "hello")
+    .await
+    .unwrap();
+  c1.abort_upload(&workspace_id, &upload.upload_id)
+    .await
+    .unwrap();
+
+  let err = c1
+    .complete_upload(&workspace_id, &upload.upload_id)
+    .await
+    .unwrap_err();
+  assert_eq!(err.code, ErrorCode::RecordNotFound);
+
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  let err = c1.get_blob(&url).await.unwrap_err();
+  assert_eq!(err.code, ErrorCode::RecordNotFound);
+}
+
+#[tokio::test]
+async fn reject_small_part_that_is_not_the_last() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let mime = mime::APPLICATION_OCTET_STREAM;
+  let file_id = uuid::Uuid::new_v4().to_string();
+
+  let upload = c1
+    .create_upload(&workspace_id, &file_id, &mime)
+    .await
+    .unwrap();
+  c1.upload_part(&workspace_id, &upload.upload_id, 1, vec![1u8; 1024])
+    .await
+    .unwrap();
+  c1.upload_part(&workspace_id, &upload.upload_id, 2, vec![2u8; 1024])
+    .await
+    .unwrap();
+
+  let err = c1
+    .complete_upload(&workspace_id, &upload.upload_id)
+    .await
+    .unwrap_err();
+  assert_eq!(err.code, ErrorCode::InvalidRequest);
+}
+
+#[tokio::test]
+async fn upload_same_part_again_within_storage_limit() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  admin_user_client()
+    .await
+    .update_workspace_quota(
+      &workspace_id,
+      &UpdateWorkspaceQuotaParams {
+        storage_limit: Some(15),
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+  let mime = mime::APPLICATION_OCTET_STREAM;
+  let file_id = uuid::Uuid::new_v4().to_string();
+
+  let upload = c1
+    .create_upload(&workspace_id, &file_id, &mime)
+    .await
+    .unwrap();
+  c1.upload_part(&workspace_id, &upload.upload_id, 1, vec![1u8; 10])
+    .await
+    .unwrap();
+  // The part replaces the previous one, so the upload still fits in the limit
+  c1.upload_part(&workspace_id, &upload.upload_id, 1, vec![2u8; 10])
+    .await
+    .unwrap();
+  let error = c1
+    .upload_part(&workspace_id, &upload.upload_id, 2, vec![3u8; 10])
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::StorageSpaceNotEnough);
+
+  c1.complete_upload(&workspace_id, &upload.upload_id)
+    .await
+    .unwrap();
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  let (_, got_data) = c1.get_blob(&url).await.unwrap();
+  assert_eq!(got_data, vec![2u8; 10]);
+}
@@ -1,5 +1,8 @@
 use app_error::ErrorCode;
-use client_api_test_util::{generate_unique_registered_user_client, workspace_id_from_client};
+use client_api::ClientConfiguration;
+use client_api_test_util::{
+  generate_unique_registered_user_client, localhost_client_with_config, workspace_id_from_client,
+};
 
 #[tokio::test]
 async fn get_but_not_exists() {
@@ -40,6 +43,65 @@ async fn put_and_get() {
   c1.delete_blob(&url).await.unwrap();
 }
 
+#[tokio::test]
+async fn put_and_get_metadata() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let mime = mime::IMAGE_PNG;
+  let data = vec![7u8; 4096];
+  let file_id = uuid::Uuid::new_v4().to_string();
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  c1.put_blob(&url, data.clone(), &mime).await.unwrap();
+
+  // Uploading the same file again is a no-op
+  c1.put_blob(&url, data.clone(), &mime).await.unwrap();
+
+  let metadata_url = c1.get_blob_metadata_url(&workspace_id, &file_id);
+  let metadata = c1.get_blob_metadata(&metadata_url).await.unwrap();
+  assert_eq!(metadata.file_id, file_id);
+  assert_eq!(metadata.file_type, mime.to_string());
+  assert_eq!(metadata.file_size as usize, data.len());
+
+  let (got_mime, got_data) = c1.get_blob(&url).await.unwrap();
+  assert_eq!(got_mime, mime);
+  assert_eq!(got_data, data);
+  c1.delete_blob(&url).await.unwrap();
+}
+
+#[tokio::test]
+async fn put_and_get_with_presigned_upload() {
+  let (_, user) = generate_unique_registered_user_client().await;
+  let config = ClientConfiguration::default().with_presigned_blob_upload(true);
+  let c1 = localhost_client_with_config(&uuid::Uuid::new_v4().to_string(), config);
+  c1.sign_in_password(&user.email, &user.password)
+    .await
+    .unwrap();
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let mime = mime::TEXT_PLAIN_UTF_8;
+  let data = "hello presigned world";
+
+  // Falls back to the upload through the server if the server doesn't enable presigned urls.
+  // Either way, deleting one of the blobs with the same content doesn't affect the other.
+  let file_ids = [
+    uuid::Uuid::new_v4().to_string(),
+    uuid::Uuid::new_v4().to_string(),
+  ];
+  for file_id in &file_ids {
+    let url = c1.get_blob_url(&workspace_id, file_id);
+    c1.put_blob(&url, data, &mime).await.unwrap();
+    let metadata_url = c1.get_blob_metadata_url(&workspace_id, file_id);
+    let metadata = c1.get_blob_metadata(&metadata_url).await.unwrap();
+    assert_eq!(metadata.file_size as usize, data.len());
+  }
+
+  let url = c1.get_blob_url(&workspace_id, &file_ids[0]);
+  c1.delete_blob(&url).await.unwrap();
+  let url = c1.get_blob_url(&workspace_id, &file_ids[1]);
+  let (got_mime, got_data) = c1.get_blob(&url).await.unwrap();
+  assert_eq!(got_mime, mime);
+  assert_eq!(got_data, data.as_bytes());
+}
+
 // TODO: fix inconsistent behavior due to different error handling with nginx
 #[tokio::test]
 async fn put_giant_file() {
@@ -0,0 +1,101 @@
+use client_api_test_util::{generate_unique_registered_user_client, workspace_id_from_client};
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_NONE_MATCH, IF_RANGE, RANGE};
+use reqwest::{Method, StatusCode};
+
+#[tokio::test]
+async fn get_blob_with_range() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let file_id = uuid::Uuid::new_v4().to_string();
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  c1.put_blob(&url, "hello world", &mime::TEXT_PLAIN_UTF_8)
+    .await
+    .unwrap();
+
+  for (range, expected, content_range) in [
+    ("bytes=0-4", "hello", "bytes 0-4/11"),
+    ("bytes=6-", "world", "bytes 6-10/11"),
+    ("bytes=-5", "world", "bytes 6-10/11"),
+    ("bytes=6-100", "world", "bytes 6-10/11"),
+  ] {
+    let resp = c1
+      .http_client_with_auth(Method::GET, &url)
+      .await
+      .unwrap()
+      .header(RANGE, range)
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resp.headers().get(CONTENT_RANGE).unwrap(), content_range);
+    assert_eq!(resp.text().await.unwrap(), expected);
+  }
+
+  let resp = c1
+    .http_client_with_auth(Method::GET, &url)
+    .await
+    .unwrap()
+    .header(RANGE, "bytes=20-30")
+    .send()
+    .await
+    .unwrap();
+  assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+  // Outdated If-Range validator returns the whole blob
+  let resp = c1
+    .http_client_with_auth(Method::GET, &url)
+    .await
+    .unwrap()
+    .header(RANGE, "bytes=0-4")
+    .header(IF_RANGE, "\"outdated\"")
+    .send()
+    .await
+    .unwrap();
+  assert_eq!(resp.status(), StatusCode::OK);
+  assert_eq!(resp.text().await.unwrap(), "hello world");
+
+  c1.delete_blob(&url).await.unwrap();
+}
+
+#[tokio::test]
+async fn get_blob_with_etag() {
+  let (c1, _user1) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c1).await;
+  let file_id = uuid::Uuid::new_v4().to_string();
+  let url = c1.get_blob_url(&workspace_id, &file_id);
+  c1.put_blob(&url, "hello world", &mime::TEXT_PLAIN_UTF_8)
+    .await
+    .unwrap();
+
+  let resp = c1
+    .http_client_with_auth(Method::GET, &url)
+    .await
+    .unwrap()
+    .send()
+    .await
+    .unwrap();
+  assert_eq!(resp.status(), StatusCode::OK);
+  let e_tag = resp.headers().get(ETAG).unwrap().clone();
+
+  let resp = c1
+    .http_client_with_auth(Method::GET, &url)
+    .await
+    .unwrap()
+    .header(IF_NONE_MATCH, e_tag)
+    .send()
+    .await
+    .unwrap();
+  assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+  let resp = c1
+    .http_client_with_auth(Method::GET, &url)
+    .await
+    .unwrap()
+    .header(IF_NONE_MATCH, "\"another\"")
+    .send()
+    .await
+    .unwrap();
+  assert_eq!(resp.status(), StatusCode::OK);
+
+  c1.delete_blob(&url).await.unwrap();
+}
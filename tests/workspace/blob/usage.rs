@@ -34,3 +34,30 @@ async fn workspace_usage_put_and_then_delete_blob_test() {
   let usage = client.get_workspace_usage().await;
   assert_eq!(usage.consumed_capacity, 0);
 }
+
+#[tokio::test]
+async fn workspace_usage_put_same_content_blob_test() {
+  let client = TestClient::new_user_without_ws_conn().await;
+  let mime = mime::TEXT_PLAIN_UTF_8;
+  let file_id_1 = uuid::Uuid::new_v4().to_string();
+  let file_id_2 = uuid::Uuid::new_v4().to_string();
+  client.upload_blob(&file_id_1, "123", &mime).await;
+  client.upload_blob(&file_id_2, "123", &mime).await;
+
+  // blobs with the same content are only counted once
+  let usage = client.get_workspace_usage().await;
+  assert_eq!(usage.consumed_capacity, 3);
+
+  // the content is still available after one of the blobs is deleted
+  client.delete_file(&file_id_1).await;
+  let usage = client.get_workspace_usage().await;
+  assert_eq!(usage.consumed_capacity, 3);
+  let workspace_id = client.workspace_id().await;
+  let url = client.api_client.get_blob_url(&workspace_id, &file_id_2);
+  let (_, data) = client.api_client.get_blob(&url).await.unwrap();
+  assert_eq!(data, b"123");
+
+  client.delete_file(&file_id_2).await;
+  let usage = client.get_workspace_usage().await;
+  assert_eq!(usage.consumed_capacity, 0);
+}
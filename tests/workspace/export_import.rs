@@ -0,0 +1,145 @@
+use app_error::ErrorCode;
+use client_api_test_util::TestClient;
+use collab::core::origin::CollabOrigin;
+use collab::preclude::Collab;
+use collab_entity::CollabType;
+use database_entity::dto::{AFRole, AFWorkspaceExport, QueryCollabParams, WorkspaceExportStatus};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn export_and_import_workspace_test() {
+  let owner = TestClient::new_user_without_ws_conn().await;
+  let member = TestClient::new_user_without_ws_conn().await;
+  let workspace_id = owner.workspace_id().await;
+  owner
+    .add_workspace_member(&workspace_id, &member, AFRole::Member)
+    .await;
+  let old_folder = folder_json(&owner, &workspace_id).await;
+  assert!(old_folder.contains(&workspace_id));
+
+  let job = owner
+    .api_client
+    .export_workspace(&workspace_id)
+    .await
+    .unwrap();
+  let job = wait_export_completed(&owner, &workspace_id, &job.job_id).await;
+  let archive = owner
+    .api_client
+    .download_workspace_export(&workspace_id, &job.job_id)
+    .await
+    .unwrap();
+  assert_eq!(job.file_size, Some(archive.len() as i64));
+
+  // Another user imports the archive
+  let importer = TestClient::new_user_without_ws_conn().await;
+  let import = importer.api_client.import_workspace(archive).await.unwrap();
+  assert!(import.collab_count > 0);
+  assert!(import.skipped_members.is_empty());
+  let new_workspace_id = import.workspace.workspace_id.to_string();
+  assert_ne!(new_workspace_id, workspace_id);
+
+  // The folder refers to the new workspace
+  let new_folder = folder_json(&importer, &new_workspace_id).await;
+  assert!(new_folder.contains(&new_workspace_id));
+  assert!(!new_folder.contains(&workspace_id));
+
+  // The members are added as members, even the owner of the exported workspace
+  let member_email = member.email().await;
+  let owner_email = owner.email().await;
+  let importer_email = importer.email().await;
+  let members = importer.get_workspace_members(&new_workspace_id).await;
+  assert!(members
+    .iter()
+    .any(|m| m.email == member_email && m.role == AFRole::Member));
+  assert!(members
+    .iter()
+    .any(|m| m.email == owner_email && m.role == AFRole::Member));
+  assert!(members
+    .iter()
+    .filter(|m| m.role == AFRole::Owner)
+    .all(|m| m.email == importer_email));
+}
+
+#[tokio::test]
+async fn export_of_other_user_test() {
+  let owner = TestClient::new_user_without_ws_conn().await;
+  let member = TestClient::new_user_without_ws_conn().await;
+  let workspace_id = owner.workspace_id().await;
+  owner
+    .add_workspace_member(&workspace_id, &member, AFRole::Member)
+    .await;
+
+  // Only the owner can export the workspace
+  let error = member
+    .api_client
+    .export_workspace(&workspace_id)
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::NotEnoughPermissions);
+
+  // The export job is only visible to the user who started it
+  let job = owner
+    .api_client
+    .export_workspace(&workspace_id)
+    .await
+    .unwrap();
+  let error = member
+    .api_client
+    .get_workspace_export(&workspace_id, &job.job_id)
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::NotEnoughPermissions);
+}
+
+#[tokio::test]
+async fn import_invalid_archive_test() {
+  let c = TestClient::new_user_without_ws_conn().await;
+  let error = c
+    .api_client
+    .import_workspace(b"not a workspace archive".to_vec())
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::InvalidRequest);
+  assert_eq!(c.api_client.get_workspaces().await.unwrap().0.len(), 1);
+}
+
+async fn wait_export_completed(
+  c: &TestClient,
+  workspace_id: &str,
+  job_id: &Uuid,
+) -> AFWorkspaceExport {
+  for _ in 0..30 {
+    let job = c
+      .api_client
+      .get_workspace_export(workspace_id, job_id)
+      .await
+      .unwrap();
+    match job.status {
+      WorkspaceExportStatus::Running => tokio::time::sleep(Duration::from_secs(1)).await,
+      WorkspaceExportStatus::Completed => return job,
+      WorkspaceExportStatus::Failed => panic!("export failed: {:?}", job.error),
+    }
+  }
+  panic!("export is not completed in time");
+}
+
+async fn folder_json(c: &TestClient, workspace_id: &str) -> String {
+  let encoded_collab = c
+    .api_client
+    .get_collab(QueryCollabParams::new(
+      workspace_id,
+      CollabType::Folder,
+      workspace_id,
+    ))
+    .await
+    .unwrap();
+  let collab = Collab::new_with_doc_state(
+    CollabOrigin::Empty,
+    workspace_id,
+    encoded_collab.doc_state.to_vec(),
+    vec![],
+  )
+  .unwrap();
+  collab.to_json_value().to_string()
+}
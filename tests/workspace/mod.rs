@@ -1,4 +1,6 @@
 mod blob;
+mod export_import;
 mod member_crud;
+mod quota;
 mod template_test;
 mod workspace_crud;
@@ -0,0 +1,168 @@
+use app_error::ErrorCode;
+use client_api::ClientConfiguration;
+use client_api_test_util::*;
+use collab::core::collab_plugin::EncodedCollab;
+use collab_entity::CollabType;
+use database_entity::dto::CreateCollabParams;
+use shared_entity::dto::workspace_dto::UpdateWorkspaceQuotaParams;
+use sqlx::types::Uuid;
+
+#[tokio::test]
+async fn only_admin_can_update_workspace_quota_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let params = UpdateWorkspaceQuotaParams {
+    storage_limit: Some(u32::MAX as u64),
+    ..Default::default()
+  };
+  let error = c
+    .update_workspace_quota(&workspace_id, &params)
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::NotEnoughPermissions);
+
+  let admin_client = admin_user_client().await;
+  let quota = admin_client
+    .update_workspace_quota(&workspace_id, &params)
+    .await
+    .unwrap();
+  assert_eq!(quota.storage_limit, u32::MAX as u64);
+}
+
+#[tokio::test]
+async fn workspace_storage_limit_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  let admin_client = admin_user_client().await;
+  admin_client
+    .update_workspace_quota(
+      &workspace_id,
+      &UpdateWorkspaceQuotaParams {
+        storage_limit: Some(5),
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+  let usage = c.get_workspace_usage(&workspace_id).await.unwrap();
+  assert_eq!(usage.total_capacity, 5);
+
+  let mime = mime::TEXT_PLAIN_UTF_8;
+  let url = c.get_blob_url(&workspace_id, &Uuid::new_v4().to_string());
+  c.put_blob(&url, "123", &mime).await.unwrap();
+  let url = c.get_blob_url(&workspace_id, &Uuid::new_v4().to_string());
+  let error = c.put_blob(&url, "456", &mime).await.unwrap_err();
+  assert_eq!(error.code, ErrorCode::StorageSpaceNotEnough);
+
+  // fall back to the limit of the plan after resetting the quota
+  let quota = admin_client
+    .reset_workspace_quota(&workspace_id)
+    .await
+    .unwrap();
+  let plan = admin_client
+    .get_workspace_plans()
+    .await
+    .unwrap()
+    .0
+    .into_iter()
+    .find(|plan| plan.workspace_type == quota.workspace_type)
+    .unwrap();
+  assert_eq!(quota.storage_limit, plan.storage_limit);
+  c.put_blob(&url, "456", &mime).await.unwrap();
+}
+
+#[tokio::test]
+async fn concurrent_uploads_within_storage_limit_test() {
+  let (_, user) = generate_unique_registered_user_client().await;
+  // Uploads through presigned urls if the server enables them, otherwise through the server
+  let config = ClientConfiguration::default().with_presigned_blob_upload(true);
+  let c = localhost_client_with_config(&Uuid::new_v4().to_string(), config);
+  c.sign_in_password(&user.email, &user.password)
+    .await
+    .unwrap();
+  let workspace_id = workspace_id_from_client(&c).await;
+  admin_user_client()
+    .await
+    .update_workspace_quota(
+      &workspace_id,
+      &UpdateWorkspaceQuotaParams {
+        storage_limit: Some(5),
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+  // Each upload fits the limit on its own, but not both of them
+  let mime = mime::TEXT_PLAIN_UTF_8;
+  let url_1 = c.get_blob_url(&workspace_id, &Uuid::new_v4().to_string());
+  let url_2 = c.get_blob_url(&workspace_id, &Uuid::new_v4().to_string());
+  let (result_1, result_2) = tokio::join!(
+    c.put_blob(&url_1, "123", &mime),
+    c.put_blob(&url_2, "456", &mime)
+  );
+  let errors: Vec<_> = [result_1, result_2]
+    .into_iter()
+    .filter_map(|result| result.err())
+    .collect();
+  assert_eq!(errors.len(), 1);
+  assert_eq!(errors[0].code, ErrorCode::StorageSpaceNotEnough);
+
+  let usage = c.get_workspace_usage(&workspace_id).await.unwrap();
+  assert_eq!(usage.consumed_capacity, 3);
+}
+
+#[tokio::test]
+async fn workspace_blob_size_limit_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  admin_user_client()
+    .await
+    .update_workspace_quota(
+      &workspace_id,
+      &UpdateWorkspaceQuotaParams {
+        blob_size_limit: Some(2),
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+  let mime = mime::TEXT_PLAIN_UTF_8;
+  let url = c.get_blob_url(&workspace_id, &Uuid::new_v4().to_string());
+  let error = c.put_blob(&url, "123", &mime).await.unwrap_err();
+  assert_eq!(error.code, ErrorCode::PayloadTooLarge);
+}
+
+#[tokio::test]
+async fn workspace_collab_size_limit_test() {
+  let (c, _user) = generate_unique_registered_user_client().await;
+  let workspace_id = workspace_id_from_client(&c).await;
+  admin_user_client()
+    .await
+    .update_workspace_quota(
+      &workspace_id,
+      &UpdateWorkspaceQuotaParams {
+        collab_size_limit: Some(10),
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+  let encoded_collab_v1 = EncodedCollab::new_v1(vec![], vec![0; 1024])
+    .encode_to_bytes()
+    .unwrap();
+  let error = c
+    .create_collab(CreateCollabParams {
+      object_id: Uuid::new_v4().to_string(),
+      encoded_collab_v1,
+      collab_type: CollabType::Document,
+      override_if_exist: false,
+      workspace_id: workspace_id.clone(),
+    })
+    .await
+    .unwrap_err();
+  assert_eq!(error.code, ErrorCode::PayloadTooLarge);
+}